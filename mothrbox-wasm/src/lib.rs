@@ -2,7 +2,11 @@ use mothrbox_engine::{
     ecc,
     encryption::{
         aes, chacha,
-        ecc::{MothrboxEccPublicKey, ToEncodedPoint},
+        ecc::{Curve, MothrboxPublicKey},
+        header::Algorithm,
+        hpke,
+        secret::{LockedKey, Zeroizing},
+        sui,
     },
 };
 use wasm_bindgen::prelude::*;
@@ -11,7 +15,8 @@ use wasm_bindgen::prelude::*;
 #[wasm_bindgen]
 pub struct KeyPairResult {
     // We use Vec<u8> because wasm-bindgen turns this into a JS Uint8Array automatically
-    private: Vec<u8>,
+    // The private key is wiped when JS calls free()
+    private: Zeroizing<Vec<u8>>,
     public: Vec<u8>,
 }
 
@@ -20,7 +25,7 @@ pub struct KeyPairResult {
 impl KeyPairResult {
     #[wasm_bindgen(getter)]
     pub fn private_key(&self) -> Vec<u8> {
-        self.private.clone()
+        self.private.to_vec()
     }
 
     #[wasm_bindgen(getter)]
@@ -45,6 +50,12 @@ pub fn aes_decrypt(ciphertext: &[u8], password: &str) -> Result<Vec<u8>, String>
     }
 }
 
+// Blobs written before the MBX1 header, which aes_decrypt no longer reads
+#[wasm_bindgen]
+pub fn aes_decrypt_legacy(ciphertext: &[u8], password: &str) -> Result<Vec<u8>, String> {
+    aes::AESEncryption::decrypt_legacy(ciphertext, password).map_err(|err| err.to_string())
+}
+
 #[wasm_bindgen]
 pub fn chacha_encrypt(plaintext: &[u8], password: &str) -> Result<Vec<u8>, String> {
     match chacha::ChaChaEncryption::encrypt(plaintext, password) {
@@ -61,17 +72,22 @@ pub fn chacha_decrypt(ciphertext: &[u8], password: &str) -> Result<Vec<u8>, Stri
     }
 }
 
+// Blobs written before the MBX1 header, which chacha_decrypt no longer reads
+#[wasm_bindgen]
+pub fn chacha_decrypt_legacy(ciphertext: &[u8], password: &str) -> Result<Vec<u8>, String> {
+    chacha::ChaChaEncryption::decrypt_legacy(ciphertext, password).map_err(|err| err.to_string())
+}
+
 #[wasm_bindgen]
 pub fn ecc_generate_key() -> KeyPairResult {
     // Call your original function
     let (priv_array, pub_key_struct) = ecc::ECCEncryption::generate_keypair();
 
-    // A. Convert [u8; 32] private key to Vec<u8>
-    let private_vec = priv_array.to_vec();
+    // A. Convert the private key to Vec<u8>
+    let private_vec = Zeroizing::new(priv_array.to_vec());
 
-    // B. Convert the P256 Public Key struct to bytes (SEC1 encoding)
-    // false = compressed format (33 bytes), true = uncompressed (65 bytes)
-    let public_vec = pub_key_struct.to_encoded_point(false).as_bytes().to_vec();
+    // B. Convert the P256 Public Key struct to bytes (uncompressed SEC1, 65 bytes)
+    let public_vec = pub_key_struct.to_bytes();
 
     // Return the wrapper struct
     KeyPairResult {
@@ -80,6 +96,32 @@ pub fn ecc_generate_key() -> KeyPairResult {
     }
 }
 
+// curve is "p256" or "x25519"; ecc_encrypt takes either public key
+#[wasm_bindgen]
+pub fn ecc_generate_key_for_curve(curve: &str) -> Result<KeyPairResult, JsError> {
+    let curve: Curve = curve
+        .parse()
+        .map_err(|e: mothrbox_engine::Error| JsError::new(&e.to_string()))?;
+    let (private_key, public_key) = curve.generate_keypair();
+
+    Ok(KeyPairResult {
+        private: Zeroizing::new(private_key.to_vec()),
+        public: public_key.to_bytes(),
+    })
+}
+
+// X25519 public key to ecc_encrypt to a Sui account, from the 32-byte
+// Ed25519 public key its wallet reports
+#[wasm_bindgen]
+pub fn sui_x25519_public_key(ed25519_public_key: &[u8]) -> Result<Vec<u8>, JsError> {
+    let ed25519_public_key: [u8; 32] = ed25519_public_key
+        .try_into()
+        .map_err(|_| JsError::new("Ed25519 public key must be exactly 32 bytes"))?;
+    let public_key =
+        sui::x25519_public_key(&ed25519_public_key).map_err(|e| JsError::new(&e.to_string()))?;
+    Ok(public_key.to_bytes())
+}
+
 #[wasm_bindgen]
 pub fn ecc_encrypt(
     plaintext: &[u8],
@@ -87,18 +129,17 @@ pub fn ecc_encrypt(
     sender_private_key_bytes: &[u8],
 ) -> Result<Vec<u8>, JsError> {
     // 1. Convert JS bytes back into a Rust Public Key
-    let recipient_key = MothrboxEccPublicKey::from_sec1_bytes(recipient_public_key_bytes)
+    let recipient_key = MothrboxPublicKey::from_bytes(recipient_public_key_bytes)
         .map_err(|_| JsError::new("Invalid recipient public key format"))?;
 
     // 2. Validate Private Key Length (Must be 32 bytes)
-    let my_secret_array: [u8; 32] = sender_private_key_bytes
-        .try_into()
+    let my_secret_array = LockedKey::from_slice(sender_private_key_bytes)
         .map_err(|_| JsError::new("Private key must be exactly 32 bytes"))?;
 
     // 3. Call your Rust logic (the function we fixed earlier)
     let encrypted_data =
         ecc::ECCEncryption::encrypt_authenticated(plaintext, &recipient_key, &my_secret_array)
-            .map_err(|e| JsError::new(&e))?; // Convert String error to JS Error
+            .map_err(|e| JsError::new(&e.to_string()))?;
 
     // 4. Return bytes (automatically becomes Uint8Array in JS)
     Ok(encrypted_data)
@@ -107,13 +148,86 @@ pub fn ecc_encrypt(
 #[wasm_bindgen]
 pub fn ecc_decrypt(encrypted_data: &[u8], my_private_key_bytes: &[u8]) -> Result<Vec<u8>, JsError> {
     // 1. Validate Private Key Length
-    let my_secret_array: [u8; 32] = my_private_key_bytes
-        .try_into()
+    let my_secret_array = LockedKey::from_slice(my_private_key_bytes)
         .map_err(|_| JsError::new("Private key must be exactly 32 bytes"))?;
 
     // 2. Call the new authenticated decrypt function
     let decrypted_data =
         ecc::ECCEncryption::decrypt_authenticated(encrypted_data, &my_secret_array)
-            .map_err(|e| JsError::new(&e))?;
+            .map_err(|e| JsError::new(&e.to_string()))?;
     Ok(decrypted_data)
 }
+
+// Blobs ecc_encrypt wrote before the MBX1 header, which ecc_decrypt no longer reads
+#[wasm_bindgen]
+pub fn ecc_decrypt_legacy(
+    encrypted_data: &[u8],
+    my_private_key_bytes: &[u8],
+) -> Result<Vec<u8>, JsError> {
+    let my_secret_array = LockedKey::from_slice(my_private_key_bytes)
+        .map_err(|_| JsError::new("Private key must be exactly 32 bytes"))?;
+
+    ecc::ECCEncryption::decrypt_legacy(encrypted_data, &my_secret_array)
+        .map_err(|e| JsError::new(&e.to_string()))
+}
+
+// HPKE (RFC 9180): kem is "x25519" or "p256", aead is "aes" or "chacha"
+#[wasm_bindgen]
+pub fn hpke_generate_key(kem: &str) -> Result<KeyPairResult, JsError> {
+    let kem: hpke::Kem = kem
+        .parse()
+        .map_err(|e: mothrbox_engine::Error| JsError::new(&e.to_string()))?;
+    let (private_key, public_key) = kem
+        .generate_keypair()
+        .map_err(|e| JsError::new(&e.to_string()))?;
+
+    Ok(KeyPairResult {
+        private: Zeroizing::new(private_key.to_vec()),
+        public: public_key.as_bytes().to_vec(),
+    })
+}
+
+// Pass an empty sender_private_key for base mode; with one it is auth mode
+#[wasm_bindgen]
+pub fn hpke_encrypt(
+    plaintext: &[u8],
+    recipient_public_key_bytes: &[u8],
+    sender_private_key_bytes: &[u8],
+    aead: &str,
+) -> Result<Vec<u8>, JsError> {
+    let recipient = hpke::PublicKey::from_bytes(recipient_public_key_bytes)
+        .map_err(|e| JsError::new(&e.to_string()))?;
+    let algorithm: Algorithm = aead
+        .parse()
+        .map_err(|e: mothrbox_engine::Error| JsError::new(&e.to_string()))?;
+    let sender = match sender_private_key_bytes {
+        [] => None,
+        bytes => Some(
+            LockedKey::from_slice(bytes)
+                .map_err(|_| JsError::new("Private key must be exactly 32 bytes"))?,
+        ),
+    };
+
+    hpke::encrypt(plaintext, &recipient, sender.as_deref(), algorithm)
+        .map_err(|e| JsError::new(&e.to_string()))
+}
+
+// Pass the sender's public key for auth mode data, or an empty array for base mode
+#[wasm_bindgen]
+pub fn hpke_decrypt(
+    encrypted_data: &[u8],
+    my_private_key_bytes: &[u8],
+    sender_public_key_bytes: &[u8],
+) -> Result<Vec<u8>, JsError> {
+    let my_secret_array = LockedKey::from_slice(my_private_key_bytes)
+        .map_err(|_| JsError::new("Private key must be exactly 32 bytes"))?;
+    let sender = match sender_public_key_bytes {
+        [] => None,
+        bytes => {
+            Some(hpke::PublicKey::from_bytes(bytes).map_err(|e| JsError::new(&e.to_string()))?)
+        }
+    };
+
+    hpke::decrypt(encrypted_data, &my_secret_array, sender.as_ref())
+        .map_err(|e| JsError::new(&e.to_string()))
+}
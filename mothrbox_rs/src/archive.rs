@@ -0,0 +1,943 @@
+// archive.rs - Encrypted archives of whole directories
+//
+// An archive is a sequence of length-prefixed JSON records, each file's
+// record followed by its contents:
+//
+//   MBXA [version(1)]
+//   [len(4)][ArchiveHeader JSON]
+//   [len(4)][ArchiveEntry JSON][contents]   repeated, contents for files only
+//   [0(4)]                                  end of archive
+//
+// The whole thing is sealed as one streamed MBX1 container, so directories
+// of any size are packed and unpacked without holding them in memory.
+// Uploaded one blob per file instead, the archive is only an index: file
+// records carry a blob id and hash, and each blob is encrypted under a random
+// data key kept in the (encrypted) index header.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::atomic::AtomicFile;
+use crate::secret::{LockedKey, Zeroize, Zeroizing};
+use crate::storage::StorageBackend;
+use crate::{DecryptReader, DecryptionKey, EncryptOptions, EncryptWriter, EncryptionKey, Error};
+
+pub const ARCHIVE_MAGIC: &[u8; 4] = b"MBXA";
+pub const ARCHIVE_VERSION: u8 = 1;
+
+/// Largest single record; paths and link targets are far shorter
+const MAX_RECORD_LEN: usize = 1024 * 1024;
+
+/// Archive-wide settings, the first record
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ArchiveHeader {
+    /// Key the file blobs are encrypted with, when uploaded one blob per file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob_key: Option<BlobKey>,
+}
+
+/// Random data key for per-file blobs
+#[derive(Serialize, Deserialize)]
+pub struct BlobKey {
+    /// `aes` or `chacha`
+    pub algorithm: String,
+    /// Hex-encoded 256-bit key
+    pub key: String,
+}
+
+impl Drop for BlobKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl std::fmt::Debug for BlobKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BlobKey({}, [REDACTED])", self.algorithm)
+    }
+}
+
+/// One file, directory or symlink
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    /// Path below the packed directory, `/`-separated
+    pub path: String,
+    #[serde(flatten)]
+    pub kind: EntryKind,
+    /// Permission bits (`0o644`, `0o755`, ...)
+    pub mode: u32,
+    /// Modification time, seconds since the Unix epoch
+    pub modified: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum EntryKind {
+    Dir,
+    File {
+        size: u64,
+        /// Hex SHA-256 of the contents, recorded for per-file blobs
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+        /// Blob holding the contents; inline in the archive when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        blob_id: Option<String>,
+    },
+    Symlink {
+        target: String,
+    },
+}
+
+/// Which paths below the packed directory to archive.
+///
+/// Patterns use `*` and `?` (not crossing `/`) and `**` (any number of
+/// directories). A pattern without `/` matches the file name at any depth,
+/// one with `/` matches the whole path, like `.gitignore`. Excluding a
+/// directory skips everything in it; with include patterns, only matching
+/// files and symlinks (and the directories leading to them) are kept.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl Filter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, Error> {
+        for pattern in include.iter().chain(exclude) {
+            if pattern.is_empty() || pattern.starts_with('/') {
+                return Err(Error::InvalidInput(format!(
+                    "invalid pattern '{}': patterns are relative to the packed directory",
+                    pattern
+                )));
+            }
+        }
+        Ok(Self {
+            include: include.to_vec(),
+            exclude: exclude.to_vec(),
+        })
+    }
+
+    fn excludes(&self, path: &str) -> bool {
+        self.exclude
+            .iter()
+            .any(|pattern| path_matches(pattern, path))
+    }
+
+    fn includes(&self, path: &str) -> bool {
+        self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| path_matches(pattern, path))
+    }
+}
+
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    if pattern.contains('/') {
+        glob_matches(pattern.as_bytes(), path.as_bytes())
+    } else {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        glob_matches(pattern.as_bytes(), name.as_bytes())
+    }
+}
+
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            // `**/` also matches no directories at all
+            if let [b'/', after @ ..] = rest {
+                if glob_matches(after, text) {
+                    return true;
+                }
+            }
+            (0..=text.len()).any(|i| glob_matches(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != b'/')
+            .any(|i| glob_matches(rest, &text[i..])),
+        [b'?', rest @ ..] => {
+            matches!(text, [c, tail @ ..] if *c != b'/' && glob_matches(rest, tail))
+        }
+        [p, rest @ ..] => matches!(text, [c, tail @ ..] if c == p && glob_matches(rest, tail)),
+    }
+}
+
+/// List what `root` holds, parents before children, in name order
+pub fn scan(root: &Path, filter: &Filter) -> Result<Vec<ArchiveEntry>, Error> {
+    let metadata = fs::metadata(root).map_err(|e| Error::io("Failed to read directory", e))?;
+    if !metadata.is_dir() {
+        return Err(Error::InvalidInput(format!(
+            "'{}' is not a directory",
+            root.display()
+        )));
+    }
+    let mut entries = Vec::new();
+    scan_dir(root, "", filter, &mut entries)?;
+    Ok(entries)
+}
+
+fn scan_dir(
+    dir: &Path,
+    prefix: &str,
+    filter: &Filter,
+    entries: &mut Vec<ArchiveEntry>,
+) -> Result<(), Error> {
+    let mut children = fs::read_dir(dir)
+        .and_then(|children| children.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| Error::io("Failed to read directory", e))?;
+    children.sort_by_key(|child| child.file_name());
+
+    for child in children {
+        let name = child.file_name().into_string().map_err(|name| {
+            Error::InvalidInput(format!("'{}' is not valid UTF-8", name.to_string_lossy()))
+        })?;
+        let path = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        if filter.excludes(&path) {
+            continue;
+        }
+
+        let metadata = fs::symlink_metadata(child.path())
+            .map_err(|e| Error::io("Failed to read file metadata", e))?;
+        let kind = if metadata.is_dir() {
+            let at = entries.len();
+            entries.push(entry(path.clone(), EntryKind::Dir, &metadata));
+            scan_dir(&child.path(), &path, filter, entries)?;
+            // With include patterns, directories are only kept on the way to a match
+            if !filter.include.is_empty() && entries.len() == at + 1 {
+                entries.truncate(at);
+            }
+            continue;
+        } else if metadata.is_symlink() {
+            let target =
+                fs::read_link(child.path()).map_err(|e| Error::io("Failed to read symlink", e))?;
+            let target = target.into_os_string().into_string().map_err(|target| {
+                Error::InvalidInput(format!(
+                    "symlink target '{}' is not valid UTF-8",
+                    target.to_string_lossy()
+                ))
+            })?;
+            EntryKind::Symlink { target }
+        } else if metadata.is_file() {
+            EntryKind::File {
+                size: metadata.len(),
+                sha256: None,
+                blob_id: None,
+            }
+        } else {
+            // Sockets, FIFOs and devices have no contents to archive
+            continue;
+        };
+        if filter.includes(&path) {
+            entries.push(entry(path, kind, &metadata));
+        }
+    }
+    Ok(())
+}
+
+fn entry(path: String, kind: EntryKind, metadata: &fs::Metadata) -> ArchiveEntry {
+    ArchiveEntry {
+        path,
+        kind,
+        mode: permissions::mode(metadata),
+        modified: metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs()),
+    }
+}
+
+/// Write an unencrypted archive of `entries` (from `scan(root, ..)`) to `writer`
+pub fn write_archive<W: Write>(
+    root: &Path,
+    header: &ArchiveHeader,
+    entries: &[ArchiveEntry],
+    writer: &mut W,
+) -> Result<(), Error> {
+    writer.write_all(ARCHIVE_MAGIC)?;
+    writer.write_all(&[ARCHIVE_VERSION])?;
+    write_record(writer, header)?;
+
+    for entry in entries {
+        write_record(writer, entry)?;
+        if let EntryKind::File {
+            size,
+            blob_id: None,
+            ..
+        } = entry.kind
+        {
+            let file = File::open(root.join(&entry.path))
+                .map_err(|e| Error::io(&format!("Failed to open {}", entry.path), e))?;
+            let copied = std::io::copy(&mut file.take(size), writer)?;
+            if copied != size {
+                return Err(Error::InvalidInput(format!(
+                    "{} changed while it was being packed",
+                    entry.path
+                )));
+            }
+        }
+    }
+    writer.write_all(&0u32.to_be_bytes())?;
+    Ok(())
+}
+
+fn write_record<W: Write, T: Serialize>(writer: &mut W, record: &T) -> Result<(), Error> {
+    let json = serde_json::to_vec(record).expect("archive records serialize");
+    if json.len() > MAX_RECORD_LEN {
+        return Err(Error::InvalidInput("archive record too large".to_string()));
+    }
+    writer.write_all(&(json.len() as u32).to_be_bytes())?;
+    writer.write_all(&json)?;
+    Ok(())
+}
+
+/// Next record, `None` at the end of the archive
+fn read_record<R: Read, T: DeserializeOwned>(reader: &mut R) -> Result<Option<T>, Error> {
+    let mut len = [0u8; 4];
+    reader
+        .read_exact(&mut len)
+        .map_err(|_| Error::Truncated("archive ends before its last entry".to_string()))?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 {
+        return Ok(None);
+    }
+    if len > MAX_RECORD_LEN {
+        return Err(Error::MalformedHeader(format!(
+            "archive record of {} bytes",
+            len
+        )));
+    }
+    let mut json = vec![0u8; len];
+    reader
+        .read_exact(&mut json)
+        .map_err(|_| Error::Truncated("archive record is cut short".to_string()))?;
+    serde_json::from_slice(&json)
+        .map(Some)
+        .map_err(|e| Error::MalformedHeader(format!("invalid archive record: {}", e)))
+}
+
+/// Pack `root` into the encrypted archive `output_path`
+pub fn pack_file(
+    root: &str,
+    output_path: &str,
+    key: &EncryptionKey,
+    filter: &Filter,
+) -> Result<Vec<ArchiveEntry>, Error> {
+    let entries = scan(Path::new(root), filter)?;
+    let output = BufWriter::new(AtomicFile::create(output_path)?);
+    let mut writer = EncryptWriter::new(output, key)?;
+    write_archive(
+        Path::new(root),
+        &ArchiveHeader::default(),
+        &entries,
+        &mut writer,
+    )?;
+    writer
+        .finish()?
+        .into_inner()
+        .map_err(|e| Error::io("Failed to write output file", e.into_error()))?
+        .commit()?;
+    Ok(entries)
+}
+
+/// Pack `root` and store the encrypted archive as a single blob
+pub fn pack_to_blob(
+    backend: &dyn StorageBackend,
+    root: &str,
+    key: &EncryptionKey,
+    filter: &Filter,
+) -> Result<(String, Vec<ArchiveEntry>), Error> {
+    let entries = scan(Path::new(root), filter)?;
+    let mut writer = EncryptWriter::new(Vec::new(), key)?;
+    write_archive(
+        Path::new(root),
+        &ArchiveHeader::default(),
+        &entries,
+        &mut writer,
+    )?;
+    let blob_id = backend.put(&writer.finish()?)?;
+    Ok((blob_id, entries))
+}
+
+/// Store every file of `root` as its own blob, plus an encrypted index of
+/// them all. Returns the index blob id, the handle for the whole directory.
+///
+/// Files are encrypted under a fresh random data key (with `key`'s algorithm,
+/// AES for ECC), each bound to its path so blobs cannot be swapped around;
+/// only the index is encrypted with `key` itself.
+pub fn pack_to_blobs(
+    backend: &dyn StorageBackend,
+    root: &str,
+    key: &EncryptionKey,
+    filter: &Filter,
+) -> Result<(String, Vec<ArchiveEntry>), Error> {
+    let mut entries = scan(Path::new(root), filter)?;
+    let algorithm = key.data_algorithm();
+    let data_key = crate::aes::AESEncryption::generate_random_key();
+    let file_key = EncryptionKey::RawKey {
+        algorithm,
+        key: &data_key,
+    };
+
+    for entry in &mut entries {
+        if let EntryKind::File {
+            size,
+            sha256,
+            blob_id,
+        } = &mut entry.kind
+        {
+            let contents = fs::read(Path::new(root).join(&entry.path))
+                .map_err(|e| Error::io(&format!("Failed to read {}", entry.path), e))?;
+            let options = EncryptOptions {
+                aad: entry.path.as_bytes(),
+                ..Default::default()
+            };
+            let sealed = crate::encrypt_with_options(&contents, &file_key, &options)?;
+            *size = contents.len() as u64;
+            *sha256 = Some(hex::encode(Sha256::digest(&contents)));
+            *blob_id = Some(backend.put(&sealed)?);
+        }
+    }
+
+    let header = ArchiveHeader {
+        blob_key: Some(BlobKey {
+            algorithm: algorithm.name().to_string(),
+            key: hex::encode(&data_key[..]),
+        }),
+    };
+    let mut writer = EncryptWriter::new(Vec::new(), key)?;
+    write_archive(Path::new(root), &header, &entries, &mut writer)?;
+    let index_id = backend.put(&writer.finish()?)?;
+    Ok((index_id, entries))
+}
+
+/// Unpack the encrypted archive `input_path` into `dest`.
+/// `backend` fetches the files of an index for per-file blobs.
+pub fn unpack_file(
+    input_path: &str,
+    dest: &str,
+    key: &DecryptionKey,
+    backend: Option<&dyn StorageBackend>,
+) -> Result<Vec<ArchiveEntry>, Error> {
+    let input =
+        File::open(input_path).map_err(|e| Error::io("Failed to open encrypted file", e))?;
+    let reader = DecryptReader::new(BufReader::new(input), key);
+    unpack(reader, Path::new(dest), backend)
+}
+
+/// Fetch an archive (or the index of per-file blobs) and unpack it into `dest`
+pub fn unpack_blob(
+    backend: &dyn StorageBackend,
+    blob_id: &str,
+    dest: &str,
+    key: &DecryptionKey,
+) -> Result<Vec<ArchiveEntry>, Error> {
+    let sealed = backend.get(blob_id)?;
+    unpack(
+        DecryptReader::new(&sealed[..], key),
+        Path::new(dest),
+        Some(backend),
+    )
+}
+
+/// Recreate the archive read from `reader` below `dest`, replacing
+/// existing files. See `Unpacker` for what keeps it inside `dest`.
+pub fn unpack<R: Read>(
+    mut reader: R,
+    dest: &Path,
+    backend: Option<&dyn StorageBackend>,
+) -> Result<Vec<ArchiveEntry>, Error> {
+    let mut magic = [0u8; 5];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|e| match Error::from(e) {
+            Error::Io(_) => Error::Truncated("archive is empty".to_string()),
+            err => err,
+        })?;
+    if &magic[..4] != ARCHIVE_MAGIC {
+        return Err(Error::MalformedHeader("not a MothrBox archive".to_string()));
+    }
+    if magic[4] != ARCHIVE_VERSION {
+        return Err(Error::UnsupportedVersion(magic[4]));
+    }
+    let header: ArchiveHeader = read_record(&mut reader)?
+        .ok_or_else(|| Error::MalformedHeader("archive has no header".to_string()))?;
+    let blob_key = header
+        .blob_key
+        .as_ref()
+        .map(|blob_key| {
+            let key = Zeroizing::new(
+                hex::decode(&blob_key.key)
+                    .map_err(|_| Error::MalformedHeader("invalid blob key".to_string()))?,
+            );
+            LockedKey::from_slice(&key)
+        })
+        .transpose()?;
+
+    let mut unpacker = Unpacker::new(dest)?;
+    let mut entries = Vec::new();
+    while let Some(entry) = read_record::<_, ArchiveEntry>(&mut reader)? {
+        match &entry.kind {
+            EntryKind::File {
+                size,
+                blob_id: None,
+                ..
+            } => unpacker.file(&entry, |output| {
+                let copied = std::io::copy(&mut (&mut reader).take(*size), output)?;
+                if copied != *size {
+                    return Err(Error::Truncated(format!(
+                        "archive ends inside {}",
+                        entry.path
+                    )));
+                }
+                Ok(())
+            })?,
+            EntryKind::File {
+                size,
+                sha256,
+                blob_id: Some(blob_id),
+            } => {
+                let (Some(backend), Some(data_key)) = (backend, &blob_key) else {
+                    return Err(Error::InvalidInput(format!(
+                        "{} is stored in blob {}: unpack the index from its backend",
+                        entry.path, blob_id
+                    )));
+                };
+                unpacker.file(&entry, |output| {
+                    let sealed = backend.get(blob_id)?;
+                    let contents = Zeroizing::new(crate::decrypt_with_aad(
+                        &sealed,
+                        &DecryptionKey::RawKey(data_key),
+                        entry.path.as_bytes(),
+                    )?);
+                    if contents.len() as u64 != *size
+                        || sha256.as_deref() != Some(&hex::encode(Sha256::digest(&contents)))
+                    {
+                        return Err(Error::storage(
+                            backend.name(),
+                            format!("blob {} does not match {}", blob_id, entry.path),
+                        ));
+                    }
+                    output
+                        .write_all(&contents)
+                        .map_err(|e| Error::io("Failed to write output file", e))
+                })?
+            }
+            EntryKind::Dir | EntryKind::Symlink { .. } => unpacker.add(&entry)?,
+        }
+        entries.push(entry);
+    }
+
+    // Reading on to the end authenticates the final chunk of the stream
+    let mut trailing = Vec::new();
+    reader.read_to_end(&mut trailing)?;
+    if !trailing.is_empty() {
+        return Err(Error::MalformedHeader(
+            "unexpected data after the end of the archive".to_string(),
+        ));
+    }
+    unpacker.finish()?;
+    Ok(entries)
+}
+
+/// Recreates entries below a destination directory.
+///
+/// Paths must stay inside it and are never written through a symlink; the
+/// entries' own symlinks are only created by `finish` for that reason.
+/// Directory times and permissions are applied there too, deepest first, so
+/// that filling a directory neither bumps its mtime nor hits a read-only mode.
+pub(crate) struct Unpacker<'a> {
+    dest: &'a Path,
+    deferred: Vec<ArchiveEntry>,
+}
+
+impl<'a> Unpacker<'a> {
+    pub(crate) fn new(dest: &'a Path) -> Result<Self, Error> {
+        fs::create_dir_all(dest).map_err(|e| Error::io("Failed to create output directory", e))?;
+        Ok(Self {
+            dest,
+            deferred: Vec::new(),
+        })
+    }
+
+    /// Create a directory now, or a symlink in `finish`
+    pub(crate) fn add(&mut self, entry: &ArchiveEntry) -> Result<(), Error> {
+        if let EntryKind::Dir = entry.kind {
+            let path = self.dest.join(safe_path(&entry.path)?);
+            check_no_symlinks(self.dest, &path)?;
+            fs::create_dir_all(&path).map_err(|e| Error::io("Failed to create directory", e))?;
+        }
+        self.deferred.push(entry.clone());
+        Ok(())
+    }
+
+    /// Write a file whose contents `write` produces, then give it the
+    /// entry's time and permissions
+    pub(crate) fn file(
+        &mut self,
+        entry: &ArchiveEntry,
+        write: impl FnOnce(&mut AtomicFile) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let path = self.dest.join(safe_path(&entry.path)?);
+        // The rename replaces a symlink at `path` itself rather than following it
+        check_no_symlinks(self.dest, path.parent().unwrap_or(self.dest))?;
+        let mut output = AtomicFile::create(&path)?;
+        write(&mut output)?;
+        output.set_modified(unix_time(entry.modified))?;
+        output.commit()?;
+        permissions::set(&path, entry.mode)
+    }
+
+    pub(crate) fn finish(self) -> Result<(), Error> {
+        for entry in self.deferred.iter().rev() {
+            let path = self.dest.join(safe_path(&entry.path)?);
+            match &entry.kind {
+                EntryKind::Symlink { target } => {
+                    check_no_symlinks(self.dest, path.parent().unwrap_or(self.dest))?;
+                    match fs::symlink_metadata(&path) {
+                        Ok(metadata) if !metadata.is_dir() => fs::remove_file(&path)
+                            .map_err(|e| Error::io("Failed to replace existing file", e))?,
+                        _ => {}
+                    }
+                    permissions::symlink(target, &path)?;
+                }
+                EntryKind::Dir => {
+                    File::open(&path)
+                        .and_then(|dir| dir.set_modified(unix_time(entry.modified)))
+                        .map_err(|e| Error::io("Failed to set modification time", e))?;
+                    permissions::set(&path, entry.mode)?;
+                }
+                EntryKind::File { .. } => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `path` as a relative path of plain components
+fn safe_path(path: &str) -> Result<PathBuf, Error> {
+    let plain = !path.is_empty()
+        && !path.contains(['\\', '\0'])
+        && path
+            .split('/')
+            .all(|part| matches!(Path::new(part).components().next(), Some(Component::Normal(name)) if name == part));
+    if plain {
+        Ok(PathBuf::from(path))
+    } else {
+        Err(Error::MalformedHeader(format!(
+            "archive path '{}' is not a plain relative path",
+            path.escape_debug()
+        )))
+    }
+}
+
+/// Refuse to write below `path` if it, or anything between `dest` and it,
+/// is a symlink
+fn check_no_symlinks(dest: &Path, path: &Path) -> Result<(), Error> {
+    let relative = path.strip_prefix(dest).expect("joined onto dest");
+    let mut current = dest.to_path_buf();
+    for component in relative.components() {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.is_symlink() => {
+                return Err(Error::InvalidInput(format!(
+                    "refusing to unpack through the symlink {}",
+                    current.display()
+                )))
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+            Err(e) => return Err(Error::io("Failed to read file metadata", e)),
+        }
+    }
+    Ok(())
+}
+
+fn unix_time(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[cfg(unix)]
+mod permissions {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    use crate::Error;
+
+    pub fn mode(metadata: &fs::Metadata) -> u32 {
+        metadata.permissions().mode() & 0o777
+    }
+
+    pub fn set(path: &Path, mode: u32) -> Result<(), Error> {
+        // Never restore setuid / setgid / sticky bits from an archive
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+            .map_err(|e| Error::io("Failed to set permissions", e))
+    }
+
+    pub fn symlink(target: &str, path: &Path) -> Result<(), Error> {
+        std::os::unix::fs::symlink(target, path)
+            .map_err(|e| Error::io("Failed to create symlink", e))
+    }
+}
+
+#[cfg(not(unix))]
+mod permissions {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::Error;
+
+    pub fn mode(metadata: &fs::Metadata) -> u32 {
+        match (metadata.is_dir(), metadata.permissions().readonly()) {
+            (true, _) => 0o755,
+            (false, true) => 0o444,
+            (false, false) => 0o644,
+        }
+    }
+
+    pub fn set(path: &Path, mode: u32) -> Result<(), Error> {
+        let mut permissions = fs::metadata(path)
+            .map_err(|e| Error::io("Failed to read file metadata", e))?
+            .permissions();
+        permissions.set_readonly(mode & 0o222 == 0);
+        fs::set_permissions(path, permissions)
+            .map_err(|e| Error::io("Failed to set permissions", e))
+    }
+
+    pub fn symlink(_target: &str, path: &Path) -> Result<(), Error> {
+        Err(Error::Unsupported(format!(
+            "symlinks on this platform ({})",
+            path.display()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::Algorithm;
+    use crate::storage::MemoryBackend;
+
+    fn write(path: &Path, contents: &str, modified: u64) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(unix_time(modified))
+            .unwrap();
+    }
+
+    fn sample_tree(root: &Path) {
+        write(&root.join("notes.txt"), "remember the milk", 1_600_000_000);
+        write(&root.join("src/main.rs"), "fn main() {}", 1_600_000_100);
+        write(&root.join("src/deep/log.txt"), "", 1_600_000_200);
+        write(&root.join("target/debug/app"), "binary", 1_600_000_300);
+        fs::create_dir(root.join("empty")).unwrap();
+        #[cfg(unix)]
+        {
+            permissions::set(&root.join("notes.txt"), 0o600).unwrap();
+            std::os::unix::fs::symlink("notes.txt", root.join("latest")).unwrap();
+        }
+    }
+
+    fn paths(entries: &[ArchiveEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.path.as_str()).collect()
+    }
+
+    #[test]
+    fn test_glob_patterns() {
+        assert!(path_matches("*.txt", "a/b/notes.txt"));
+        assert!(!path_matches("src/*.rs", "src/deep/x.rs"));
+        assert!(path_matches("src/**/*.rs", "src/x.rs"));
+        assert!(path_matches("src/**/*.rs", "src/deep/er/x.rs"));
+        assert!(path_matches("**/target", "target"));
+        assert!(path_matches("target/", "a/target"));
+        assert!(path_matches("log?.txt", "log1.txt"));
+        assert!(!path_matches("log?.txt", "log12.txt"));
+        assert!(Filter::new(&["/etc".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn test_pack_and_unpack_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, dest) = (dir.path().join("project"), dir.path().join("restored"));
+        sample_tree(&root);
+        let archive = dir.path().join("project.mbx");
+        let key = [7u8; 32];
+
+        let filter = Filter::new(&[], &["target".to_string()]).unwrap();
+        let packed = pack_file(
+            root.to_str().unwrap(),
+            archive.to_str().unwrap(),
+            &EncryptionKey::RawKey {
+                algorithm: Algorithm::ChaCha20Poly1305,
+                key: &key,
+            },
+            &filter,
+        )
+        .unwrap();
+        assert!(!paths(&packed).iter().any(|path| path.starts_with("target")));
+
+        let unpacked = unpack_file(
+            archive.to_str().unwrap(),
+            dest.to_str().unwrap(),
+            &DecryptionKey::RawKey(&key),
+            None,
+        )
+        .unwrap();
+        assert_eq!(unpacked, packed);
+        assert_eq!(
+            fs::read_to_string(dest.join("src/main.rs")).unwrap(),
+            "fn main() {}"
+        );
+        assert_eq!(fs::read(dest.join("src/deep/log.txt")).unwrap(), b"");
+        assert!(dest.join("empty").is_dir());
+        assert!(!dest.join("target").exists());
+        assert_eq!(
+            fs::metadata(dest.join("notes.txt"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            unix_time(1_600_000_000)
+        );
+        #[cfg(unix)]
+        {
+            let metadata = fs::metadata(dest.join("notes.txt")).unwrap();
+            assert_eq!(permissions::mode(&metadata), 0o600);
+            assert_eq!(
+                fs::read_link(dest.join("latest")).unwrap(),
+                Path::new("notes.txt")
+            );
+        }
+
+        // Unpacking again replaces what is there
+        unpack_file(
+            archive.to_str().unwrap(),
+            dest.to_str().unwrap(),
+            &DecryptionKey::RawKey(&key),
+            None,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            unpack_file(
+                archive.to_str().unwrap(),
+                dest.to_str().unwrap(),
+                &DecryptionKey::RawKey(&[8u8; 32]),
+                None,
+            ),
+            Err(Error::AuthenticationFailed)
+        ));
+
+        // Includes keep only matches and the directories leading to them
+        let rust_only = Filter::new(&["*.rs".to_string()], &[]).unwrap();
+        assert_eq!(
+            paths(&scan(&root, &rust_only).unwrap()),
+            ["src", "src/main.rs"]
+        );
+    }
+
+    #[test]
+    fn test_per_file_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, dest) = (dir.path().join("project"), dir.path().join("restored"));
+        sample_tree(&root);
+        let backend = MemoryBackend::new();
+        let key = EncryptionKey::Password {
+            algorithm: Algorithm::Aes256Gcm,
+            password: "pw",
+            kdf: Default::default(),
+        };
+
+        let (index_id, packed) =
+            pack_to_blobs(&backend, root.to_str().unwrap(), &key, &Filter::default()).unwrap();
+        let files = packed
+            .iter()
+            .filter(|entry| matches!(entry.kind, EntryKind::File { .. }))
+            .count();
+        assert_eq!(files, 4);
+
+        unpack_blob(
+            &backend,
+            &index_id,
+            dest.to_str().unwrap(),
+            &DecryptionKey::Password("pw"),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(dest.join("target/debug/app")).unwrap(),
+            "binary"
+        );
+
+        // The index alone cannot recreate the files
+        let index = dir.path().join("index.mbx");
+        fs::write(&index, backend.get(&index_id).unwrap()).unwrap();
+        assert!(unpack_file(
+            index.to_str().unwrap(),
+            dir.path().join("elsewhere").to_str().unwrap(),
+            &DecryptionKey::Password("pw"),
+            None,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_unpack_rejects_unsafe_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("dest");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(&outside).unwrap();
+
+        let archive_of = |path: &str| {
+            let mut archive = Vec::new();
+            archive.extend_from_slice(ARCHIVE_MAGIC);
+            archive.push(ARCHIVE_VERSION);
+            write_record(&mut archive, &ArchiveHeader::default()).unwrap();
+            let entry = ArchiveEntry {
+                path: path.to_string(),
+                kind: EntryKind::File {
+                    size: 4,
+                    sha256: None,
+                    blob_id: None,
+                },
+                mode: 0o644,
+                modified: 0,
+            };
+            write_record(&mut archive, &entry).unwrap();
+            archive.extend_from_slice(b"evil");
+            archive.extend_from_slice(&0u32.to_be_bytes());
+            archive
+        };
+
+        for path in ["../outside/x", "/tmp/x", "a/../../x", "./x", "a//x", ""] {
+            assert!(
+                unpack(&archive_of(path)[..], &dest, None).is_err(),
+                "{}",
+                path
+            );
+        }
+        assert!(unpack(&archive_of("ok.txt")[..], &dest, None).is_ok());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&outside, dest.join("link")).unwrap();
+            assert!(unpack(&archive_of("link/x")[..], &dest, None).is_err());
+        }
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,705 @@
+// backup.rs - Incremental backups of a directory as deduplicated snapshots
+//
+// A backup walks a directory and stores each file through a `ChunkStore`, so
+// only chunks the backend does not hold yet are uploaded. Files whose size
+// and mtime match the vault's previous snapshot are not even read: their
+// chunks are carried over. The result is a snapshot, an immutable list of
+// every entry and its chunks, encrypted with the caller's key and stored as
+// one more blob:
+//
+//   MBXS [version(1)] [Snapshot JSON]
+//
+// The snapshot log (`snapshots.jsonl`, beside the catalog) names each
+// snapshot blob so snapshots can be listed, diffed, restored and pruned.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::{self, ArchiveEntry, EntryKind, Filter, Unpacker};
+use crate::atomic::write_atomic;
+use crate::compress::Compression;
+use crate::dedup::{self, ChunkIndex, ChunkRef, ChunkStore, DedupStats};
+use crate::secret::Zeroizing;
+use crate::storage::StorageBackend;
+use crate::{DecryptionKey, EncryptOptions, EncryptionKey, Error};
+
+/// Marks the decrypted contents of a snapshot blob
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"MBXS";
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+/// Everything in a directory at the time of a backup. Holds chunk keys, so
+/// it is only ever stored encrypted.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub vault: String,
+    /// Seconds since the Unix epoch, taken before the directory was read
+    pub created: u64,
+    /// Directory that was backed up
+    pub root: String,
+    /// Snapshot this one was made against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    pub entries: Vec<SnapshotEntry>,
+}
+
+/// An archive entry, and for files the chunks holding its contents
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    #[serde(flatten)]
+    pub entry: ArchiveEntry,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<ChunkRef>,
+}
+
+impl Snapshot {
+    /// Magic, version, then JSON
+    pub fn encode(&self) -> Zeroizing<Vec<u8>> {
+        let mut encoded = Zeroizing::new(SNAPSHOT_MAGIC.to_vec());
+        encoded.push(SNAPSHOT_VERSION);
+        serde_json::to_writer(&mut *encoded, self).expect("snapshots serialize");
+        encoded
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let rest = data
+            .strip_prefix(&SNAPSHOT_MAGIC[..])
+            .ok_or_else(|| Error::MalformedHeader("not a MothrBox snapshot".to_string()))?;
+        match rest.split_first() {
+            Some((&SNAPSHOT_VERSION, json)) => serde_json::from_slice(json)
+                .map_err(|e| Error::MalformedHeader(format!("invalid snapshot: {}", e))),
+            Some((&version, _)) => Err(Error::UnsupportedVersion(version)),
+            None => Err(Error::Truncated("snapshot has no version".to_string())),
+        }
+    }
+
+    /// Fetch and decrypt the snapshot stored in `blob_id`
+    pub fn open(
+        backend: &dyn StorageBackend,
+        blob_id: &str,
+        key: &DecryptionKey,
+    ) -> Result<Self, Error> {
+        let sealed = backend.get(blob_id)?;
+        let plaintext = Zeroizing::new(crate::decrypt(&sealed, key)?);
+        Self::parse(&plaintext)
+    }
+
+    fn chunks(&self) -> impl Iterator<Item = &ChunkRef> {
+        self.entries.iter().flat_map(|entry| &entry.chunks)
+    }
+}
+
+/// Log entry naming a snapshot blob. Holds no keys.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotRecord {
+    pub id: String,
+    pub vault: String,
+    pub blob_id: String,
+    /// Backend spec the snapshot and its chunks live on
+    pub backend: String,
+    pub created: u64,
+    pub root: String,
+    pub files: usize,
+    /// Plaintext bytes of every file
+    pub size: u64,
+    /// Encrypted bytes this backup uploaded, snapshot included
+    pub uploaded: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+}
+
+/// JSON-lines file of `SnapshotRecord`, oldest first, rewritten atomically
+/// like the catalog
+pub struct SnapshotLog {
+    path: PathBuf,
+}
+
+impl SnapshotLog {
+    pub fn at(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// `snapshots.jsonl` in the same directory as the catalog at `catalog_path`
+    pub fn beside(catalog_path: &Path) -> Self {
+        Self::at(catalog_path.with_file_name("snapshots.jsonl"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every snapshot, oldest first; empty if the file does not exist yet
+    pub fn records(&self) -> Result<Vec<SnapshotRecord>, Error> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::io("Failed to read snapshot log", e)),
+        };
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    Error::InvalidInput(format!("{} line {}: {}", self.path.display(), i + 1, e))
+                })
+            })
+            .collect()
+    }
+
+    /// The newest snapshot of `vault` on `backend`
+    pub fn latest(&self, vault: &str, backend: &str) -> Result<Option<SnapshotRecord>, Error> {
+        Ok(self
+            .records()?
+            .into_iter()
+            .rev()
+            .find(|record| record.vault == vault && record.backend == backend))
+    }
+
+    /// Look a snapshot up by id or unique id prefix, or `latest` for the
+    /// newest one; either limited to `vault` if given
+    pub fn find(&self, reference: &str, vault: Option<&str>) -> Result<SnapshotRecord, Error> {
+        let mut records: Vec<_> = self
+            .records()?
+            .into_iter()
+            .filter(|record| vault.is_none_or(|vault| record.vault == vault))
+            .collect();
+        if reference == "latest" {
+            return records
+                .pop()
+                .ok_or_else(|| Error::not_found("snapshot log", vault.unwrap_or(reference)));
+        }
+        if let Some(record) = records.iter().find(|record| record.id == reference) {
+            return Ok(record.clone());
+        }
+        let mut matching = records
+            .into_iter()
+            .filter(|record| !reference.is_empty() && record.id.starts_with(reference));
+        match (matching.next(), matching.next()) {
+            (Some(record), None) => Ok(record),
+            (Some(_), Some(_)) => Err(Error::InvalidInput(format!(
+                "snapshot id '{}' is ambiguous",
+                reference
+            ))),
+            (None, _) => Err(Error::not_found("snapshot log", reference)),
+        }
+    }
+
+    pub fn append(&self, record: SnapshotRecord) -> Result<(), Error> {
+        let mut records = self.records()?;
+        if records.iter().any(|r| r.id == record.id) {
+            return Err(Error::InvalidInput(format!(
+                "snapshot {} is already recorded",
+                record.id
+            )));
+        }
+        records.push(record);
+        self.save(&records)
+    }
+
+    /// Forget the snapshots with these ids; their blobs are left alone
+    pub fn remove(&self, ids: &[String]) -> Result<(), Error> {
+        let mut records = self.records()?;
+        records.retain(|record| !ids.contains(&record.id));
+        self.save(&records)
+    }
+
+    fn save(&self, records: &[SnapshotRecord]) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .map_err(|e| Error::io("Failed to create snapshot log directory", e))?;
+        }
+        let mut text = String::new();
+        for record in records {
+            text.push_str(&serde_json::to_string(record).expect("snapshot records serialize"));
+            text.push('\n');
+        }
+        write_atomic(&self.path, text.as_bytes())
+    }
+}
+
+/// What a backup found and sent
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BackupStats {
+    pub files: usize,
+    /// Files carried over from the previous snapshot, or read and found identical
+    pub unchanged: usize,
+    pub changed: usize,
+    pub added: usize,
+    /// Files in the previous snapshot that are gone
+    pub removed: usize,
+    /// Chunks of the files that were read
+    pub dedup: DedupStats,
+}
+
+/// Back up `root` as a new snapshot of `vault`, sealed with `key`.
+///
+/// Files with the same size and mtime as in `previous` keep its chunks
+/// without being read, unless they were modified in the second that
+/// snapshot was taken (a later write in that second would not show).
+/// Everything else is chunked, and only new chunks are uploaded. The caller
+/// records the returned snapshot in its `SnapshotLog`.
+pub fn backup(
+    store: &mut ChunkStore,
+    root: &str,
+    vault: &str,
+    filter: &Filter,
+    key: &EncryptionKey,
+    previous: Option<Snapshot>,
+) -> Result<(SnapshotRecord, BackupStats), Error> {
+    crate::catalog::check_name(vault)?;
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let scanned = archive::scan(Path::new(root), filter)?;
+
+    let (parent, previous_created, previous_files) = match previous {
+        Some(snapshot) => (
+            Some(snapshot.id),
+            snapshot.created,
+            snapshot
+                .entries
+                .into_iter()
+                .filter(|e| matches!(e.entry.kind, EntryKind::File { .. }))
+                .map(|e| (e.entry.path.clone(), e))
+                .collect(),
+        ),
+        None => (None, 0, HashMap::new()),
+    };
+    let mut stats = BackupStats::default();
+    let result = snapshot_entries(
+        store,
+        Path::new(root),
+        scanned,
+        previous_files,
+        previous_created,
+        &mut stats,
+    );
+    store.save_index()?;
+    let entries = result?;
+
+    let mut id = [0u8; 4];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut id);
+    let snapshot = Snapshot {
+        id: hex::encode(id),
+        vault: vault.to_string(),
+        created,
+        root: fs::canonicalize(root)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| root.to_string()),
+        parent,
+        entries,
+    };
+    let options = EncryptOptions {
+        compression: Compression::lz4(),
+        ..Default::default()
+    };
+    let sealed = crate::encrypt_with_options(&snapshot.encode(), key, &options)?;
+    let stored = store.backend().store(&sealed)?;
+
+    let record = SnapshotRecord {
+        id: snapshot.id,
+        vault: snapshot.vault,
+        blob_id: stored.blob_id,
+        backend: store.backend_spec().to_string(),
+        created,
+        root: snapshot.root,
+        files: stats.files,
+        size: snapshot
+            .entries
+            .iter()
+            .map(|e| match e.entry.kind {
+                EntryKind::File { size, .. } => size,
+                _ => 0,
+            })
+            .sum(),
+        uploaded: stats.dedup.uploaded_bytes + sealed.len() as u64,
+        parent: snapshot.parent,
+    };
+    Ok((record, stats))
+}
+
+fn snapshot_entries(
+    store: &mut ChunkStore,
+    root: &Path,
+    scanned: Vec<ArchiveEntry>,
+    mut previous_files: HashMap<String, SnapshotEntry>,
+    previous_created: u64,
+    stats: &mut BackupStats,
+) -> Result<Vec<SnapshotEntry>, Error> {
+    let mut entries = Vec::with_capacity(scanned.len());
+    for entry in scanned {
+        let EntryKind::File { size, .. } = entry.kind else {
+            entries.push(SnapshotEntry {
+                entry,
+                chunks: Vec::new(),
+            });
+            continue;
+        };
+        stats.files += 1;
+        let previous = previous_files.remove(&entry.path);
+        let previous_sha256 = match &previous {
+            Some(SnapshotEntry {
+                entry:
+                    ArchiveEntry {
+                        kind: EntryKind::File { sha256, .. },
+                        ..
+                    },
+                ..
+            }) => sha256.clone(),
+            _ => None,
+        };
+
+        if let Some(previous) = previous.filter(|previous| {
+            matches!(previous.entry.kind, EntryKind::File { size: previous_size, .. } if previous_size == size)
+                && previous.entry.modified == entry.modified
+                && entry.modified < previous_created
+        }) {
+            // Permissions change without touching the mtime, so take them from the scan
+            stats.unchanged += 1;
+            entries.push(SnapshotEntry {
+                entry: ArchiveEntry {
+                    kind: previous.entry.kind.clone(),
+                    ..entry
+                },
+                chunks: previous.chunks,
+            });
+            continue;
+        }
+
+        let file = File::open(root.join(&entry.path))
+            .map_err(|e| Error::io("Failed to open input file", e))?;
+        let (list, chunk_stats) = store.put_chunks(BufReader::new(file), None)?;
+        stats.dedup.add(chunk_stats);
+        match &previous_sha256 {
+            Some(sha256) if *sha256 == list.sha256 => stats.unchanged += 1,
+            Some(_) => stats.changed += 1,
+            None => stats.added += 1,
+        }
+        // The file may have changed since it was scanned: record what was read
+        entries.push(SnapshotEntry {
+            entry: ArchiveEntry {
+                kind: EntryKind::File {
+                    size: list.size,
+                    sha256: Some(list.sha256),
+                    blob_id: None,
+                },
+                ..entry
+            },
+            chunks: list.chunks,
+        });
+    }
+    stats.removed = previous_files.len();
+    Ok(entries)
+}
+
+/// Recreate `snapshot` below `dest`, fetching its chunks from `backend`.
+/// Paths are kept inside `dest` and never written through a symlink, as
+/// when unpacking an archive.
+pub fn restore(backend: &dyn StorageBackend, snapshot: &Snapshot, dest: &str) -> Result<(), Error> {
+    let mut unpacker = Unpacker::new(Path::new(dest))?;
+    for SnapshotEntry { entry, chunks } in &snapshot.entries {
+        match &entry.kind {
+            EntryKind::File { size, sha256, .. } => {
+                let sha256 = sha256.as_deref().ok_or_else(|| {
+                    Error::MalformedHeader(format!("snapshot has no hash for {}", entry.path))
+                })?;
+                unpacker.file(entry, |output| {
+                    dedup::write_chunks(backend, chunks, *size, sha256, output)
+                })?
+            }
+            EntryKind::Dir | EntryKind::Symlink { .. } => unpacker.add(entry)?,
+        }
+    }
+    unpacker.finish()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl Change {
+    /// `+`, `-` or `M`, as `mothrbox diff` prints it
+    pub fn symbol(&self) -> char {
+        match self {
+            Change::Added => '+',
+            Change::Removed => '-',
+            Change::Modified => 'M',
+        }
+    }
+}
+
+/// Paths that differ between two snapshots, in path order. Contents, type,
+/// link target and permissions count; a new mtime alone does not.
+pub fn diff<'a>(old: &'a Snapshot, new: &'a Snapshot) -> Vec<(Change, &'a str)> {
+    let by_path = |snapshot: &'a Snapshot| {
+        snapshot
+            .entries
+            .iter()
+            .map(|e| (e.entry.path.as_str(), &e.entry))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let (old, new) = (by_path(old), by_path(new));
+    let mut changes: Vec<_> = old
+        .iter()
+        .filter_map(|(path, entry)| match new.get(path) {
+            None => Some((Change::Removed, *path)),
+            Some(other) if entry.mode != other.mode || entry.kind != other.kind => {
+                Some((Change::Modified, *path))
+            }
+            Some(_) => None,
+        })
+        .chain(
+            new.keys()
+                .filter(|path| !old.contains_key(*path))
+                .map(|path| (Change::Added, *path)),
+        )
+        .collect();
+    changes.sort_by_key(|(_, path)| *path);
+    changes
+}
+
+/// Snapshots of `vault` beyond its newest `keep`, oldest first
+pub fn prune_plan(records: &[SnapshotRecord], vault: &str, keep: usize) -> Vec<SnapshotRecord> {
+    let of_vault: Vec<_> = records.iter().filter(|r| r.vault == vault).collect();
+    let excess = of_vault.len().saturating_sub(keep);
+    of_vault[..excess].iter().map(|r| (*r).clone()).collect()
+}
+
+/// What deleting snapshots freed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PruneStats {
+    pub snapshots: usize,
+    pub chunks: usize,
+    /// Encrypted bytes of the deleted chunks
+    pub bytes: u64,
+}
+
+/// Delete the blobs of the `pruned` snapshots from `backend`: each snapshot
+/// blob, and every chunk none of the `kept` snapshots references.
+///
+/// Chunks are shared by content, so `kept` must hold every other snapshot
+/// (of any vault) whose chunks live on `backend`, and nothing else stored
+/// there may use the same chunks. Deleted chunks are dropped from `index`,
+/// which is saved even if a deletion fails part way.
+pub fn delete_snapshots(
+    backend: &dyn StorageBackend,
+    index: &mut ChunkIndex,
+    backend_spec: &str,
+    pruned: &[(SnapshotRecord, Snapshot)],
+    kept: &[Snapshot],
+) -> Result<PruneStats, Error> {
+    let referenced: HashSet<&str> = kept
+        .iter()
+        .flat_map(Snapshot::chunks)
+        .map(|chunk| chunk.id.as_str())
+        .collect();
+    let mut stats = PruneStats::default();
+    let mut deleted = HashSet::new();
+    let mut result = Ok(());
+    for chunk in pruned.iter().flat_map(|(_, snapshot)| snapshot.chunks()) {
+        if referenced.contains(chunk.id.as_str()) || !deleted.insert(chunk.id.as_str()) {
+            continue;
+        }
+        match backend.delete(&chunk.blob_id) {
+            Ok(()) | Err(Error::NotFound { .. }) => {}
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+        index.remove(backend_spec, &chunk.id);
+        stats.chunks += 1;
+        stats.bytes += chunk.stored_size;
+    }
+    index.save()?;
+    result?;
+
+    for (record, _) in pruned {
+        match backend.delete(&record.blob_id) {
+            Ok(()) | Err(Error::NotFound { .. }) => {}
+            Err(e) => return Err(e),
+        }
+        stats.snapshots += 1;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dedup::{ChunkKeying, Chunker};
+    use crate::header::Algorithm;
+    use crate::storage::MemoryBackend;
+
+    const KEY: [u8; 32] = [3u8; 32];
+
+    fn write(path: &Path, contents: &[u8]) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000))
+            .unwrap();
+    }
+
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn run_backup(
+        backend: &MemoryBackend,
+        index: &Path,
+        root: &Path,
+        previous: Option<&SnapshotRecord>,
+    ) -> (SnapshotRecord, BackupStats) {
+        let mut store = ChunkStore::new(
+            backend,
+            "memory",
+            ChunkIndex::open(index).unwrap(),
+            ChunkKeying::Convergent,
+        )
+        .with_chunker(Chunker::new(4096).unwrap());
+        let previous = previous
+            .map(|r| Snapshot::open(backend, &r.blob_id, &DecryptionKey::RawKey(&KEY)).unwrap());
+        backup(
+            &mut store,
+            root.to_str().unwrap(),
+            "home",
+            &Filter::default(),
+            &EncryptionKey::RawKey {
+                algorithm: Algorithm::Aes256Gcm,
+                key: &KEY,
+            },
+            previous,
+        )
+        .unwrap()
+    }
+
+    fn open(backend: &MemoryBackend, record: &SnapshotRecord) -> Snapshot {
+        Snapshot::open(backend, &record.blob_id, &DecryptionKey::RawKey(&KEY)).unwrap()
+    }
+
+    #[test]
+    fn test_incremental_backup_diff_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, index) = (dir.path().join("home"), dir.path().join("chunks.jsonl"));
+        let backend = MemoryBackend::new();
+        let big = noise(60_000, 1);
+        write(&root.join("big.bin"), &big);
+        write(&root.join("notes.txt"), b"hello");
+        write(&root.join("old/gone.txt"), b"soon deleted");
+
+        let (first, stats) = run_backup(&backend, &index, &root, None);
+        assert_eq!((stats.files, stats.added), (3, 3));
+        assert_eq!(stats.dedup.new_chunks, stats.dedup.chunks);
+        assert_eq!(first.size, big.len() as u64 + 17);
+
+        // An unchanged file is not read again; the others only send new chunks
+        write(&root.join("notes.txt"), b"hello again");
+        fs::remove_file(root.join("old/gone.txt")).unwrap();
+        write(&root.join("new.txt"), b"soon deleted");
+        let (second, stats) = run_backup(&backend, &index, &root, Some(&first));
+        assert_eq!(second.parent.as_deref(), Some(first.id.as_str()));
+        assert_eq!(
+            (stats.unchanged, stats.changed, stats.added, stats.removed),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(stats.dedup.bytes, 11 + 12);
+        assert_eq!((stats.dedup.chunks, stats.dedup.new_chunks), (2, 1));
+
+        let (old, new) = (open(&backend, &first), open(&backend, &second));
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                (Change::Added, "new.txt"),
+                (Change::Modified, "notes.txt"),
+                (Change::Removed, "old/gone.txt"),
+            ]
+        );
+        assert!(diff(&new, &new).is_empty());
+
+        let dest = dir.path().join("restored");
+        restore(&backend, &new, dest.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read(dest.join("big.bin")).unwrap(), big);
+        assert_eq!(fs::read(dest.join("notes.txt")).unwrap(), b"hello again");
+        assert!(dest.join("old").is_dir() && !dest.join("old/gone.txt").exists());
+
+        // The log finds snapshots by id, prefix or recency
+        let log = SnapshotLog::beside(&dir.path().join("catalog.jsonl"));
+        log.append(first.clone()).unwrap();
+        log.append(second.clone()).unwrap();
+        assert!(log.append(second.clone()).is_err());
+        assert_eq!(log.find("latest", Some("home")).unwrap(), second);
+        assert_eq!(log.find(&first.id[..6], None).unwrap(), first);
+        assert!(log.find("latest", Some("work")).is_err());
+        assert_eq!(log.latest("home", "memory").unwrap(), Some(second));
+    }
+
+    #[test]
+    fn test_prune_deletes_only_unshared_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, index) = (dir.path().join("home"), dir.path().join("chunks.jsonl"));
+        let backend = MemoryBackend::new();
+        write(&root.join("kept.bin"), &noise(20_000, 2));
+        write(&root.join("edited.bin"), &noise(20_000, 3));
+        let (first, _) = run_backup(&backend, &index, &root, None);
+        write(&root.join("edited.bin"), &noise(30_000, 4));
+        let (second, _) = run_backup(&backend, &index, &root, Some(&first));
+
+        let records = [first.clone(), second.clone()];
+        assert_eq!(prune_plan(&records, "home", 1), vec![first.clone()]);
+        assert!(prune_plan(&records, "home", 2).is_empty());
+        assert!(prune_plan(&records, "work", 0).is_empty());
+
+        let (old, new) = (open(&backend, &first), open(&backend, &second));
+        let only_old: Vec<_> = old
+            .chunks()
+            .filter(|chunk| !new.chunks().any(|c| c.id == chunk.id))
+            .map(|chunk| chunk.blob_id.clone())
+            .collect();
+        assert!(!only_old.is_empty());
+
+        let mut chunk_index = ChunkIndex::open(&index).unwrap();
+        let before = chunk_index.len();
+        let stats = delete_snapshots(
+            &backend,
+            &mut chunk_index,
+            "memory",
+            &[(first.clone(), old)],
+            &[new],
+        )
+        .unwrap();
+        assert_eq!((stats.snapshots, stats.chunks), (1, only_old.len()));
+        assert_eq!(
+            ChunkIndex::open(&index).unwrap().len(),
+            before - only_old.len()
+        );
+        assert!(!backend.exists(&first.blob_id).unwrap());
+        for blob_id in &only_old {
+            assert!(!backend.exists(blob_id).unwrap());
+        }
+
+        let dest = dir.path().join("restored");
+        restore(&backend, &open(&backend, &second), dest.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read(dest.join("edited.bin")).unwrap(), noise(30_000, 4));
+    }
+}
@@ -0,0 +1,61 @@
+// walrus_mock.rs - Run the mock Walrus publisher/aggregator on a fixed port
+
+use std::thread;
+use std::time::Duration;
+
+use clap::Parser;
+
+use mothrbox_engine::walrus::mock::MockWalrus;
+
+#[derive(Parser)]
+#[command(name = "walrus-mock")]
+#[command(about = "Local Walrus publisher + aggregator for tests and offline demos")]
+struct Args {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:31415")]
+    addr: String,
+    /// Delay every response by this many milliseconds
+    #[arg(long, default_value_t = 0)]
+    latency_ms: u64,
+    /// Fail every Nth request with --fail-status
+    #[arg(long, value_name = "N")]
+    fail_every: Option<u32>,
+    /// HTTP status for injected failures
+    #[arg(long, default_value_t = 503)]
+    fail_status: u16,
+    /// Advance the storage epoch every this many seconds, expiring blobs
+    #[arg(long, value_name = "SECS")]
+    epoch_secs: Option<u64>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let walrus = match MockWalrus::bind(&args.addr) {
+        Ok(walrus) => walrus,
+        Err(e) => {
+            eprintln!("❌ Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    walrus.set_latency(Duration::from_millis(args.latency_ms));
+    if let Some(n) = args.fail_every {
+        walrus.fail_every(n, args.fail_status);
+    }
+
+    println!("🦭 Mock Walrus listening on {}", walrus.url());
+    println!("export MOTHRBOX_BACKEND=walrus");
+    println!("export WALRUS_PUBLISHER_URL={}", walrus.url());
+    println!("export WALRUS_AGGREGATOR_URL={}", walrus.url());
+
+    loop {
+        match args.epoch_secs {
+            Some(secs) => {
+                thread::sleep(Duration::from_secs(secs));
+                walrus.advance_epochs(1);
+                println!("⏱️  Epoch {}", walrus.epoch());
+            }
+            None => thread::park(),
+        }
+    }
+}
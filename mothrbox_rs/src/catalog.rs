@@ -0,0 +1,353 @@
+// catalog.rs - Local index of uploaded blobs, so files can be fetched by name
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::atomic::write_atomic;
+use crate::dedup::ChunkList;
+use crate::header::{Header, Kdf};
+use crate::storage::StoredBlob;
+use crate::Error;
+
+/// Environment variable overriding the catalog file
+pub const CATALOG_ENV: &str = "MOTHRBOX_CATALOG";
+
+/// One uploaded file. Holds no key material: only what is needed to find
+/// the blob again and to tell which key it wants.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    /// Unique name the file is fetched by
+    pub name: String,
+    pub blob_id: String,
+    /// Backend spec the blob was stored with, e.g. `walrus` or `local:/srv/blobs`
+    pub backend: String,
+    /// File name of the uploaded plaintext
+    pub filename: String,
+    /// Plaintext size in bytes
+    pub size: u64,
+    /// Stored (encrypted) size in bytes
+    pub stored_size: u64,
+    /// Hex SHA-256 of the plaintext, to check a download against
+    pub sha256: String,
+    /// `aes`, `chacha` or `ecc`
+    pub algorithm: String,
+    /// Argon2 parameters, for password-protected blobs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kdf: Option<KdfRecord>,
+    /// Codec applied before encryption, `none` if uncompressed
+    pub compression: String,
+    /// Whether associated data must be supplied to decrypt
+    #[serde(default)]
+    pub aad: bool,
+    /// Seconds since the Unix epoch
+    pub uploaded_at: u64,
+    /// Last Walrus epoch the blob is paid for, when the backend reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_epoch: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Number of chunks, for deduplicated uploads whose blob is a chunk list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<usize>,
+}
+
+/// Password hashing cost recorded in the blob header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfRecord {
+    pub algorithm: String,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl CatalogEntry {
+    /// Describe `encrypted`, the container stored as `stored`, sealing `plaintext`
+    pub fn new(
+        name: &str,
+        filename: &str,
+        backend: &str,
+        plaintext: &[u8],
+        encrypted: &[u8],
+        stored: &StoredBlob,
+    ) -> Result<Self, Error> {
+        let sha256 = hex::encode(Sha256::digest(plaintext));
+        Self::describe(
+            name,
+            filename,
+            backend,
+            plaintext.len() as u64,
+            sha256,
+            encrypted,
+            stored,
+        )
+    }
+
+    /// Describe a deduplicated upload, `sealed_list` being the encrypted
+    /// chunk list stored as `stored`
+    pub fn for_chunks(
+        name: &str,
+        backend: &str,
+        list: &ChunkList,
+        sealed_list: &[u8],
+        stored: &StoredBlob,
+    ) -> Result<Self, Error> {
+        let filename = list.filename.as_deref().unwrap_or_default();
+        let mut entry = Self::describe(
+            name,
+            filename,
+            backend,
+            list.size,
+            list.sha256.clone(),
+            sealed_list,
+            stored,
+        )?;
+        entry.stored_size += list.stored_size();
+        entry.compression = list.compression.clone();
+        entry.chunks = Some(list.chunks.len());
+        Ok(entry)
+    }
+
+    fn describe(
+        name: &str,
+        filename: &str,
+        backend: &str,
+        size: u64,
+        sha256: String,
+        encrypted: &[u8],
+        stored: &StoredBlob,
+    ) -> Result<Self, Error> {
+        check_name(name)?;
+        let (header, _) = Header::parse(encrypted)?;
+        let kdf = match &header.kdf {
+            Kdf::Argon2 { params, .. } => Some(KdfRecord {
+                algorithm: params.algorithm.name().to_string(),
+                m_cost: params.m_cost,
+                t_cost: params.t_cost,
+                p_cost: params.p_cost,
+            }),
+            Kdf::None => None,
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            blob_id: stored.blob_id.clone(),
+            backend: backend.to_string(),
+            filename: filename.to_string(),
+            size,
+            stored_size: encrypted.len() as u64,
+            sha256,
+            algorithm: header.algorithm.name().to_string(),
+            kdf,
+            compression: header.codec.name().to_string(),
+            aad: header.requires_aad(),
+            uploaded_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            end_epoch: stored.end_epoch,
+            tags: Vec::new(),
+            chunks: None,
+        })
+    }
+
+    /// Whether `plaintext` is the file that was uploaded
+    pub fn matches(&self, plaintext: &[u8]) -> bool {
+        hex::encode(Sha256::digest(plaintext)) == self.sha256
+    }
+}
+
+/// Names are typed on the command line and shown in tables
+pub fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.chars().any(|c| c.is_control()) {
+        return Err(Error::InvalidInput(format!(
+            "invalid catalog name '{}'",
+            name.escape_debug()
+        )));
+    }
+    Ok(())
+}
+
+/// JSON-lines file of `CatalogEntry`, one per name.
+///
+/// Every change rewrites the file atomically, so a crash never leaves a
+/// half-written catalog. It is readable only by its owner.
+pub struct Catalog {
+    path: PathBuf,
+}
+
+impl Catalog {
+    /// Catalog stored at `path`
+    pub fn at(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// `MOTHRBOX_CATALOG`, else `~/.mothrbox/catalog.jsonl`
+    pub fn open_default() -> Result<Self, Error> {
+        if let Some(path) = std::env::var_os(CATALOG_ENV).filter(|path| !path.is_empty()) {
+            return Ok(Self::at(path));
+        }
+        let dir = crate::storage::mothrbox_dir(&format!("set {}", CATALOG_ENV))?;
+        Ok(Self::at(dir.join("catalog.jsonl")))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every entry, oldest upload first; empty if the file does not exist yet
+    pub fn entries(&self) -> Result<Vec<CatalogEntry>, Error> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::io("Failed to read catalog", e)),
+        };
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    Error::InvalidInput(format!("{} line {}: {}", self.path.display(), i + 1, e))
+                })
+            })
+            .collect()
+    }
+
+    /// Record `entry`, replacing any entry with the same name (returned)
+    pub fn insert(&self, entry: CatalogEntry) -> Result<Option<CatalogEntry>, Error> {
+        check_name(&entry.name)?;
+        let mut entries = self.entries()?;
+        let replaced = entries
+            .iter()
+            .position(|e| e.name == entry.name)
+            .map(|i| entries.remove(i));
+        entries.push(entry);
+        self.save(&entries)?;
+        Ok(replaced)
+    }
+
+    /// Look an entry up by name, falling back to its blob id
+    pub fn find(&self, name: &str) -> Result<CatalogEntry, Error> {
+        let entries = self.entries()?;
+        entries
+            .iter()
+            .find(|e| e.name == name)
+            .or_else(|| entries.iter().find(|e| e.blob_id == name))
+            .cloned()
+            .ok_or_else(|| Error::not_found("catalog", name))
+    }
+
+    /// Forget the entry called `name`; the blob itself is left alone
+    pub fn remove(&self, name: &str) -> Result<CatalogEntry, Error> {
+        let mut entries = self.entries()?;
+        let i = entries
+            .iter()
+            .position(|e| e.name == name)
+            .ok_or_else(|| Error::not_found("catalog", name))?;
+        let removed = entries.remove(i);
+        self.save(&entries)?;
+        Ok(removed)
+    }
+
+    fn save(&self, entries: &[CatalogEntry]) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .map_err(|e| Error::io("Failed to create catalog directory", e))?;
+        }
+        let mut text = String::new();
+        for entry in entries {
+            text.push_str(&serde_json::to_string(entry).expect("catalog entries serialize"));
+            text.push('\n');
+        }
+        write_atomic(&self.path, text.as_bytes())
+    }
+}
+
+/// `YYYY-MM-DD HH:MM` (UTC) for a Unix timestamp
+pub fn format_time(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let minutes = secs % 86_400 / 60;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encrypt, EncryptionKey};
+
+    #[test]
+    fn test_catalog_insert_find_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let catalog = Catalog::at(dir.path().join("nested").join("catalog.jsonl"));
+        assert!(catalog.entries().unwrap().is_empty());
+
+        let plaintext = b"quarterly numbers";
+        let key = EncryptionKey::Password {
+            algorithm: crate::header::Algorithm::ChaCha20Poly1305,
+            password: "pw",
+            kdf: Default::default(),
+        };
+        let encrypted = encrypt(plaintext, &key).unwrap();
+        let stored = StoredBlob {
+            blob_id: "blob-1".to_string(),
+            end_epoch: Some(4),
+        };
+        let entry = CatalogEntry::new(
+            "report",
+            "report.txt",
+            "walrus",
+            plaintext,
+            &encrypted,
+            &stored,
+        )
+        .unwrap();
+        assert_eq!(entry.algorithm, "chacha");
+        assert_eq!(entry.kdf.as_ref().unwrap().algorithm, "argon2id");
+        assert!(entry.matches(plaintext));
+
+        assert!(catalog.insert(entry.clone()).unwrap().is_none());
+        assert_eq!(catalog.find("report").unwrap(), entry);
+        assert_eq!(catalog.find("blob-1").unwrap(), entry);
+
+        // Same name again replaces the entry
+        let mut newer = entry.clone();
+        newer.blob_id = "blob-2".to_string();
+        assert_eq!(catalog.insert(newer.clone()).unwrap(), Some(entry));
+        assert_eq!(catalog.entries().unwrap(), vec![newer.clone()]);
+
+        assert_eq!(catalog.remove("report").unwrap(), newer);
+        assert!(matches!(
+            catalog.find("report"),
+            Err(Error::NotFound { .. })
+        ));
+        assert!(CatalogEntry::new("", "x", "walrus", plaintext, &encrypted, &stored).is_err());
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "1970-01-01 00:00");
+        assert_eq!(format_time(951_782_400 + 3_660), "2000-02-29 01:01");
+        assert_eq!(format_time(1_792_108_800), "2026-10-16 00:00");
+    }
+}
@@ -0,0 +1,779 @@
+// dedup.rs - Content-defined chunking and deduplicated uploads
+//
+// Files are cut into chunks with FastCDC, so an edit only changes the chunks
+// around it. Each chunk is encrypted under a key derived from its contents
+// and stored as its own blob; the local chunk index remembers which chunks a
+// backend already holds, so re-uploading a slightly changed file only sends
+// the chunks that changed. A chunk list naming every chunk blob, with the key
+// to each, is then encrypted with the caller's key and stored as the handle
+// for the whole file.
+//
+// Chunk keys are either
+//
+// - convergent: SHA-256 of the chunk. Anyone holding the same chunk derives
+//   the same key and id, which deduplicates across users but lets someone
+//   who can guess a file confirm that it was stored;
+// - per vault: HMAC-SHA256 of the chunk under a secret vault key, which only
+//   deduplicates between uploads made with that vault key.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::atomic::{write_atomic, AtomicFile};
+use crate::compress::Compression;
+use crate::header::Algorithm;
+use crate::secret::{LockedKey, Zeroize, Zeroizing};
+use crate::storage::{StorageBackend, StoredBlob};
+use crate::{DecryptionKey, EncryptOptions, EncryptionKey, Error};
+
+/// Starts the decrypted contents of a chunk list blob. Only checked once the
+/// header says the blob is a chunk list (`FLAG_CHUNK_LIST`): ordinary files
+/// may start with it too.
+pub const CHUNK_LIST_MAGIC: &[u8; 4] = b"MBXC";
+pub const CHUNK_LIST_VERSION: u8 = 1;
+
+/// Average chunk size by default. Every blob has a fixed cost on Walrus, so
+/// chunks are much larger than the few KiB usual for local deduplication.
+pub const DEFAULT_AVG_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Gear hash table. Part of the format in the sense that changing it moves
+/// every chunk boundary, so nothing stored earlier would deduplicate.
+const GEAR: [u64; 256] = {
+    // splitmix64, seeded with "mothrbox"
+    let mut table = [0u64; 256];
+    let mut state = 0x6d6f_7468_7262_6f78u64;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// FastCDC with normalized chunking: boundaries are harder to hit before the
+/// average size and easier after it, so sizes cluster around the average
+/// between `avg / 4` and `avg * 4`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunker {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    mask_hard: u64,
+    mask_easy: u64,
+}
+
+impl Chunker {
+    /// Chunker averaging `avg_size` bytes, a power of two from 256 B to 64 MiB
+    pub fn new(avg_size: usize) -> Result<Self, Error> {
+        if !avg_size.is_power_of_two() || !(256..=64 * 1024 * 1024).contains(&avg_size) {
+            return Err(Error::InvalidInput(format!(
+                "average chunk size must be a power of two from 256 B to 64 MiB, got {}",
+                avg_size
+            )));
+        }
+        let bits = avg_size.trailing_zeros();
+        // The hash is shifted left, so its top bits have seen the most input
+        let mask = |bits: u32| !0u64 << (64 - bits);
+        Ok(Self {
+            min_size: avg_size / 4,
+            avg_size,
+            max_size: avg_size * 4,
+            mask_hard: mask(bits + 1),
+            mask_easy: mask(bits - 1),
+        })
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Length of the first chunk of `data`, all of it if it is the last
+    pub fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+        let end = data.len().min(self.max_size);
+        let normal = end.min(self.avg_size);
+
+        let mut hash = 0u64;
+        for (i, &byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            let mask = if i < normal {
+                self.mask_hard
+            } else {
+                self.mask_easy
+            };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+
+    /// Split everything `reader` produces, holding at most one maximum-size
+    /// chunk in memory
+    pub fn chunks<R: Read>(&self, reader: R) -> Chunks<R> {
+        Chunks {
+            chunker: *self,
+            reader,
+            buf: Vec::new(),
+            eof: false,
+        }
+    }
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Self::new(DEFAULT_AVG_CHUNK_SIZE).expect("default chunk size is valid")
+    }
+}
+
+/// Iterator over the chunks of a reader
+pub struct Chunks<R: Read> {
+    chunker: Chunker,
+    reader: R,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Iterator for Chunks<R> {
+    type Item = Result<Vec<u8>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let max = self.chunker.max_size;
+        while !self.eof && self.buf.len() < max {
+            let start = self.buf.len();
+            self.buf.resize(max, 0);
+            match self.reader.read(&mut self.buf[start..]) {
+                Ok(n) => {
+                    self.buf.truncate(start + n);
+                    self.eof = n == 0;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                    self.buf.truncate(start);
+                }
+                Err(e) => {
+                    self.buf.truncate(start);
+                    return Some(Err(Error::io("Read error", e)));
+                }
+            }
+        }
+        if self.buf.is_empty() {
+            return None;
+        }
+        let cut = self.chunker.cut(&self.buf);
+        let rest = self.buf.split_off(cut);
+        Some(Ok(std::mem::replace(&mut self.buf, rest)))
+    }
+}
+
+/// How chunk keys are derived from chunk contents
+pub enum ChunkKeying {
+    /// Key is the SHA-256 of the chunk
+    Convergent,
+    /// Key is an HMAC of the chunk under this secret
+    Vault(LockedKey),
+}
+
+impl ChunkKeying {
+    fn name(&self) -> &'static str {
+        match self {
+            ChunkKeying::Convergent => "convergent",
+            ChunkKeying::Vault(_) => "vault",
+        }
+    }
+
+    /// The chunk's key and its id: a hash of the key, so the id in the
+    /// index or a chunk list never reveals the key itself
+    fn derive(&self, chunk: &[u8]) -> (LockedKey, String) {
+        let mut key = LockedKey::zeroed();
+        match self {
+            ChunkKeying::Convergent => {
+                let digest = Sha256::new()
+                    .chain_update(b"mothrbox convergent chunk key\0")
+                    .chain_update(chunk)
+                    .finalize();
+                key.copy_from_slice(&digest);
+            }
+            ChunkKeying::Vault(vault_key) => {
+                let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&vault_key[..])
+                    .expect("HMAC accepts any key length");
+                mac.update(chunk);
+                key.copy_from_slice(&mac.finalize().into_bytes());
+            }
+        }
+        let id = hex::encode(
+            Sha256::new()
+                .chain_update(b"mothrbox chunk id\0")
+                .chain_update(&key[..])
+                .finalize(),
+        );
+        (key, id)
+    }
+}
+
+/// One chunk of a stored file
+#[derive(Serialize, Deserialize)]
+pub struct ChunkRef {
+    /// Content id, the key the index knows the chunk by
+    pub id: String,
+    pub blob_id: String,
+    /// Plaintext bytes
+    pub size: u64,
+    /// Encrypted bytes
+    pub stored_size: u64,
+    /// Hex chunk key
+    pub key: String,
+}
+
+impl Drop for ChunkRef {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl std::fmt::Debug for ChunkRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkRef")
+            .field("id", &self.id)
+            .field("blob_id", &self.blob_id)
+            .field("size", &self.size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Everything needed to reassemble a file from its chunks. Holds the chunk
+/// keys, so it is only ever stored encrypted.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkList {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// Plaintext bytes
+    pub size: u64,
+    /// Hex SHA-256 of the whole plaintext
+    pub sha256: String,
+    /// `convergent` or `vault`
+    pub keying: String,
+    /// Algorithm the chunks are encrypted with
+    pub algorithm: String,
+    /// Codec each chunk was compressed with
+    pub compression: String,
+    pub chunks: Vec<ChunkRef>,
+}
+
+impl ChunkList {
+    /// Magic, version, then JSON
+    pub fn encode(&self) -> Zeroizing<Vec<u8>> {
+        let mut encoded = Zeroizing::new(CHUNK_LIST_MAGIC.to_vec());
+        encoded.push(CHUNK_LIST_VERSION);
+        serde_json::to_writer(&mut *encoded, self).expect("chunk lists serialize");
+        encoded
+    }
+
+    /// The chunk list in a decrypted blob whose header has `FLAG_CHUNK_LIST`
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let Some(rest) = data.strip_prefix(&CHUNK_LIST_MAGIC[..]) else {
+            return Err(Error::MalformedHeader(
+                "chunk list blob has no chunk list".to_string(),
+            ));
+        };
+        match rest.split_first() {
+            Some((&CHUNK_LIST_VERSION, json)) => serde_json::from_slice(json)
+                .map_err(|e| Error::MalformedHeader(format!("invalid chunk list: {}", e))),
+            Some((&version, _)) => Err(Error::UnsupportedVersion(version)),
+            None => Err(Error::Truncated("chunk list has no version".to_string())),
+        }
+    }
+
+    /// Bytes stored for the file: every chunk, shared or not
+    pub fn stored_size(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.stored_size).sum()
+    }
+}
+
+/// What an upload actually sent
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DedupStats {
+    pub chunks: usize,
+    /// Chunks not already stored, and uploaded
+    pub new_chunks: usize,
+    pub bytes: u64,
+    /// Encrypted bytes uploaded
+    pub uploaded_bytes: u64,
+}
+
+impl DedupStats {
+    pub fn add(&mut self, other: DedupStats) {
+        self.chunks += other.chunks;
+        self.new_chunks += other.new_chunks;
+        self.bytes += other.bytes;
+        self.uploaded_bytes += other.uploaded_bytes;
+    }
+}
+
+/// Chunk recorded in the index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedChunk {
+    id: String,
+    /// Backend spec the blob lives on
+    backend: String,
+    blob_id: String,
+    stored_size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    end_epoch: Option<u32>,
+}
+
+/// JSON-lines record of the chunks each backend already holds. Holds no
+/// keys, only content ids.
+pub struct ChunkIndex {
+    path: PathBuf,
+    chunks: HashMap<(String, String), IndexedChunk>,
+}
+
+impl ChunkIndex {
+    /// Load the index at `path`, empty if it does not exist yet
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(Error::io("Failed to read chunk index", e)),
+        };
+        let mut chunks = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let chunk: IndexedChunk = serde_json::from_str(line).map_err(|e| {
+                Error::InvalidInput(format!("{} line {}: {}", path.display(), i + 1, e))
+            })?;
+            chunks.insert((chunk.backend.clone(), chunk.id.clone()), chunk);
+        }
+        Ok(Self { path, chunks })
+    }
+
+    /// `chunks.jsonl` in the same directory as the catalog at `catalog_path`
+    pub fn beside(catalog_path: &Path) -> Result<Self, Error> {
+        Self::open(catalog_path.with_file_name("chunks.jsonl"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    fn get(&self, backend: &str, id: &str) -> Option<&IndexedChunk> {
+        self.chunks.get(&(backend.to_string(), id.to_string()))
+    }
+
+    fn insert(&mut self, chunk: IndexedChunk) {
+        self.chunks
+            .insert((chunk.backend.clone(), chunk.id.clone()), chunk);
+    }
+
+    /// Forget a chunk whose blob was deleted from `backend`
+    pub fn remove(&mut self, backend: &str, id: &str) -> bool {
+        self.chunks
+            .remove(&(backend.to_string(), id.to_string()))
+            .is_some()
+    }
+
+    /// Rewrite the index file atomically
+    pub fn save(&self) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .map_err(|e| Error::io("Failed to create chunk index directory", e))?;
+        }
+        let mut chunks: Vec<_> = self.chunks.values().collect();
+        chunks.sort_by(|a, b| (&a.backend, &a.id).cmp(&(&b.backend, &b.id)));
+        let mut text = String::new();
+        for chunk in chunks {
+            text.push_str(&serde_json::to_string(chunk).expect("index entries serialize"));
+            text.push('\n');
+        }
+        write_atomic(&self.path, text.as_bytes())
+    }
+}
+
+/// Splits data into chunks and stores the ones `backend` does not hold yet
+pub struct ChunkStore<'a> {
+    backend: &'a dyn StorageBackend,
+    backend_spec: String,
+    index: ChunkIndex,
+    keying: ChunkKeying,
+    algorithm: Algorithm,
+    compression: Compression,
+    chunker: Chunker,
+}
+
+impl<'a> ChunkStore<'a> {
+    /// Store chunks with `backend`, known to the index as `backend_spec`,
+    /// encrypted with AES-256-GCM and uncompressed
+    pub fn new(
+        backend: &'a dyn StorageBackend,
+        backend_spec: &str,
+        index: ChunkIndex,
+        keying: ChunkKeying,
+    ) -> Self {
+        Self {
+            backend,
+            backend_spec: backend_spec.to_string(),
+            index,
+            keying,
+            algorithm: Algorithm::Aes256Gcm,
+            compression: Compression::NONE,
+            chunker: Chunker::default(),
+        }
+    }
+
+    /// Encrypt chunks with `algorithm` (AES or ChaCha)
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Compress each chunk before encrypting it
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_chunker(mut self, chunker: Chunker) -> Self {
+        self.chunker = chunker;
+        self
+    }
+
+    pub fn backend(&self) -> &'a dyn StorageBackend {
+        self.backend
+    }
+
+    pub fn backend_spec(&self) -> &str {
+        &self.backend_spec
+    }
+
+    /// Chunk and store everything `reader` produces. The index is saved
+    /// even if this fails part way, so chunks already sent are reused.
+    pub fn put<R: Read>(
+        &mut self,
+        reader: R,
+        filename: Option<&str>,
+    ) -> Result<(ChunkList, DedupStats), Error> {
+        let result = self.put_chunks(reader, filename);
+        self.index.save()?;
+        result
+    }
+
+    /// `put` for the file at `path`, recording its file name
+    pub fn put_file(&mut self, path: &str) -> Result<(ChunkList, DedupStats), Error> {
+        let file = File::open(path).map_err(|e| Error::io("Failed to open input file", e))?;
+        let filename = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        self.put(BufReader::new(file), filename.as_deref())
+    }
+
+    /// Save the chunk index, after a run of `put_chunks`
+    pub(crate) fn save_index(&self) -> Result<(), Error> {
+        self.index.save()
+    }
+
+    /// `put` without saving the index, for callers storing many files
+    pub(crate) fn put_chunks<R: Read>(
+        &mut self,
+        reader: R,
+        filename: Option<&str>,
+    ) -> Result<(ChunkList, DedupStats), Error> {
+        let mut stats = DedupStats::default();
+        let mut hasher = Sha256::new();
+        let mut chunks = Vec::new();
+
+        for chunk in self.chunker.chunks(reader) {
+            let chunk = Zeroizing::new(chunk?);
+            hasher.update(&chunk);
+            let (key, id) = self.keying.derive(&chunk);
+            stats.chunks += 1;
+            stats.bytes += chunk.len() as u64;
+
+            let stored = match self.index.get(&self.backend_spec, &id) {
+                Some(stored) => stored.clone(),
+                None => {
+                    let sealed = crate::encrypt_with_options(
+                        &chunk,
+                        &EncryptionKey::RawKey {
+                            algorithm: self.algorithm,
+                            key: &key,
+                        },
+                        &EncryptOptions {
+                            aad: id.as_bytes(),
+                            compression: self.compression,
+                            ..Default::default()
+                        },
+                    )?;
+                    let StoredBlob { blob_id, end_epoch } = self.backend.store(&sealed)?;
+                    stats.new_chunks += 1;
+                    stats.uploaded_bytes += sealed.len() as u64;
+                    let stored = IndexedChunk {
+                        id: id.clone(),
+                        backend: self.backend_spec.clone(),
+                        blob_id,
+                        stored_size: sealed.len() as u64,
+                        end_epoch,
+                    };
+                    self.index.insert(stored.clone());
+                    stored
+                }
+            };
+            chunks.push(ChunkRef {
+                id,
+                blob_id: stored.blob_id,
+                size: chunk.len() as u64,
+                stored_size: stored.stored_size,
+                key: hex::encode(&key[..]),
+            });
+        }
+
+        let list = ChunkList {
+            filename: filename.map(str::to_string),
+            size: stats.bytes,
+            sha256: hex::encode(hasher.finalize()),
+            keying: self.keying.name().to_string(),
+            algorithm: self.algorithm.name().to_string(),
+            compression: self.compression.codec.name().to_string(),
+            chunks,
+        };
+        Ok((list, stats))
+    }
+}
+
+/// Encrypt `list` with `key` (bound to `aad`) and store it. Returns the
+/// stored blob and its encrypted bytes.
+pub fn store_chunk_list(
+    backend: &dyn StorageBackend,
+    list: &ChunkList,
+    key: &EncryptionKey,
+    aad: &[u8],
+) -> Result<(StoredBlob, Vec<u8>), Error> {
+    let options = EncryptOptions {
+        aad,
+        compression: Compression::lz4(),
+        chunk_list: true,
+        ..Default::default()
+    };
+    let sealed = crate::encrypt_with_options(&list.encode(), key, &options)?;
+    let stored = backend.store(&sealed)?;
+    Ok((stored, sealed))
+}
+
+/// Fetch, decrypt and check every chunk of `list` in order, writing the
+/// plaintext to `writer`. Fails if the result does not hash to `list.sha256`;
+/// the caller decides what to do with what was already written.
+pub fn reassemble<W: Write>(
+    backend: &dyn StorageBackend,
+    list: &ChunkList,
+    writer: &mut W,
+) -> Result<(), Error> {
+    write_chunks(backend, &list.chunks, list.size, &list.sha256, writer)
+}
+
+/// `reassemble` for chunks recorded outside a chunk list
+pub(crate) fn write_chunks<W: Write>(
+    backend: &dyn StorageBackend,
+    chunks: &[ChunkRef],
+    expected_size: u64,
+    expected_sha256: &str,
+    writer: &mut W,
+) -> Result<(), Error> {
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    for chunk in chunks {
+        let key = Zeroizing::new(
+            hex::decode(&chunk.key)
+                .map_err(|_| Error::MalformedHeader("invalid chunk key".to_string()))?,
+        );
+        let key = LockedKey::from_slice(&key)?;
+        let sealed = backend.get(&chunk.blob_id)?;
+        let plaintext = Zeroizing::new(crate::decrypt_with_aad(
+            &sealed,
+            &DecryptionKey::RawKey(&key),
+            chunk.id.as_bytes(),
+        )?);
+        if plaintext.len() as u64 != chunk.size {
+            return Err(Error::storage(
+                backend.name(),
+                format!("chunk blob {} has the wrong size", chunk.blob_id),
+            ));
+        }
+        hasher.update(&plaintext);
+        size += chunk.size;
+        writer
+            .write_all(&plaintext)
+            .map_err(|e| Error::io("Failed to write output file", e))?;
+    }
+
+    if size != expected_size || hex::encode(hasher.finalize()) != expected_sha256 {
+        return Err(Error::storage(
+            backend.name(),
+            "reassembled chunks do not match the recorded SHA-256".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// `reassemble` into `output_path`, which only appears once every chunk checked out
+pub fn reassemble_file(
+    backend: &dyn StorageBackend,
+    list: &ChunkList,
+    output_path: &str,
+) -> Result<(), Error> {
+    let mut output = std::io::BufWriter::new(AtomicFile::create(output_path)?);
+    reassemble(backend, list, &mut output)?;
+    output
+        .into_inner()
+        .map_err(|e| Error::io("Failed to write output file", e.into_error()))?
+        .commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::Header;
+    use crate::storage::MemoryBackend;
+
+    /// Deterministic incompressible bytes
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunk_sizes(chunker: &Chunker, data: &[u8]) -> Vec<usize> {
+        chunker
+            .chunks(data)
+            .map(|chunk| chunk.unwrap().len())
+            .collect()
+    }
+
+    #[test]
+    fn test_chunk_boundaries_follow_content() {
+        let chunker = Chunker::new(4096).unwrap();
+        let data = noise(200_000, 1);
+        let sizes = chunk_sizes(&chunker, &data);
+        assert_eq!(sizes.iter().sum::<usize>(), data.len());
+        assert!(sizes.len() > 20);
+        for size in &sizes[..sizes.len() - 1] {
+            assert!((1024..=16384).contains(size), "{}", size);
+        }
+
+        // An insertion near the start leaves later boundaries where they were
+        let mut edited = data[..1000].to_vec();
+        edited.extend_from_slice(b"inserted");
+        edited.extend_from_slice(&data[1000..]);
+        let edited_sizes = chunk_sizes(&chunker, &edited);
+        let tail = |sizes: &[usize]| sizes[sizes.len() - 10..].to_vec();
+        assert_eq!(tail(&sizes), tail(&edited_sizes));
+
+        assert!(Chunker::new(3000).is_err());
+        assert!(chunk_sizes(&chunker, b"").is_empty());
+    }
+
+    #[test]
+    fn test_reupload_only_sends_changed_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = MemoryBackend::new();
+        let index_path = dir.path().join("chunks.jsonl");
+        let store = |keying| {
+            ChunkStore::new(
+                &backend,
+                "memory",
+                ChunkIndex::open(&index_path).unwrap(),
+                keying,
+            )
+            .with_chunker(Chunker::new(4096).unwrap())
+            .with_algorithm(Algorithm::ChaCha20Poly1305)
+        };
+
+        let data = noise(300_000, 2);
+        let (list, first) = store(ChunkKeying::Convergent)
+            .put(&data[..], Some("disk.img"))
+            .unwrap();
+        assert_eq!(first.new_chunks, first.chunks);
+
+        let mut edited = data.clone();
+        edited[150_000] ^= 0xff;
+        let (edited_list, second) = store(ChunkKeying::Convergent)
+            .put(&edited[..], None)
+            .unwrap();
+        assert_eq!(second.chunks, first.chunks);
+        assert!(second.new_chunks <= 2, "{:?}", second);
+        assert!(second.uploaded_bytes * 10 < first.uploaded_bytes);
+
+        let mut restored = Vec::new();
+        reassemble(&backend, &edited_list, &mut restored).unwrap();
+        assert_eq!(restored, edited);
+
+        // Chunk lists survive being sealed and parsed again
+        let sealed_key = EncryptionKey::RawKey {
+            algorithm: Algorithm::Aes256Gcm,
+            key: &[5u8; 32],
+        };
+        let (stored, _) = store_chunk_list(&backend, &list, &sealed_key, b"").unwrap();
+        let sealed = backend.get(&stored.blob_id).unwrap();
+        assert!(Header::parse(&sealed).unwrap().0.is_chunk_list());
+        let opened = crate::decrypt(&sealed, &DecryptionKey::RawKey(&[5u8; 32])).unwrap();
+        let parsed = ChunkList::parse(&opened).unwrap();
+        assert_eq!(parsed.filename.as_deref(), Some("disk.img"));
+        let output = dir.path().join("disk.img");
+        reassemble_file(&backend, &parsed, output.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), data);
+        assert!(ChunkList::parse(b"plain file").is_err());
+
+        // A vault key gives different chunk ids, so nothing is shared
+        let (_, vault) = store(ChunkKeying::Vault(LockedKey::from(&[9u8; 32])))
+            .put(&data[..], None)
+            .unwrap();
+        assert_eq!(vault.new_chunks, vault.chunks);
+    }
+
+    #[test]
+    fn test_tampered_chunk_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = MemoryBackend::new();
+        let mut store = ChunkStore::new(
+            &backend,
+            "memory",
+            ChunkIndex::open(dir.path().join("chunks.jsonl")).unwrap(),
+            ChunkKeying::Convergent,
+        )
+        .with_chunker(Chunker::new(4096).unwrap());
+        let (mut list, _) = store.put(&noise(50_000, 3)[..], None).unwrap();
+
+        // Swapping two chunk blobs fails authentication: each is bound to its id
+        let first = list.chunks[0].blob_id.clone();
+        list.chunks[0].blob_id = list.chunks[1].blob_id.clone();
+        list.chunks[1].blob_id = first;
+        assert!(reassemble(&backend, &list, &mut Vec::new()).is_err());
+    }
+}
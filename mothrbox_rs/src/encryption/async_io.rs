@@ -0,0 +1,189 @@
+//! Tokio `AsyncRead` / `AsyncWrite` versions of the adapters in `io`,
+//! enabled with the `async` cargo feature.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::io::{DecryptCore, EncryptCore, Secret, Step, READ_BUF_SIZE};
+use super::stream::DEFAULT_CHUNK_SIZE;
+use crate::{DecryptionKey, EncryptionKey, Error};
+
+/// Async counterpart of `EncryptWriter`.
+///
+/// `shutdown` seals the final chunk; a writer dropped without it produces
+/// output that is rejected as truncated.
+pub struct AsyncEncryptWriter<W> {
+    inner: W,
+    core: EncryptCore,
+    /// Bytes of `core.pending` already written to `inner`
+    written: usize,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncEncryptWriter<W> {
+    pub fn new(inner: W, key: &EncryptionKey) -> Result<Self, Error> {
+        Self::with_chunk_size(inner, key, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(inner: W, key: &EncryptionKey, chunk_size: u32) -> Result<Self, Error> {
+        Self::with_aad(inner, key, chunk_size, &[])
+    }
+
+    /// Bind the ciphertext to `aad`, which the reader must supply again
+    pub fn with_aad(
+        inner: W,
+        key: &EncryptionKey,
+        chunk_size: u32,
+        aad: &[u8],
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            inner,
+            core: EncryptCore::new(key, chunk_size, aad)?,
+            written: 0,
+        })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.core.pending.len() {
+            let n = ready!(
+                Pin::new(&mut self.inner).poll_write(cx, &self.core.pending[self.written..])
+            )?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.core.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncEncryptWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Poll::Ready(this.core.accept(buf).map_err(io::Error::from))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.core.finished {
+            this.core.finish()?;
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Async counterpart of `DecryptReader`
+pub struct AsyncDecryptReader<R> {
+    inner: R,
+    core: DecryptCore,
+    input: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl<R: AsyncRead + Unpin> AsyncDecryptReader<R> {
+    pub fn new(inner: R, key: &DecryptionKey) -> Self {
+        Self::with_aad(inner, key, &[])
+    }
+
+    /// Decrypt a container that was bound to `aad`
+    pub fn with_aad(inner: R, key: &DecryptionKey, aad: &[u8]) -> Self {
+        Self {
+            inner,
+            core: DecryptCore::new(Secret::from_key(key), aad),
+            input: vec![0u8; READ_BUF_SIZE],
+            plaintext: Vec::new(),
+            position: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncDecryptReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.position < this.plaintext.len() {
+                let available = &this.plaintext[this.position..];
+                let n = available.len().min(buf.remaining());
+                buf.put_slice(&available[..n]);
+                this.position += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            match this.core.step()? {
+                Step::Plaintext(plaintext) => {
+                    this.plaintext = plaintext;
+                    this.position = 0;
+                }
+                Step::NeedInput => {
+                    let mut input = ReadBuf::new(&mut this.input);
+                    ready!(Pin::new(&mut this.inner).poll_read(cx, &mut input))?;
+                    if input.filled().is_empty() {
+                        this.core.end_of_input();
+                    } else {
+                        this.core.push(input.filled());
+                    }
+                }
+                Step::Done => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::Algorithm;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_async_roundtrip() {
+        let data = vec![7u8; 50_000];
+        let key = [3u8; 32];
+        let encryption_key = EncryptionKey::RawKey {
+            algorithm: Algorithm::ChaCha20Poly1305,
+            key: &key,
+        };
+
+        let mut writer =
+            AsyncEncryptWriter::with_chunk_size(Vec::new(), &encryption_key, 4096).unwrap();
+        writer.write_all(&data).await.unwrap();
+        writer.shutdown().await.unwrap();
+        let sealed = writer.into_inner();
+
+        let mut opened = Vec::new();
+        AsyncDecryptReader::new(&sealed[..], &DecryptionKey::RawKey(&key))
+            .read_to_end(&mut opened)
+            .await
+            .unwrap();
+        assert_eq!(opened, data);
+    }
+}
@@ -0,0 +1,135 @@
+//! Output files that only appear once they are complete.
+//!
+//! Data goes to a temporary file beside the destination, readable only by
+//! the owner, and is renamed over the destination by `commit`. Dropping an
+//! uncommitted file deletes the temporary, so a failed or interrupted
+//! decryption never leaves partial or unauthenticated plaintext behind, and
+//! concurrent runs never see each other's half-written output.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use rand::RngCore;
+
+use crate::Error;
+
+/// A file written under a temporary name and moved into place on `commit`
+pub struct AtomicFile {
+    file: File,
+    temp_path: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Start writing `path`; nothing appears there until `commit`
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let name = path.file_name().ok_or_else(|| {
+            Error::InvalidInput(format!("output path '{}' has no file name", path.display()))
+        })?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        loop {
+            let mut suffix = [0u8; 8];
+            rand::thread_rng().fill_bytes(&mut suffix);
+            let temp_path = dir.join(format!(
+                ".{}.{}.tmp",
+                name.to_string_lossy(),
+                hex::encode(suffix)
+            ));
+            match create_private(&temp_path) {
+                Ok(file) => {
+                    return Ok(Self {
+                        file,
+                        temp_path,
+                        path,
+                        committed: false,
+                    })
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(Error::io("Failed to create output file", e)),
+            }
+        }
+    }
+
+    /// Set the modification time the file will have once committed
+    pub fn set_modified(&self, time: std::time::SystemTime) -> Result<(), Error> {
+        self.file
+            .set_modified(time)
+            .map_err(|e| Error::io("Failed to set modification time", e))
+    }
+
+    /// Sync the data to disk and rename it over the destination
+    pub fn commit(mut self) -> Result<(), Error> {
+        self.file
+            .sync_all()
+            .map_err(|e| Error::io("Failed to write output file", e))?;
+        fs::rename(&self.temp_path, &self.path)
+            .map_err(|e| Error::io("Failed to move output file into place", e))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Create a new file only the owner can read
+pub(crate) fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// Write `data` to `path` in one step: either all of it appears or nothing does
+pub fn write_atomic(path: impl AsRef<Path>, data: &[u8]) -> Result<(), Error> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(data)
+        .map_err(|e| Error::io("Failed to write output file", e))?;
+    file.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_appears_only_on_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plain.txt");
+        fs::write(&path, b"old").unwrap();
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"new contents").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        drop(file);
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        write_atomic(&path, b"new contents").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new contents");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
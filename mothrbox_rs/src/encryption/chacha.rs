@@ -1,14 +1,20 @@
 use chacha20poly1305::{
-    aead::{Aead, KeyInit, OsRng},
+    aead::{Aead, KeyInit, OsRng, Payload},
     ChaCha20Poly1305, Nonce, Key,
 };
-use argon2::{
-    Argon2,
-    password_hash::SaltString,
-};
+use argon2::Argon2;
 use rand::RngCore;
 use std::fs;
 
+use super::atomic::write_atomic;
+use super::compress::Compression;
+use super::header::{self, Algorithm, Header, Kdf, KdfParams};
+use super::manifest::{self, Manifest};
+use super::options::EncryptOptions;
+use super::secret::LockedKey;
+use super::stream;
+use crate::Error;
+
 /// ChaCha20-Poly1305 Encryption with Argon2 Key Derivation
 /// Alternative to AES, faster on systems without hardware AES acceleration
 pub struct ChaChaEncryption;
@@ -16,48 +22,52 @@ pub struct ChaChaEncryption;
 impl ChaChaEncryption {
     /// Encrypt data using ChaCha20-Poly1305
     /// Uses Argon2 to derive key from password
-    pub fn encrypt(plaintext: &[u8], password: &str) -> Result<Vec<u8>, String> {
-        // 1. Generate random salt for Argon2
-        let salt = SaltString::generate(&mut OsRng);
+    pub fn encrypt(plaintext: &[u8], password: &str) -> Result<Vec<u8>, Error> {
+        Self::encrypt_with_options(plaintext, password, &EncryptOptions::default())
+    }
+    
+    /// Encrypt with the Argon2 cost, AAD, compression and manifest in `options`
+    pub fn encrypt_with_options(plaintext: &[u8], password: &str, options: &EncryptOptions) -> Result<Vec<u8>, Error> {
+        // 1. Generate random salt and record the Argon2 parameters and codec in the header
+        let header = options.header(Algorithm::ChaCha20Poly1305, Kdf::argon2(options.kdf));
         
         // 2. Derive 256-bit key from password using Argon2
-        let argon2 = Argon2::default();
-        let mut key_bytes = [0u8; 32];
+        let key_bytes = header.kdf.derive_key(password)?;
         
-        argon2.hash_password_into(
-            password.as_bytes(),
-            salt.as_str().as_bytes(),
-            &mut key_bytes
-        ).map_err(|e| format!("Key derivation failed: {}", e))?;
-        
-        // 3. Create ChaCha20-Poly1305 cipher
-        let key = Key::from_slice(&key_bytes);
-        let cipher = ChaCha20Poly1305::new(key);
-        
-        // 4. Generate random 96-bit nonce
-        let mut nonce_bytes = [0u8; 12];
-        OsRng.fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
-        
-        // 5. Encrypt the plaintext
-        let ciphertext = cipher.encrypt(nonce, plaintext)
-            .map_err(|e| format!("Encryption failed: {}", e))?;
-        
-        // 6. Format: [salt_len(1)][salt][nonce(12)][ciphertext+tag]
-        let salt_bytes = salt.as_str().as_bytes();
-        let mut result = Vec::new();
-        result.push(salt_bytes.len() as u8);
-        result.extend_from_slice(salt_bytes);
-        result.extend_from_slice(&nonce_bytes);
-        result.extend_from_slice(&ciphertext);
-        
-        Ok(result)
+        // 3. Compress, then seal under the derived key, authenticating the header and AAD
+        let payload = manifest::pack(options.manifest, plaintext)?;
+        Self::seal(&header, &options.compression.compress(&payload)?, &key_bytes, options.aad)
     }
     
     /// Decrypt data using ChaCha20-Poly1305
-    pub fn decrypt(encrypted_data: &[u8], password: &str) -> Result<Vec<u8>, String> {
+    pub fn decrypt(encrypted_data: &[u8], password: &str) -> Result<Vec<u8>, Error> {
+        Self::decrypt_with_aad(encrypted_data, password, &[])
+    }
+    
+    /// Decrypt data that was bound to `aad` when encrypted
+    pub fn decrypt_with_aad(encrypted_data: &[u8], password: &str, aad: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(Self::decrypt_with_manifest(encrypted_data, password, aad)?.1)
+    }
+    
+    /// Decrypt, also returning the manifest sealed with the data (if any)
+    pub fn decrypt_with_manifest(encrypted_data: &[u8], password: &str, aad: &[u8]) -> Result<(Option<Manifest>, Vec<u8>), Error> {
+        let (header, header_len) = Self::parse_header(encrypted_data)?;
+        header.check_aad(aad)?;
+        if header.is_stream() {
+            let mut plaintext = Vec::new();
+            stream::decrypt(encrypted_data, &mut plaintext, password, aad)?;
+            return Ok((None, plaintext));
+        }
+        let key_bytes = header.kdf.derive_key(password)?;
+        let payload = header.codec.decompress(Self::open(&header, encrypted_data, header_len, &key_bytes, aad)?)?;
+        manifest::unpack(&header, payload)
+    }
+    
+    /// Decrypt data produced before the MBX1 container header was introduced
+    /// Format: [salt_len(1)][salt][nonce(12)][ciphertext+tag]
+    pub fn decrypt_legacy(encrypted_data: &[u8], password: &str) -> Result<Vec<u8>, Error> {
         if encrypted_data.len() < 30 {
-            return Err("Encrypted data too short".to_string());
+            return Err(Error::Truncated("encrypted data too short".to_string()));
         }
         
         let mut offset = 0;
@@ -67,7 +77,7 @@ impl ChaChaEncryption {
         offset += 1;
         
         if encrypted_data.len() < offset + salt_len {
-            return Err("Invalid salt length".to_string());
+            return Err(Error::MalformedHeader("invalid salt length".to_string()));
         }
         
         let salt_bytes = &encrypted_data[offset..offset + salt_len];
@@ -75,7 +85,7 @@ impl ChaChaEncryption {
         
         // 2. Extract nonce (12 bytes)
         if encrypted_data.len() < offset + 12 {
-            return Err("Invalid nonce".to_string());
+            return Err(Error::Truncated("missing nonce".to_string()));
         }
         let nonce_bytes = &encrypted_data[offset..offset + 12];
         let nonce = Nonce::from_slice(nonce_bytes);
@@ -86,51 +96,120 @@ impl ChaChaEncryption {
         
         // 4. Derive key from password using same salt
         let argon2 = Argon2::default();
-        let mut key_bytes = [0u8; 32];
+        let mut key_bytes = LockedKey::zeroed();
         
         argon2.hash_password_into(
             password.as_bytes(),
             salt_bytes,
-            &mut key_bytes
-        ).map_err(|e| format!("Key derivation failed: {}", e))?;
+            &mut key_bytes[..]
+        ).map_err(|e| Error::KeyDerivation(e.to_string()))?;
         
         // 5. Create cipher and decrypt
-        let key = Key::from_slice(&key_bytes);
+        let key = Key::from_slice(&key_bytes[..]);
         let cipher = ChaCha20Poly1305::new(key);
         
         let plaintext = cipher.decrypt(nonce, ciphertext)
-            .map_err(|e| format!("Decryption failed (wrong password or corrupted data): {}", e))?;
+            .map_err(|_| Error::AuthenticationFailed)?;
         
         Ok(plaintext)
     }
     
     /// Encrypt a file
     pub fn encrypt_file(input_path: &str, output_path: &str, password: &str) -> std::io::Result<()> {
+        Self::encrypt_file_with_params(input_path, output_path, password, KdfParams::default())
+    }
+    
+    /// Encrypt a file with an explicit Argon2 cost
+    pub fn encrypt_file_with_params(input_path: &str, output_path: &str, password: &str, params: KdfParams) -> std::io::Result<()> {
         let plaintext = fs::read(input_path)?;
-        let encrypted = Self::encrypt(&plaintext, password)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        fs::write(output_path, encrypted)?;
+        let options = EncryptOptions { kdf: params, ..Default::default() };
+        let encrypted = Self::encrypt_with_options(&plaintext, password, &options)?;
+        write_atomic(output_path, &encrypted)?;
         Ok(())
     }
     
     /// Decrypt a file
     pub fn decrypt_file(input_path: &str, output_path: &str, password: &str) -> std::io::Result<()> {
         let encrypted = fs::read(input_path)?;
-        let plaintext = Self::decrypt(&encrypted, password)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        fs::write(output_path, plaintext)?;
+        let plaintext = Self::decrypt(&encrypted, password)?;
+        write_atomic(output_path, &plaintext)?;
         Ok(())
     }
     
     /// Generate a random key (for advanced users who want to manage keys themselves)
-    pub fn generate_random_key() -> [u8; 32] {
-        let mut key = [0u8; 32];
-        OsRng.fill_bytes(&mut key);
+    pub fn generate_random_key() -> LockedKey {
+        let mut key = LockedKey::zeroed();
+        OsRng.fill_bytes(&mut key[..]);
         key
     }
     
     /// Encrypt with raw key (no password derivation)
-    pub fn encrypt_with_key(plaintext: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, String> {
+    pub fn encrypt_with_key(plaintext: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        Self::encrypt_with_key_and_options(plaintext, key, &EncryptOptions::default())
+    }
+    
+    /// Encrypt with raw key and the AAD, compression and manifest in `options`
+    pub fn encrypt_with_key_and_options(plaintext: &[u8], key: &[u8; 32], options: &EncryptOptions) -> Result<Vec<u8>, Error> {
+        let header = options.header(Algorithm::ChaCha20Poly1305, Kdf::None);
+        let payload = manifest::pack(options.manifest, plaintext)?;
+        Self::seal(&header, &options.compression.compress(&payload)?, key, options.aad)
+    }
+    
+    /// Decrypt with raw key (no password derivation)
+    pub fn decrypt_with_key(encrypted_data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        Self::decrypt_with_key_and_aad(encrypted_data, key, &[])
+    }
+    
+    /// Decrypt raw-key data that was bound to `aad` when encrypted
+    pub fn decrypt_with_key_and_aad(encrypted_data: &[u8], key: &[u8; 32], aad: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(Self::decrypt_with_key_and_manifest(encrypted_data, key, aad)?.1)
+    }
+    
+    /// Raw-key decrypt, also returning the manifest sealed with the data (if any)
+    pub fn decrypt_with_key_and_manifest(encrypted_data: &[u8], key: &[u8; 32], aad: &[u8]) -> Result<(Option<Manifest>, Vec<u8>), Error> {
+        let (header, header_len) = Self::parse_header(encrypted_data)?;
+        if header.kdf != Kdf::None {
+            return Err(Error::InvalidKey(
+                "container is password-protected, not raw-key encrypted".to_string(),
+            ));
+        }
+        header.check_aad(aad)?;
+        if header.is_stream() {
+            let mut plaintext = Vec::new();
+            stream::decrypt_with_key(encrypted_data, &mut plaintext, key, aad)?;
+            return Ok((None, plaintext));
+        }
+        let payload = header.codec.decompress(Self::open(&header, encrypted_data, header_len, key, aad)?)?;
+        manifest::unpack(&header, payload)
+    }
+    
+    /// Decrypt raw-key data produced before the MBX1 container header was introduced
+    pub fn decrypt_with_key_legacy(encrypted_data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        if encrypted_data.len() < 28 { // 12 nonce + 16 tag minimum
+            return Err(Error::Truncated("encrypted data too short".to_string()));
+        }
+        
+        let nonce = Nonce::from_slice(&encrypted_data[0..12]);
+        let ciphertext = &encrypted_data[12..];
+        
+        let cipher_key = Key::from_slice(key);
+        let cipher = ChaCha20Poly1305::new(cipher_key);
+        
+        let plaintext = cipher.decrypt(nonce, ciphertext)
+            .map_err(|_| Error::AuthenticationFailed)?;
+        
+        Ok(plaintext)
+    }
+    
+    /// Parse the container header and check it was written by ChaCha20-Poly1305
+    fn parse_header(encrypted_data: &[u8]) -> Result<(Header, usize), Error> {
+        let (header, header_len) = Header::parse(encrypted_data)?;
+        check_algorithm(&header)?;
+        Ok((header, header_len))
+    }
+    
+    /// Format: [header][nonce(12)][ciphertext+tag], header and AAD bound as associated data
+    pub(crate) fn seal(header: &Header, plaintext: &[u8], key: &[u8; 32], aad: &[u8]) -> Result<Vec<u8>, Error> {
         let cipher_key = Key::from_slice(key);
         let cipher = ChaCha20Poly1305::new(cipher_key);
         
@@ -138,42 +217,164 @@ impl ChaChaEncryption {
         OsRng.fill_bytes(&mut nonce_bytes);
         let nonce = Nonce::from_slice(&nonce_bytes);
         
-        let ciphertext = cipher.encrypt(nonce, plaintext)
-            .map_err(|e| format!("Encryption failed: {}", e))?;
+        let mut result = header.encode();
+        let ciphertext = cipher.encrypt(nonce, Payload { msg: plaintext, aad: &header::associated_data(&header.authenticated(), aad) })
+            .map_err(|e| Error::Encryption(e.to_string()))?;
         
-        // Format: [nonce(12)][ciphertext+tag]
-        let mut result = Vec::new();
         result.extend_from_slice(&nonce_bytes);
         result.extend_from_slice(&ciphertext);
         
         Ok(result)
     }
     
-    /// Decrypt with raw key (no password derivation)
-    pub fn decrypt_with_key(encrypted_data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, String> {
-        if encrypted_data.len() < 28 { // 12 nonce + 16 tag minimum
-            return Err("Encrypted data too short".to_string());
+    fn open(header: &Header, encrypted_data: &[u8], header_len: usize, key: &[u8; 32], aad: &[u8]) -> Result<Vec<u8>, Error> {
+        if encrypted_data.len() < header_len + 28 {
+            return Err(Error::Truncated("encrypted data too short".to_string()));
         }
         
-        let nonce = Nonce::from_slice(&encrypted_data[0..12]);
-        let ciphertext = &encrypted_data[12..];
+        let body = &encrypted_data[header_len..];
+        let nonce = Nonce::from_slice(&body[0..12]);
+        let ciphertext = &body[12..];
         
         let cipher_key = Key::from_slice(key);
         let cipher = ChaCha20Poly1305::new(cipher_key);
         
-        let plaintext = cipher.decrypt(nonce, ciphertext)
-            .map_err(|e| format!("Decryption failed: {}", e))?;
-        
-        Ok(plaintext)
+        cipher.decrypt(nonce, Payload { msg: ciphertext, aad: &header::associated_data(&header.authenticated(), aad) })
+            .map_err(|_| Error::AuthenticationFailed)
+    }
+}
+/// Check a container header was written by ChaCha20-Poly1305
+fn check_algorithm(header: &Header) -> Result<(), Error> {
+    if header.algorithm != Algorithm::ChaCha20Poly1305 {
+        return Err(Error::AlgorithmMismatch {
+            expected: "chacha",
+            found: header.algorithm.name(),
+        });
     }
+    Ok(())
 }
+
 // Wrapper functions for CLI compatibility
-pub fn encrypt_file(input_path: &str, output_path: &str, password: &str) -> Result<(), String> {
+pub fn encrypt_file(input_path: &str, output_path: &str, password: &str) -> Result<(), Error> {
     ChaChaEncryption::encrypt_file(input_path, output_path, password)
-        .map_err(|e| e.to_string())
+        .map_err(Error::from)
+}
+
+/// Encrypt a file with an explicit Argon2 cost, bound to `aad` (may be empty)
+pub fn encrypt_file_with_params(input_path: &str, output_path: &str, password: &str, params: KdfParams, aad: &[u8], compression: Compression) -> Result<(), Error> {
+    let plaintext = fs::read(input_path).map_err(|e| Error::io("Failed to read input file", e))?;
+    let options = EncryptOptions { kdf: params, aad, compression, ..Default::default() };
+    let encrypted = ChaChaEncryption::encrypt_with_options(&plaintext, password, &options)?;
+    write_atomic(output_path, &encrypted)
+}
+
+pub fn decrypt_file(input_path: &str, output_path: &str, password: &str) -> Result<(), Error> {
+    decrypt_file_with_aad(input_path, output_path, password, &[])
 }
 
-pub fn decrypt_file(input_path: &str, output_path: &str, password: &str) -> Result<(), String> {
-    ChaChaEncryption::decrypt_file(input_path, output_path, password)
-        .map_err(|e| e.to_string())
+/// Decrypt a password-protected file, streamed or not, that was bound to `aad`
+pub fn decrypt_file_with_aad(input_path: &str, output_path: &str, password: &str, aad: &[u8]) -> Result<(), Error> {
+    if let Some(header) = stream::peek_file_header(input_path)? {
+        check_algorithm(&header)?;
+        if header.is_stream() {
+            return stream::decrypt_file(input_path, output_path, password, aad);
+        }
+    }
+    let encrypted = fs::read(input_path).map_err(|e| Error::io("Failed to read input file", e))?;
+    let plaintext = ChaChaEncryption::decrypt_with_aad(&encrypted, password, aad)?;
+    write_atomic(output_path, &plaintext)
+}
+
+/// Encrypt a file in fixed-size authenticated chunks without loading it into memory
+pub fn encrypt_file_stream(input_path: &str, output_path: &str, password: &str, params: KdfParams, chunk_size: u32, aad: &[u8]) -> Result<(), Error> {
+    stream::encrypt_file(input_path, output_path, password, params, Algorithm::ChaCha20Poly1305, chunk_size, aad)
+}
+
+/// Encrypt a file under a raw 256-bit key
+pub fn encrypt_file_with_key(input_path: &str, output_path: &str, key: &[u8; 32], aad: &[u8], compression: Compression) -> Result<(), Error> {
+    let plaintext = fs::read(input_path).map_err(|e| Error::io("Failed to read input file", e))?;
+    let options = EncryptOptions { aad, compression, ..Default::default() };
+    let encrypted = ChaChaEncryption::encrypt_with_key_and_options(&plaintext, key, &options)?;
+    write_atomic(output_path, &encrypted)
+}
+
+/// Stream-encrypt a file under a raw 256-bit key
+pub fn encrypt_file_stream_with_key(input_path: &str, output_path: &str, key: &[u8; 32], chunk_size: u32, aad: &[u8]) -> Result<(), Error> {
+    stream::encrypt_file_with_key(input_path, output_path, Algorithm::ChaCha20Poly1305, key, chunk_size, aad)
+}
+
+/// Decrypt a raw-key file, streamed or not
+pub fn decrypt_file_with_key(input_path: &str, output_path: &str, key: &[u8; 32], aad: &[u8]) -> Result<(), Error> {
+    if let Some(header) = stream::peek_file_header(input_path)? {
+        check_algorithm(&header)?;
+        if header.is_stream() {
+            return stream::decrypt_file_with_key(input_path, output_path, key, aad);
+        }
+    }
+    let encrypted = fs::read(input_path).map_err(|e| Error::io("Failed to read input file", e))?;
+    let plaintext = ChaChaEncryption::decrypt_with_key_and_aad(&encrypted, key, aad)?;
+    write_atomic(output_path, &plaintext)
+}
+
+/// Decrypt a file written before the MBX1 container header was introduced
+pub fn decrypt_file_legacy(input_path: &str, output_path: &str, password: &str) -> Result<(), Error> {
+    let encrypted = fs::read(input_path).map_err(|e| Error::io("Failed to read input file", e))?;
+    let plaintext = ChaChaEncryption::decrypt_legacy(&encrypted, password)?;
+    write_atomic(output_path, &plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seal `plaintext` the way releases before the MBX1 header did:
+    /// `[salt_len(1)][salt][nonce(12)][ciphertext+tag]`
+    fn seal_legacy(plaintext: &[u8], password: &str) -> Vec<u8> {
+        let salt = [3u8; 16];
+        let nonce = [5u8; 12];
+        let mut key = [0u8; 32];
+        Argon2::default().hash_password_into(password.as_bytes(), &salt, &mut key).unwrap();
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .unwrap();
+
+        let mut sealed = vec![salt.len() as u8];
+        sealed.extend_from_slice(&salt);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    #[test]
+    fn test_password_roundtrip() {
+        let sealed = ChaChaEncryption::encrypt(b"attack at dawn", "correct horse").unwrap();
+        assert_eq!(ChaChaEncryption::decrypt(&sealed, "correct horse").unwrap(), b"attack at dawn");
+        assert!(matches!(ChaChaEncryption::decrypt(&sealed, "wrong horse"), Err(Error::AuthenticationFailed)));
+    }
+
+    #[test]
+    fn test_raw_key_roundtrip() {
+        let key = ChaChaEncryption::generate_random_key();
+        let sealed = ChaChaEncryption::encrypt_with_key(b"attack at dawn", &key).unwrap();
+        assert_eq!(ChaChaEncryption::decrypt_with_key(&sealed, &key).unwrap(), b"attack at dawn");
+        assert!(matches!(ChaChaEncryption::decrypt_with_key(&sealed, &[0u8; 32]), Err(Error::AuthenticationFailed)));
+    }
+
+    #[test]
+    fn test_legacy_headerless_input() {
+        let sealed = seal_legacy(b"written by v0", "correct horse");
+        assert_eq!(ChaChaEncryption::decrypt_legacy(&sealed, "correct horse").unwrap(), b"written by v0");
+        assert!(matches!(ChaChaEncryption::decrypt_legacy(&sealed, "wrong horse"), Err(Error::AuthenticationFailed)));
+
+        // Raw-key blobs were just `[nonce(12)][ciphertext+tag]`
+        let key = [7u8; 32];
+        let mut sealed = vec![1u8; 12];
+        sealed.extend(
+            ChaCha20Poly1305::new(Key::from_slice(&key))
+                .encrypt(Nonce::from_slice(&sealed[..12]), &b"written by v0"[..])
+                .unwrap(),
+        );
+        assert_eq!(ChaChaEncryption::decrypt_with_key_legacy(&sealed, &key).unwrap(), b"written by v0");
+        assert!(matches!(ChaChaEncryption::decrypt_with_key_legacy(&sealed, &[0u8; 32]), Err(Error::AuthenticationFailed)));
+    }
 }
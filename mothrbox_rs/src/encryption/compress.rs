@@ -0,0 +1,245 @@
+//! Optional compression applied to the plaintext before it is encrypted.
+//!
+//! The codec is recorded in the container header (`FLAG_COMPRESSED`) so
+//! decryption decompresses automatically; the level only matters when
+//! compressing and is not stored. Compression is off unless asked for.
+//!
+//! Do not compress data that mixes a secret with attacker-influenced content
+//! (session tokens next to user input, API keys in templated requests, ...):
+//! the ciphertext length then leaks how well the two compress together, which
+//! is what CRIME and BREACH exploit. Use `Compression::NONE` for such data.
+//!
+//! Decompression only runs on authenticated plaintext, but that does not make
+//! the plaintext trusted: anyone with the public key can seal an ECC, HPKE or
+//! multi-recipient container, and a service decrypting blobs on a caller's
+//! behalf opens whatever it is pointed at. Decompressed output is therefore
+//! capped at `MAX_DECOMPRESSED_LEN`, and more than that is never compressed.
+
+use std::io::{Read, Write};
+
+use crate::Error;
+
+/// Compression codec recorded in the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl Codec {
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Zstd => 1,
+            Codec::Lz4 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, Error> {
+        match id {
+            0 => Ok(Codec::None),
+            1 => Ok(Codec::Zstd),
+            2 => Ok(Codec::Lz4),
+            _ => Err(Error::Unsupported(format!("compression codec id {}", id))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::None => "none",
+            Codec::Zstd => "zstd",
+            Codec::Lz4 => "lz4",
+        }
+    }
+
+    /// Reverse `Compression::compress` for data written with this codec,
+    /// refusing output over `MAX_DECOMPRESSED_LEN`
+    pub fn decompress(self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        self.decompress_at_most(data, MAX_DECOMPRESSED_LEN)
+    }
+
+    fn decompress_at_most(self, data: Vec<u8>, limit: u64) -> Result<Vec<u8>, Error> {
+        let corrupt = |e: std::io::Error| {
+            Error::MalformedHeader(format!("{} payload is corrupt: {}", self.name(), e))
+        };
+        // One byte past the limit tells a payload at the limit from a bomb
+        let mut plaintext = Vec::new();
+        match self {
+            Codec::None => return Ok(data),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => zstd::stream::read::Decoder::new(&data[..])
+                .map_err(corrupt)?
+                .take(limit + 1)
+                .read_to_end(&mut plaintext),
+            #[cfg(not(feature = "zstd"))]
+            Codec::Zstd => return Err(zstd_unavailable()),
+            Codec::Lz4 => lz4_flex::frame::FrameDecoder::new(&data[..])
+                .take(limit + 1)
+                .read_to_end(&mut plaintext),
+        }
+        .map_err(corrupt)?;
+        if plaintext.len() as u64 > limit {
+            return Err(Error::MalformedHeader(format!(
+                "{} payload decompresses to more than {} bytes",
+                self.name(),
+                limit
+            )));
+        }
+        Ok(plaintext)
+    }
+}
+
+/// Most bytes a compressed payload may decompress to (1 GiB). Single-shot
+/// containers are decrypted in memory, so nothing larger is compressed either.
+pub const MAX_DECOMPRESSED_LEN: u64 = 1 << 30;
+
+/// Default zstd level, zstd's own default
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Highest zstd level accepted
+pub const MAX_ZSTD_LEVEL: i32 = 22;
+
+/// Codec and level used when encrypting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Compression {
+    pub codec: Codec,
+    /// zstd level (1-22); unused by lz4
+    pub level: i32,
+}
+
+impl Compression {
+    /// Store the plaintext as is
+    pub const NONE: Compression = Compression {
+        codec: Codec::None,
+        level: 0,
+    };
+
+    pub fn zstd(level: i32) -> Self {
+        Self {
+            codec: Codec::Zstd,
+            level,
+        }
+    }
+
+    pub fn lz4() -> Self {
+        Self {
+            codec: Codec::Lz4,
+            level: 0,
+        }
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.codec == Codec::None
+    }
+
+    /// Compress `plaintext`; returns it unchanged for `Codec::None`. Refuses
+    /// more than `MAX_DECOMPRESSED_LEN`, which could not be decompressed again.
+    pub fn compress<'a>(&self, plaintext: &'a [u8]) -> Result<std::borrow::Cow<'a, [u8]>, Error> {
+        let failed = |e: std::io::Error| {
+            Error::Encryption(format!("{} compression failed: {}", self.codec.name(), e))
+        };
+        if !self.is_none() && plaintext.len() as u64 > MAX_DECOMPRESSED_LEN {
+            return Err(Error::InvalidInput(format!(
+                "cannot compress more than {} bytes; encrypt without compression",
+                MAX_DECOMPRESSED_LEN
+            )));
+        }
+        match self.codec {
+            Codec::None => Ok(plaintext.into()),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Ok(zstd::bulk::compress(plaintext, self.level)
+                .map_err(failed)?
+                .into()),
+            #[cfg(not(feature = "zstd"))]
+            Codec::Zstd => Err(zstd_unavailable()),
+            Codec::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(plaintext).map_err(failed)?;
+                let compressed = encoder
+                    .finish()
+                    .map_err(|e| failed(std::io::Error::other(e)))?;
+                Ok(compressed.into())
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = Error;
+
+    /// `none`, `lz4`, `zstd` or `zstd:<level>`
+    fn from_str(spec: &str) -> Result<Self, Error> {
+        let invalid = || {
+            Error::InvalidInput(format!(
+                "unknown compression '{}' (use none, lz4, zstd or zstd:<1-{}>)",
+                spec, MAX_ZSTD_LEVEL
+            ))
+        };
+        match spec.split_once(':') {
+            None => match spec {
+                "none" => Ok(Compression::NONE),
+                "lz4" => Ok(Compression::lz4()),
+                "zstd" => Ok(Compression::zstd(DEFAULT_ZSTD_LEVEL)),
+                _ => Err(invalid()),
+            },
+            Some(("zstd", level)) => match level.parse() {
+                Ok(level @ 1..=MAX_ZSTD_LEVEL) => Ok(Compression::zstd(level)),
+                _ => Err(invalid()),
+            },
+            Some(_) => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_unavailable() -> Error {
+    Error::Unsupported("zstd compression (built without the 'zstd' feature)".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codecs_roundtrip() {
+        let data = b"mothrbox ".repeat(1000);
+        for spec in ["none", "lz4", "zstd", "zstd:19"] {
+            let compression: Compression = spec.parse().unwrap();
+            let compressed = compression.compress(&data).unwrap();
+            if !compression.is_none() {
+                assert!(compressed.len() < data.len() / 10);
+            }
+            let restored = compression
+                .codec
+                .decompress(compressed.into_owned())
+                .unwrap();
+            assert_eq!(restored, data);
+        }
+
+        for spec in ["gzip", "zstd:0", "zstd:23", "lz4:9"] {
+            assert!(spec.parse::<Compression>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_decompression_bomb_is_refused() {
+        let zeros = vec![0u8; 1 << 20];
+        for spec in ["lz4", "zstd:19"] {
+            let compression: Compression = spec.parse().unwrap();
+            let bomb = compression.compress(&zeros).unwrap().into_owned();
+            assert!(bomb.len() < 8 * 1024);
+
+            let codec = compression.codec;
+            assert_eq!(
+                codec.decompress_at_most(bomb.clone(), 1 << 20).unwrap(),
+                zeros
+            );
+            assert!(matches!(
+                codec.decompress_at_most(bomb, (1 << 20) - 1),
+                Err(Error::MalformedHeader(_))
+            ));
+        }
+    }
+}
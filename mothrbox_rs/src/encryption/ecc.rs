@@ -1,79 +1,376 @@
 use aes::cipher::generic_array::GenericArray;
 use aes::Aes256;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::Aes256Gcm;
 use ctr::cipher::{KeyIvInit, StreamCipher};
 use hkdf::Hkdf;
 use hmac::{Hmac, Mac};
 pub use p256::elliptic_curve::sec1::ToEncodedPoint;
-use p256::{ecdh::EphemeralSecret, PublicKey, SecretKey};
+use p256::{PublicKey, SecretKey};
 use rand::RngCore;
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::Path;
+use x25519_dalek::StaticSecret;
+
+use super::atomic::{create_private, write_atomic};
+use super::compress::Compression;
+use super::header::{self, Algorithm, Header, Kdf, Recipient, RECIPIENT_P256, RECIPIENT_X25519};
+use super::manifest::{self, Manifest};
+use super::options::EncryptOptions;
+use super::secret::{LockedKey, Zeroizing};
+use crate::Error;
 
 type HmacSha256 = Hmac<Sha256>;
 type Aes256Ctr = ctr::Ctr128BE<Aes256>;
 
-pub type MothrboxEccPublicKey = PublicKey;
+/// Labels the v2 key derivation, which also binds both public keys
+const ECIES_V2_INFO: &[u8] = b"mothrbox-ecies-v2";
 
-/// ECIES (Elliptic Curve Integrated Encryption Scheme) Implementation
-/// Uses P-256 curve with AES-256-CTR and HMAC-SHA256
+/// Curve of an ECC key pair. Private keys are 32 bytes on both, so private
+/// key files do not say which; public key files do (see `MothrboxPublicKey`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curve {
+    P256,
+    X25519,
+}
+
+impl Curve {
+    /// `p256` or `x25519`, as taken by the CLI
+    pub fn name(self) -> &'static str {
+        match self {
+            Curve::P256 => "p256",
+            Curve::X25519 => "x25519",
+        }
+    }
+
+    /// Curve an ECIES container was encrypted on, `None` for other algorithms
+    pub fn of(algorithm: Algorithm) -> Option<Self> {
+        match algorithm {
+            Algorithm::EciesP256 | Algorithm::EciesP256V1 => Some(Curve::P256),
+            Algorithm::EciesX25519 => Some(Curve::X25519),
+            Algorithm::Aes256Gcm | Algorithm::ChaCha20Poly1305 | Algorithm::Hpke => None,
+        }
+    }
+
+    /// Algorithm recorded for containers encrypted to a key on this curve
+    pub fn algorithm(self) -> Algorithm {
+        match self {
+            Curve::P256 => Algorithm::EciesP256,
+            Curve::X25519 => Algorithm::EciesX25519,
+        }
+    }
+
+    /// Kind of the envelope stanzas wrapped for keys on this curve
+    fn recipient_kind(self) -> u8 {
+        match self {
+            Curve::P256 => RECIPIENT_P256,
+            Curve::X25519 => RECIPIENT_X25519,
+        }
+    }
+
+    /// Generate a new key pair
+    pub fn generate_keypair(self) -> (LockedKey, MothrboxPublicKey) {
+        match self {
+            Curve::P256 => {
+                let secret = SecretKey::random(&mut rand::thread_rng());
+                let public = secret.public_key();
+                let secret_bytes = Zeroizing::new(secret.to_bytes());
+                (LockedKey::from(secret_bytes.as_ref()), public.into())
+            }
+            Curve::X25519 => {
+                let secret = StaticSecret::random_from_rng(rand::thread_rng());
+                let public = x25519_dalek::PublicKey::from(&secret);
+                (LockedKey::from(secret.as_bytes()), public.into())
+            }
+        }
+    }
+
+    /// Public key belonging to a raw private key
+    pub fn public_key(self, private_key: &[u8; 32]) -> Result<MothrboxPublicKey, Error> {
+        match self {
+            Curve::P256 => SecretKey::from_slice(private_key)
+                .map(|secret_key| secret_key.public_key().into())
+                .map_err(|e| Error::InvalidKey(format!("invalid private key: {}", e))),
+            Curve::X25519 => {
+                let secret = StaticSecret::from(*private_key);
+                Ok(x25519_dalek::PublicKey::from(&secret).into())
+            }
+        }
+    }
+
+    /// ECDH between `private_key` and an encoded public key on this curve
+    fn diffie_hellman(
+        self,
+        private_key: &[u8; 32],
+        public_bytes: &[u8],
+    ) -> Result<LockedKey, Error> {
+        match self {
+            Curve::P256 => {
+                let public_key = PublicKey::from_sec1_bytes(public_bytes)
+                    .map_err(|e| Error::InvalidKey(format!("invalid public key: {}", e)))?;
+                let secret_key = SecretKey::from_slice(private_key)
+                    .map_err(|e| Error::InvalidKey(format!("invalid private key: {}", e)))?;
+                let shared_secret = p256::ecdh::diffie_hellman(
+                    secret_key.to_nonzero_scalar(),
+                    public_key.as_affine(),
+                );
+                LockedKey::from_slice(shared_secret.raw_secret_bytes())
+            }
+            Curve::X25519 => {
+                let point: [u8; 32] = public_bytes.try_into().map_err(|_| {
+                    Error::InvalidKey("X25519 public key must be 32 bytes".to_string())
+                })?;
+                let shared_secret = StaticSecret::from(*private_key)
+                    .diffie_hellman(&x25519_dalek::PublicKey::from(point));
+                // An all-zero secret means a low-order point, which anyone could predict
+                if !shared_secret.was_contributory() {
+                    return Err(Error::InvalidKey(
+                        "X25519 public key has low order".to_string(),
+                    ));
+                }
+                Ok(LockedKey::from(shared_secret.as_bytes()))
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Curve {
+    type Err = Error;
+
+    /// `p256` or `x25519`, as printed by `name`
+    fn from_str(name: &str) -> Result<Self, Error> {
+        match name {
+            "p256" => Ok(Curve::P256),
+            "x25519" => Ok(Curve::X25519),
+            _ => Err(Error::InvalidInput(format!(
+                "unknown curve '{}' (use p256 or x25519)",
+                name
+            ))),
+        }
+    }
+}
+
+/// Recipient public key on either curve
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MothrboxPublicKey {
+    P256(PublicKey),
+    X25519(x25519_dalek::PublicKey),
+}
+
+impl MothrboxPublicKey {
+    /// Parse a SEC1 P-256 key (33 or 65 bytes) or a 32-byte X25519 key
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        match <[u8; 32]>::try_from(bytes) {
+            Ok(point) => Ok(MothrboxPublicKey::X25519(point.into())),
+            Err(_) => PublicKey::from_sec1_bytes(bytes)
+                .map(MothrboxPublicKey::P256)
+                .map_err(|e| Error::InvalidKey(format!("invalid public key: {}", e))),
+        }
+    }
+
+    /// Uncompressed SEC1 (65 bytes) for P-256, 32 bytes for X25519, as
+    /// written to public key files
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            MothrboxPublicKey::P256(public_key) => {
+                public_key.to_encoded_point(false).as_bytes().to_vec()
+            }
+            MothrboxPublicKey::X25519(public_key) => public_key.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn curve(&self) -> Curve {
+        match self {
+            MothrboxPublicKey::P256(_) => Curve::P256,
+            MothrboxPublicKey::X25519(_) => Curve::X25519,
+        }
+    }
+}
+
+impl From<PublicKey> for MothrboxPublicKey {
+    fn from(public_key: PublicKey) -> Self {
+        MothrboxPublicKey::P256(public_key)
+    }
+}
+
+impl From<x25519_dalek::PublicKey> for MothrboxPublicKey {
+    fn from(public_key: x25519_dalek::PublicKey) -> Self {
+        MothrboxPublicKey::X25519(public_key)
+    }
+}
+
+/// ECIES (Elliptic Curve Integrated Encryption Scheme) on P-256 or X25519.
+///
+/// v2 (algorithm id 4 on P-256, 6 on X25519, written) derives a key with
+/// HKDF-SHA256 from the ECDH secret, bound to the sender and recipient public
+/// keys, and seals with AES-256-GCM over the header and AAD. v1 (id 3,
+/// P-256 only) used AES-256-CTR and HMAC-SHA256 under a fixed HKDF label; it
+/// is only decrypted.
 pub struct ECCEncryption;
 
 impl ECCEncryption {
     /// Encrypt data using ECIES
-    pub fn encrypt(plaintext: &[u8], recipient_public_key: &PublicKey) -> Result<Vec<u8>, String> {
-        // 1. Generate ephemeral key pair
-        let ephemeral_secret = EphemeralSecret::random(&mut rand::thread_rng());
-        let ephemeral_public = ephemeral_secret.public_key();
+    pub fn encrypt(
+        plaintext: &[u8],
+        recipient_public_key: &MothrboxPublicKey,
+    ) -> Result<Vec<u8>, Error> {
+        Self::encrypt_with_options(plaintext, recipient_public_key, &EncryptOptions::default())
+    }
 
-        // 2. Perform ECDH to get shared secret
-        let shared_secret = ephemeral_secret.diffie_hellman(recipient_public_key);
+    /// Encrypt with the AAD, compression and manifest in `options`
+    pub fn encrypt_with_options(
+        plaintext: &[u8],
+        recipient_public_key: &MothrboxPublicKey,
+        options: &EncryptOptions,
+    ) -> Result<Vec<u8>, Error> {
+        let header = options.header(recipient_public_key.curve().algorithm(), Kdf::None);
+        let payload = manifest::pack(options.manifest, plaintext)?;
+        let payload = options.compression.compress(&payload)?;
+
+        let (shared_secret, ephemeral_public_bytes, recipient_public_bytes) =
+            Self::agree_ephemeral(recipient_public_key)?;
+        Self::seal(
+            &header,
+            &payload,
+            &shared_secret[..],
+            &ephemeral_public_bytes,
+            &recipient_public_bytes,
+            options.aad,
+        )
+    }
 
-        // 3. Derive encryption and MAC keys using HKDF
-        let hk = Hkdf::<Sha256>::new(None, shared_secret.raw_secret_bytes());
-        let mut key_material = [0u8; 64]; // 32 bytes for AES + 32 bytes for HMAC
-        hk.expand(b"ecies-encryption", &mut key_material)
-            .map_err(|e| format!("HKDF error: {}", e))?;
+    /// Decrypt data using ECIES
+    pub fn decrypt(encrypted_data: &[u8], private_key: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        Self::decrypt_with_aad(encrypted_data, private_key, &[])
+    }
 
-        let enc_key = &key_material[0..32];
-        let mac_key = &key_material[32..64];
+    /// Decrypt data that was bound to `aad` when encrypted
+    pub fn decrypt_with_aad(
+        encrypted_data: &[u8],
+        private_key: &[u8; 32],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        Ok(Self::decrypt_with_manifest(encrypted_data, private_key, aad)?.1)
+    }
 
-        // 4. Generate random IV for AES-CTR
-        let mut iv = [0u8; 16];
-        rand::thread_rng().fill_bytes(&mut iv);
+    /// Decrypt, also returning the manifest sealed with the data (if any)
+    pub fn decrypt_with_manifest(
+        encrypted_data: &[u8],
+        private_key: &[u8; 32],
+        aad: &[u8],
+    ) -> Result<(Option<Manifest>, Vec<u8>), Error> {
+        let (header, header_bytes, body) = Self::split_header(encrypted_data)?;
+        header.check_aad(aad)?;
+        let payload = match header.algorithm {
+            Algorithm::EciesP256V1 => Self::open_v1(header_bytes, body, private_key, aad)?,
+            Algorithm::EciesX25519 => Self::open(Curve::X25519, &header, body, private_key, aad)?,
+            _ => Self::open(Curve::P256, &header, body, private_key, aad)?,
+        };
+        manifest::unpack(&header, header.codec.decompress(payload)?)
+    }
 
-        // 5. Encrypt with AES-256-CTR
-        let mut ciphertext = plaintext.to_vec();
-        let mut cipher = Aes256Ctr::new(
-            GenericArray::from_slice(enc_key),
-            GenericArray::from_slice(&iv),
-        );
-        cipher.apply_keystream(&mut ciphertext);
+    /// Decrypt data produced before the MBX1 container header was introduced
+    pub fn decrypt_legacy(encrypted_data: &[u8], private_key: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        Self::open_v1(&[], encrypted_data, private_key, &[])
+    }
 
-        // 6. Create HMAC over ephemeral public key + IV + ciphertext
-        let ephemeral_public_bytes = ephemeral_public.to_encoded_point(false);
-        let mut mac =
-            HmacSha256::new_from_slice(mac_key).map_err(|e| format!("HMAC error: {}", e))?;
+    /// Parse the container header and check it was written by ECIES
+    fn split_header(encrypted_data: &[u8]) -> Result<(Header, &[u8], &[u8]), Error> {
+        let (header, header_len) = Header::parse(encrypted_data)?;
+        if !header.algorithm.is_ecies() {
+            return Err(Error::AlgorithmMismatch {
+                expected: "ecc",
+                found: header.algorithm.name(),
+            });
+        }
+        let (header_bytes, body) = encrypted_data.split_at(header_len);
+        Ok((header, header_bytes, body))
+    }
 
-        mac.update(ephemeral_public_bytes.as_bytes());
-        mac.update(&iv);
-        mac.update(&ciphertext);
-        let tag = mac.finalize().into_bytes();
-
-        // 7. Format: [ephemeral_public_key_len(2)][ephemeral_public_key][iv(16)][ciphertext][mac(32)]
-        let mut result = Vec::new();
-        let pub_key_bytes = ephemeral_public_bytes.as_bytes();
-        result.extend_from_slice(&(pub_key_bytes.len() as u16).to_be_bytes());
-        result.extend_from_slice(pub_key_bytes);
-        result.extend_from_slice(&iv);
+    /// Format: `[header][sender_public_key_len(2)][sender_public_key][nonce(12)][ciphertext+tag]`.
+    /// The sender key is ephemeral, except for `encrypt_authenticated`.
+    fn seal(
+        header: &Header,
+        payload: &[u8],
+        shared_secret: &[u8],
+        sender_public_bytes: &[u8],
+        recipient_public_bytes: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let key = Self::derive_key(
+            ECIES_V2_INFO,
+            shared_secret,
+            sender_public_bytes,
+            recipient_public_bytes,
+        )?;
+
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = Self::aead(&key)
+            .encrypt(
+                GenericArray::from_slice(&nonce),
+                Payload {
+                    msg: payload,
+                    aad: &header::associated_data(&header.authenticated(), aad),
+                },
+            )
+            .map_err(|e| Error::Encryption(e.to_string()))?;
+
+        let mut result = header.encode();
+        result.extend_from_slice(&(sender_public_bytes.len() as u16).to_be_bytes());
+        result.extend_from_slice(sender_public_bytes);
+        result.extend_from_slice(&nonce);
         result.extend_from_slice(&ciphertext);
-        result.extend_from_slice(&tag);
-
         Ok(result)
     }
 
-    /// Decrypt data using ECIES
-    pub fn decrypt(encrypted_data: &[u8], private_key: &[u8; 32]) -> Result<Vec<u8>, String> {
+    /// Decrypt a v2 body; the header and `aad` are authenticated by the AEAD
+    fn open(
+        curve: Curve,
+        header: &Header,
+        body: &[u8],
+        private_key: &[u8; 32],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        if body.len() < 2 {
+            return Err(Error::Truncated("encrypted data too short".to_string()));
+        }
+        let pub_key_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+        if body.len() < 2 + pub_key_len + 12 + 16 {
+            return Err(Error::Truncated("encrypted data too short".to_string()));
+        }
+        let (sender_public_bytes, rest) = body[2..].split_at(pub_key_len);
+        let (nonce, ciphertext) = rest.split_at(12);
+
+        let shared_secret = curve.diffie_hellman(private_key, sender_public_bytes)?;
+        let key = Self::derive_key(
+            ECIES_V2_INFO,
+            &shared_secret[..],
+            sender_public_bytes,
+            &curve.public_key(private_key)?.to_bytes(),
+        )?;
+
+        Self::aead(&key)
+            .decrypt(
+                GenericArray::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &header::associated_data(&header.authenticated(), aad),
+                },
+            )
+            .map_err(|_| Error::AuthenticationFailed)
+    }
+
+    /// Verify and decrypt an ECIES v1 body; `header_bytes` and `aad` are covered by the MAC
+    fn open_v1(
+        header_bytes: &[u8],
+        encrypted_data: &[u8],
+        private_key: &[u8; 32],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, Error> {
         if encrypted_data.len() < 50 {
-            return Err("Encrypted data too short".to_string());
+            return Err(Error::Truncated("encrypted data too short".to_string()));
         }
 
         let mut offset = 0;
@@ -84,27 +381,29 @@ impl ECCEncryption {
 
         // 2. Extract ephemeral public key
         if encrypted_data.len() < offset + pub_key_len {
-            return Err("Invalid ephemeral public key".to_string());
+            return Err(Error::MalformedHeader(
+                "invalid ephemeral public key length".to_string(),
+            ));
         }
         let ephemeral_public_bytes = &encrypted_data[offset..offset + pub_key_len];
         let ephemeral_public = PublicKey::from_sec1_bytes(ephemeral_public_bytes)
-            .map_err(|e| format!("Invalid public key: {}", e))?;
+            .map_err(|e| Error::InvalidKey(format!("invalid public key: {}", e)))?;
         offset += pub_key_len;
 
         // 3. Extract IV (16 bytes)
+        if encrypted_data.len() < offset + 16 + 32 {
+            return Err(Error::Truncated("missing IV or MAC".to_string()));
+        }
         let iv = &encrypted_data[offset..offset + 16];
         offset += 16;
 
         // 4. Extract MAC (last 32 bytes)
-        if encrypted_data.len() < offset + 32 {
-            return Err("Invalid MAC".to_string());
-        }
         let mac_tag = &encrypted_data[encrypted_data.len() - 32..];
         let ciphertext = &encrypted_data[offset..encrypted_data.len() - 32];
 
         // 5. Recover private key and perform ECDH
         let secret_key = SecretKey::from_slice(private_key)
-            .map_err(|e| format!("Invalid private key: {}", e))?;
+            .map_err(|e| Error::InvalidKey(format!("invalid private key: {}", e)))?;
 
         let shared_secret = p256::ecdh::diffie_hellman(
             secret_key.to_nonzero_scalar(),
@@ -113,22 +412,24 @@ impl ECCEncryption {
 
         // 6. Derive encryption and MAC keys using HKDF
         let hk = Hkdf::<Sha256>::new(None, shared_secret.raw_secret_bytes());
-        let mut key_material = [0u8; 64];
-        hk.expand(b"ecies-encryption", &mut key_material)
-            .map_err(|e| format!("HKDF error: {}", e))?;
+        let mut key_material = Zeroizing::new([0u8; 64]);
+        hk.expand(b"ecies-encryption", &mut key_material[..])
+            .map_err(|e| Error::KeyDerivation(format!("HKDF error: {}", e)))?;
 
         let enc_key = &key_material[0..32];
         let mac_key = &key_material[32..64];
 
         // 7. Verify HMAC
-        let mut mac =
-            HmacSha256::new_from_slice(mac_key).map_err(|e| format!("HMAC error: {}", e))?;
+        let mut mac = HmacSha256::new_from_slice(mac_key)
+            .map_err(|e| Error::KeyDerivation(format!("HMAC error: {}", e)))?;
+        mac.update(header_bytes);
+        update_aad(&mut mac, aad);
         mac.update(ephemeral_public_bytes);
         mac.update(iv);
         mac.update(ciphertext);
 
         mac.verify_slice(mac_tag)
-            .map_err(|_| "MAC verification failed - data may be tampered")?;
+            .map_err(|_| Error::AuthenticationFailed)?;
 
         // 8. Decrypt with AES-256-CTR
         let mut plaintext = ciphertext.to_vec();
@@ -141,161 +442,269 @@ impl ECCEncryption {
         Ok(plaintext)
     }
 
-    /// Generate a new ECC key pair
-    pub fn generate_keypair() -> ([u8; 32], PublicKey) {
-        let secret = SecretKey::random(&mut rand::thread_rng());
-        let public = secret.public_key();
-        (*secret.to_bytes().as_ref(), public)
+    /// Generate a new P-256 key pair (see `Curve::generate_keypair` for X25519)
+    pub fn generate_keypair() -> (LockedKey, MothrboxPublicKey) {
+        Curve::P256.generate_keypair()
     }
 
+    /// Encrypt from a static sender key instead of an ephemeral one: the body
+    /// carries the sender's public key, and only the recipient can open it
     pub fn encrypt_authenticated(
         plaintext: &[u8],
-        recipient_public_key: &PublicKey,
-        my_secret_bytes: &[u8; 32], // <--- Input your private key here
-    ) -> Result<Vec<u8>, String> {
-        // 1. Reconstruct YOUR SecretKey from the raw bytes
-        let sender_secret = p256::SecretKey::from_slice(my_secret_bytes)
-            .map_err(|_| "Invalid secret key bytes provided".to_string())?;
-
-        // 2. Perform ECDH: (Your Private Key) + (Their Public Key)
-        // We use the low-level diffie_hellman function for static keys
-        let shared_secret = p256::elliptic_curve::ecdh::diffie_hellman(
-            sender_secret.to_nonzero_scalar(),
-            recipient_public_key.as_affine(),
-        );
-
-        // 3. Derive encryption keys (Same as before)
-        let hk = Hkdf::<Sha256>::new(None, shared_secret.raw_secret_bytes());
-        let mut key_material = [0u8; 64];
-        hk.expand(b"ecies-encryption", &mut key_material)
-            .map_err(|e| format!("HKDF error: {}", e))?;
-
-        let enc_key = &key_material[0..32];
-        let mac_key = &key_material[32..64];
-
-        // 4. Generate random IV (Same as before)
-        let mut iv = [0u8; 16];
-        rand::thread_rng().fill_bytes(&mut iv);
-
-        // 5. Encrypt (Same as before)
-        let mut ciphertext = plaintext.to_vec();
-        let mut cipher = Aes256Ctr::new(
-            GenericArray::from_slice(enc_key),
-            GenericArray::from_slice(&iv),
-        );
-        cipher.apply_keystream(&mut ciphertext);
-
-        // 6. PREPARE HEADER: Use YOUR Public Key
-        // Crucial Step: We attach YOUR public key instead of a random one.
-        // The recipient needs this to calculate the shared secret on their end.
-        let sender_public_key = sender_secret.public_key();
-        let sender_public_bytes = sender_public_key.to_encoded_point(false);
-
-        // 7. MAC (Same as before, but over YOUR public key bytes)
-        let mut mac =
-            HmacSha256::new_from_slice(mac_key).map_err(|e| format!("HMAC error: {}", e))?;
-
-        mac.update(sender_public_bytes.as_bytes());
-        mac.update(&iv);
-        mac.update(&ciphertext);
-        let tag = mac.finalize().into_bytes();
-
-        // 8. Pack Result
-        let mut result = Vec::new();
-        let pub_key_bytes = sender_public_bytes.as_bytes();
-        result.extend_from_slice(&(pub_key_bytes.len() as u16).to_be_bytes());
-        result.extend_from_slice(pub_key_bytes); // <--- This is now YOUR key
-        result.extend_from_slice(&iv);
-        result.extend_from_slice(&ciphertext);
-        result.extend_from_slice(&tag);
-
-        Ok(result)
+        recipient_public_key: &MothrboxPublicKey,
+        my_secret_bytes: &[u8; 32],
+    ) -> Result<Vec<u8>, Error> {
+        let curve = recipient_public_key.curve();
+        let sender_public_key = curve
+            .public_key(my_secret_bytes)
+            .map_err(|_| Error::InvalidKey("invalid secret key bytes provided".to_string()))?;
+        let recipient_public_bytes = recipient_public_key.to_bytes();
+        let shared_secret = curve.diffie_hellman(my_secret_bytes, &recipient_public_bytes)?;
+        Self::seal(
+            &Header::new(curve.algorithm(), Kdf::None),
+            plaintext,
+            &shared_secret[..],
+            &sender_public_key.to_bytes(),
+            &recipient_public_bytes,
+            &[],
+        )
     }
 
+    /// Decrypt `encrypt_authenticated` output, v1 or v2. The body names the
+    /// sender key, so this is plain ECIES decryption.
     pub fn decrypt_authenticated(
         encrypted_data: &[u8],
         my_secret_bytes: &[u8; 32],
-    ) -> Result<Vec<u8>, String> {
-        // 1. Reconstruct YOUR Secret Key
-        let my_secret = SecretKey::from_slice(my_secret_bytes)
-            .map_err(|_| "Invalid private key bytes".to_string())?;
-
-        // --- PARSE THE PACKET ---
-        // Minimal length check: 2 (len) + 33 (min pubkey) + 16 (iv) + 32 (tag) = 83 bytes
-        if encrypted_data.len() < 83 {
-            return Err("Message too short".to_string());
-        }
-
-        let mut offset = 0;
-
-        // A. Read Sender Public Key Length (2 bytes)
-        let pub_key_len =
-            u16::from_be_bytes(encrypted_data[offset..offset + 2].try_into().unwrap()) as usize;
-        offset += 2;
-
-        // B. Read Sender Public Key
-        if offset + pub_key_len > encrypted_data.len() {
-            return Err("Malformed packet: invalid public key length".to_string());
-        }
-        let sender_pub_key_bytes = &encrypted_data[offset..offset + pub_key_len];
-        let sender_public_key = PublicKey::from_sec1_bytes(sender_pub_key_bytes)
-            .map_err(|_| "Invalid sender public key".to_string())?;
-        offset += pub_key_len;
-
-        // C. Read IV (16 bytes)
-        let iv_bytes = &encrypted_data[offset..offset + 16];
-        offset += 16;
+    ) -> Result<Vec<u8>, Error> {
+        Self::decrypt(encrypted_data, my_secret_bytes)
+    }
 
-        // D. Read Ciphertext (Variable length)
-        // The tag is always at the end (32 bytes), so ciphertext is everything between IV and Tag
-        let ciphertext_len = encrypted_data.len() - offset - 32;
-        let ciphertext_bytes = &encrypted_data[offset..offset + ciphertext_len];
-        offset += ciphertext_len;
+    /// Ephemeral key agreement for ECC streams (see `stream`).
+    /// Returns the key prefix `[ephemeral_public_key_len(2)][ephemeral_public_key]`
+    /// written after the header, and the 256-bit stream key.
+    pub fn stream_key_for_recipient(
+        recipient_public_key: &MothrboxPublicKey,
+    ) -> Result<(Vec<u8>, LockedKey), Error> {
+        let (shared_secret, ephemeral_public_bytes, recipient_public_bytes) =
+            Self::agree_ephemeral(recipient_public_key)?;
+
+        let key = Self::derive_key(
+            b"mothrbox-ecies-stream-v1",
+            &shared_secret[..],
+            &ephemeral_public_bytes,
+            &recipient_public_bytes,
+        )?;
+
+        let mut prefix = Vec::with_capacity(2 + ephemeral_public_bytes.len());
+        prefix.extend_from_slice(&(ephemeral_public_bytes.len() as u16).to_be_bytes());
+        prefix.extend_from_slice(&ephemeral_public_bytes);
+
+        Ok((prefix, key))
+    }
 
-        // E. Read Tag (32 bytes)
-        let received_tag = &encrypted_data[offset..];
+    /// Recover an ECC stream key on `curve` from the ephemeral public key in
+    /// its key prefix
+    pub fn stream_key_from_private_key(
+        curve: Curve,
+        ephemeral_public_bytes: &[u8],
+        private_key: &[u8; 32],
+    ) -> Result<LockedKey, Error> {
+        let shared_secret = curve.diffie_hellman(private_key, ephemeral_public_bytes)?;
+
+        Self::derive_key(
+            b"mothrbox-ecies-stream-v1",
+            &shared_secret[..],
+            ephemeral_public_bytes,
+            &curve.public_key(private_key)?.to_bytes(),
+        )
+    }
 
-        // --- CRYPTO OPERATIONS ---
+    /// ECDH with a fresh key pair on the recipient's curve. Returns the shared
+    /// secret and the encoded ephemeral and recipient public keys.
+    fn agree_ephemeral(
+        recipient_public_key: &MothrboxPublicKey,
+    ) -> Result<(LockedKey, Vec<u8>, Vec<u8>), Error> {
+        let curve = recipient_public_key.curve();
+        let (ephemeral_secret, ephemeral_public) = curve.generate_keypair();
+        let recipient_public_bytes = recipient_public_key.to_bytes();
+        let shared_secret = curve.diffie_hellman(&ephemeral_secret, &recipient_public_bytes)?;
+        Ok((
+            shared_secret,
+            ephemeral_public.to_bytes(),
+            recipient_public_bytes,
+        ))
+    }
 
-        // 2. Perform ECDH: (My Private) + (Sender Public)
-        // This regenerates the exact same shared secret created during encryption
-        let shared_secret = p256::elliptic_curve::ecdh::diffie_hellman(
-            my_secret.to_nonzero_scalar(),
-            sender_public_key.as_affine(),
-        );
+    /// HKDF over the shared secret, bound to both public keys:
+    /// info is `label || sender_public_key || recipient_public_key`
+    fn derive_key(
+        label: &[u8],
+        shared_secret: &[u8],
+        sender_public_bytes: &[u8],
+        recipient_public_bytes: &[u8],
+    ) -> Result<LockedKey, Error> {
+        let mut info = label.to_vec();
+        info.extend_from_slice(sender_public_bytes);
+        info.extend_from_slice(recipient_public_bytes);
+
+        let hk = Hkdf::<Sha256>::new(None, shared_secret);
+        let mut key = LockedKey::zeroed();
+        hk.expand(&info, &mut key[..])
+            .map_err(|e| Error::KeyDerivation(format!("HKDF error: {}", e)))?;
+        Ok(key)
+    }
 
-        // 3. Derive Keys (HKDF)
-        let hk = Hkdf::<Sha256>::new(None, shared_secret.raw_secret_bytes());
-        let mut key_material = [0u8; 64];
-        hk.expand(b"ecies-encryption", &mut key_material)
-            .map_err(|e| format!("HKDF error: {}", e))?;
+    fn aead(key: &[u8; 32]) -> Aes256Gcm {
+        <Aes256Gcm as aes_gcm::KeyInit>::new(GenericArray::from_slice(key))
+    }
 
-        let enc_key = &key_material[0..32];
-        let mac_key = &key_material[32..64];
+    /// Encrypt once under a random data key, wrapped for each of `recipients`
+    /// (at most 255), who can each decrypt with their own private key. The
+    /// payload is sealed with `algorithm`, AES-256-GCM or ChaCha20-Poly1305.
+    pub fn encrypt_for_recipients(
+        plaintext: &[u8],
+        recipients: &[MothrboxPublicKey],
+        algorithm: Algorithm,
+        options: &EncryptOptions,
+    ) -> Result<Vec<u8>, Error> {
+        let data_key = Self::random_data_key();
+        let header = options
+            .header(algorithm, Kdf::None)
+            .with_recipients(Self::wrap_for_all(&data_key, recipients)?);
+        let payload = manifest::pack(options.manifest, plaintext)?;
+        let payload = options.compression.compress(&payload)?;
+        let aad = options.aad;
+        match algorithm {
+            Algorithm::Aes256Gcm => {
+                super::aes::AESEncryption::seal(&header, &payload, &data_key, aad)
+            }
+            Algorithm::ChaCha20Poly1305 => {
+                super::chacha::ChaChaEncryption::seal(&header, &payload, &data_key, aad)
+            }
+            Algorithm::EciesP256
+            | Algorithm::EciesP256V1
+            | Algorithm::EciesX25519
+            | Algorithm::Hpke => Err(Error::InvalidInput(
+                "envelope payloads are sealed with aes or chacha".to_string(),
+            )),
+        }
+    }
 
-        // 4. Verify MAC (Authentication)
-        // We must re-calculate the MAC over [SenderPubKey + IV + Ciphertext] and compare
-        let mut mac =
-            HmacSha256::new_from_slice(mac_key).map_err(|e| format!("HMAC error: {}", e))?;
+    pub(crate) fn random_data_key() -> LockedKey {
+        let mut data_key = LockedKey::zeroed();
+        rand::thread_rng().fill_bytes(&mut data_key[..]);
+        data_key
+    }
 
-        mac.update(sender_pub_key_bytes); // The bytes we extracted from the packet
-        mac.update(iv_bytes);
-        mac.update(ciphertext_bytes);
+    /// A recipient stanza for each public key
+    pub fn wrap_for_all(
+        data_key: &[u8; 32],
+        recipients: &[MothrboxPublicKey],
+    ) -> Result<Vec<Recipient>, Error> {
+        if recipients.is_empty() || recipients.len() > 255 {
+            return Err(Error::InvalidInput(format!(
+                "an envelope needs 1 to 255 recipients, got {}",
+                recipients.len()
+            )));
+        }
+        recipients
+            .iter()
+            .map(|recipient| Self::wrap_key(data_key, recipient))
+            .collect()
+    }
 
-        // Verify ensures constant-time comparison to prevent timing attacks
-        mac.verify_slice(received_tag)
-            .map_err(|_| "Decryption failed: Integrity check failed (MAC mismatch)".to_string())?;
+    /// Wrap a data key for `recipient`: ephemeral ECDH, a wrapping key from
+    /// HKDF bound to both public keys, then AES-256-GCM
+    pub fn wrap_key(
+        data_key: &[u8; 32],
+        recipient: &MothrboxPublicKey,
+    ) -> Result<Recipient, Error> {
+        let (shared_secret, ephemeral_public_bytes, recipient_public_bytes) =
+            Self::agree_ephemeral(recipient)?;
+        let key_id = Self::key_id(recipient);
+
+        let wrapping_key = Self::derive_key(
+            b"mothrbox-envelope-v1",
+            &shared_secret[..],
+            &ephemeral_public_bytes,
+            &recipient_public_bytes,
+        )?;
+        // Each wrapping key is used once, so a fixed nonce is safe
+        let wrapped_key = Self::aead(&wrapping_key)
+            .encrypt(
+                GenericArray::from_slice(&[0u8; 12]),
+                Payload {
+                    msg: data_key,
+                    aad: &key_id,
+                },
+            )
+            .map_err(|e| Error::Encryption(e.to_string()))?;
+
+        Ok(Recipient {
+            kind: recipient.curve().recipient_kind(),
+            key_id,
+            ephemeral_public_key: ephemeral_public_bytes,
+            wrapped_key,
+        })
+    }
 
-        // 5. Decrypt (AES-CTR)
-        // AES-CTR is symmetric: applying the keystream again decrypts it
-        let mut plaintext = ciphertext_bytes.to_vec();
-        let mut cipher = Aes256Ctr::new(
-            GenericArray::from_slice(enc_key),
-            GenericArray::from_slice(iv_bytes),
-        );
-        cipher.apply_keystream(&mut plaintext);
+    /// Recover the data key from the stanza wrapped for `private_key`, read
+    /// as a key on each stanza's curve. The stanza whose key id matches is
+    /// tried first, then every other one.
+    pub fn unwrap_key(
+        recipients: &[Recipient],
+        private_key: &[u8; 32],
+    ) -> Result<LockedKey, Error> {
+        // Not every 32-byte string is a P-256 scalar, but all are X25519 keys
+        let own_keys: Vec<_> = [Curve::P256, Curve::X25519]
+            .into_iter()
+            .filter_map(|curve| curve.public_key(private_key).ok())
+            .collect();
+
+        let (mine, others): (Vec<_>, Vec<_>) = recipients
+            .iter()
+            .filter_map(|recipient| {
+                own_keys
+                    .iter()
+                    .find(|public_key| public_key.curve().recipient_kind() == recipient.kind)
+                    .map(|public_key| (recipient, public_key))
+            })
+            .partition(|(recipient, public_key)| recipient.key_id == Self::key_id(public_key));
+        for (recipient, public_key) in mine.into_iter().chain(others) {
+            let Ok(shared_secret) = public_key
+                .curve()
+                .diffie_hellman(private_key, &recipient.ephemeral_public_key)
+            else {
+                continue;
+            };
+            let wrapping_key = Self::derive_key(
+                b"mothrbox-envelope-v1",
+                &shared_secret[..],
+                &recipient.ephemeral_public_key,
+                &public_key.to_bytes(),
+            )?;
+            let unwrapped = Self::aead(&wrapping_key).decrypt(
+                GenericArray::from_slice(&[0u8; 12]),
+                Payload {
+                    msg: &recipient.wrapped_key,
+                    aad: &recipient.key_id,
+                },
+            );
+            if let Ok(data_key) = unwrapped.map(Zeroizing::new) {
+                return LockedKey::from_slice(&data_key);
+            }
+        }
+        Err(Error::InvalidKey(format!(
+            "this private key is not one of the {} recipients",
+            recipients.len()
+        )))
+    }
 
-        Ok(plaintext)
+    /// First 8 bytes of the SHA-256 of a public key as written to key files
+    /// (uncompressed SEC1 for P-256)
+    pub fn key_id(public_key: &MothrboxPublicKey) -> [u8; 8] {
+        let digest = Sha256::digest(public_key.to_bytes());
+        digest[..8].try_into().expect("8 bytes")
     }
 
     /// Save encrypted data to file
@@ -308,18 +717,42 @@ impl ECCEncryption {
         std::fs::read(filename)
     }
 }
+/// MAC the caller's AAD, length-prefixed so it cannot run into the public key
+fn update_aad(mac: &mut HmacSha256, aad: &[u8]) {
+    if !aad.is_empty() {
+        mac.update(&(aad.len() as u64).to_be_bytes());
+        mac.update(aad);
+    }
+}
+
 use std::fs;
 
 // File operation functions
-pub fn generate_keypair(private_key_path: &str, public_key_path: &str) -> Result<(), String> {
-    let (private_key, public_key) = ECCEncryption::generate_keypair();
+pub fn generate_keypair(
+    curve: Curve,
+    private_key_path: &str,
+    public_key_path: &str,
+) -> Result<MothrboxPublicKey, Error> {
+    let (private_key, public_key) = curve.generate_keypair();
+    write_keypair(&private_key, &public_key, private_key_path, public_key_path)?;
+    Ok(public_key)
+}
 
-    fs::write(private_key_path, &private_key)
-        .map_err(|e| format!("Failed to write private key: {}", e))?;
+/// Write a raw private key and its public key as `read_private_key` and
+/// `read_public_key` expect them. The private key file is readable only by
+/// its owner, and an existing one is never replaced.
+pub fn write_keypair(
+    private_key: &[u8; 32],
+    public_key: &MothrboxPublicKey,
+    private_key_path: &str,
+    public_key_path: &str,
+) -> Result<(), Error> {
+    create_private(Path::new(private_key_path))
+        .and_then(|mut file| file.write_all(private_key))
+        .map_err(|e| Error::io("Failed to write private key", e))?;
 
-    let public_key_bytes = public_key.to_encoded_point(false);
-    fs::write(public_key_path, public_key_bytes.as_bytes())
-        .map_err(|e| format!("Failed to write public key: {}", e))?;
+    fs::write(public_key_path, public_key.to_bytes())
+        .map_err(|e| Error::io("Failed to write public key", e))?;
 
     Ok(())
 }
@@ -328,20 +761,51 @@ pub fn encrypt_file(
     input_path: &str,
     output_path: &str,
     public_key_path: &str,
-) -> Result<(), String> {
-    let plaintext =
-        fs::read(input_path).map_err(|e| format!("Failed to read input file: {}", e))?;
+) -> Result<(), Error> {
+    encrypt_file_compressed(input_path, output_path, public_key_path, Compression::NONE)
+}
 
-    let public_key_bytes =
-        fs::read(public_key_path).map_err(|e| format!("Failed to read public key: {}", e))?;
+/// Encrypt a file for a recipient, compressing it first
+pub fn encrypt_file_compressed(
+    input_path: &str,
+    output_path: &str,
+    public_key_path: &str,
+    compression: Compression,
+) -> Result<(), Error> {
+    let plaintext = fs::read(input_path).map_err(|e| Error::io("Failed to read input file", e))?;
+
+    let public_key = read_public_key(public_key_path)?;
 
-    let public_key = PublicKey::from_sec1_bytes(&public_key_bytes)
-        .map_err(|e| format!("Invalid public key: {}", e))?;
+    let options = EncryptOptions {
+        compression,
+        ..Default::default()
+    };
+    let ciphertext = ECCEncryption::encrypt_with_options(&plaintext, &public_key, &options)?;
 
-    let ciphertext = ECCEncryption::encrypt(&plaintext, &public_key)?;
+    write_atomic(output_path, &ciphertext)?;
 
-    fs::write(output_path, ciphertext)
-        .map_err(|e| format!("Failed to write output file: {}", e))?;
+    Ok(())
+}
+
+/// Encrypt a file once for several recipients, sealing the payload with
+/// `algorithm` (see `ECCEncryption::encrypt_for_recipients`)
+pub fn encrypt_file_for_recipients(
+    input_path: &str,
+    output_path: &str,
+    recipients: &[MothrboxPublicKey],
+    algorithm: Algorithm,
+    compression: Compression,
+) -> Result<(), Error> {
+    let plaintext = fs::read(input_path).map_err(|e| Error::io("Failed to read input file", e))?;
+
+    let options = EncryptOptions {
+        compression,
+        ..Default::default()
+    };
+    let ciphertext =
+        ECCEncryption::encrypt_for_recipients(&plaintext, recipients, algorithm, &options)?;
+
+    write_atomic(output_path, &ciphertext)?;
 
     Ok(())
 }
@@ -350,23 +814,184 @@ pub fn decrypt_file(
     input_path: &str,
     output_path: &str,
     private_key_path: &str,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     let ciphertext =
-        fs::read(input_path).map_err(|e| format!("Failed to read encrypted file: {}", e))?;
+        fs::read(input_path).map_err(|e| Error::io("Failed to read encrypted file", e))?;
+
+    let private_key = read_private_key(private_key_path)?;
+
+    // Also opens envelopes wrapped for several recipients
+    let plaintext = crate::decrypt(
+        &ciphertext,
+        &crate::DecryptionKey::EccPrivateKey(&private_key),
+    )?;
+
+    write_atomic(output_path, &plaintext)?;
+
+    Ok(())
+}
+
+/// Decrypt a file written before the MBX1 container header was introduced
+pub fn decrypt_file_legacy(
+    input_path: &str,
+    output_path: &str,
+    private_key_path: &str,
+) -> Result<(), Error> {
+    let ciphertext =
+        fs::read(input_path).map_err(|e| Error::io("Failed to read encrypted file", e))?;
+    let private_key = read_private_key(private_key_path)?;
+
+    let plaintext = ECCEncryption::decrypt_legacy(&ciphertext, &private_key)?;
+
+    write_atomic(output_path, &plaintext)?;
+
+    Ok(())
+}
+
+/// Read a SEC1-encoded P-256 or a raw 32-byte X25519 public key file
+pub fn read_public_key(public_key_path: &str) -> Result<MothrboxPublicKey, Error> {
+    let public_key_bytes =
+        fs::read(public_key_path).map_err(|e| Error::io("Failed to read public key", e))?;
 
-    let private_key_bytes =
-        fs::read(private_key_path).map_err(|e| format!("Failed to read private key: {}", e))?;
+    MothrboxPublicKey::from_bytes(&public_key_bytes)
+}
+
+/// Read a raw 32-byte private key file (either curve)
+pub fn read_private_key(private_key_path: &str) -> Result<LockedKey, Error> {
+    let private_key_bytes = Zeroizing::new(
+        fs::read(private_key_path).map_err(|e| Error::io("Failed to read private key", e))?,
+    );
 
     if private_key_bytes.len() != 32 {
-        return Err("Invalid private key length".to_string());
+        return Err(Error::InvalidKey(
+            "private key must be 32 bytes".to_string(),
+        ));
     }
 
-    let mut private_key = [0u8; 32];
-    private_key.copy_from_slice(&private_key_bytes);
+    LockedKey::from_slice(&private_key_bytes)
+}
 
-    let plaintext = ECCEncryption::decrypt(&ciphertext, &private_key)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seal `plaintext` for a P-256 key as ECIES v1 did before the MBX1
+    /// header: `[len(2)][ephemeral pk][iv(16)][ciphertext][mac(32)]`
+    fn seal_v1(plaintext: &[u8], recipient: &PublicKey) -> Vec<u8> {
+        let ephemeral = SecretKey::random(&mut rand::rngs::OsRng);
+        let ephemeral_public = ephemeral.public_key().to_encoded_point(false);
+        let shared_secret =
+            p256::ecdh::diffie_hellman(ephemeral.to_nonzero_scalar(), recipient.as_affine());
+        let mut key_material = [0u8; 64];
+        Hkdf::<Sha256>::new(None, shared_secret.raw_secret_bytes())
+            .expand(b"ecies-encryption", &mut key_material)
+            .unwrap();
 
-    fs::write(output_path, plaintext).map_err(|e| format!("Failed to write output file: {}", e))?;
+        let iv = [9u8; 16];
+        let mut ciphertext = plaintext.to_vec();
+        Aes256Ctr::new(
+            GenericArray::from_slice(&key_material[..32]),
+            GenericArray::from_slice(&iv),
+        )
+        .apply_keystream(&mut ciphertext);
+        let mut mac = HmacSha256::new_from_slice(&key_material[32..]).unwrap();
+        mac.update(ephemeral_public.as_bytes());
+        mac.update(&iv);
+        mac.update(&ciphertext);
 
-    Ok(())
+        let mut sealed = (ephemeral_public.len() as u16).to_be_bytes().to_vec();
+        sealed.extend_from_slice(ephemeral_public.as_bytes());
+        sealed.extend_from_slice(&iv);
+        sealed.extend_from_slice(&ciphertext);
+        sealed.extend_from_slice(&mac.finalize().into_bytes());
+        sealed
+    }
+
+    #[test]
+    fn test_roundtrip_on_both_curves() {
+        for curve in [Curve::P256, Curve::X25519] {
+            let (private_key, public_key) = curve.generate_keypair();
+            let sealed = ECCEncryption::encrypt(b"attack at dawn", &public_key).unwrap();
+            assert_eq!(
+                ECCEncryption::decrypt(&sealed, &private_key).unwrap(),
+                b"attack at dawn"
+            );
+
+            let (other_key, _) = curve.generate_keypair();
+            assert!(matches!(
+                ECCEncryption::decrypt(&sealed, &other_key),
+                Err(Error::AuthenticationFailed)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_legacy_headerless_input() {
+        let (private_key, public_key) = Curve::P256.generate_keypair();
+        let MothrboxPublicKey::P256(public_key) = public_key else {
+            unreachable!()
+        };
+        let sealed = seal_v1(b"written by v0", &public_key);
+        assert_eq!(
+            ECCEncryption::decrypt_legacy(&sealed, &private_key).unwrap(),
+            b"written by v0"
+        );
+
+        let (other_key, _) = Curve::P256.generate_keypair();
+        assert!(matches!(
+            ECCEncryption::decrypt_legacy(&sealed, &other_key),
+            Err(Error::AuthenticationFailed)
+        ));
+    }
+
+    #[test]
+    fn test_v1_body_too_short_for_iv_and_mac() {
+        let (private_key, public_key) = Curve::P256.generate_keypair();
+        let MothrboxPublicKey::P256(public_key) = public_key else {
+            unreachable!()
+        };
+        // A compressed point leaves 15 bytes of a 50-byte body for IV and MAC
+        let point = public_key.to_encoded_point(true);
+        let mut body = (point.len() as u16).to_be_bytes().to_vec();
+        body.extend_from_slice(point.as_bytes());
+        body.resize(50, 0);
+        assert!(matches!(
+            ECCEncryption::decrypt_legacy(&body, &private_key),
+            Err(Error::Truncated(_))
+        ));
+
+        let mut sealed = Header::new(Algorithm::EciesP256V1, Kdf::None).encode();
+        sealed.extend_from_slice(&body);
+        assert!(matches!(
+            ECCEncryption::decrypt(&sealed, &private_key),
+            Err(Error::Truncated(_))
+        ));
+    }
+
+    #[test]
+    fn test_write_keypair_keeps_existing_private_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let private_path = dir.path().join("private.key");
+        let public_path = dir.path().join("public.key");
+        let (private_path, public_path) = (
+            private_path.to_str().unwrap(),
+            public_path.to_str().unwrap(),
+        );
+
+        let public_key = generate_keypair(Curve::X25519, private_path, public_path).unwrap();
+        let private_key = read_private_key(private_path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(private_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        assert!(matches!(
+            generate_keypair(Curve::X25519, private_path, public_path),
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists
+        ));
+        assert_eq!(*read_private_key(private_path).unwrap(), *private_key);
+        assert_eq!(read_public_key(public_path).unwrap(), public_key);
+    }
 }
@@ -0,0 +1,803 @@
+//! MBX1 container header shared by every MothrBox ciphertext.
+//!
+//! Layout (all integers big-endian):
+//!
+//! ```text
+//! [magic "MBX1"(4)][version(1)][algorithm(1)][kdf(1)][flags(2)][kdf params]
+//! ```
+//!
+//! Algorithm ids: 1 = AES-256-GCM, 2 = ChaCha20-Poly1305, 4 = ECIES v2 on
+//! P-256, 5 = HPKE (see `hpke`), 6 = ECIES on X25519; 3 is ECIES v1, which is
+//! still read but no longer written (see `ecc`).
+//! The KDF parameter section depends on the KDF id (0 = none, 1 = Argon2id,
+//! 2 = Argon2i, 3 = Argon2d). For Argon2 it is
+//! `[m_cost(4)][t_cost(4)][p_cost(4)][salt_len(1)][salt]`; for `None` it is empty.
+//! Decryption always uses the recorded cost, so it can be raised for new data
+//! without breaking old blobs.
+//! Optional sections follow in flag-bit order: `FLAG_STREAM` appends `[chunk_size(4)]`,
+//! `FLAG_COMPRESSED` appends `[codec(1)]` (1 = zstd, 2 = lz4, see `compress`).
+//! The encoded header is authenticated by every algorithm, so tampering with
+//! any header byte makes decryption fail.
+//!
+//! `FLAG_AAD` adds no bytes. It records that the ciphertext was bound to
+//! caller-supplied associated data (a filename, owner address, Sui object id,
+//! Walrus epoch, ...). The AAD itself is not stored and must be passed again
+//! to decrypt.
+//!
+//! `FLAG_MANIFEST` adds no header bytes either: the encrypted payload starts
+//! with the file's manifest (name, type, size, times, hash), see `manifest`.
+//!
+//! `FLAG_CHUNK_LIST` adds no bytes. It marks the payload as the chunk list of
+//! a deduplicated upload (see `dedup`) rather than file contents, so a file
+//! that happens to look like a chunk list is still returned as is.
+//!
+//! `FLAG_RECIPIENTS` marks a multi-recipient envelope: the payload is sealed
+//! under a random data key, wrapped once per recipient in a stanza appended
+//! last as `[count(1)]` then, per recipient,
+//! `[kind(1)][key_id(8)][ephemeral_len(1)][ephemeral public key][wrapped_len(1)][wrapped key]`.
+//! The stanzas are the only header bytes the payload is not bound to (see
+//! `Header::authenticated`), so recipients can be added or removed without
+//! touching the payload. Each wrapped key is an AEAD of its own.
+
+use argon2::{Argon2, Params, Version};
+use rand::{rngs::OsRng, RngCore};
+use std::io::{self, Read};
+
+use super::compress::Codec;
+use super::secret::LockedKey;
+use crate::Error;
+
+pub const MAGIC: &[u8; 4] = b"MBX1";
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Length of the fixed part of the header (magic, version, algorithm, kdf, flags)
+const FIXED_LEN: usize = 9;
+
+/// Length of freshly generated Argon2 salts
+const SALT_LEN: usize = 16;
+
+/// Payload is split into independently authenticated chunks (see `stream`)
+pub const FLAG_STREAM: u16 = 0x0001;
+
+/// Ciphertext is bound to associated data that must be supplied to decrypt
+pub const FLAG_AAD: u16 = 0x0002;
+
+/// Plaintext was compressed before encryption; the codec follows the header
+pub const FLAG_COMPRESSED: u16 = 0x0004;
+
+/// Encrypted payload starts with a manifest describing the original file
+pub const FLAG_MANIFEST: u16 = 0x0008;
+
+/// Data key is wrapped for each of a list of public keys, which follows the header
+pub const FLAG_RECIPIENTS: u16 = 0x0010;
+
+/// Payload is the chunk list of a deduplicated upload, not file contents
+pub const FLAG_CHUNK_LIST: u16 = 0x0020;
+
+/// Bits of `Header::flags` understood by this version of the format
+pub const KNOWN_FLAGS: u16 =
+    FLAG_STREAM | FLAG_AAD | FLAG_COMPRESSED | FLAG_MANIFEST | FLAG_RECIPIENTS | FLAG_CHUNK_LIST;
+
+/// Recipient stanza kind: ephemeral ECDH on P-256
+pub const RECIPIENT_P256: u8 = 1;
+
+/// Recipient stanza kind: ephemeral X25519
+pub const RECIPIENT_X25519: u8 = 2;
+
+/// Encryption algorithm recorded in the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+    /// ECIES v2: HKDF bound to both public keys, then AES-256-GCM
+    EciesP256,
+    /// ECIES v1: AES-256-CTR + HMAC-SHA256 under an unbound HKDF. Decrypt-only.
+    EciesP256V1,
+    /// RFC 9180 HPKE; the suite follows the header
+    Hpke,
+    /// ECIES v2 with X25519 in place of P-256
+    EciesX25519,
+}
+
+impl Algorithm {
+    pub fn id(self) -> u8 {
+        match self {
+            Algorithm::Aes256Gcm => 1,
+            Algorithm::ChaCha20Poly1305 => 2,
+            Algorithm::EciesP256V1 => 3,
+            Algorithm::EciesP256 => 4,
+            Algorithm::Hpke => 5,
+            Algorithm::EciesX25519 => 6,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, Error> {
+        match id {
+            1 => Ok(Algorithm::Aes256Gcm),
+            2 => Ok(Algorithm::ChaCha20Poly1305),
+            3 => Ok(Algorithm::EciesP256V1),
+            4 => Ok(Algorithm::EciesP256),
+            5 => Ok(Algorithm::Hpke),
+            6 => Ok(Algorithm::EciesX25519),
+            _ => Err(Error::Unsupported(format!("algorithm id {}", id))),
+        }
+    }
+
+    /// Short name as used by the CLI and the Nautilus API; every ECIES
+    /// variant is `ecc`
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Aes256Gcm => "aes",
+            Algorithm::ChaCha20Poly1305 => "chacha",
+            Algorithm::EciesP256 | Algorithm::EciesP256V1 | Algorithm::EciesX25519 => "ecc",
+            Algorithm::Hpke => "hpke",
+        }
+    }
+
+    /// Encrypts under a password or raw key (AES / ChaCha)
+    pub fn is_symmetric(self) -> bool {
+        matches!(self, Algorithm::Aes256Gcm | Algorithm::ChaCha20Poly1305)
+    }
+
+    /// Encrypts to a P-256 or X25519 public key (any ECIES variant)
+    pub fn is_ecies(self) -> bool {
+        matches!(
+            self,
+            Algorithm::EciesP256 | Algorithm::EciesP256V1 | Algorithm::EciesX25519
+        )
+    }
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = Error;
+
+    /// `aes`, `chacha` or `ecc`, as printed by `name`
+    fn from_str(name: &str) -> Result<Self, Error> {
+        match name {
+            "aes" => Ok(Algorithm::Aes256Gcm),
+            "chacha" => Ok(Algorithm::ChaCha20Poly1305),
+            "ecc" => Ok(Algorithm::EciesP256),
+            _ => Err(Error::InvalidInput(format!(
+                "unknown algorithm '{}' (use aes, chacha or ecc)",
+                name
+            ))),
+        }
+    }
+}
+
+/// Argon2 variant; each has its own KDF id in the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KdfAlgorithm {
+    Argon2id,
+    Argon2i,
+    Argon2d,
+}
+
+impl KdfAlgorithm {
+    fn id(self) -> u8 {
+        match self {
+            KdfAlgorithm::Argon2id => 1,
+            KdfAlgorithm::Argon2i => 2,
+            KdfAlgorithm::Argon2d => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(KdfAlgorithm::Argon2id),
+            2 => Some(KdfAlgorithm::Argon2i),
+            3 => Some(KdfAlgorithm::Argon2d),
+            _ => None,
+        }
+    }
+
+    /// Lowercase name, e.g. `argon2id`
+    pub fn name(self) -> &'static str {
+        match self {
+            KdfAlgorithm::Argon2id => "argon2id",
+            KdfAlgorithm::Argon2i => "argon2i",
+            KdfAlgorithm::Argon2d => "argon2d",
+        }
+    }
+
+    fn to_argon2(self) -> argon2::Algorithm {
+        match self {
+            KdfAlgorithm::Argon2id => argon2::Algorithm::Argon2id,
+            KdfAlgorithm::Argon2i => argon2::Algorithm::Argon2i,
+            KdfAlgorithm::Argon2d => argon2::Algorithm::Argon2d,
+        }
+    }
+}
+
+/// Largest Argon2 memory cost accepted, in KiB (4 GiB). Bounds what a
+/// crafted header can make the decrypting side allocate.
+pub const MAX_M_COST: u32 = 4 * 1024 * 1024;
+
+/// Largest Argon2 iteration count accepted
+pub const MAX_T_COST: u32 = 64;
+
+/// Largest Argon2 lane count accepted
+pub const MAX_P_COST: u32 = 64;
+
+/// Password hashing cost, recorded in every password-protected container
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub algorithm: KdfAlgorithm,
+    /// Memory cost in KiB
+    pub m_cost: u32,
+    /// Number of passes over memory
+    pub t_cost: u32,
+    /// Degree of parallelism (lanes)
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    /// The argon2 crate defaults (19 MiB, 2 passes, 1 lane), used before
+    /// parameters were configurable
+    fn default() -> Self {
+        Self {
+            algorithm: KdfAlgorithm::Argon2id,
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Argon2id with the cost of a named profile
+    pub fn profile(profile: KdfProfile) -> Self {
+        let (m_cost, t_cost) = match profile {
+            KdfProfile::Interactive => (64 * 1024, 2),
+            KdfProfile::Moderate => (256 * 1024, 3),
+            KdfProfile::Sensitive => (1024 * 1024, 4),
+        };
+        Self {
+            algorithm: KdfAlgorithm::Argon2id,
+            m_cost,
+            t_cost,
+            p_cost: 1,
+        }
+    }
+
+    /// Check the parameters are usable and within the limits this build accepts
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.m_cost > MAX_M_COST || self.t_cost > MAX_T_COST || self.p_cost > MAX_P_COST {
+            return Err(Error::KeyDerivation(format!(
+                "Argon2 cost exceeds limits (memory {} KiB max, {} iterations max, {} lanes max)",
+                MAX_M_COST, MAX_T_COST, MAX_P_COST
+            )));
+        }
+        self.to_argon2().map(|_| ())
+    }
+
+    fn to_argon2(self) -> Result<Params, Error> {
+        Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| Error::KeyDerivation(format!("invalid Argon2 parameters: {}", e)))
+    }
+}
+
+/// Named Argon2id cost presets, matching libsodium's opslimit/memlimit levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KdfProfile {
+    /// 64 MiB, 2 passes: for keys derived while a user waits
+    Interactive,
+    /// 256 MiB, 3 passes
+    Moderate,
+    /// 1 GiB, 4 passes: for long-term archives
+    Sensitive,
+}
+
+impl std::str::FromStr for KdfProfile {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Error> {
+        match name {
+            "interactive" => Ok(KdfProfile::Interactive),
+            "moderate" => Ok(KdfProfile::Moderate),
+            "sensitive" => Ok(KdfProfile::Sensitive),
+            _ => Err(Error::InvalidInput(format!(
+                "unknown KDF profile '{}' (use interactive, moderate or sensitive)",
+                name
+            ))),
+        }
+    }
+}
+
+/// Key derivation recorded in the header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kdf {
+    /// Key supplied directly (raw key or public-key encryption)
+    None,
+    /// Password hashed with Argon2
+    Argon2 { params: KdfParams, salt: Vec<u8> },
+}
+
+impl Kdf {
+    pub fn id(&self) -> u8 {
+        match self {
+            Kdf::None => 0,
+            Kdf::Argon2 { params, .. } => params.algorithm.id(),
+        }
+    }
+
+    /// Argon2 with the given cost and a fresh random salt
+    pub fn argon2(params: KdfParams) -> Self {
+        let mut salt = vec![0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Kdf::Argon2 { params, salt }
+    }
+
+    /// Derive a 256-bit key from `password` using the recorded parameters
+    pub fn derive_key(&self, password: &str) -> Result<LockedKey, Error> {
+        match self {
+            Kdf::None => Err(Error::InvalidKey(
+                "container is raw-key encrypted and does not take a password".to_string(),
+            )),
+            Kdf::Argon2 { params, salt } => {
+                params.validate()?;
+                let argon2 = Argon2::new(
+                    params.algorithm.to_argon2(),
+                    Version::V0x13,
+                    params.to_argon2()?,
+                );
+
+                let mut key_bytes = LockedKey::zeroed();
+                argon2
+                    .hash_password_into(password.as_bytes(), salt, &mut key_bytes[..])
+                    .map_err(|e| Error::KeyDerivation(e.to_string()))?;
+                Ok(key_bytes)
+            }
+        }
+    }
+}
+
+/// One recipient's copy of an envelope's data key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recipient {
+    /// Key agreement used, e.g. `RECIPIENT_P256`
+    pub kind: u8,
+    /// Truncated hash of the recipient's public key, so its stanza is tried first
+    pub key_id: [u8; 8],
+    pub ephemeral_public_key: Vec<u8>,
+    /// Data key sealed under the key agreed with the recipient
+    pub wrapped_key: Vec<u8>,
+}
+
+/// Parsed MBX1 header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub algorithm: Algorithm,
+    pub kdf: Kdf,
+    pub flags: u16,
+    /// Plaintext bytes per chunk, present when `FLAG_STREAM` is set
+    pub chunk_size: Option<u32>,
+    /// Codec the plaintext was compressed with, `Codec::None` unless `FLAG_COMPRESSED`
+    pub codec: Codec,
+    /// Wrapped data keys, present when `FLAG_RECIPIENTS` is set
+    pub recipients: Vec<Recipient>,
+}
+
+impl Header {
+    pub fn new(algorithm: Algorithm, kdf: Kdf) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            algorithm,
+            kdf,
+            flags: 0,
+            chunk_size: None,
+            codec: Codec::None,
+            recipients: Vec::new(),
+        }
+    }
+
+    /// Header for the chunked streaming format
+    pub fn new_stream(algorithm: Algorithm, kdf: Kdf, chunk_size: u32) -> Self {
+        Self {
+            flags: FLAG_STREAM,
+            chunk_size: Some(chunk_size),
+            ..Self::new(algorithm, kdf)
+        }
+    }
+
+    /// Record that the ciphertext is bound to `aad` (no-op when it is empty)
+    pub fn with_aad(mut self, aad: &[u8]) -> Self {
+        if !aad.is_empty() {
+            self.flags |= FLAG_AAD;
+        }
+        self
+    }
+
+    /// Record that the plaintext was compressed with `codec` (no-op for `Codec::None`)
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        if codec != Codec::None {
+            self.flags |= FLAG_COMPRESSED;
+        }
+        self.codec = codec;
+        self
+    }
+
+    /// Record that the payload starts with a manifest (no-op when `present` is false)
+    pub fn with_manifest(mut self, present: bool) -> Self {
+        if present {
+            self.flags |= FLAG_MANIFEST;
+        }
+        self
+    }
+
+    /// Record that the payload is a chunk list (no-op when `chunk_list` is false)
+    pub fn with_chunk_list(mut self, chunk_list: bool) -> Self {
+        if chunk_list {
+            self.flags |= FLAG_CHUNK_LIST;
+        }
+        self
+    }
+
+    /// Record the data key wrapped for each recipient (no-op when there are none)
+    pub fn with_recipients(mut self, recipients: Vec<Recipient>) -> Self {
+        if !recipients.is_empty() {
+            self.flags |= FLAG_RECIPIENTS;
+        }
+        self.recipients = recipients;
+        self
+    }
+
+    /// Serialize the header
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(FIXED_LEN + 32);
+        out.extend_from_slice(MAGIC);
+        out.push(self.version);
+        out.push(self.algorithm.id());
+        out.push(self.kdf.id());
+        out.extend_from_slice(&self.flags.to_be_bytes());
+
+        if let Kdf::Argon2 { params, salt } = &self.kdf {
+            out.extend_from_slice(&params.m_cost.to_be_bytes());
+            out.extend_from_slice(&params.t_cost.to_be_bytes());
+            out.extend_from_slice(&params.p_cost.to_be_bytes());
+            out.push(salt.len() as u8);
+            out.extend_from_slice(salt);
+        }
+
+        if let Some(chunk_size) = self.chunk_size {
+            out.extend_from_slice(&chunk_size.to_be_bytes());
+        }
+
+        if self.flags & FLAG_COMPRESSED != 0 {
+            out.push(self.codec.id());
+        }
+
+        if self.flags & FLAG_RECIPIENTS != 0 {
+            out.push(self.recipients.len() as u8);
+            for recipient in &self.recipients {
+                out.push(recipient.kind);
+                out.extend_from_slice(&recipient.key_id);
+                out.push(recipient.ephemeral_public_key.len() as u8);
+                out.extend_from_slice(&recipient.ephemeral_public_key);
+                out.push(recipient.wrapped_key.len() as u8);
+                out.extend_from_slice(&recipient.wrapped_key);
+            }
+        }
+
+        out
+    }
+
+    /// The header bytes the payload is bound to: all of them, except that the
+    /// recipient list is encoded as empty
+    pub fn authenticated(&self) -> Vec<u8> {
+        if self.recipients.is_empty() {
+            return self.encode();
+        }
+        Header {
+            recipients: Vec::new(),
+            ..self.clone()
+        }
+        .encode()
+    }
+
+    /// Parse a header from the start of `data`.
+    /// Returns the header and the number of bytes it occupies.
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), Error> {
+        let mut remaining = data;
+        let header = Self::read_from(&mut remaining)?;
+        Ok((header, data.len() - remaining.len()))
+    }
+
+    /// Read a header from the start of a stream, consuming exactly its bytes
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let mut fixed = [0u8; FIXED_LEN];
+        read_header_bytes(reader, &mut fixed[..MAGIC.len()])?;
+        if !is_container(&fixed) {
+            return Err(Error::MalformedHeader(
+                "not a MothrBox container (missing MBX1 header)".to_string(),
+            ));
+        }
+        read_header_bytes(reader, &mut fixed[MAGIC.len()..])?;
+
+        let version = fixed[4];
+        if version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let algorithm = Algorithm::from_id(fixed[5])?;
+        let kdf_id = fixed[6];
+        let flags = u16::from_be_bytes([fixed[7], fixed[8]]);
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(Error::Unsupported(format!(
+                "container flags {:#06x}",
+                flags
+            )));
+        }
+
+        let kdf = match (kdf_id, KdfAlgorithm::from_id(kdf_id)) {
+            (0, _) => Kdf::None,
+            (_, Some(algorithm)) => {
+                let mut params = [0u8; 13];
+                read_header_bytes(reader, &mut params)?;
+                let read_u32 = |at: usize| {
+                    u32::from_be_bytes([params[at], params[at + 1], params[at + 2], params[at + 3]])
+                };
+
+                let mut salt = vec![0u8; params[12] as usize];
+                read_header_bytes(reader, &mut salt)?;
+
+                Kdf::Argon2 {
+                    params: KdfParams {
+                        algorithm,
+                        m_cost: read_u32(0),
+                        t_cost: read_u32(4),
+                        p_cost: read_u32(8),
+                    },
+                    salt,
+                }
+            }
+            _ => return Err(Error::Unsupported(format!("KDF id {}", kdf_id))),
+        };
+
+        let chunk_size = if flags & FLAG_STREAM != 0 {
+            let mut size = [0u8; 4];
+            read_header_bytes(reader, &mut size)?;
+            let size = u32::from_be_bytes(size);
+            if size == 0 {
+                return Err(Error::MalformedHeader("stream chunk size is 0".to_string()));
+            }
+            Some(size)
+        } else {
+            None
+        };
+
+        if algorithm == Algorithm::Hpke && flags & (FLAG_STREAM | FLAG_RECIPIENTS) != 0 {
+            return Err(Error::Unsupported(
+                "streamed or multi-recipient HPKE".to_string(),
+            ));
+        }
+
+        if flags & FLAG_MANIFEST != 0 && flags & FLAG_STREAM != 0 {
+            return Err(Error::Unsupported("manifests in streams".to_string()));
+        }
+        if flags & FLAG_CHUNK_LIST != 0 && flags & FLAG_STREAM != 0 {
+            return Err(Error::Unsupported("chunk lists in streams".to_string()));
+        }
+
+        let codec = if flags & FLAG_COMPRESSED != 0 {
+            if flags & FLAG_STREAM != 0 {
+                return Err(Error::Unsupported("compressed streams".to_string()));
+            }
+            let mut id = [0u8; 1];
+            read_header_bytes(reader, &mut id)?;
+            match Codec::from_id(id[0])? {
+                Codec::None => {
+                    return Err(Error::MalformedHeader(
+                        "compression flag set without a codec".to_string(),
+                    ))
+                }
+                codec => codec,
+            }
+        } else {
+            Codec::None
+        };
+
+        let mut recipients = Vec::new();
+        if flags & FLAG_RECIPIENTS != 0 {
+            if !algorithm.is_symmetric() || kdf != Kdf::None {
+                return Err(Error::MalformedHeader(
+                    "recipients on a password or ECIES container".to_string(),
+                ));
+            }
+            let mut count = [0u8; 1];
+            read_header_bytes(reader, &mut count)?;
+            if count[0] == 0 {
+                return Err(Error::MalformedHeader("empty recipient list".to_string()));
+            }
+            for _ in 0..count[0] {
+                let mut fixed = [0u8; 10];
+                read_header_bytes(reader, &mut fixed)?;
+                let mut ephemeral_public_key = vec![0u8; fixed[9] as usize];
+                read_header_bytes(reader, &mut ephemeral_public_key)?;
+                let mut len = [0u8; 1];
+                read_header_bytes(reader, &mut len)?;
+                let mut wrapped_key = vec![0u8; len[0] as usize];
+                read_header_bytes(reader, &mut wrapped_key)?;
+                recipients.push(Recipient {
+                    kind: fixed[0],
+                    key_id: fixed[1..9].try_into().expect("8 bytes"),
+                    ephemeral_public_key,
+                    wrapped_key,
+                });
+            }
+        }
+
+        Ok(Header {
+            version,
+            algorithm,
+            kdf,
+            flags,
+            chunk_size,
+            codec,
+            recipients,
+        })
+    }
+
+    /// True if the payload uses the chunked streaming format
+    pub fn is_stream(&self) -> bool {
+        self.flags & FLAG_STREAM != 0
+    }
+
+    /// True if the payload starts with a manifest
+    pub fn has_manifest(&self) -> bool {
+        self.flags & FLAG_MANIFEST != 0
+    }
+
+    /// True if the payload is a deduplicated upload's chunk list
+    pub fn is_chunk_list(&self) -> bool {
+        self.flags & FLAG_CHUNK_LIST != 0
+    }
+
+    /// True if the data key is wrapped for public-key recipients
+    pub fn has_recipients(&self) -> bool {
+        self.flags & FLAG_RECIPIENTS != 0
+    }
+
+    /// True if decryption needs the associated data used to encrypt
+    pub fn requires_aad(&self) -> bool {
+        self.flags & FLAG_AAD != 0
+    }
+
+    /// Check the caller's associated data against the header before decrypting.
+    /// Fails early with a clear message instead of a bare authentication error,
+    /// and rejects unbound ciphertext when the caller expects a binding.
+    pub fn check_aad(&self, aad: &[u8]) -> Result<(), Error> {
+        match (self.requires_aad(), aad.is_empty()) {
+            (true, true) => Err(Error::InvalidInput(
+                "ciphertext is bound to associated data, which must be supplied to decrypt"
+                    .to_string(),
+            )),
+            (false, false) => Err(Error::InvalidInput(
+                "ciphertext is not bound to the given associated data".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// AEAD associated data: the encoded header followed by the caller's context
+pub fn associated_data(header_bytes: &[u8], aad: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(header_bytes.len() + aad.len());
+    out.extend_from_slice(header_bytes);
+    out.extend_from_slice(aad);
+    out
+}
+
+fn read_header_bytes<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), Error> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => {
+            Error::MalformedHeader("container header truncated".to_string())
+        }
+        _ => Error::io("Failed to read container header", e),
+    })
+}
+
+/// Returns true if `data` starts with the MBX1 magic bytes
+pub fn is_container(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = Header::new(
+            Algorithm::ChaCha20Poly1305,
+            Kdf::Argon2 {
+                params: KdfParams {
+                    algorithm: KdfAlgorithm::Argon2i,
+                    m_cost: 19456,
+                    t_cost: 2,
+                    p_cost: 1,
+                },
+                salt: vec![7u8; 16],
+            },
+        );
+        let mut encoded = header.encode();
+        let header_len = encoded.len();
+        encoded.extend_from_slice(b"body");
+
+        let (parsed, consumed) = Header::parse(&encoded).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(consumed, header_len);
+
+        let compressed = Header::new(Algorithm::EciesP256, Kdf::None).with_codec(Codec::Lz4);
+        let encoded = compressed.encode();
+        assert_eq!(
+            Header::parse(&encoded).unwrap(),
+            (compressed, encoded.len())
+        );
+
+        // Recipient stanzas round-trip, but are left out of the bound bytes
+        let recipient = Recipient {
+            kind: RECIPIENT_P256,
+            key_id: [1; 8],
+            ephemeral_public_key: vec![4; 65],
+            wrapped_key: vec![9; 48],
+        };
+        let envelope = |recipients: usize| {
+            Header::new(Algorithm::Aes256Gcm, Kdf::None)
+                .with_codec(Codec::Lz4)
+                .with_recipients(vec![recipient.clone(); recipients])
+        };
+        let encoded = envelope(2).encode();
+        assert_eq!(
+            Header::parse(&encoded).unwrap(),
+            (envelope(2), encoded.len())
+        );
+        assert_eq!(envelope(2).authenticated(), envelope(1).authenticated());
+        assert!(matches!(
+            Header::parse(&envelope(2).authenticated()),
+            Err(Error::MalformedHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_header_rejects_unknown_fields() {
+        let mut encoded = Header::new(Algorithm::EciesP256, Kdf::None).encode();
+        assert!(Header::parse(&encoded[..6]).is_err());
+
+        encoded[4] = 99;
+        assert!(Header::parse(&encoded).is_err());
+
+        encoded[4] = FORMAT_VERSION;
+        encoded[5] = 3;
+        assert_eq!(
+            Header::parse(&encoded).unwrap().0.algorithm,
+            Algorithm::EciesP256V1
+        );
+        encoded[5] = 42;
+        assert!(Header::parse(&encoded).is_err());
+
+        assert!(Header::parse(b"not a container").is_err());
+    }
+
+    #[test]
+    fn test_kdf_params_are_bounded() {
+        let params: KdfParams = KdfParams::profile("sensitive".parse().unwrap());
+        assert_eq!(params.m_cost, 1024 * 1024);
+        assert!(params.validate().is_ok());
+        assert!("paranoid".parse::<KdfProfile>().is_err());
+
+        // A crafted header must not be able to demand unbounded memory
+        let hostile = Kdf::Argon2 {
+            params: KdfParams {
+                m_cost: MAX_M_COST + 1,
+                ..KdfParams::default()
+            },
+            salt: vec![0u8; 16],
+        };
+        assert!(matches!(
+            hostile.derive_key("pw"),
+            Err(Error::KeyDerivation(_))
+        ));
+    }
+}
@@ -0,0 +1,567 @@
+//! `Read` / `Write` adapters so callers can encrypt into sockets, pipes or
+//! HTTP bodies without temp files.
+//!
+//! `EncryptWriter` always produces the chunked streaming format (see `stream`).
+//! `DecryptReader` auto-detects the algorithm and also accepts single-shot
+//! containers, which it has to buffer in full before authenticating.
+
+use std::io::{self, Read, Write};
+
+use super::ecc::{Curve, ECCEncryption};
+use super::header::{Header, Kdf};
+use super::secret::{LockedKey, Zeroizing};
+use super::stream::{
+    StreamDecryptor, StreamEncryptor, DEFAULT_CHUNK_SIZE, MAX_CHUNK_SIZE, STREAM_SALT_LEN,
+};
+use crate::{DecryptionKey, EncryptionKey, Error};
+
+/// Ciphertext bytes pulled from the inner reader per read call
+pub(crate) const READ_BUF_SIZE: usize = 64 * 1024;
+
+/// Build the stream encryptor and the preamble (header, key prefix, stream salt)
+fn start_stream(
+    key: &EncryptionKey,
+    chunk_size: u32,
+    aad: &[u8],
+) -> Result<(StreamEncryptor, Vec<u8>), Error> {
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(Error::InvalidInput(format!(
+            "Chunk size must be between 1 and {} bytes",
+            MAX_CHUNK_SIZE
+        )));
+    }
+
+    let (header, key_prefix, stream_key) = match key {
+        EncryptionKey::Password {
+            algorithm,
+            password,
+            kdf,
+        } => {
+            if !algorithm.is_symmetric() {
+                return Err(Error::InvalidKey(format!(
+                    "'{}' encrypts to a public key, not a password",
+                    algorithm.name()
+                )));
+            }
+            let header =
+                Header::new_stream(*algorithm, Kdf::argon2(*kdf), chunk_size).with_aad(aad);
+            let stream_key = header.kdf.derive_key(password)?;
+            (header, Vec::new(), stream_key)
+        }
+        EncryptionKey::RawKey { algorithm, key } => {
+            if !algorithm.is_symmetric() {
+                return Err(Error::InvalidKey(format!(
+                    "'{}' encrypts to a public key, not a raw key",
+                    algorithm.name()
+                )));
+            }
+            let header = Header::new_stream(*algorithm, Kdf::None, chunk_size).with_aad(aad);
+            (header, Vec::new(), LockedKey::from(*key))
+        }
+        EncryptionKey::EccPublicKey(public_key) => {
+            let header = Header::new_stream(public_key.curve().algorithm(), Kdf::None, chunk_size)
+                .with_aad(aad);
+            let (key_prefix, stream_key) = ECCEncryption::stream_key_for_recipient(public_key)?;
+            (header, key_prefix, stream_key)
+        }
+        EncryptionKey::EccRecipients {
+            algorithm,
+            recipients,
+        } => {
+            if !algorithm.is_symmetric() {
+                return Err(Error::InvalidInput(
+                    "envelope payloads are sealed with aes or chacha".to_string(),
+                ));
+            }
+            let data_key = ECCEncryption::random_data_key();
+            let header = Header::new_stream(*algorithm, Kdf::None, chunk_size)
+                .with_recipients(ECCEncryption::wrap_for_all(&data_key, recipients)?)
+                .with_aad(aad);
+            (header, Vec::new(), data_key)
+        }
+    };
+
+    let (encryptor, stream_salt) = StreamEncryptor::new(&header, &stream_key, aad)?;
+    let mut preamble = header.encode();
+    preamble.extend_from_slice(&key_prefix);
+    preamble.extend_from_slice(&stream_salt);
+    Ok((encryptor, preamble))
+}
+
+/// IO-independent encryption state shared by the sync and async writers
+pub(crate) struct EncryptCore {
+    encryptor: StreamEncryptor,
+    /// Plaintext not yet sealed; never more than one chunk after `accept`
+    plaintext: Vec<u8>,
+    /// Sealed bytes waiting to be written to the inner writer
+    pub(crate) pending: Vec<u8>,
+    pub(crate) finished: bool,
+}
+
+impl EncryptCore {
+    pub(crate) fn new(key: &EncryptionKey, chunk_size: u32, aad: &[u8]) -> Result<Self, Error> {
+        let (encryptor, preamble) = start_stream(key, chunk_size, aad)?;
+        Ok(Self {
+            encryptor,
+            plaintext: Vec::new(),
+            pending: preamble,
+            finished: false,
+        })
+    }
+
+    /// Take up to one chunk of `buf`, sealing every chunk known not to be the last.
+    /// Returns the number of bytes consumed.
+    pub(crate) fn accept(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if self.finished {
+            return Err(Error::InvalidInput("write after finish".to_string()));
+        }
+
+        let chunk_size = self.encryptor.chunk_size();
+        let room = chunk_size + 1 - self.plaintext.len();
+        let taken = buf.len().min(room);
+        self.plaintext.extend_from_slice(&buf[..taken]);
+
+        // A chunk is only sealed as non-final once a byte beyond it has arrived
+        if self.plaintext.len() > chunk_size {
+            let sealed = self
+                .encryptor
+                .seal_chunk(&self.plaintext[..chunk_size], false)?;
+            self.pending.extend_from_slice(&sealed);
+            self.plaintext.drain(..chunk_size);
+        }
+        Ok(taken)
+    }
+
+    /// Seal the final chunk
+    pub(crate) fn finish(&mut self) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        let sealed = self.encryptor.seal_chunk(&self.plaintext, true)?;
+        self.pending.extend_from_slice(&sealed);
+        self.plaintext.clear();
+        self.finished = true;
+        Ok(())
+    }
+}
+
+/// Encrypts everything written to it into the inner writer.
+///
+/// `finish` must be called once all plaintext has been written: it seals the
+/// final chunk, without which the output is rejected as truncated. Dropping
+/// the writer does not finish it, so an aborted write never looks complete.
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    core: EncryptCore,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    pub fn new(inner: W, key: &EncryptionKey) -> Result<Self, Error> {
+        Self::with_chunk_size(inner, key, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(inner: W, key: &EncryptionKey, chunk_size: u32) -> Result<Self, Error> {
+        Self::with_aad(inner, key, chunk_size, &[])
+    }
+
+    /// Bind the ciphertext to `aad`, which the reader must supply again
+    pub fn with_aad(
+        inner: W,
+        key: &EncryptionKey,
+        chunk_size: u32,
+        aad: &[u8],
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            inner,
+            core: EncryptCore::new(key, chunk_size, aad)?,
+        })
+    }
+
+    /// Seal the final chunk, flush, and return the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.core.finish()?;
+        self.write_pending()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        if !self.core.pending.is_empty() {
+            self.inner.write_all(&self.core.pending)?;
+            self.core.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let taken = self.core.accept(buf)?;
+        self.write_pending()?;
+        Ok(taken)
+    }
+
+    /// Flushes sealed chunks only; buffered plaintext stays until the chunk fills
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.inner.flush()
+    }
+}
+
+/// Owned copy of the caller's key, kept until the header has been read
+pub(crate) enum Secret {
+    Password(Zeroizing<String>),
+    RawKey(LockedKey),
+    EccPrivateKey(LockedKey),
+}
+
+impl Secret {
+    pub(crate) fn from_key(key: &DecryptionKey) -> Self {
+        match key {
+            DecryptionKey::Password(password) => {
+                Secret::Password(Zeroizing::new(password.to_string()))
+            }
+            DecryptionKey::RawKey(key) => Secret::RawKey(LockedKey::from(*key)),
+            DecryptionKey::EccPrivateKey(key) => Secret::EccPrivateKey(LockedKey::from(*key)),
+        }
+    }
+
+    fn as_key(&self) -> DecryptionKey<'_> {
+        match self {
+            Secret::Password(password) => DecryptionKey::Password(password),
+            Secret::RawKey(key) => DecryptionKey::RawKey(key),
+            Secret::EccPrivateKey(key) => DecryptionKey::EccPrivateKey(key),
+        }
+    }
+
+    /// Derive the stream key for a parsed preamble
+    fn stream_key(&self, header: &Header, ephemeral_public: &[u8]) -> Result<LockedKey, Error> {
+        match (header.algorithm, self) {
+            (_, Secret::EccPrivateKey(private_key)) if header.has_recipients() => {
+                ECCEncryption::unwrap_key(&header.recipients, private_key)
+            }
+            (_, _) if header.has_recipients() => Err(Error::InvalidKey(
+                "ciphertext was encrypted for ECC recipients and needs a private key".to_string(),
+            )),
+            (algorithm, Secret::EccPrivateKey(private_key)) if algorithm.is_ecies() => {
+                let curve = Curve::of(algorithm).expect("every ECIES algorithm has a curve");
+                ECCEncryption::stream_key_from_private_key(curve, ephemeral_public, private_key)
+            }
+            (algorithm, _) if algorithm.is_ecies() => Err(Error::InvalidKey(
+                "ciphertext was encrypted with 'ecc' and needs a private key".to_string(),
+            )),
+            (_, Secret::Password(password)) => header.kdf.derive_key(password),
+            (_, Secret::RawKey(key)) if header.kdf == Kdf::None => Ok(key.clone()),
+            (_, Secret::RawKey(_)) => Err(Error::InvalidKey(
+                "container is password-protected, not raw-key encrypted".to_string(),
+            )),
+            (algorithm, Secret::EccPrivateKey(_)) => Err(Error::InvalidKey(format!(
+                "ciphertext was encrypted with '{}' and needs a password",
+                algorithm.name()
+            ))),
+        }
+    }
+}
+
+/// Everything before the first chunk of a stream
+struct Preamble {
+    header: Header,
+    ephemeral_public: Vec<u8>,
+    stream_salt: [u8; STREAM_SALT_LEN],
+}
+
+fn read_preamble<R: Read>(reader: &mut R) -> Result<Preamble, Error> {
+    let header = Header::read_from(reader)?;
+    let mut ephemeral_public = Vec::new();
+    let mut stream_salt = [0u8; STREAM_SALT_LEN];
+    if !header.is_stream() {
+        return Ok(Preamble {
+            header,
+            ephemeral_public,
+            stream_salt,
+        });
+    }
+
+    let truncated = |_| Error::Truncated("incomplete stream preamble".to_string());
+    if header.algorithm.is_ecies() {
+        let mut len = [0u8; 2];
+        reader.read_exact(&mut len).map_err(truncated)?;
+        ephemeral_public.resize(u16::from_be_bytes(len) as usize, 0);
+        reader
+            .read_exact(&mut ephemeral_public)
+            .map_err(truncated)?;
+    }
+    reader.read_exact(&mut stream_salt).map_err(truncated)?;
+
+    Ok(Preamble {
+        header,
+        ephemeral_public,
+        stream_salt,
+    })
+}
+
+/// Slice reader that records whether a parse ran out of input
+struct Probe<'a> {
+    data: &'a [u8],
+    exhausted: bool,
+}
+
+impl Read for Probe<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.data.is_empty() && !buf.is_empty() {
+            self.exhausted = true;
+        }
+        self.data.read(buf)
+    }
+}
+
+/// Result of driving a `DecryptCore`
+pub(crate) enum Step {
+    Plaintext(Vec<u8>),
+    NeedInput,
+    Done,
+}
+
+enum DecryptState {
+    Preamble,
+    Stream(StreamDecryptor),
+    Done,
+}
+
+/// IO-independent decryption state shared by the sync and async readers.
+/// Ciphertext is pushed in, authenticated plaintext chunks are pulled out.
+pub(crate) struct DecryptCore {
+    secret: Secret,
+    aad: Vec<u8>,
+    ciphertext: Vec<u8>,
+    input_done: bool,
+    state: DecryptState,
+}
+
+impl DecryptCore {
+    pub(crate) fn new(secret: Secret, aad: &[u8]) -> Self {
+        Self {
+            secret,
+            aad: aad.to_vec(),
+            ciphertext: Vec::new(),
+            input_done: false,
+            state: DecryptState::Preamble,
+        }
+    }
+
+    pub(crate) fn push(&mut self, data: &[u8]) {
+        self.ciphertext.extend_from_slice(data);
+    }
+
+    pub(crate) fn end_of_input(&mut self) {
+        self.input_done = true;
+    }
+
+    pub(crate) fn step(&mut self) -> Result<Step, Error> {
+        loop {
+            match &mut self.state {
+                DecryptState::Preamble => {
+                    let mut probe = Probe {
+                        data: &self.ciphertext,
+                        exhausted: false,
+                    };
+                    let preamble = match read_preamble(&mut probe) {
+                        Ok(preamble) => preamble,
+                        Err(_) if probe.exhausted && !self.input_done => {
+                            return Ok(Step::NeedInput)
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    let consumed = self.ciphertext.len() - probe.data.len();
+
+                    if !preamble.header.is_stream() {
+                        // Single-shot container: authenticate only once it is complete
+                        if !self.input_done {
+                            return Ok(Step::NeedInput);
+                        }
+                        let plaintext = crate::decrypt_with_aad(
+                            &self.ciphertext,
+                            &self.secret.as_key(),
+                            &self.aad,
+                        )?;
+                        self.ciphertext.clear();
+                        self.state = DecryptState::Done;
+                        return Ok(Step::Plaintext(plaintext));
+                    }
+
+                    preamble.header.check_aad(&self.aad)?;
+                    let key = self
+                        .secret
+                        .stream_key(&preamble.header, &preamble.ephemeral_public)?;
+                    let decryptor = StreamDecryptor::from_salt(
+                        &preamble.header,
+                        &key,
+                        &preamble.stream_salt,
+                        &self.aad,
+                    )?;
+                    self.ciphertext.drain(..consumed);
+                    self.state = DecryptState::Stream(decryptor);
+                }
+                DecryptState::Stream(decryptor) => {
+                    let full = decryptor.encrypted_chunk_size();
+                    if self.ciphertext.len() > full {
+                        let plaintext = decryptor.open_chunk(&self.ciphertext[..full], false)?;
+                        self.ciphertext.drain(..full);
+                        return Ok(Step::Plaintext(plaintext));
+                    }
+                    if !self.input_done {
+                        return Ok(Step::NeedInput);
+                    }
+                    if self.ciphertext.is_empty() {
+                        return Err(Error::Truncated("missing final chunk".to_string()));
+                    }
+                    let plaintext = decryptor.open_chunk(&self.ciphertext, true)?;
+                    self.ciphertext.clear();
+                    self.state = DecryptState::Done;
+                    return Ok(Step::Plaintext(plaintext));
+                }
+                DecryptState::Done => {
+                    if !self.ciphertext.is_empty() {
+                        return Err(Error::MalformedHeader(
+                            "unexpected data after end of stream".to_string(),
+                        ));
+                    }
+                    if !self.input_done {
+                        return Ok(Step::NeedInput);
+                    }
+                    return Ok(Step::Done);
+                }
+            }
+        }
+    }
+}
+
+/// Decrypts a MothrBox container read from the inner reader.
+///
+/// Streamed containers yield plaintext chunk by chunk, each authenticated
+/// before it is returned; a truncated or tampered stream surfaces as an
+/// `InvalidData` error, and data already read must then be discarded.
+pub struct DecryptReader<R: Read> {
+    inner: R,
+    core: DecryptCore,
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecryptReader<R> {
+    pub fn new(inner: R, key: &DecryptionKey) -> Self {
+        Self::with_aad(inner, key, &[])
+    }
+
+    /// Decrypt a container that was bound to `aad`
+    pub fn with_aad(inner: R, key: &DecryptionKey, aad: &[u8]) -> Self {
+        Self {
+            inner,
+            core: DecryptCore::new(Secret::from_key(key), aad),
+            plaintext: Vec::new(),
+            position: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = Vec::new();
+        loop {
+            if self.position < self.plaintext.len() {
+                let available = &self.plaintext[self.position..];
+                let n = available.len().min(buf.len());
+                buf[..n].copy_from_slice(&available[..n]);
+                self.position += n;
+                return Ok(n);
+            }
+
+            match self.core.step()? {
+                Step::Plaintext(plaintext) => {
+                    self.plaintext = plaintext;
+                    self.position = 0;
+                }
+                Step::NeedInput => {
+                    input.resize(READ_BUF_SIZE, 0);
+                    let n = self.inner.read(&mut input)?;
+                    if n == 0 {
+                        self.core.end_of_input();
+                    } else {
+                        self.core.push(&input[..n]);
+                    }
+                }
+                Step::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::Algorithm;
+
+    #[test]
+    fn test_writer_reader_roundtrip_all_algorithms() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 253) as u8).collect();
+        let raw_key = [5u8; 32];
+        let (private_key, public_key) = ECCEncryption::generate_keypair();
+
+        let cases = [
+            (
+                EncryptionKey::RawKey {
+                    algorithm: Algorithm::Aes256Gcm,
+                    key: &raw_key,
+                },
+                DecryptionKey::RawKey(&raw_key),
+            ),
+            (
+                EncryptionKey::RawKey {
+                    algorithm: Algorithm::ChaCha20Poly1305,
+                    key: &raw_key,
+                },
+                DecryptionKey::RawKey(&raw_key),
+            ),
+            (
+                EncryptionKey::EccPublicKey(&public_key),
+                DecryptionKey::EccPrivateKey(&private_key),
+            ),
+        ];
+
+        for (encryption_key, decryption_key) in cases {
+            let mut writer =
+                EncryptWriter::with_chunk_size(Vec::new(), &encryption_key, 1000).unwrap();
+            for piece in data.chunks(777) {
+                writer.write_all(piece).unwrap();
+            }
+            let sealed = writer.finish().unwrap();
+
+            let mut opened = Vec::new();
+            DecryptReader::new(&sealed[..], &decryption_key)
+                .read_to_end(&mut opened)
+                .unwrap();
+            assert_eq!(opened, data);
+
+            // Dropping the last chunk must not go unnoticed
+            let truncated = &sealed[..sealed.len() - 100];
+            let mut sink = Vec::new();
+            assert!(DecryptReader::new(truncated, &decryption_key)
+                .read_to_end(&mut sink)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_reader_accepts_single_shot_containers() {
+        let blob = crate::chacha::ChaChaEncryption::encrypt(b"one shot", "pw").unwrap();
+        let mut opened = Vec::new();
+        DecryptReader::new(&blob[..], &DecryptionKey::Password("pw"))
+            .read_to_end(&mut opened)
+            .unwrap();
+        assert_eq!(opened, b"one shot");
+    }
+}
@@ -0,0 +1,242 @@
+//! Armored key files for raw-key (password-less) encryption.
+//!
+//! ```text
+//! -----BEGIN MOTHRBOX KEY-----
+//! Algorithm: aes
+//! Key-Id: 5f1c0e8a9b2d4c37
+//! Created: 1760659200
+//! Checksum: 0a1b2c3d
+//!
+//! <64 hex digits>
+//! -----END MOTHRBOX KEY-----
+//! ```
+//!
+//! The key id is derived from the key, so it identifies a key without
+//! revealing it. The checksum covers every field and catches truncated or
+//! hand-edited files before they are used to encrypt anything.
+
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::header::Algorithm;
+use super::secret::{LockedKey, Zeroizing};
+use crate::Error;
+
+const BEGIN: &str = "-----BEGIN MOTHRBOX KEY-----";
+const END: &str = "-----END MOTHRBOX KEY-----";
+
+/// A 256-bit symmetric key together with the algorithm it is meant for
+#[derive(Clone)]
+pub struct KeyFile {
+    pub algorithm: Algorithm,
+    pub key: LockedKey,
+    /// Creation time, seconds since the Unix epoch
+    pub created: u64,
+}
+
+impl KeyFile {
+    /// Generate a fresh random key for `algorithm` ('aes' or 'chacha')
+    pub fn generate(algorithm: Algorithm) -> Result<Self, Error> {
+        check_symmetric(algorithm)?;
+        let mut key = LockedKey::zeroed();
+        OsRng.fill_bytes(&mut key[..]);
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        Ok(Self {
+            algorithm,
+            key,
+            created,
+        })
+    }
+
+    /// Short public identifier of the key (first 8 bytes of a SHA-256 over it)
+    pub fn key_id(&self) -> String {
+        let digest = Sha256::new()
+            .chain_update(b"mothrbox-key-id")
+            .chain_update(&self.key[..])
+            .finalize();
+        hex::encode(&digest[..8])
+    }
+
+    fn checksum(&self) -> String {
+        let digest = Sha256::new()
+            .chain_update(self.algorithm.name())
+            .chain_update(b"\n")
+            .chain_update(self.key_id())
+            .chain_update(b"\n")
+            .chain_update(self.created.to_string())
+            .chain_update(b"\n")
+            .chain_update(&self.key[..])
+            .finalize();
+        hex::encode(&digest[..4])
+    }
+
+    /// Serialize to the armored text format
+    pub fn encode(&self) -> Zeroizing<String> {
+        let key_hex = Zeroizing::new(hex::encode(&self.key[..]));
+        Zeroizing::new(format!(
+            "{}\nAlgorithm: {}\nKey-Id: {}\nCreated: {}\nChecksum: {}\n\n{}\n{}\n",
+            BEGIN,
+            self.algorithm.name(),
+            self.key_id(),
+            self.created,
+            self.checksum(),
+            key_hex.as_str(),
+            END
+        ))
+    }
+
+    /// Parse and verify an armored key file
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let invalid = |msg: &str| Error::InvalidKey(format!("key file {}", msg));
+
+        let mut lines = text.lines().map(str::trim);
+        if lines.next() != Some(BEGIN) {
+            return Err(invalid("is missing its BEGIN line"));
+        }
+
+        let (mut algorithm, mut key_id, mut created, mut checksum) = (None, None, None, None);
+        for line in lines.by_ref() {
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("has a malformed header line"))?;
+            let value = value.trim();
+            match name {
+                "Algorithm" => algorithm = Some(value.to_string()),
+                "Key-Id" => key_id = Some(value.to_string()),
+                "Created" => created = Some(value.to_string()),
+                "Checksum" => checksum = Some(value.to_string()),
+                _ => return Err(invalid(&format!("has unknown field '{}'", name))),
+            }
+        }
+
+        let mut body = Zeroizing::new(String::new());
+        let mut ended = false;
+        for line in lines {
+            if line == END {
+                ended = true;
+                break;
+            }
+            body.push_str(line);
+        }
+        if !ended {
+            return Err(invalid("is missing its END line"));
+        }
+
+        let algorithm = match algorithm.as_deref() {
+            Some("aes") => Algorithm::Aes256Gcm,
+            Some("chacha") => Algorithm::ChaCha20Poly1305,
+            Some(other) => return Err(invalid(&format!("has unsupported algorithm '{}'", other))),
+            None => return Err(invalid("has no Algorithm field")),
+        };
+        let created = created
+            .ok_or_else(|| invalid("has no Created field"))?
+            .parse()
+            .map_err(|_| invalid("has an invalid Created field"))?;
+        let key = hex::decode(body.as_str())
+            .ok()
+            .map(Zeroizing::new)
+            .and_then(|bytes| LockedKey::from_slice(&bytes).ok())
+            .ok_or_else(|| invalid("must hold exactly 32 hex-encoded bytes"))?;
+
+        let parsed = Self {
+            algorithm,
+            key,
+            created,
+        };
+        if key_id.as_deref() != Some(parsed.key_id().as_str())
+            || checksum.as_deref() != Some(parsed.checksum().as_str())
+        {
+            return Err(invalid(
+                "checksum mismatch (file is corrupted or was edited)",
+            ));
+        }
+        Ok(parsed)
+    }
+
+    /// Write the key file, readable by the owner only where supported
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options
+            .open(path)
+            .and_then(|mut file| file.write_all(self.encode().as_bytes()))
+            .map_err(|e| Error::io("Failed to write key file", e))
+    }
+
+    /// Read and verify a key file
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let text = Zeroizing::new(
+            fs::read_to_string(path).map_err(|e| Error::io("Failed to read key file", e))?,
+        );
+        Self::parse(&text)
+    }
+
+    /// Check the key was generated for `algorithm`
+    pub fn expect_algorithm(&self, algorithm: Algorithm) -> Result<&[u8; 32], Error> {
+        if self.algorithm != algorithm {
+            return Err(Error::InvalidKey(format!(
+                "key file is for '{}', not '{}'",
+                self.algorithm.name(),
+                algorithm.name()
+            )));
+        }
+        Ok(&self.key)
+    }
+}
+
+fn check_symmetric(algorithm: Algorithm) -> Result<(), Error> {
+    match algorithm {
+        Algorithm::Aes256Gcm | Algorithm::ChaCha20Poly1305 => Ok(()),
+        Algorithm::EciesP256
+        | Algorithm::EciesP256V1
+        | Algorithm::EciesX25519
+        | Algorithm::Hpke => Err(Error::InvalidInput(format!(
+            "'{0}' uses key pairs, see '{0} keygen'",
+            algorithm.name()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_file_roundtrip_and_checksum() {
+        let key_file = KeyFile::generate(Algorithm::ChaCha20Poly1305).unwrap();
+        let text = key_file.encode();
+        let parsed = KeyFile::parse(&text).unwrap();
+        assert_eq!(*parsed.key, *key_file.key);
+        assert_eq!(parsed.created, key_file.created);
+
+        // Flip one hex digit of the key
+        let body_at = text.find("\n\n").unwrap() + 2;
+        let mut tampered = text.as_bytes().to_vec();
+        tampered[body_at] = if tampered[body_at] == b'0' {
+            b'1'
+        } else {
+            b'0'
+        };
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert!(matches!(
+            KeyFile::parse(&tampered),
+            Err(Error::InvalidKey(_))
+        ));
+
+        assert!(KeyFile::generate(Algorithm::EciesP256).is_err());
+    }
+}
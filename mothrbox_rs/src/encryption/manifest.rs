@@ -0,0 +1,238 @@
+//! Encrypted description of the original file, sealed with its contents.
+//!
+//! When the header has `FLAG_MANIFEST`, the payload (before compression) is
+//!
+//! ```text
+//! [manifest_len(4)][manifest JSON][file contents]
+//! ```
+//!
+//! so the file name, content type, size, timestamps, SHA-256 and any custom
+//! tags are only readable by someone holding the key, and are authenticated
+//! along with the data. Decryption checks the size and hash before handing
+//! the contents back.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::header::Header;
+use crate::Error;
+
+/// Largest manifest accepted, far more than any file name and tags need
+pub const MAX_MANIFEST_LEN: usize = 64 * 1024;
+
+/// What is known about a file at encryption time
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Original file name, without any directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// MIME type guessed from the file name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Size of the contents in bytes
+    pub size: u64,
+    /// Hex SHA-256 of the contents
+    pub sha256: String,
+    /// Last modification time, seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+    /// Creation time where the filesystem records it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    /// Caller-defined key/value pairs
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    /// Describe `data`, named `filename` if given
+    pub fn for_data(filename: Option<&str>, data: &[u8]) -> Self {
+        Self {
+            filename: filename.map(str::to_string),
+            content_type: filename.map(|name| content_type(name).to_string()),
+            size: data.len() as u64,
+            sha256: hex::encode(Sha256::digest(data)),
+            ..Self::default()
+        }
+    }
+
+    /// Describe the file at `path`, whose contents are `data`
+    pub fn for_file(path: &str, data: &[u8]) -> Result<Self, Error> {
+        let filename = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        let metadata =
+            std::fs::metadata(path).map_err(|e| Error::io("Failed to read file metadata", e))?;
+        Ok(Self {
+            modified: metadata.modified().ok().and_then(unix_seconds),
+            created: metadata.created().ok().and_then(unix_seconds),
+            ..Self::for_data(filename.as_deref(), data)
+        })
+    }
+
+    /// The recorded file name, if it is safe to create in a directory: a
+    /// single path component that is not `.` or `..`
+    pub fn safe_filename(&self) -> Result<&str, Error> {
+        let name = self.filename.as_deref().ok_or_else(|| {
+            Error::InvalidInput("the manifest does not record a file name".to_string())
+        })?;
+        let plain = !name.is_empty()
+            && name != "."
+            && name != ".."
+            && !name.contains(['/', '\\', '\0'])
+            && Path::new(name).file_name() == Some(name.as_ref());
+        if plain {
+            Ok(name)
+        } else {
+            Err(Error::InvalidInput(format!(
+                "manifest file name '{}' is not a plain file name",
+                name.escape_debug()
+            )))
+        }
+    }
+
+    /// Modification time as a `SystemTime`
+    pub fn modified_time(&self) -> Option<SystemTime> {
+        self.modified
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+}
+
+/// Prefix `plaintext` with `manifest`; borrowed as is when there is none
+pub(crate) fn pack<'a>(
+    manifest: Option<&Manifest>,
+    plaintext: &'a [u8],
+) -> Result<Cow<'a, [u8]>, Error> {
+    let Some(manifest) = manifest else {
+        return Ok(Cow::Borrowed(plaintext));
+    };
+    let json = serde_json::to_vec(manifest).expect("manifests serialize");
+    if json.len() > MAX_MANIFEST_LEN {
+        return Err(Error::InvalidInput(format!(
+            "manifest is {} bytes, the limit is {}",
+            json.len(),
+            MAX_MANIFEST_LEN
+        )));
+    }
+
+    let mut payload = Vec::with_capacity(4 + json.len() + plaintext.len());
+    payload.extend_from_slice(&(json.len() as u32).to_be_bytes());
+    payload.extend_from_slice(&json);
+    payload.extend_from_slice(plaintext);
+    Ok(Cow::Owned(payload))
+}
+
+/// Split a decrypted, decompressed payload into its manifest (if the header
+/// says there is one) and the file contents
+pub(crate) fn unpack(
+    header: &Header,
+    mut payload: Vec<u8>,
+) -> Result<(Option<Manifest>, Vec<u8>), Error> {
+    if !header.has_manifest() {
+        return Ok((None, payload));
+    }
+
+    let corrupt = |what: &str| Error::MalformedHeader(format!("manifest {}", what));
+    if payload.len() < 4 {
+        return Err(corrupt("length is missing"));
+    }
+    let len = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize;
+    if len > MAX_MANIFEST_LEN || payload.len() < 4 + len {
+        return Err(corrupt("length is out of range"));
+    }
+    let manifest: Manifest = serde_json::from_slice(&payload[4..4 + len])
+        .map_err(|e| corrupt(&format!("is not valid: {}", e)))?;
+
+    let contents = payload.split_off(4 + len);
+    if contents.len() as u64 != manifest.size
+        || hex::encode(Sha256::digest(&contents)) != manifest.sha256
+    {
+        return Err(corrupt("does not match the decrypted contents"));
+    }
+    Ok((Some(manifest), contents))
+}
+
+fn unix_seconds(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|since| since.as_secs())
+}
+
+/// MIME type for common extensions, `application/octet-stream` otherwise
+pub fn content_type(filename: &str) -> &'static str {
+    let extension = Path::new(filename)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "txt" | "log" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{Algorithm, Kdf};
+
+    #[test]
+    fn test_pack_and_unpack() {
+        let header = Header::new(Algorithm::Aes256Gcm, Kdf::None).with_manifest(true);
+        let mut manifest = Manifest::for_data(Some("report.pdf"), b"contents");
+        manifest.tags.insert("owner".to_string(), "ops".to_string());
+        assert_eq!(manifest.content_type.as_deref(), Some("application/pdf"));
+
+        let payload = pack(Some(&manifest), b"contents").unwrap().into_owned();
+        assert_eq!(
+            unpack(&header, payload.clone()).unwrap(),
+            (Some(manifest.clone()), b"contents".to_vec())
+        );
+
+        // Without the flag the payload is returned untouched
+        let plain = Header::new(Algorithm::Aes256Gcm, Kdf::None);
+        assert_eq!(unpack(&plain, payload.clone()).unwrap().1, payload);
+
+        // The contents must match what the manifest recorded
+        let mut tampered = payload;
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(unpack(&header, tampered).is_err());
+        assert!(unpack(&header, vec![0xff; 8]).is_err());
+    }
+
+    #[test]
+    fn test_safe_filename() {
+        let named = |name: &str| Manifest {
+            filename: Some(name.to_string()),
+            ..Manifest::default()
+        };
+        assert_eq!(named("report.pdf").safe_filename().unwrap(), "report.pdf");
+        for hostile in ["", ".", "..", "../etc/passwd", "/etc/passwd", "a\\b"] {
+            assert!(named(hostile).safe_filename().is_err(), "{}", hostile);
+        }
+        assert!(Manifest::default().safe_filename().is_err());
+    }
+}
@@ -1,3 +1,17 @@
 pub mod aes;
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod atomic;
 pub mod chacha;
+pub mod compress;
 pub mod ecc;
+pub mod header;
+pub mod hpke;
+pub mod io;
+pub mod keyfile;
+pub mod manifest;
+pub mod options;
+pub mod rekey;
+pub mod secret;
+pub mod stream;
+pub mod sui;
@@ -0,0 +1,35 @@
+//! Options shared by the single-shot `encrypt_with_options` of every cipher.
+
+use super::compress::Compression;
+use super::header::{Algorithm, Header, Kdf, KdfParams};
+use super::manifest::Manifest;
+
+/// How to seal a single-shot container. `Default` is what plain `encrypt`
+/// does: default Argon2 cost, no AAD, no compression, no manifest and not a
+/// chunk list.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EncryptOptions<'a> {
+    /// Argon2 cost for password keys, recorded in the header; unused for raw
+    /// and public keys
+    pub kdf: KdfParams,
+    /// Associated data the ciphertext is bound to, required again to decrypt
+    pub aad: &'a [u8],
+    /// Compression applied before encrypting. See `compress` before
+    /// compressing data that mixes secrets with attacker input.
+    pub compression: Compression,
+    /// Manifest sealed ahead of the plaintext
+    pub manifest: Option<&'a Manifest>,
+    /// Mark the plaintext as a deduplicated upload's chunk list (see `dedup`)
+    pub chunk_list: bool,
+}
+
+impl EncryptOptions<'_> {
+    /// Header recording these options for `algorithm` and `kdf`
+    pub(crate) fn header(&self, algorithm: Algorithm, kdf: Kdf) -> Header {
+        Header::new(algorithm, kdf)
+            .with_aad(self.aad)
+            .with_codec(self.compression.codec)
+            .with_manifest(self.manifest.is_some())
+            .with_chunk_list(self.chunk_list)
+    }
+}
@@ -0,0 +1,222 @@
+//! Changing who can open a multi-recipient envelope (see `FLAG_RECIPIENTS`
+//! in `header`).
+//!
+//! The payload is bound to the header without its recipient stanzas, so
+//! granting or dropping access only rewrites the stanzas: the ciphertext after
+//! the header is copied unchanged, streamed or not.
+//!
+//! Dropping a stanza is not revocation. A removed recipient who kept the file
+//! (or just its data key) can still read it, and the stanza can be put back by
+//! anyone holding the old header. `rotate` re-encrypts under a fresh data key
+//! when access must really end.
+
+use super::compress::{Compression, DEFAULT_ZSTD_LEVEL};
+use super::ecc::{Curve, ECCEncryption, MothrboxPublicKey};
+use super::header::{Algorithm, Header, Recipient};
+use crate::{DecryptReader, DecryptionKey, EncryptOptions, EncryptWriter, EncryptionKey, Error};
+
+/// Wrap the data key of `container` for `recipients` as well, proving access
+/// with `private_key`. Recipients that already have a stanza are skipped.
+///
+/// A single-recipient ECC container has no data key to share; it is
+/// re-encrypted as an envelope for the holder and `recipients` (which needs
+/// the `aad` it was bound to, if any).
+pub fn add_recipients(
+    container: &[u8],
+    private_key: &[u8; 32],
+    recipients: &[MothrboxPublicKey],
+    aad: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let (header, header_len) = Header::parse(container)?;
+    if !header.has_recipients() {
+        let Some(curve) = Curve::of(header.algorithm) else {
+            return Err(not_an_envelope(&header));
+        };
+        let holder = curve.public_key(private_key)?;
+        let mut everyone = vec![holder];
+        everyone.extend(
+            recipients
+                .iter()
+                .filter(|recipient| **recipient != holder)
+                .copied(),
+        );
+        return rotate(container, private_key, &everyone, aad);
+    }
+
+    let data_key = ECCEncryption::unwrap_key(&header.recipients, private_key)?;
+    let mut stanzas = header.recipients.clone();
+    for recipient in recipients {
+        let key_id = ECCEncryption::key_id(recipient);
+        if stanzas.iter().all(|stanza| stanza.key_id != key_id) {
+            stanzas.push(ECCEncryption::wrap_key(&data_key, recipient)?);
+        }
+    }
+    if stanzas.len() > 255 {
+        return Err(Error::InvalidInput(format!(
+            "an envelope holds at most 255 recipients, this would need {}",
+            stanzas.len()
+        )));
+    }
+    Ok(with_stanzas(container, header, header_len, stanzas))
+}
+
+/// Drop the stanzas for `key_ids` (see `ECCEncryption::key_id`). Needs no
+/// key, and does not stop a removed recipient who kept the data key; see
+/// `rotate`.
+pub fn remove_recipients(container: &[u8], key_ids: &[[u8; 8]]) -> Result<Vec<u8>, Error> {
+    let (header, header_len) = Header::parse(container)?;
+    if !header.has_recipients() {
+        return Err(not_an_envelope(&header));
+    }
+    if let Some(missing) = key_ids.iter().find(|key_id| {
+        header
+            .recipients
+            .iter()
+            .all(|stanza| stanza.key_id != **key_id)
+    }) {
+        return Err(Error::InvalidInput(format!(
+            "key id {} is not a recipient",
+            hex::encode(missing)
+        )));
+    }
+
+    let stanzas: Vec<_> = header
+        .recipients
+        .iter()
+        .filter(|stanza| !key_ids.contains(&stanza.key_id))
+        .cloned()
+        .collect();
+    if stanzas.is_empty() {
+        return Err(Error::InvalidInput(
+            "cannot remove every recipient; at least one must remain".to_string(),
+        ));
+    }
+    Ok(with_stanzas(container, header, header_len, stanzas))
+}
+
+/// Decrypt `container` with `private_key` and encrypt it again under a fresh
+/// data key for exactly `recipients`, keeping its cipher, compression,
+/// manifest, chunking, chunk-list flag and `aad`
+pub fn rotate(
+    container: &[u8],
+    private_key: &[u8; 32],
+    recipients: &[MothrboxPublicKey],
+    aad: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let (header, _) = Header::parse(container)?;
+    let key = EncryptionKey::EccRecipients {
+        algorithm: match header.algorithm {
+            algorithm if algorithm.is_ecies() => Algorithm::Aes256Gcm,
+            algorithm => algorithm,
+        },
+        recipients,
+    };
+    let private_key = DecryptionKey::EccPrivateKey(private_key);
+
+    if let Some(chunk_size) = header.chunk_size {
+        let mut writer = EncryptWriter::with_aad(Vec::new(), &key, chunk_size, aad)?;
+        let mut reader = DecryptReader::with_aad(container, &private_key, aad);
+        std::io::copy(&mut reader, &mut writer)?;
+        return Ok(writer.finish()?);
+    }
+
+    let (manifest, plaintext) = crate::decrypt_with_manifest(container, &private_key, aad)?;
+    let compression = Compression {
+        codec: header.codec,
+        level: DEFAULT_ZSTD_LEVEL,
+    };
+    let options = EncryptOptions {
+        aad,
+        compression,
+        manifest: manifest.as_ref(),
+        chunk_list: header.is_chunk_list(),
+        ..Default::default()
+    };
+    crate::encrypt_with_options(&plaintext, &key, &options)
+}
+
+fn with_stanzas(
+    container: &[u8],
+    header: Header,
+    header_len: usize,
+    stanzas: Vec<Recipient>,
+) -> Vec<u8> {
+    let mut rewritten = header.with_recipients(stanzas).encode();
+    rewritten.extend_from_slice(&container[header_len..]);
+    rewritten
+}
+
+fn not_an_envelope(header: &Header) -> Error {
+    Error::InvalidInput(format!(
+        "container is not a multi-recipient envelope ('{}'{})",
+        header.algorithm.name(),
+        if header.algorithm.is_ecies() {
+            ", re-encrypt it with add-recipient first"
+        } else {
+            ""
+        }
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recipients_change_without_touching_the_payload() {
+        let keypairs: Vec<_> = (0..3).map(|_| ECCEncryption::generate_keypair()).collect();
+        let public_keys: Vec<_> = keypairs.iter().map(|(_, public_key)| *public_key).collect();
+        let opens = |container: &[u8], who: usize| {
+            crate::decrypt(container, &DecryptionKey::EccPrivateKey(&keypairs[who].0)).is_ok()
+        };
+        let sealed = crate::encrypt(
+            b"quarterly numbers",
+            &EncryptionKey::EccRecipients {
+                algorithm: Algorithm::Aes256Gcm,
+                recipients: &public_keys[..1],
+            },
+        )
+        .unwrap();
+        let (_, header_len) = Header::parse(&sealed).unwrap();
+
+        let added = add_recipients(&sealed, &keypairs[0].0, &public_keys[1..], b"").unwrap();
+        let (_, added_len) = Header::parse(&added).unwrap();
+        assert_eq!(added[added_len..], sealed[header_len..]);
+        assert!((0..3).all(|who| opens(&added, who)));
+        // Only a recipient can grant access
+        assert!(matches!(
+            add_recipients(&sealed, &keypairs[1].0, &public_keys[1..], b""),
+            Err(Error::InvalidKey(_))
+        ));
+
+        let bob = ECCEncryption::key_id(&public_keys[1]);
+        let removed = remove_recipients(&added, &[bob]).unwrap();
+        assert!(opens(&removed, 0) && !opens(&removed, 1) && opens(&removed, 2));
+        assert!(remove_recipients(&removed, &[bob]).is_err());
+        let everyone: Vec<_> = public_keys.iter().map(ECCEncryption::key_id).collect();
+        assert!(remove_recipients(&added, &everyone).is_err());
+
+        // A rotated copy no longer shares a data key with the old one
+        let rotated = rotate(
+            &added,
+            &keypairs[0].0,
+            &[public_keys[0], public_keys[2]],
+            b"",
+        )
+        .unwrap();
+        assert!(opens(&rotated, 0) && !opens(&rotated, 1) && opens(&rotated, 2));
+        let old_stanza = Header::parse(&added).unwrap().0.recipients[1].clone();
+        let (rotated_header, rotated_len) = Header::parse(&rotated).unwrap();
+        let mut stanzas = rotated_header.recipients.clone();
+        stanzas.push(old_stanza);
+        let replayed = with_stanzas(&rotated, rotated_header, rotated_len, stanzas);
+        assert!(!opens(&replayed, 1));
+
+        // A single-recipient ECC container becomes an envelope
+        let classic = ECCEncryption::encrypt(b"legacy", &public_keys[0]).unwrap();
+        let converted = add_recipients(&classic, &keypairs[0].0, &public_keys[1..2], b"").unwrap();
+        assert_eq!(Header::parse(&converted).unwrap().0.recipients.len(), 2);
+        assert!(opens(&converted, 0) && opens(&converted, 1) && !opens(&converted, 2));
+        assert!(remove_recipients(&classic, &[bob]).is_err());
+    }
+}
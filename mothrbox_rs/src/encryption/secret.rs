@@ -0,0 +1,153 @@
+//! Key material handling.
+//!
+//! Derived keys, raw keys and private keys live in `LockedKey`, a heap buffer
+//! that is wiped when dropped and, on Linux, `mlock`ed so it is never written
+//! to swap. Shorter-lived secrets (passwords, HKDF output, decoded key files)
+//! are wrapped in `Zeroizing`.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+pub use zeroize::{Zeroize, Zeroizing};
+
+use crate::Error;
+
+/// A 256-bit key that is wiped on drop and locked into RAM where supported
+pub struct LockedKey(Box<[u8; 32]>);
+
+impl LockedKey {
+    /// All-zero key, to be filled in place (e.g. by a KDF)
+    pub fn zeroed() -> Self {
+        let key = Box::new([0u8; 32]);
+        pages::lock(&key[..]);
+        Self(key)
+    }
+
+    /// Copy a 32-byte key out of `bytes`
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != 32 {
+            return Err(Error::InvalidKey("key must be 32 bytes".to_string()));
+        }
+        let mut key = Self::zeroed();
+        key.copy_from_slice(bytes);
+        Ok(key)
+    }
+}
+
+impl From<&[u8; 32]> for LockedKey {
+    fn from(bytes: &[u8; 32]) -> Self {
+        let mut key = Self::zeroed();
+        key.copy_from_slice(bytes);
+        key
+    }
+}
+
+impl Clone for LockedKey {
+    fn clone(&self) -> Self {
+        Self::from(&*self.0)
+    }
+}
+
+impl Deref for LockedKey {
+    type Target = [u8; 32];
+
+    fn deref(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl DerefMut for LockedKey {
+    fn deref_mut(&mut self) -> &mut [u8; 32] {
+        &mut self.0
+    }
+}
+
+impl fmt::Debug for LockedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LockedKey([REDACTED])")
+    }
+}
+
+impl Drop for LockedKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        pages::unlock(&self.0[..]);
+    }
+}
+
+/// Best-effort `mlock` of the pages backing key buffers.
+///
+/// Several keys can share a page and `munlock` is not reference counted, so a
+/// page stays locked until the last key on it is dropped. Failures (e.g. a low
+/// `RLIMIT_MEMLOCK`) are ignored: the key is still wiped on drop.
+#[cfg(target_os = "linux")]
+mod pages {
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    static LOCKED: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+    fn page_size() -> usize {
+        // SAFETY: sysconf has no preconditions
+        match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            size if size > 0 => size as usize,
+            _ => 4096,
+        }
+    }
+
+    /// Start addresses of the pages `bytes` spans
+    fn pages(bytes: &[u8]) -> impl Iterator<Item = usize> {
+        let page_size = page_size();
+        let start = bytes.as_ptr() as usize & !(page_size - 1);
+        let end = bytes.as_ptr() as usize + bytes.len();
+        (start..end).step_by(page_size)
+    }
+
+    pub fn lock(bytes: &[u8]) {
+        let mut locked = LOCKED.lock().unwrap_or_else(|e| e.into_inner());
+        for page in pages(bytes) {
+            let count = locked.entry(page).or_insert(0);
+            *count += 1;
+            if *count == 1 {
+                // SAFETY: the page is mapped, it contains part of `bytes`
+                unsafe { libc::mlock(page as *const libc::c_void, page_size()) };
+            }
+        }
+    }
+
+    pub fn unlock(bytes: &[u8]) {
+        let mut locked = LOCKED.lock().unwrap_or_else(|e| e.into_inner());
+        for page in pages(bytes) {
+            if let Some(count) = locked.get_mut(&page) {
+                *count -= 1;
+                if *count == 0 {
+                    locked.remove(&page);
+                    // SAFETY: the page is mapped, it contains part of `bytes`
+                    unsafe { libc::munlock(page as *const libc::c_void, page_size()) };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod pages {
+    pub fn lock(_bytes: &[u8]) {}
+
+    pub fn unlock(_bytes: &[u8]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locked_key_copies_and_hides_its_bytes() {
+        let key = LockedKey::from(&[7u8; 32]);
+        let copy = key.clone();
+        drop(key);
+        assert_eq!(*copy, [7u8; 32]);
+        assert_eq!(format!("{:?}", copy), "LockedKey([REDACTED])");
+        assert!(LockedKey::from_slice(&[0u8; 16]).is_err());
+    }
+}
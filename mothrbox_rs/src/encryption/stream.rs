@@ -0,0 +1,611 @@
+//! Chunked streaming AEAD (STREAM construction, Hoang-Reyhanitabar-Rogaway-Vizár)
+//! for inputs that do not fit in memory.
+//!
+//! Format: `[header (FLAG_STREAM)][key prefix][stream_salt(16)][chunk]...[final chunk]`
+//!
+//! The key prefix is empty for password and raw-key streams; ECC streams carry
+//! the ephemeral public key there (see `ecc::stream_key_for_recipient`) and
+//! seal chunks with AES-256-GCM. A per-stream key and 7-byte nonce prefix are
+//! derived with HKDF-SHA256 from the password-derived (or raw) key and the
+//! random stream salt. Every chunk
+//! holds `chunk_size` plaintext bytes plus a 16-byte tag and is sealed under
+//! the nonce `[prefix(7)][counter(4)][last(1)]` with the header (followed by
+//! the caller's AAD, if any) as associated data, so truncation, reordering and
+//! splicing chunks across streams all fail authentication. Only the final
+//! chunk may be shorter than `chunk_size`.
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm,
+};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use super::atomic::AtomicFile;
+use super::header::{self, Algorithm, Header, Kdf, KdfParams};
+use crate::Error;
+
+/// Default plaintext bytes per chunk
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+
+/// Largest chunk size accepted when reading, bounds per-chunk memory use
+pub const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+pub const STREAM_SALT_LEN: usize = 16;
+const NONCE_PREFIX_LEN: usize = 7;
+pub const TAG_LEN: usize = 16;
+
+enum ChunkCipher {
+    Aes(Box<Aes256Gcm>),
+    ChaCha(ChaCha20Poly1305),
+}
+
+impl ChunkCipher {
+    fn seal(&self, nonce: &[u8; 12], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+        let payload = Payload { msg, aad };
+        match self {
+            ChunkCipher::Aes(cipher) => cipher.encrypt(nonce.into(), payload),
+            ChunkCipher::ChaCha(cipher) => cipher.encrypt(nonce.into(), payload),
+        }
+        .map_err(|e| Error::Encryption(e.to_string()))
+    }
+
+    fn open(&self, nonce: &[u8; 12], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+        let payload = Payload { msg, aad };
+        match self {
+            ChunkCipher::Aes(cipher) => cipher.decrypt(nonce.into(), payload),
+            ChunkCipher::ChaCha(cipher) => cipher.decrypt(nonce.into(), payload),
+        }
+        .map_err(|_| Error::AuthenticationFailed)
+    }
+}
+
+/// Per-stream state shared by the encryptor and decryptor
+struct StreamState {
+    cipher: ChunkCipher,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    /// Encoded header (less any recipient stanzas) followed by the caller's AAD, bound to every chunk
+    associated_data: Vec<u8>,
+    counter: u32,
+    finished: bool,
+}
+
+impl StreamState {
+    fn new(header: &Header, key: &[u8; 32], stream_salt: &[u8], aad: &[u8]) -> Result<Self, Error> {
+        let hk = Hkdf::<Sha256>::new(Some(stream_salt), key);
+        let mut okm = [0u8; 32 + NONCE_PREFIX_LEN];
+        hk.expand(b"mothrbox-stream-v1", &mut okm)
+            .map_err(|e| Error::KeyDerivation(format!("HKDF error: {}", e)))?;
+
+        let cipher = match header.algorithm {
+            Algorithm::Aes256Gcm
+            | Algorithm::EciesP256
+            | Algorithm::EciesP256V1
+            | Algorithm::EciesX25519 => {
+                ChunkCipher::Aes(Box::new(Aes256Gcm::new((&okm[..32]).into())))
+            }
+            Algorithm::ChaCha20Poly1305 => {
+                ChunkCipher::ChaCha(ChaCha20Poly1305::new((&okm[..32]).into()))
+            }
+            Algorithm::Hpke => return Err(Error::Unsupported("streamed HPKE".to_string())),
+        };
+
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        nonce_prefix.copy_from_slice(&okm[32..]);
+
+        Ok(Self {
+            cipher,
+            nonce_prefix,
+            associated_data: header::associated_data(&header.authenticated(), aad),
+            counter: 0,
+            finished: false,
+        })
+    }
+
+    fn nonce(&self, last: bool) -> Result<[u8; 12], Error> {
+        if self.finished {
+            return Err(Error::InvalidInput("stream already finished".to_string()));
+        }
+
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[11] = last as u8;
+        Ok(nonce)
+    }
+
+    fn advance(&mut self, last: bool) -> Result<(), Error> {
+        if last {
+            self.finished = true;
+        } else {
+            self.counter = self.counter.checked_add(1).ok_or_else(|| {
+                Error::InvalidInput("stream too long (chunk counter overflow)".to_string())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Seals plaintext chunks one at a time
+pub struct StreamEncryptor {
+    state: StreamState,
+    chunk_size: usize,
+}
+
+impl StreamEncryptor {
+    /// Start a stream; returns the encryptor and the random stream salt that
+    /// must be written (after the header and any key prefix) before the first chunk.
+    /// `aad` must be empty unless the header carries `FLAG_AAD`.
+    pub fn new(
+        header: &Header,
+        key: &[u8; 32],
+        aad: &[u8],
+    ) -> Result<(Self, [u8; STREAM_SALT_LEN]), Error> {
+        let chunk_size = match header.chunk_size {
+            Some(size) if header.is_stream() && size > 0 => size,
+            _ => {
+                return Err(Error::InvalidInput(
+                    "header is not a stream header".to_string(),
+                ))
+            }
+        };
+
+        let mut stream_salt = [0u8; STREAM_SALT_LEN];
+        OsRng.fill_bytes(&mut stream_salt);
+
+        Ok((
+            Self {
+                state: StreamState::new(header, key, &stream_salt, aad)?,
+                chunk_size: chunk_size as usize,
+            },
+            stream_salt,
+        ))
+    }
+
+    /// Plaintext bytes expected per non-final chunk
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Seal one chunk. Non-final chunks must be exactly `chunk_size` bytes.
+    pub fn seal_chunk(&mut self, chunk: &[u8], last: bool) -> Result<Vec<u8>, Error> {
+        if chunk.len() > self.chunk_size || (!last && chunk.len() != self.chunk_size) {
+            return Err(Error::InvalidInput("invalid chunk length".to_string()));
+        }
+        let nonce = self.state.nonce(last)?;
+        let sealed = self
+            .state
+            .cipher
+            .seal(&nonce, chunk, &self.state.associated_data)?;
+        self.state.advance(last)?;
+        Ok(sealed)
+    }
+}
+
+/// Opens ciphertext chunks one at a time
+pub struct StreamDecryptor {
+    state: StreamState,
+    chunk_size: usize,
+    /// Byte read ahead of the previous chunk to detect end of stream
+    carry: Vec<u8>,
+}
+
+impl StreamDecryptor {
+    /// Start decrypting after `header` has been read; consumes the stream salt
+    pub fn new<R: Read>(
+        header: &Header,
+        key: &[u8; 32],
+        reader: &mut R,
+        aad: &[u8],
+    ) -> Result<Self, Error> {
+        let mut stream_salt = [0u8; STREAM_SALT_LEN];
+        reader
+            .read_exact(&mut stream_salt)
+            .map_err(|_| Error::Truncated("missing stream salt".to_string()))?;
+        Self::from_salt(header, key, &stream_salt, aad)
+    }
+
+    /// Start decrypting with a stream salt the caller has already read
+    pub fn from_salt(
+        header: &Header,
+        key: &[u8; 32],
+        stream_salt: &[u8; STREAM_SALT_LEN],
+        aad: &[u8],
+    ) -> Result<Self, Error> {
+        let chunk_size = match header.chunk_size {
+            Some(size) if header.is_stream() && size <= MAX_CHUNK_SIZE => size,
+            Some(size) if header.is_stream() => {
+                return Err(Error::MalformedHeader(format!(
+                    "stream chunk size too large: {}",
+                    size
+                )))
+            }
+            _ => return Err(Error::InvalidInput("container is not a stream".to_string())),
+        };
+
+        Ok(Self {
+            state: StreamState::new(header, key, stream_salt, aad)?,
+            chunk_size: chunk_size as usize,
+            carry: Vec::new(),
+        })
+    }
+
+    /// Ciphertext bytes of every non-final chunk
+    pub fn encrypted_chunk_size(&self) -> usize {
+        self.chunk_size + TAG_LEN
+    }
+
+    /// True once the final chunk has been authenticated
+    pub fn is_finished(&self) -> bool {
+        self.state.finished
+    }
+
+    /// Open one chunk, `last` marks the final chunk of the stream
+    pub fn open_chunk(&mut self, chunk: &[u8], last: bool) -> Result<Vec<u8>, Error> {
+        if chunk.len() > self.encrypted_chunk_size()
+            || chunk.len() < TAG_LEN
+            || (!last && chunk.len() != self.encrypted_chunk_size())
+        {
+            return Err(Error::MalformedHeader(
+                "invalid stream chunk length".to_string(),
+            ));
+        }
+        let nonce = self.state.nonce(last)?;
+        let plaintext = self
+            .state
+            .cipher
+            .open(&nonce, chunk, &self.state.associated_data)?;
+        self.state.advance(last)?;
+        Ok(plaintext)
+    }
+
+    /// Read the next chunk from `reader` and open it.
+    /// Returns `None` once the final chunk has been processed.
+    pub fn read_chunk<R: Read>(&mut self, reader: &mut R) -> Result<Option<Vec<u8>>, Error> {
+        if self.is_finished() {
+            return Ok(None);
+        }
+
+        // Read one byte past a full chunk to learn whether this chunk is the last one
+        let full = self.encrypted_chunk_size();
+        let mut buf = std::mem::take(&mut self.carry);
+        let start = buf.len();
+        buf.resize(full + 1, 0);
+        let filled = start + read_full(reader, &mut buf[start..])?;
+        if filled == 0 {
+            return Err(Error::Truncated("missing final chunk".to_string()));
+        }
+
+        if filled <= full {
+            return self.open_chunk(&buf[..filled], true).map(Some);
+        }
+
+        let plaintext = self.open_chunk(&buf[..full], false)?;
+        self.carry.push(buf[full]);
+        Ok(Some(plaintext))
+    }
+}
+
+/// Fill `buf` from `reader`, stopping early only at end of input
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(Error::io("Read error", e)),
+        }
+    }
+    Ok(filled)
+}
+
+/// Encrypt everything from `reader` into `writer` as a password-protected stream,
+/// bound to `aad` (may be empty). Returns the number of plaintext bytes processed.
+pub fn encrypt<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    algorithm: Algorithm,
+    password: &str,
+    params: KdfParams,
+    chunk_size: u32,
+    aad: &[u8],
+) -> Result<u64, Error> {
+    let header = Header::new_stream(algorithm, Kdf::argon2(params), chunk_size).with_aad(aad);
+    let key = header.kdf.derive_key(password)?;
+    encrypt_with_header(&mut reader, &mut writer, &header, &key, aad)
+}
+
+/// Encrypt everything from `reader` into `writer` as a raw-key stream
+pub fn encrypt_with_key<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    algorithm: Algorithm,
+    key: &[u8; 32],
+    chunk_size: u32,
+    aad: &[u8],
+) -> Result<u64, Error> {
+    let header = Header::new_stream(algorithm, Kdf::None, chunk_size).with_aad(aad);
+    encrypt_with_header(&mut reader, &mut writer, &header, key, aad)
+}
+
+fn encrypt_with_header<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    header: &Header,
+    key: &[u8; 32],
+    aad: &[u8],
+) -> Result<u64, Error> {
+    if header.chunk_size.is_some_and(|size| size > MAX_CHUNK_SIZE) {
+        return Err(Error::InvalidInput(format!(
+            "Chunk size must be at most {} bytes",
+            MAX_CHUNK_SIZE
+        )));
+    }
+
+    let (mut encryptor, stream_salt) = StreamEncryptor::new(header, key, aad)?;
+    writer
+        .write_all(&header.encode())
+        .and_then(|_| writer.write_all(&stream_salt))
+        .map_err(|e| Error::io("Write error", e))?;
+
+    // Keep one full chunk buffered so the final chunk is known before sealing
+    let chunk_size = encryptor.chunk_size();
+    let mut current = vec![0u8; chunk_size];
+    let mut current_len = read_full(reader, &mut current)?;
+    let mut total = current_len as u64;
+
+    loop {
+        let mut next = vec![0u8; chunk_size];
+        let next_len = if current_len == chunk_size {
+            read_full(reader, &mut next)?
+        } else {
+            0
+        };
+
+        let last = next_len == 0;
+        let sealed = encryptor.seal_chunk(&current[..current_len], last)?;
+        writer
+            .write_all(&sealed)
+            .map_err(|e| Error::io("Write error", e))?;
+
+        if last {
+            break;
+        }
+        current = next;
+        current_len = next_len;
+        total += next_len as u64;
+    }
+
+    writer.flush().map_err(|e| Error::io("Write error", e))?;
+    Ok(total)
+}
+
+/// Decrypt a password-protected stream from `reader` into `writer`.
+/// Each chunk is authenticated before it is written; on error the writer
+/// may hold a prefix of the plaintext and must be discarded.
+pub fn decrypt<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    password: &str,
+    aad: &[u8],
+) -> Result<u64, Error> {
+    let header = Header::read_from(&mut reader)?;
+    // Fail before the (deliberately slow) key derivation
+    header.check_aad(aad)?;
+    let key = header.kdf.derive_key(password)?;
+    decrypt_with_header(&mut reader, writer, &header, &key, aad)
+}
+
+/// Decrypt a raw-key stream from `reader` into `writer`
+pub fn decrypt_with_key<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    key: &[u8; 32],
+    aad: &[u8],
+) -> Result<u64, Error> {
+    let header = Header::read_from(&mut reader)?;
+    if header.kdf != Kdf::None {
+        return Err(Error::InvalidKey(
+            "container is password-protected, not raw-key encrypted".to_string(),
+        ));
+    }
+    header.check_aad(aad)?;
+    decrypt_with_header(&mut reader, writer, &header, key, aad)
+}
+
+/// Decrypt the rest of a stream whose header has already been read
+pub fn decrypt_with_header<R: Read, W: Write>(
+    reader: &mut R,
+    mut writer: W,
+    header: &Header,
+    key: &[u8; 32],
+    aad: &[u8],
+) -> Result<u64, Error> {
+    let mut decryptor = StreamDecryptor::new(header, key, reader, aad)?;
+    let mut total = 0u64;
+
+    while let Some(plaintext) = decryptor.read_chunk(reader)? {
+        writer
+            .write_all(&plaintext)
+            .map_err(|e| Error::io("Write error", e))?;
+        total += plaintext.len() as u64;
+    }
+
+    writer.flush().map_err(|e| Error::io("Write error", e))?;
+    Ok(total)
+}
+
+/// Stream-encrypt a file without loading it into memory
+pub fn encrypt_file(
+    input_path: &str,
+    output_path: &str,
+    password: &str,
+    params: KdfParams,
+    algorithm: Algorithm,
+    chunk_size: u32,
+    aad: &[u8],
+) -> Result<(), Error> {
+    process_file(input_path, output_path, |reader, writer| {
+        encrypt(reader, writer, algorithm, password, params, chunk_size, aad)
+    })
+}
+
+/// Stream-encrypt a file under a raw 256-bit key
+pub fn encrypt_file_with_key(
+    input_path: &str,
+    output_path: &str,
+    algorithm: Algorithm,
+    key: &[u8; 32],
+    chunk_size: u32,
+    aad: &[u8],
+) -> Result<(), Error> {
+    process_file(input_path, output_path, |reader, writer| {
+        encrypt_with_key(reader, writer, algorithm, key, chunk_size, aad)
+    })
+}
+
+/// Stream-decrypt a file without loading it into memory.
+/// Nothing is written to `output_path` unless every chunk authenticates.
+pub fn decrypt_file(
+    input_path: &str,
+    output_path: &str,
+    password: &str,
+    aad: &[u8],
+) -> Result<(), Error> {
+    process_file(input_path, output_path, |reader, writer| {
+        decrypt(reader, writer, password, aad)
+    })
+}
+
+/// Stream-decrypt a raw-key file without loading it into memory
+pub fn decrypt_file_with_key(
+    input_path: &str,
+    output_path: &str,
+    key: &[u8; 32],
+    aad: &[u8],
+) -> Result<(), Error> {
+    process_file(input_path, output_path, |reader, writer| {
+        decrypt_with_key(reader, writer, key, aad)
+    })
+}
+
+/// Run `f` from `input_path` into `output_path`; the output only appears if `f` succeeds
+fn process_file<F>(input_path: &str, output_path: &str, f: F) -> Result<(), Error>
+where
+    F: FnOnce(BufReader<File>, &mut BufWriter<AtomicFile>) -> Result<u64, Error>,
+{
+    let input = File::open(input_path).map_err(|e| Error::io("Failed to open input file", e))?;
+    let mut output = BufWriter::new(AtomicFile::create(output_path)?);
+
+    f(BufReader::new(input), &mut output)?;
+    output
+        .into_inner()
+        .map_err(|e| Error::io("Failed to write output file", e.into_error()))?
+        .commit()
+}
+
+/// Peek at a file's header, `None` if it is not an MBX1 container
+pub fn peek_file_header(path: &str) -> Result<Option<Header>, Error> {
+    let mut file = File::open(path).map_err(|e| Error::io("Failed to open file", e))?;
+    let mut magic = [0u8; 4];
+    if read_full(&mut file, &mut magic)? < magic.len() || !super::header::is_container(&magic) {
+        return Ok(None);
+    }
+    let mut chained = (&magic[..]).chain(file);
+    Header::read_from(&mut chained).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(len: usize, chunk_size: u32) {
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let key = [9u8; 32];
+
+        let mut sealed = Vec::new();
+        encrypt_with_key(
+            &data[..],
+            &mut sealed,
+            Algorithm::ChaCha20Poly1305,
+            &key,
+            chunk_size,
+            &[],
+        )
+        .unwrap();
+
+        let mut opened = Vec::new();
+        decrypt_with_key(&sealed[..], &mut opened, &key, &[]).unwrap();
+        assert_eq!(opened, data);
+    }
+
+    #[test]
+    fn test_stream_roundtrip_boundaries() {
+        for len in [0, 1, 63, 64, 65, 128, 1000] {
+            roundtrip(len, 64);
+        }
+    }
+
+    #[test]
+    fn test_stream_detects_truncation_and_reordering() {
+        let data = vec![42u8; 64 * 3 + 10];
+        let key = [1u8; 32];
+        let mut sealed = Vec::new();
+        encrypt_with_key(&data[..], &mut sealed, Algorithm::Aes256Gcm, &key, 64, &[]).unwrap();
+
+        let (_, header_len) = Header::parse(&sealed).unwrap();
+        let body = header_len + STREAM_SALT_LEN;
+        let chunk = 64 + TAG_LEN;
+
+        // Drop the final chunk: the last full chunk is not marked final
+        let truncated = &sealed[..body + 3 * chunk];
+        assert!(decrypt_with_key(truncated, Vec::new(), &key, &[]).is_err());
+
+        // Swap the first two chunks
+        let mut swapped = sealed.clone();
+        swapped[body..body + chunk].copy_from_slice(&sealed[body + chunk..body + 2 * chunk]);
+        swapped[body + chunk..body + 2 * chunk].copy_from_slice(&sealed[body..body + chunk]);
+        assert!(decrypt_with_key(&swapped[..], Vec::new(), &key, &[]).is_err());
+
+        // Splice a chunk from another stream under the same key
+        let mut other = Vec::new();
+        encrypt_with_key(&data[..], &mut other, Algorithm::Aes256Gcm, &key, 64, &[]).unwrap();
+        let mut spliced = sealed.clone();
+        spliced[body..body + chunk].copy_from_slice(&other[body..body + chunk]);
+        assert!(decrypt_with_key(&spliced[..], Vec::new(), &key, &[]).is_err());
+    }
+
+    #[test]
+    fn test_failed_file_decrypt_leaves_no_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("plain");
+        let sealed = dir.path().join("sealed");
+        let output = dir.path().join("opened");
+        let path = |p: &std::path::Path| p.to_str().unwrap().to_string();
+        std::fs::write(&input, vec![7u8; 64 * 4]).unwrap();
+        let key = [3u8; 32];
+        encrypt_file_with_key(
+            &path(&input),
+            &path(&sealed),
+            Algorithm::Aes256Gcm,
+            &key,
+            64,
+            &[],
+        )
+        .unwrap();
+
+        // Corrupt the last chunk: the earlier ones authenticate and are written
+        let mut bytes = std::fs::read(&sealed).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        std::fs::write(&sealed, bytes).unwrap();
+
+        assert!(decrypt_file_with_key(&path(&sealed), &path(&output), &key, &[]).is_err());
+        assert!(!output.exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
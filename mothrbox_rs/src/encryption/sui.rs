@@ -0,0 +1,281 @@
+//! Encrypting to Sui accounts.
+//!
+//! Sui wallets sign with Ed25519. An Ed25519 public key maps to an X25519 one
+//! (Edwards to Montgomery form, as libsodium's
+//! `crypto_sign_ed25519_pk_to_curve25519`), and its seed to the matching X25519
+//! private key, so anyone can `ecc` encrypt to the key an account has published
+//! and its owner decrypts with a key derived from the wallet's.
+//!
+//! An address is a hash of the public key and cannot be converted by itself;
+//! `address` recomputes it so a published key can be checked against the
+//! account it claims to belong to. The derived private key is as sensitive as
+//! the wallet key it comes from.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use blake2::digest::consts::U32;
+use blake2::Blake2b;
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use sha2::{Digest, Sha512};
+use std::fs;
+
+use super::ecc::MothrboxPublicKey;
+use super::secret::{LockedKey, Zeroizing};
+use crate::Error;
+
+/// Signature scheme flag Sui prefixes Ed25519 keys with
+pub const ED25519_FLAG: u8 = 0x00;
+
+/// Human-readable part of bech32 private keys (`sui keytool export`)
+const PRIVATE_KEY_HRP: &str = "suiprivkey";
+
+/// Parse a Sui Ed25519 public key: base64 `flag || key` as `sui keytool list`
+/// prints it, base64 of the bare 32-byte key, or hex (`0x` optional)
+pub fn parse_public_key(encoded: &str) -> Result<[u8; 32], Error> {
+    let encoded = encoded.trim();
+    let bytes = match hex::decode(encoded.strip_prefix("0x").unwrap_or(encoded)) {
+        Ok(bytes) => bytes,
+        Err(_) => STANDARD
+            .decode(encoded)
+            .map_err(|_| Error::InvalidKey("Sui public key must be base64 or hex".to_string()))?,
+    };
+    match <[u8; 32]>::try_from(bytes.as_slice()) {
+        Ok(key) => Ok(key),
+        Err(_) => strip_flag(&bytes, "public key"),
+    }
+}
+
+/// Parse a Sui Ed25519 private key, returning its 32-byte seed:
+/// `suiprivkey1...` as `sui keytool export` prints it, or a base64
+/// `flag || seed` entry of `sui.keystore`
+pub fn parse_private_key(encoded: &str) -> Result<LockedKey, Error> {
+    let encoded = encoded.trim();
+    let bytes = Zeroizing::new(if encoded.starts_with(PRIVATE_KEY_HRP) {
+        let (hrp, data) = bech32::decode(encoded)
+            .map_err(|e| Error::InvalidKey(format!("invalid Sui private key: {}", e)))?;
+        if hrp.as_str() != PRIVATE_KEY_HRP {
+            return Err(Error::InvalidKey(format!(
+                "expected a '{}' key, got '{}'",
+                PRIVATE_KEY_HRP, hrp
+            )));
+        }
+        data
+    } else {
+        STANDARD.decode(encoded).map_err(|_| {
+            Error::InvalidKey("Sui private key must be suiprivkey1... or base64".to_string())
+        })?
+    });
+    Ok(LockedKey::from(&strip_flag(&bytes, "private key")?))
+}
+
+/// Read a `sui.keystore` (a JSON list of base64 keys) or a file holding one
+/// key (see `parse_private_key`), returning the seed. `address` picks the
+/// entry of a keystore holding several Ed25519 keys; keys of other schemes
+/// in a keystore are skipped.
+pub fn read_private_key(path: &str, address: Option<&str>) -> Result<LockedKey, Error> {
+    let contents = Zeroizing::new(
+        fs::read_to_string(path).map_err(|e| Error::io("Failed to read Sui key", e))?,
+    );
+    let mut seeds = Vec::new();
+    if contents.trim_start().starts_with('[') {
+        let entries: Zeroizing<Vec<String>> = Zeroizing::new(
+            serde_json::from_str(&contents)
+                .map_err(|e| Error::InvalidInput(format!("invalid Sui keystore: {}", e)))?,
+        );
+        for entry in entries.iter() {
+            match parse_private_key(entry) {
+                Ok(seed) => seeds.push(seed),
+                Err(Error::Unsupported(_)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    } else {
+        seeds.push(parse_private_key(&contents)?);
+    }
+
+    if let Some(address) = address {
+        return seeds
+            .into_iter()
+            .find(|seed| same_address(&self::address(&ed25519_public_key(seed)), address))
+            .ok_or_else(|| {
+                Error::InvalidKey(format!("{} has no Ed25519 key for {}", path, address))
+            });
+    }
+    match seeds.len() {
+        1 => Ok(seeds.remove(0)),
+        0 => Err(Error::InvalidKey(format!("{} has no Ed25519 keys", path))),
+        count => Err(Error::InvalidInput(format!(
+            "{} holds {} Ed25519 keys; pick one by its address",
+            path, count
+        ))),
+    }
+}
+
+/// Ed25519 public key of a seed
+pub fn ed25519_public_key(seed: &[u8; 32]) -> [u8; 32] {
+    EdwardsPoint::mul_base_clamped(*signing_scalar(seed))
+        .compress()
+        .to_bytes()
+}
+
+/// X25519 public key matching an Ed25519 one
+pub fn x25519_public_key(ed25519_public_key: &[u8; 32]) -> Result<MothrboxPublicKey, Error> {
+    let point = CompressedEdwardsY(*ed25519_public_key)
+        .decompress()
+        .ok_or_else(|| Error::InvalidKey("not an Ed25519 public key".to_string()))?;
+    // Small-order points would make every shared secret predictable
+    if point.is_small_order() {
+        return Err(Error::InvalidKey(
+            "Ed25519 public key has small order".to_string(),
+        ));
+    }
+    Ok(MothrboxPublicKey::X25519(
+        point.to_montgomery().to_bytes().into(),
+    ))
+}
+
+/// X25519 private key matching an Ed25519 seed: the clamped scalar Ed25519
+/// signs with, so its X25519 public key is `x25519_public_key` of the seed's
+pub fn x25519_private_key(seed: &[u8; 32]) -> LockedKey {
+    LockedKey::from(&*signing_scalar(seed))
+}
+
+/// Sui address of an Ed25519 public key: `0x` and the hex BLAKE2b-256 of
+/// `flag || key`
+pub fn address(ed25519_public_key: &[u8; 32]) -> String {
+    let mut hasher = Blake2b::<U32>::new();
+    hasher.update([ED25519_FLAG]);
+    hasher.update(ed25519_public_key);
+    format!("0x{}", hex::encode(hasher.finalize()))
+}
+
+/// Compare addresses, ignoring case and a missing `0x`
+pub fn same_address(a: &str, b: &str) -> bool {
+    let normalize = |address: &str| {
+        let address = address.trim();
+        address
+            .strip_prefix("0x")
+            .unwrap_or(address)
+            .to_ascii_lowercase()
+    };
+    normalize(a) == normalize(b)
+}
+
+/// First half of SHA-512(seed), clamped as RFC 8032 prescribes
+fn signing_scalar(seed: &[u8; 32]) -> Zeroizing<[u8; 32]> {
+    let hash = Zeroizing::new(<[u8; 64]>::from(Sha512::digest(seed)));
+    let mut scalar = Zeroizing::new([0u8; 32]);
+    scalar.copy_from_slice(&hash[..32]);
+    scalar[0] &= 248;
+    scalar[31] &= 127;
+    scalar[31] |= 64;
+    scalar
+}
+
+/// Check the scheme flag of a Sui `flag || key` encoding and drop it
+fn strip_flag(bytes: &[u8], what: &str) -> Result<[u8; 32], Error> {
+    let Some((&flag, key)) = bytes.split_first().filter(|_| bytes.len() == 33) else {
+        return Err(Error::InvalidKey(format!(
+            "Sui {} must be a scheme flag and 32 bytes, not {} bytes",
+            what,
+            bytes.len()
+        )));
+    };
+    match flag {
+        ED25519_FLAG => Ok(key.try_into().expect("32 bytes")),
+        0x01 => Err(Error::Unsupported(format!("Sui secp256k1 {}s", what))),
+        0x02 => Err(Error::Unsupported(format!("Sui secp256r1 {}s", what))),
+        flag => Err(Error::Unsupported(format!(
+            "Sui {} with scheme flag {:#04x}",
+            what, flag
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecc::{Curve, ECCEncryption};
+
+    /// RFC 8032 section 7.1, test 1
+    const SEED: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+    const PUBLIC_KEY: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+
+    fn hex32(hex: &str) -> [u8; 32] {
+        hex::decode(hex).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn test_ed25519_keys_convert_to_a_matching_x25519_pair() {
+        let seed = hex32(SEED);
+        let ed25519_public = hex32(PUBLIC_KEY);
+        assert_eq!(ed25519_public_key(&seed), ed25519_public);
+
+        let public_key = x25519_public_key(&ed25519_public).unwrap();
+        let private_key = x25519_private_key(&seed);
+        assert_eq!(Curve::X25519.public_key(&private_key).unwrap(), public_key);
+        let sealed = ECCEncryption::encrypt(b"for a Sui account", &public_key).unwrap();
+        assert_eq!(
+            ECCEncryption::decrypt(&sealed, &private_key).unwrap(),
+            b"for a Sui account"
+        );
+
+        // The identity is a valid encoding but has small order
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        assert!(x25519_public_key(&identity).is_err());
+    }
+
+    #[test]
+    fn test_sui_key_encodings() {
+        let seed = hex32(SEED);
+        let ed25519_public = hex32(PUBLIC_KEY);
+        let mut flagged = vec![ED25519_FLAG];
+        flagged.extend_from_slice(&ed25519_public);
+        for encoded in [
+            STANDARD.encode(&flagged),
+            STANDARD.encode(ed25519_public),
+            PUBLIC_KEY.to_string(),
+            format!("0x{}", PUBLIC_KEY),
+        ] {
+            assert_eq!(parse_public_key(&encoded).unwrap(), ed25519_public);
+        }
+        flagged[0] = 0x02;
+        assert!(matches!(
+            parse_public_key(&STANDARD.encode(&flagged)),
+            Err(Error::Unsupported(_))
+        ));
+
+        let mut private = vec![ED25519_FLAG];
+        private.extend_from_slice(&seed);
+        let hrp = bech32::Hrp::parse(PRIVATE_KEY_HRP).unwrap();
+        let bech32 = bech32::encode::<bech32::Bech32>(hrp, &private).unwrap();
+        assert!(bech32.starts_with("suiprivkey1"));
+        assert_eq!(*parse_private_key(&bech32).unwrap(), seed);
+        assert_eq!(
+            *parse_private_key(&STANDARD.encode(&private)).unwrap(),
+            seed
+        );
+
+        // A keystore with several keys needs the address to pick one
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = dir.path().join("sui.keystore");
+        let other_seed = [7u8; 32];
+        let mut other = vec![ED25519_FLAG];
+        other.extend_from_slice(&other_seed);
+        let secp256k1 = STANDARD.encode([0x01; 33]);
+        let entries = [
+            STANDARD.encode(&other),
+            secp256k1,
+            STANDARD.encode(&private),
+        ];
+        fs::write(&keystore, serde_json::to_string(&entries).unwrap()).unwrap();
+        let keystore = keystore.to_str().unwrap();
+
+        assert!(read_private_key(keystore, None).is_err());
+        let owner = address(&ed25519_public).to_uppercase().replace("0X", "");
+        assert_eq!(*read_private_key(keystore, Some(&owner)).unwrap(), seed);
+        assert!(read_private_key(keystore, Some("0x1234")).is_err());
+        assert_eq!(address(&ed25519_public).len(), 66);
+    }
+}
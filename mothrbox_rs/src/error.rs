@@ -0,0 +1,144 @@
+// error.rs - Error type shared by every MothrBox module
+
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while encrypting, decrypting or storing blobs
+#[derive(Debug)]
+pub enum Error {
+    /// AEAD tag or MAC check failed: wrong password / key, or tampered data
+    AuthenticationFailed,
+    /// Container header or framing could not be parsed
+    MalformedHeader(String),
+    /// Container format version this build cannot read
+    UnsupportedVersion(u8),
+    /// Algorithm, KDF or feature flag this build does not implement
+    Unsupported(String),
+    /// Ciphertext was produced by a different algorithm than the one requested
+    AlgorithmMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// Streamed ciphertext ended before its final chunk
+    Truncated(String),
+    /// Password hashing failed or its parameters are invalid
+    KeyDerivation(String),
+    /// Key material is malformed or of the wrong kind for this ciphertext
+    InvalidKey(String),
+    /// Caller passed arguments that cannot be used
+    InvalidInput(String),
+    /// The cipher refused to encrypt (e.g. message too long)
+    Encryption(String),
+    /// Local filesystem or stream error
+    Io(io::Error),
+    /// Storage backend (Walrus, ...) failed or returned an unexpected response
+    Storage {
+        backend: &'static str,
+        message: String,
+    },
+    /// Storage backend has no blob with this id (never stored, deleted or expired)
+    NotFound {
+        backend: &'static str,
+        blob_id: String,
+    },
+}
+
+impl Error {
+    pub fn storage(backend: &'static str, message: impl Into<String>) -> Self {
+        Error::Storage {
+            backend,
+            message: message.into(),
+        }
+    }
+
+    pub fn not_found(backend: &'static str, blob_id: impl Into<String>) -> Self {
+        Error::NotFound {
+            backend,
+            blob_id: blob_id.into(),
+        }
+    }
+
+    /// Wrap an IO error with a description of what was being done
+    pub fn io(context: &str, err: io::Error) -> Self {
+        Error::Io(io::Error::new(err.kind(), format!("{}: {}", context, err)))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::AuthenticationFailed => write!(
+                f,
+                "Decryption failed (wrong password or key, or corrupted data)"
+            ),
+            Error::MalformedHeader(msg) => write!(f, "Malformed ciphertext: {}", msg),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "Unsupported container version: {}", version)
+            }
+            Error::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
+            Error::AlgorithmMismatch { expected, found } => write!(
+                f,
+                "Ciphertext was encrypted with '{}', not '{}'",
+                found, expected
+            ),
+            Error::Truncated(msg) => write!(f, "Ciphertext truncated: {}", msg),
+            Error::KeyDerivation(msg) => write!(f, "Key derivation failed: {}", msg),
+            Error::InvalidKey(msg) => write!(f, "Invalid key: {}", msg),
+            Error::InvalidInput(msg) => write!(f, "{}", msg),
+            Error::Encryption(msg) => write!(f, "Encryption failed: {}", msg),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Storage { backend, message } => write!(f, "{} error: {}", backend, message),
+            Error::NotFound { backend, blob_id } => {
+                write!(f, "Blob {} not found in {}", blob_id, backend)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        // Unwrap errors that `DecryptReader` / `EncryptWriter` tunnelled through io::Error
+        if err.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = err.into_inner().expect("checked above");
+            return *inner.downcast::<Error>().expect("checked above");
+        }
+        Error::Io(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            Error::AuthenticationFailed
+            | Error::MalformedHeader(_)
+            | Error::UnsupportedVersion(_)
+            | Error::Truncated(_) => io::Error::new(io::ErrorKind::InvalidData, err),
+            _ => io::Error::other(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_survives_io_roundtrip() {
+        let io_err: io::Error = Error::AuthenticationFailed.into();
+        assert_eq!(io_err.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(Error::from(io_err), Error::AuthenticationFailed));
+
+        let plain: io::Error = io::ErrorKind::NotFound.into();
+        assert!(matches!(Error::from(plain), Error::Io(_)));
+    }
+}
@@ -1,9 +1,88 @@
 // lib.rs - Library interface for MothrBox encryption
 
+pub mod archive;
+pub mod backup;
+pub mod catalog;
+pub mod dedup;
 pub mod encryption;
+mod error;
+pub mod storage;
+pub mod walrus;
 
 // Re-export for convenience
-pub use encryption::{aes, chacha, ecc};
+#[cfg(feature = "async")]
+pub use encryption::async_io::{AsyncDecryptReader, AsyncEncryptWriter};
+pub use encryption::io::{DecryptReader, EncryptWriter};
+pub use encryption::options::EncryptOptions;
+pub use encryption::{
+    aes, atomic, chacha, compress, ecc, header, hpke, keyfile, manifest, options, rekey, secret,
+    stream, sui,
+};
+pub use error::Error;
+
+use compress::Compression;
+use header::{Algorithm, Header, KdfParams};
+use manifest::Manifest;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Key material for `EncryptWriter`
+pub enum EncryptionKey<'a> {
+    /// Password-derived key for AES / ChaCha
+    Password {
+        algorithm: Algorithm,
+        password: &'a str,
+        kdf: KdfParams,
+    },
+    /// Raw 256-bit key for AES / ChaCha
+    RawKey {
+        algorithm: Algorithm,
+        key: &'a [u8; 32],
+    },
+    /// Recipient P-256 or X25519 public key for ECC
+    EccPublicKey(&'a ecc::MothrboxPublicKey),
+    /// Several P-256 or X25519 recipients sharing one AES / ChaCha data key,
+    /// wrapped for each in the header
+    EccRecipients {
+        algorithm: Algorithm,
+        recipients: &'a [ecc::MothrboxPublicKey],
+    },
+}
+
+impl EncryptionKey<'_> {
+    /// Symmetric algorithm for data keys generated under this key (archives,
+    /// deduplicated chunks): its own, or AES-256-GCM for an ECC recipient
+    pub fn data_algorithm(&self) -> Algorithm {
+        match self {
+            EncryptionKey::Password { algorithm, .. }
+            | EncryptionKey::RawKey { algorithm, .. }
+            | EncryptionKey::EccRecipients { algorithm, .. } => *algorithm,
+            EncryptionKey::EccPublicKey(_) => Algorithm::Aes256Gcm,
+        }
+    }
+
+    /// The key that opens what this key seals, if the caller holds it: not
+    /// for an ECC recipient, whose private key is elsewhere
+    pub fn decryption_key(&self) -> Option<DecryptionKey<'_>> {
+        match self {
+            EncryptionKey::Password { password, .. } => Some(DecryptionKey::Password(password)),
+            EncryptionKey::RawKey { key, .. } => Some(DecryptionKey::RawKey(key)),
+            EncryptionKey::EccPublicKey(_) | EncryptionKey::EccRecipients { .. } => None,
+        }
+    }
+}
+
+/// Key material for the auto-detecting `decrypt` entry point
+pub enum DecryptionKey<'a> {
+    /// Password for AES / ChaCha containers
+    Password(&'a str),
+    /// Raw 256-bit key for AES / ChaCha containers
+    RawKey(&'a [u8; 32]),
+    /// Raw P-256 or X25519 private key for ECC and HPKE containers
+    EccPrivateKey(&'a [u8; 32]),
+}
 
 // Unified encryption interface
 pub fn encrypt_file(
@@ -11,24 +90,308 @@ pub fn encrypt_file(
     output_path: &str,
     password: &str,
     algorithm: &str,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     match algorithm {
         "aes" => aes::encrypt_file(input_path, output_path, password),
         "chacha" => chacha::encrypt_file(input_path, output_path, password),
-        _ => Err("Invalid algorithm (use 'aes' or 'chacha')".into()),
+        _ => Err(Error::InvalidInput(
+            "Invalid algorithm (use 'aes' or 'chacha')".to_string(),
+        )),
+    }
+}
+
+/// Encrypt into a single-shot MBX1 container with any algorithm
+pub fn encrypt(plaintext: &[u8], key: &EncryptionKey) -> Result<Vec<u8>, Error> {
+    encrypt_with_options(plaintext, key, &EncryptOptions::default())
+}
+
+/// Encrypt with the algorithm and key in `key` and the AAD, compression and
+/// manifest in `options`. A password key brings its own Argon2 cost, which
+/// replaces `options.kdf`.
+pub fn encrypt_with_options(
+    plaintext: &[u8],
+    key: &EncryptionKey,
+    options: &EncryptOptions,
+) -> Result<Vec<u8>, Error> {
+    match key {
+        EncryptionKey::Password {
+            algorithm: Algorithm::Aes256Gcm,
+            password,
+            kdf,
+        } => aes::AESEncryption::encrypt_with_options(
+            plaintext,
+            password,
+            &EncryptOptions {
+                kdf: *kdf,
+                ..*options
+            },
+        ),
+        EncryptionKey::Password {
+            algorithm: Algorithm::ChaCha20Poly1305,
+            password,
+            kdf,
+        } => chacha::ChaChaEncryption::encrypt_with_options(
+            plaintext,
+            password,
+            &EncryptOptions {
+                kdf: *kdf,
+                ..*options
+            },
+        ),
+        EncryptionKey::RawKey {
+            algorithm: Algorithm::Aes256Gcm,
+            key,
+        } => aes::AESEncryption::encrypt_with_key_and_options(plaintext, key, options),
+        EncryptionKey::RawKey {
+            algorithm: Algorithm::ChaCha20Poly1305,
+            key,
+        } => chacha::ChaChaEncryption::encrypt_with_key_and_options(plaintext, key, options),
+        EncryptionKey::EccPublicKey(public_key) => {
+            ecc::ECCEncryption::encrypt_with_options(plaintext, public_key, options)
+        }
+        EncryptionKey::EccRecipients {
+            algorithm,
+            recipients,
+        } => ecc::ECCEncryption::encrypt_for_recipients(plaintext, recipients, *algorithm, options),
+        EncryptionKey::Password { algorithm, .. } | EncryptionKey::RawKey { algorithm, .. } => {
+            Err(Error::InvalidKey(format!(
+                "'{}' encrypts to a public key, not a password or raw key",
+                algorithm.name()
+            )))
+        }
+    }
+}
+
+/// Decrypt an MBX1 container, detecting the algorithm from its header
+pub fn decrypt(encrypted_data: &[u8], key: &DecryptionKey) -> Result<Vec<u8>, Error> {
+    decrypt_with_aad(encrypted_data, key, &[])
+}
+
+/// Decrypt an MBX1 container that was bound to `aad` when encrypted
+pub fn decrypt_with_aad(
+    encrypted_data: &[u8],
+    key: &DecryptionKey,
+    aad: &[u8],
+) -> Result<Vec<u8>, Error> {
+    Ok(decrypt_with_manifest(encrypted_data, key, aad)?.1)
+}
+
+/// Decrypt an MBX1 container, also returning the manifest sealed with the data (if any)
+pub fn decrypt_with_manifest(
+    encrypted_data: &[u8],
+    key: &DecryptionKey,
+    aad: &[u8],
+) -> Result<(Option<Manifest>, Vec<u8>), Error> {
+    let (header, _) = Header::parse(encrypted_data)?;
+    header.check_aad(aad)?;
+
+    if header.is_stream() {
+        let mut plaintext = Vec::new();
+        DecryptReader::with_aad(encrypted_data, key, aad).read_to_end(&mut plaintext)?;
+        return Ok((None, plaintext));
+    }
+
+    if header.has_recipients() {
+        return match key {
+            DecryptionKey::EccPrivateKey(private_key) => {
+                let data_key = ecc::ECCEncryption::unwrap_key(&header.recipients, private_key)?;
+                match header.algorithm {
+                    Algorithm::ChaCha20Poly1305 => {
+                        chacha::ChaChaEncryption::decrypt_with_key_and_manifest(
+                            encrypted_data,
+                            &data_key,
+                            aad,
+                        )
+                    }
+                    _ => aes::AESEncryption::decrypt_with_key_and_manifest(
+                        encrypted_data,
+                        &data_key,
+                        aad,
+                    ),
+                }
+            }
+            _ => Err(Error::InvalidKey(
+                "ciphertext was encrypted for ECC recipients and needs a private key".to_string(),
+            )),
+        };
+    }
+
+    match (header.algorithm, key) {
+        (Algorithm::Aes256Gcm, DecryptionKey::Password(password)) => {
+            aes::AESEncryption::decrypt_with_manifest(encrypted_data, password, aad)
+        }
+        (Algorithm::Aes256Gcm, DecryptionKey::RawKey(key)) => {
+            aes::AESEncryption::decrypt_with_key_and_manifest(encrypted_data, key, aad)
+        }
+        (Algorithm::ChaCha20Poly1305, DecryptionKey::Password(password)) => {
+            chacha::ChaChaEncryption::decrypt_with_manifest(encrypted_data, password, aad)
+        }
+        (Algorithm::ChaCha20Poly1305, DecryptionKey::RawKey(key)) => {
+            chacha::ChaChaEncryption::decrypt_with_key_and_manifest(encrypted_data, key, aad)
+        }
+        (algorithm, DecryptionKey::EccPrivateKey(private_key)) if algorithm.is_ecies() => {
+            ecc::ECCEncryption::decrypt_with_manifest(encrypted_data, private_key, aad)
+        }
+        // Base mode only: auth mode needs the sender's key, see `hpke::decrypt`
+        (Algorithm::Hpke, DecryptionKey::EccPrivateKey(private_key)) => {
+            hpke::decrypt_with_manifest(encrypted_data, private_key, None, aad)
+        }
+        (algorithm, _) if !algorithm.is_symmetric() => Err(Error::InvalidKey(format!(
+            "ciphertext was encrypted with '{}' and needs a private key",
+            algorithm.name()
+        ))),
+        (algorithm, _) => Err(Error::InvalidKey(format!(
+            "ciphertext was encrypted with '{}' and needs a password or raw key",
+            algorithm.name()
+        ))),
     }
 }
 
-pub fn decrypt_file(
+/// Decrypt headerless data produced before the MBX1 container format
+pub fn decrypt_legacy(
+    encrypted_data: &[u8],
+    key: &DecryptionKey,
+    algorithm: &str,
+) -> Result<Vec<u8>, Error> {
+    match (algorithm, key) {
+        ("aes", DecryptionKey::Password(password)) => {
+            aes::AESEncryption::decrypt_legacy(encrypted_data, password)
+        }
+        ("chacha", DecryptionKey::Password(password)) => {
+            chacha::ChaChaEncryption::decrypt_legacy(encrypted_data, password)
+        }
+        ("aes", DecryptionKey::RawKey(key)) => {
+            aes::AESEncryption::decrypt_with_key_legacy(encrypted_data, key)
+        }
+        ("chacha", DecryptionKey::RawKey(key)) => {
+            chacha::ChaChaEncryption::decrypt_with_key_legacy(encrypted_data, key)
+        }
+        ("ecc", DecryptionKey::EccPrivateKey(private_key)) => {
+            ecc::ECCEncryption::decrypt_legacy(encrypted_data, private_key)
+        }
+        ("aes" | "chacha" | "ecc", _) => Err(Error::InvalidKey(format!(
+            "wrong key type for legacy '{}' data",
+            algorithm
+        ))),
+        _ => Err(Error::InvalidInput(
+            "Invalid algorithm (use 'aes', 'chacha' or 'ecc')".to_string(),
+        )),
+    }
+}
+
+/// Decrypt a container file, detecting the algorithm from its header
+pub fn decrypt_file(input_path: &str, output_path: &str, key: &DecryptionKey) -> Result<(), Error> {
+    decrypt_file_with_aad(input_path, output_path, key, &[])
+}
+
+/// Decrypt a container file that was bound to `aad` when encrypted
+pub fn decrypt_file_with_aad(
+    input_path: &str,
+    output_path: &str,
+    key: &DecryptionKey,
+    aad: &[u8],
+) -> Result<(), Error> {
+    if stream::peek_file_header(input_path)?.is_some_and(|header| header.is_stream()) {
+        return decrypt_stream_file(input_path, output_path, key, aad);
+    }
+
+    let encrypted =
+        fs::read(input_path).map_err(|e| Error::io("Failed to read encrypted file", e))?;
+    let plaintext = decrypt_with_aad(&encrypted, key, aad)?;
+    atomic::write_atomic(output_path, &plaintext)
+}
+
+/// Encrypt a file with a manifest recording its name, type, size, times and
+/// hash, plus `tags`
+pub fn encrypt_file_with_manifest(
+    input_path: &str,
+    output_path: &str,
+    key: &EncryptionKey,
+    aad: &[u8],
+    compression: Compression,
+    tags: BTreeMap<String, String>,
+) -> Result<(), Error> {
+    let plaintext = fs::read(input_path).map_err(|e| Error::io("Failed to read input file", e))?;
+    let manifest = Manifest {
+        tags,
+        ..Manifest::for_file(input_path, &plaintext)?
+    };
+    let options = EncryptOptions {
+        aad,
+        compression,
+        manifest: Some(&manifest),
+        ..Default::default()
+    };
+    let encrypted = encrypt_with_options(&plaintext, key, &options)?;
+    atomic::write_atomic(output_path, &encrypted)
+}
+
+/// Decrypt a container file into `output_dir` under the file name recorded in
+/// its manifest, restoring the modification time. Returns the path written
+/// and the manifest.
+pub fn decrypt_file_restoring_name(
+    input_path: &str,
+    output_dir: &str,
+    key: &DecryptionKey,
+    aad: &[u8],
+) -> Result<(PathBuf, Manifest), Error> {
+    let encrypted =
+        fs::read(input_path).map_err(|e| Error::io("Failed to read encrypted file", e))?;
+    // Check before paying for the decryption
+    if !Header::parse(&encrypted)?.0.has_manifest() {
+        return Err(Error::InvalidInput(
+            "container has no manifest to restore the file name from".to_string(),
+        ));
+    }
+
+    let (manifest, plaintext) = decrypt_with_manifest(&encrypted, key, aad)?;
+    let manifest = manifest.expect("manifest flag checked above");
+    let path = Path::new(output_dir).join(manifest.safe_filename()?);
+
+    let mut output = atomic::AtomicFile::create(&path)?;
+    output
+        .write_all(&plaintext)
+        .map_err(|e| Error::io("Failed to write output file", e))?;
+    if let Some(modified) = manifest.modified_time() {
+        output.set_modified(modified)?;
+    }
+    output.commit()?;
+    Ok((path, manifest))
+}
+
+/// Decrypt a streamed container file chunk by chunk. The output only
+/// appears once the final chunk has been authenticated.
+fn decrypt_stream_file(
+    input_path: &str,
+    output_path: &str,
+    key: &DecryptionKey,
+    aad: &[u8],
+) -> Result<(), Error> {
+    let input =
+        fs::File::open(input_path).map_err(|e| Error::io("Failed to open encrypted file", e))?;
+    let mut output = std::io::BufWriter::new(atomic::AtomicFile::create(output_path)?);
+
+    let mut reader = DecryptReader::with_aad(std::io::BufReader::new(input), key, aad);
+    std::io::copy(&mut reader, &mut output)?;
+    output
+        .into_inner()
+        .map_err(|e| Error::io("Failed to write output file", e.into_error()))?
+        .commit()
+}
+
+/// Decrypt a headerless file produced before the MBX1 container format
+pub fn decrypt_file_legacy(
     input_path: &str,
     output_path: &str,
     password: &str,
     algorithm: &str,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     match algorithm {
-        "aes" => aes::decrypt_file(input_path, output_path, password),
-        "chacha" => chacha::decrypt_file(input_path, output_path, password),
-        _ => Err("Invalid algorithm (use 'aes' or 'chacha')".into()),
+        "aes" => aes::decrypt_file_legacy(input_path, output_path, password),
+        "chacha" => chacha::decrypt_file_legacy(input_path, output_path, password),
+        _ => Err(Error::InvalidInput(
+            "Invalid algorithm (use 'aes' or 'chacha')".to_string(),
+        )),
     }
 }
 
@@ -37,7 +400,7 @@ pub fn encrypt_file_ecc(
     input_path: &str,
     output_path: &str,
     public_key_path: &str,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     ecc::encrypt_file(input_path, output_path, public_key_path)
 }
 
@@ -45,6 +408,417 @@ pub fn decrypt_file_ecc(
     input_path: &str,
     output_path: &str,
     private_key_path: &str,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     ecc::decrypt_file(input_path, output_path, private_key_path)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decrypt_detects_algorithm() {
+        let data = b"auto-detect me";
+
+        let aes_blob = aes::AESEncryption::encrypt(data, "pw").unwrap();
+        let chacha_blob = chacha::ChaChaEncryption::encrypt(data, "pw").unwrap();
+        let key = DecryptionKey::Password("pw");
+        assert_eq!(decrypt(&aes_blob, &key).unwrap(), data);
+        assert_eq!(decrypt(&chacha_blob, &key).unwrap(), data);
+
+        let (private_key, public_key) = ecc::ECCEncryption::generate_keypair();
+        let ecc_blob = ecc::ECCEncryption::encrypt(data, &public_key).unwrap();
+        assert!(decrypt(&ecc_blob, &key).is_err());
+        assert_eq!(
+            decrypt(&ecc_blob, &DecryptionKey::EccPrivateKey(&private_key)).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn test_wrong_algorithm_is_reported() {
+        let blob = chacha::ChaChaEncryption::encrypt(b"data", "pw").unwrap();
+        let err = aes::AESEncryption::decrypt(&blob, "pw").unwrap_err();
+        assert!(matches!(
+            err,
+            Error::AlgorithmMismatch {
+                expected: "aes",
+                found: "chacha"
+            }
+        ));
+
+        let err = decrypt(&blob, &DecryptionKey::Password("wrong")).unwrap_err();
+        assert!(matches!(err, Error::AuthenticationFailed));
+    }
+
+    #[test]
+    fn test_recorded_kdf_params_are_honored() {
+        let params = KdfParams {
+            m_cost: 8 * 1024,
+            t_cost: 1,
+            ..KdfParams::default()
+        };
+        let options = EncryptOptions {
+            kdf: params,
+            ..Default::default()
+        };
+        let blob = chacha::ChaChaEncryption::encrypt_with_options(b"data", "pw", &options).unwrap();
+
+        let (header, _) = Header::parse(&blob).unwrap();
+        assert!(
+            matches!(header.kdf, header::Kdf::Argon2 { params: recorded, .. } if recorded == params)
+        );
+        assert_eq!(
+            decrypt(&blob, &DecryptionKey::Password("pw")).unwrap(),
+            b"data"
+        );
+    }
+
+    #[test]
+    fn test_aad_binds_ciphertext() {
+        let key = chacha::ChaChaEncryption::generate_random_key();
+        let raw_key = DecryptionKey::RawKey(&key);
+        let aad = b"owner:0xabc";
+
+        let bound = EncryptOptions {
+            aad,
+            ..Default::default()
+        };
+        let blob =
+            chacha::ChaChaEncryption::encrypt_with_key_and_options(b"data", &key, &bound).unwrap();
+        assert_eq!(decrypt_with_aad(&blob, &raw_key, aad).unwrap(), b"data");
+        assert!(matches!(
+            decrypt_with_aad(&blob, &raw_key, b"owner:0xdef"),
+            Err(Error::AuthenticationFailed)
+        ));
+        assert!(matches!(
+            decrypt(&blob, &raw_key),
+            Err(Error::InvalidInput(_))
+        ));
+
+        let unbound = aes::AESEncryption::encrypt_with_key(b"data", &key).unwrap();
+        assert!(matches!(
+            decrypt_with_aad(&unbound, &raw_key, aad),
+            Err(Error::InvalidInput(_))
+        ));
+
+        let mut streamed = Vec::new();
+        stream::encrypt_with_key(
+            &b"data"[..],
+            &mut streamed,
+            Algorithm::Aes256Gcm,
+            &key,
+            2,
+            aad,
+        )
+        .unwrap();
+        let mut plaintext = Vec::new();
+        stream::decrypt_with_key(&streamed[..], &mut plaintext, &key, aad).unwrap();
+        assert_eq!(plaintext, b"data");
+        assert!(stream::decrypt_with_key(&streamed[..], &mut Vec::new(), &key, b"other").is_err());
+
+        let (private_key, public_key) = ecc::ECCEncryption::generate_keypair();
+        let ecc_blob =
+            ecc::ECCEncryption::encrypt_with_options(b"data", &public_key, &bound).unwrap();
+        let ecc_key = DecryptionKey::EccPrivateKey(&private_key);
+        assert_eq!(decrypt_with_aad(&ecc_blob, &ecc_key, aad).unwrap(), b"data");
+        assert!(matches!(
+            decrypt_with_aad(&ecc_blob, &ecc_key, b"other"),
+            Err(Error::AuthenticationFailed)
+        ));
+    }
+
+    #[test]
+    fn test_compressed_containers_roundtrip() {
+        let data = b"walrus bills by the byte ".repeat(400);
+        let key = aes::AESEncryption::generate_random_key();
+        let raw_key = DecryptionKey::RawKey(&key);
+
+        let plain = aes::AESEncryption::encrypt_with_key(&data, &key).unwrap();
+        let lz4 = EncryptOptions {
+            compression: compress::Compression::lz4(),
+            ..Default::default()
+        };
+        let packed =
+            chacha::ChaChaEncryption::encrypt_with_key_and_options(&data, &key, &lz4).unwrap();
+        assert!(packed.len() < plain.len() / 10);
+        assert_eq!(decrypt(&packed, &raw_key).unwrap(), data);
+
+        let mut streamed = Vec::new();
+        DecryptReader::new(&packed[..], &raw_key)
+            .read_to_end(&mut streamed)
+            .unwrap();
+        assert_eq!(streamed, data);
+
+        let (private_key, public_key) = ecc::ECCEncryption::generate_keypair();
+        let zstd = EncryptOptions {
+            compression: "zstd:19".parse().unwrap(),
+            ..Default::default()
+        };
+        let ecc_blob = ecc::ECCEncryption::encrypt_with_options(&data, &public_key, &zstd).unwrap();
+        assert!(ecc_blob.len() < plain.len() / 10);
+        assert_eq!(
+            decrypt(&ecc_blob, &DecryptionKey::EccPrivateKey(&private_key)).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn test_manifest_is_sealed_with_the_data() {
+        let data = b"board minutes";
+        let mut manifest = Manifest::for_data(Some("minutes.txt"), data);
+        manifest
+            .tags
+            .insert("meeting".to_string(), "q3".to_string());
+        let key = EncryptionKey::Password {
+            algorithm: Algorithm::ChaCha20Poly1305,
+            password: "pw",
+            kdf: Default::default(),
+        };
+        let options = EncryptOptions {
+            compression: compress::Compression::lz4(),
+            manifest: Some(&manifest),
+            ..Default::default()
+        };
+        let sealed = encrypt_with_options(data, &key, &options).unwrap();
+
+        // Only the flag is visible; the name and tags are encrypted
+        assert!(Header::parse(&sealed).unwrap().0.has_manifest());
+        assert!(!sealed.windows(11).any(|w| w == b"minutes.txt"));
+
+        let password = DecryptionKey::Password("pw");
+        assert_eq!(
+            decrypt_with_manifest(&sealed, &password, b"").unwrap(),
+            (Some(manifest), data.to_vec())
+        );
+        assert_eq!(decrypt(&sealed, &password).unwrap(), data);
+
+        let plain = encrypt(data, &key).unwrap();
+        assert_eq!(
+            decrypt_with_manifest(&plain, &password, b"").unwrap(),
+            (None, data.to_vec())
+        );
+    }
+
+    #[test]
+    fn test_ecc_recipients_share_one_payload() {
+        let keypairs: Vec<_> = (0..3)
+            .map(|_| ecc::ECCEncryption::generate_keypair())
+            .collect();
+        let recipients: Vec<_> = keypairs.iter().map(|(_, public_key)| *public_key).collect();
+        let key = EncryptionKey::EccRecipients {
+            algorithm: Algorithm::ChaCha20Poly1305,
+            recipients: &recipients,
+        };
+        let data = b"one payload, three readers";
+        let options = EncryptOptions {
+            aad: b"team",
+            compression: Compression::lz4(),
+            ..Default::default()
+        };
+        let sealed = encrypt_with_options(data, &key, &options).unwrap();
+
+        let header = Header::parse(&sealed).unwrap().0;
+        assert_eq!(header.algorithm, Algorithm::ChaCha20Poly1305);
+        assert_eq!(header.recipients.len(), 3);
+        for (private_key, _) in &keypairs {
+            let private_key = DecryptionKey::EccPrivateKey(private_key);
+            assert_eq!(
+                decrypt_with_aad(&sealed, &private_key, b"team").unwrap(),
+                data
+            );
+        }
+
+        let (outsider, _) = ecc::ECCEncryption::generate_keypair();
+        assert!(matches!(
+            decrypt_with_aad(&sealed, &DecryptionKey::EccPrivateKey(&outsider), b"team"),
+            Err(Error::InvalidKey(_))
+        ));
+        assert!(matches!(
+            decrypt_with_aad(&sealed, &DecryptionKey::Password("pw"), b"team"),
+            Err(Error::InvalidKey(_))
+        ));
+
+        // A damaged stanza only locks out its own recipient
+        let wrapped = &header.recipients[0].wrapped_key;
+        let at = sealed
+            .windows(wrapped.len())
+            .position(|w| w == &wrapped[..])
+            .unwrap();
+        let mut damaged = sealed.clone();
+        damaged[at] ^= 1;
+        let first = DecryptionKey::EccPrivateKey(&keypairs[0].0);
+        let second = DecryptionKey::EccPrivateKey(&keypairs[1].0);
+        assert!(matches!(
+            decrypt_with_aad(&damaged, &first, b"team"),
+            Err(Error::InvalidKey(_))
+        ));
+        assert_eq!(decrypt_with_aad(&damaged, &second, b"team").unwrap(), data);
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            decrypt_with_aad(&tampered, &second, b"team"),
+            Err(Error::AuthenticationFailed)
+        ));
+
+        let mut streamed = Vec::new();
+        let mut writer = EncryptWriter::with_chunk_size(&mut streamed, &key, 8).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+        let mut opened = Vec::new();
+        DecryptReader::new(&streamed[..], &second)
+            .read_to_end(&mut opened)
+            .unwrap();
+        assert_eq!(opened, data);
+        assert!(
+            DecryptReader::new(&streamed[..], &DecryptionKey::EccPrivateKey(&outsider))
+                .read_to_end(&mut Vec::new())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_ecc_works_across_curves() {
+        let (x25519_private, x25519_public) = ecc::Curve::X25519.generate_keypair();
+        let (p256_private, p256_public) = ecc::Curve::P256.generate_keypair();
+        let x25519_key = DecryptionKey::EccPrivateKey(&x25519_private);
+        let p256_key = DecryptionKey::EccPrivateKey(&p256_private);
+        let data = b"same format, either curve";
+
+        let sealed = encrypt(data, &EncryptionKey::EccPublicKey(&x25519_public)).unwrap();
+        assert_eq!(
+            Header::parse(&sealed).unwrap().0.algorithm,
+            Algorithm::EciesX25519
+        );
+        assert_eq!(decrypt(&sealed, &x25519_key).unwrap(), data);
+        assert!(decrypt(&sealed, &p256_key).is_err());
+        let mut relabeled = sealed.clone();
+        relabeled[5] = Algorithm::EciesP256.id();
+        assert!(decrypt(&relabeled, &x25519_key).is_err());
+
+        let authenticated =
+            ecc::ECCEncryption::encrypt_authenticated(data, &x25519_public, &p256_private).unwrap();
+        assert_eq!(decrypt(&authenticated, &x25519_key).unwrap(), data);
+
+        let mut streamed = Vec::new();
+        let mut writer = EncryptWriter::with_chunk_size(
+            &mut streamed,
+            &EncryptionKey::EccPublicKey(&x25519_public),
+            8,
+        )
+        .unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(decrypt(&streamed, &x25519_key).unwrap(), data);
+
+        // One envelope can mix curves; each stanza records its own
+        let recipients = [p256_public, x25519_public];
+        let envelope = encrypt(
+            data,
+            &EncryptionKey::EccRecipients {
+                algorithm: Algorithm::Aes256Gcm,
+                recipients: &recipients,
+            },
+        )
+        .unwrap();
+        let kinds: Vec<_> = Header::parse(&envelope)
+            .unwrap()
+            .0
+            .recipients
+            .iter()
+            .map(|recipient| recipient.kind)
+            .collect();
+        assert_eq!(kinds, [header::RECIPIENT_P256, header::RECIPIENT_X25519]);
+        assert_eq!(decrypt(&envelope, &x25519_key).unwrap(), data);
+        assert_eq!(decrypt(&envelope, &p256_key).unwrap(), data);
+
+        // Public keys say their curve; P-256 keys may also be compressed
+        assert_eq!(
+            ecc::MothrboxPublicKey::from_bytes(&x25519_public.to_bytes()).unwrap(),
+            x25519_public
+        );
+        let ecc::MothrboxPublicKey::P256(point) = p256_public else {
+            panic!("expected a P-256 key");
+        };
+        use ecc::ToEncodedPoint;
+        assert_eq!(
+            ecc::MothrboxPublicKey::from_bytes(point.to_encoded_point(true).as_bytes()).unwrap(),
+            p256_public
+        );
+        assert!(ecc::MothrboxPublicKey::from_bytes(&[4u8; 40]).is_err());
+        // A low-order X25519 point is refused rather than giving a known secret
+        let low_order = ecc::MothrboxPublicKey::from_bytes(&[0u8; 32]).unwrap();
+        assert!(matches!(
+            ecc::ECCEncryption::encrypt(data, &low_order),
+            Err(Error::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_ecies_v1_is_decrypt_only() {
+        // Written by the v1 scheme (AES-256-CTR + HMAC-SHA256), no longer produced
+        let private_key: [u8; 32] =
+            hex::decode("2308bd31c05f978105ce50ba3bff68d8bcb8f49f649148a91c8bd45f9f75d53e")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let v1 = hex::decode(concat!(
+            "4d425831010300000000410429f012b853244ae6fe379c9c69e59fb1ae1184bcc63a9b32",
+            "7f9fca6415fa7f39e3b8cfde6d3965ab17f66b22910d907627773508e9a9acfa9191ca83",
+            "ae80f88de72d8b87ede6bc4c1ddac7158fd65cbb3d1a9965552ce29e1c66389c7a1b843c",
+            "2a7984e7854c732d301a4a4b497a215b05e3c215cecd4f75820d4f6551aea5cad1634c",
+        ))
+        .unwrap();
+        let key = DecryptionKey::EccPrivateKey(&private_key);
+        assert_eq!(
+            Header::parse(&v1).unwrap().0.algorithm,
+            Algorithm::EciesP256V1
+        );
+        assert_eq!(decrypt(&v1, &key).unwrap(), b"written by ECIES v1");
+        assert_eq!(
+            ecc::ECCEncryption::decrypt_authenticated(&v1, &private_key).unwrap(),
+            b"written by ECIES v1"
+        );
+        let mut tampered = v1.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            decrypt(&tampered, &key),
+            Err(Error::AuthenticationFailed)
+        ));
+
+        // New ciphertexts are v2, including the static-key mode the WASM SDK uses
+        let public_key = ecc::Curve::P256.public_key(&private_key).unwrap();
+        let (sender, _) = ecc::ECCEncryption::generate_keypair();
+        for v2 in [
+            ecc::ECCEncryption::encrypt(b"v2", &public_key).unwrap(),
+            ecc::ECCEncryption::encrypt_authenticated(b"v2", &public_key, &sender).unwrap(),
+        ] {
+            assert_eq!(
+                Header::parse(&v2).unwrap().0.algorithm,
+                Algorithm::EciesP256
+            );
+            assert_eq!(decrypt(&v2, &key).unwrap(), b"v2");
+            let mut downgraded = v2.clone();
+            downgraded[5] = Algorithm::EciesP256V1.id();
+            assert!(decrypt(&downgraded, &key).is_err());
+        }
+    }
+
+    #[test]
+    fn test_legacy_raw_key_still_readable() {
+        use aes_gcm::aead::{Aead, KeyInit};
+
+        let key = aes::AESEncryption::generate_random_key();
+        let cipher = aes_gcm::Aes256Gcm::new_from_slice(&key[..]).unwrap();
+        let nonce = [3u8; 12];
+        let mut legacy = nonce.to_vec();
+        legacy.extend(cipher.encrypt((&nonce).into(), &b"old"[..]).unwrap());
+
+        assert!(aes::AESEncryption::decrypt_with_key(&legacy, &key).is_err());
+        assert_eq!(
+            aes::AESEncryption::decrypt_with_key_legacy(&legacy, &key).unwrap(),
+            b"old"
+        );
+    }
+}
@@ -1,11 +1,16 @@
-use clap::{Parser, Subcommand};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
 
-mod encryption;
-mod walrus;
+use clap::{Args, Parser, Subcommand};
 
-use encryption::{aes, chacha, ecc};
-use walrus::{encrypt_and_upload_aes, download_and_decrypt_aes, WalrusCli};
+use mothrbox_engine::backup::{self, Snapshot, SnapshotLog, SnapshotRecord};
+use mothrbox_engine::catalog::{self, Catalog, CatalogEntry};
+use mothrbox_engine::compress::Compression;
+use mothrbox_engine::dedup::{self, ChunkIndex, ChunkKeying, ChunkList, ChunkStore};
+use mothrbox_engine::header::{Algorithm, Header, Kdf, KdfParams, KdfProfile};
+use mothrbox_engine::keyfile::KeyFile;
+use mothrbox_engine::secret::Zeroizing;
+use mothrbox_engine::{aes, archive, chacha, ecc, hpke, rekey, storage, stream, sui, walrus, DecryptionKey, EncryptionKey, Error};
 
 #[derive(Parser)]
 #[command(name = "mothrbox")]
@@ -13,6 +18,10 @@ use walrus::{encrypt_and_upload_aes, download_and_decrypt_aes, WalrusCli};
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Where walrus commands store blobs: walrus, walrus-cli, local[:<dir>] or memory
+    /// (default: $MOTHRBOX_BACKEND, else walrus)
+    #[arg(long, global = true, value_name = "NAME")]
+    backend: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -32,23 +41,526 @@ enum Commands {
         #[command(subcommand)]
         action: EccCommands,
     },
+    /// HPKE (RFC 9180) public-key encryption to P-256 or X25519 keys
+    Hpke {
+        #[command(subcommand)]
+        action: HpkeCommands,
+    },
     /// Walrus decentralized storage operations
     Walrus {
         #[command(subcommand)]
         action: WalrusCommands,
     },
+    /// Decrypt any MothrBox container, detecting the algorithm from its header
+    Decrypt(DecryptArgs),
+    /// Show a container's header without decrypting it
+    Inspect {
+        /// Container file path
+        input: String,
+    },
+    /// List uploaded files recorded in the local catalog
+    Ls {
+        /// Only files with this tag
+        #[arg(long)]
+        tag: Option<String>,
+    },
+    /// Show everything the catalog knows about a file
+    Show {
+        /// Catalog name or blob ID
+        #[arg(allow_hyphen_values = true)]
+        name: String,
+    },
+    /// Forget a file in the catalog
+    Rm {
+        /// Catalog name
+        name: String,
+        /// Also delete the blob (local and memory backends; Walrus blobs expire instead)
+        #[arg(long)]
+        delete_blob: bool,
+    },
+    /// Download and decrypt a cataloged file by name
+    Get(GetArgs),
+    /// Encrypt a directory into one archive, keeping permissions, symlinks and times
+    Pack(PackArgs),
+    /// Recreate a directory from an encrypted archive
+    Unpack(UnpackArgs),
+    /// Back up a directory as a new snapshot, uploading only changed chunks
+    Backup(BackupArgs),
+    /// List backup snapshots recorded in the local snapshot log
+    Snapshots {
+        /// Only snapshots of this vault
+        #[arg(long)]
+        vault: Option<String>,
+    },
+    /// Recreate a directory as it was in a snapshot
+    Restore(RestoreArgs),
+    /// Show files added (+), removed (-) or modified (M) between two snapshots
+    Diff(DiffArgs),
+    /// Forget all but the newest snapshots of a vault
+    Prune(PruneArgs),
+    /// Grant or revoke access to a multi-recipient ECC container
+    Rekey {
+        #[command(subcommand)]
+        action: RekeyCommands,
+    },
+}
+
+#[derive(Args)]
+struct PackArgs {
+    /// Directory to pack
+    dir: String,
+    /// Archive file path
+    #[arg(required_unless_present = "upload")]
+    output: Option<String>,
+    /// Algorithm to encrypt with: aes, chacha or ecc
+    #[arg(long, value_name = "ALGO")]
+    algo: Algorithm,
+    /// Recipient public key file (ecc); repeat to encrypt once for several recipients
+    #[arg(short = 'r', long)]
+    recipient: Vec<String>,
+    /// Encrypt under a raw key from 'aes keygen' / 'chacha keygen' instead of a password
+    #[arg(long, conflicts_with_all = ["recipient", "password", "password_file", "password_fd"])]
+    key_file: Option<String>,
+    /// Deprecated: password as an argument, visible to `ps` and shell history
+    #[arg(long, requires = "insecure_password_arg")]
+    password: Option<String>,
+    #[command(flatten)]
+    password_source: PasswordArgs,
+    #[command(flatten)]
+    kdf: KdfArgs,
+    /// Only pack matching files, e.g. '*.rs' or 'src/**'; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+    /// Skip matching files and directories, e.g. 'target' or '*.log'; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+    /// Store the archive with the storage backend instead of writing a file
+    #[arg(long, conflicts_with = "output")]
+    upload: bool,
+    /// Upload one blob per file plus an encrypted index, instead of a single blob
+    #[arg(long, requires = "upload")]
+    per_file: bool,
+}
+
+#[derive(Args)]
+struct BackupArgs {
+    /// Directory to back up
+    dir: String,
+    /// Name the snapshots of this directory are kept under
+    #[arg(long)]
+    vault: String,
+    /// Algorithm to encrypt with: aes, chacha or ecc
+    #[arg(long, value_name = "ALGO")]
+    algo: Algorithm,
+    /// Recipient public key file (ecc; every file is read again, since the previous snapshot can't be);
+    /// may be repeated
+    #[arg(short = 'r', long)]
+    recipient: Vec<String>,
+    /// Encrypt under a raw key from 'aes keygen' / 'chacha keygen' instead of a password
+    #[arg(long, conflicts_with_all = ["recipient", "password", "password_file", "password_fd"])]
+    key_file: Option<String>,
+    /// Deprecated: password as an argument, visible to `ps` and shell history
+    #[arg(long, requires = "insecure_password_arg")]
+    password: Option<String>,
+    #[command(flatten)]
+    password_source: PasswordArgs,
+    #[command(flatten)]
+    kdf: KdfArgs,
+    #[command(flatten)]
+    compress: CompressArgs,
+    /// Only back up matching files, e.g. '*.rs' or 'src/**'; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+    /// Skip matching files and directories, e.g. 'target' or '*.log'; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+    /// Derive chunk keys from this key file instead of convergent keys (see 'walrus put --vault-key')
+    #[arg(long, value_name = "KEY_FILE")]
+    vault_key: Option<String>,
+}
+
+#[derive(Args)]
+struct RestoreArgs {
+    /// Snapshot ID, unique ID prefix, or 'latest'
+    snapshot: String,
+    /// Directory to restore into
+    dest: String,
+    /// Resolve 'latest' and ID prefixes among this vault's snapshots
+    #[arg(long)]
+    vault: Option<String>,
+    /// Deprecated: password as an argument, visible to `ps` and shell history
+    #[arg(long, requires = "insecure_password_arg")]
+    password: Option<String>,
+    #[command(flatten)]
+    password_source: PasswordArgs,
+    /// Private key file path (ecc snapshots)
+    #[arg(long, conflicts_with_all = ["key_file", "password", "password_file", "password_fd"])]
+    private_key: Option<String>,
+    /// Raw key file from 'aes keygen' / 'chacha keygen'
+    #[arg(long, conflicts_with_all = ["password", "password_file", "password_fd"])]
+    key_file: Option<String>,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    /// Older snapshot ID, unique ID prefix, or 'latest'
+    old: String,
+    /// Newer snapshot (default: compare OLD with the snapshot it was made against)
+    new: Option<String>,
+    /// Resolve 'latest' and ID prefixes among this vault's snapshots
+    #[arg(long)]
+    vault: Option<String>,
+    /// Deprecated: password as an argument, visible to `ps` and shell history
+    #[arg(long, requires = "insecure_password_arg")]
+    password: Option<String>,
+    #[command(flatten)]
+    password_source: PasswordArgs,
+    /// Private key file path (ecc snapshots)
+    #[arg(long, conflicts_with_all = ["key_file", "password", "password_file", "password_fd"])]
+    private_key: Option<String>,
+    /// Raw key file from 'aes keygen' / 'chacha keygen'
+    #[arg(long, conflicts_with_all = ["password", "password_file", "password_fd"])]
+    key_file: Option<String>,
+}
+
+#[derive(Args)]
+struct PruneArgs {
+    /// Vault to prune
+    #[arg(long)]
+    vault: String,
+    /// How many of the newest snapshots to keep
+    #[arg(long, value_name = "N")]
+    keep_last: usize,
+    /// Also delete the pruned snapshots and every chunk no remaining snapshot uses
+    /// (local and memory backends; needs the key of every snapshot on the backend)
+    #[arg(long)]
+    delete_blobs: bool,
+    /// Deprecated: password as an argument, visible to `ps` and shell history
+    #[arg(long, requires = "insecure_password_arg")]
+    password: Option<String>,
+    #[command(flatten)]
+    password_source: PasswordArgs,
+    /// Private key file path (ecc snapshots)
+    #[arg(long, conflicts_with_all = ["key_file", "password", "password_file", "password_fd"])]
+    private_key: Option<String>,
+    /// Raw key file from 'aes keygen' / 'chacha keygen'
+    #[arg(long, conflicts_with_all = ["password", "password_file", "password_fd"])]
+    key_file: Option<String>,
+}
+
+#[derive(Args)]
+struct UnpackArgs {
+    /// Archive file path, or blob ID with --blob
+    #[arg(allow_hyphen_values = true)]
+    input: String,
+    /// Directory to unpack into
+    dest: String,
+    /// Fetch the archive (or per-file index) from the storage backend
+    #[arg(long)]
+    blob: bool,
+    /// Deprecated: password as an argument, visible to `ps` and shell history
+    #[arg(long, requires = "insecure_password_arg")]
+    password: Option<String>,
+    #[command(flatten)]
+    password_source: PasswordArgs,
+    /// Private key file path (ecc archives)
+    #[arg(long, conflicts_with_all = ["key_file", "password", "password_file", "password_fd"])]
+    private_key: Option<String>,
+    /// Raw key file from 'aes keygen' / 'chacha keygen'
+    #[arg(long, conflicts_with_all = ["password", "password_file", "password_fd"])]
+    key_file: Option<String>,
+}
+
+#[derive(Args)]
+struct DecryptArgs {
+    /// Input file path
+    input: String,
+    /// Output file path; with --restore-name, the directory to restore into (default: .)
+    #[arg(required_unless_present = "restore_name")]
+    output: Option<String>,
+    /// Recreate the file under the name and modification time sealed in its manifest
+    #[arg(long, conflicts_with = "bind_filename")]
+    restore_name: bool,
+    /// Deprecated: password as an argument, visible to `ps` and shell history
+    #[arg(long, requires = "insecure_password_arg")]
+    password: Option<String>,
+    #[command(flatten)]
+    password_source: PasswordArgs,
+    /// Private key file path (ECC containers)
+    #[arg(long, conflicts_with_all = ["key_file", "password", "password_file", "password_fd"])]
+    private_key: Option<String>,
+    /// Raw key file from 'aes keygen' / 'chacha keygen'
+    #[arg(long, conflicts_with_all = ["password", "password_file", "password_fd"])]
+    key_file: Option<String>,
+    #[command(flatten)]
+    aad: AadArgs,
+}
+
+#[derive(Args)]
+struct GetArgs {
+    /// Catalog name or blob ID (Walrus IDs may start with '-')
+    #[arg(allow_hyphen_values = true)]
+    name: String,
+    /// Output file path (default: the uploaded file name)
+    output: Option<String>,
+    /// Deprecated: password as an argument, visible to `ps` and shell history
+    #[arg(long, requires = "insecure_password_arg")]
+    password: Option<String>,
+    #[command(flatten)]
+    password_source: PasswordArgs,
+    /// Private key file path (ecc blobs)
+    #[arg(long, conflicts_with_all = ["key_file", "password", "password_file", "password_fd"])]
+    private_key: Option<String>,
+    /// Raw key file from 'aes keygen' / 'chacha keygen'
+    #[arg(long, conflicts_with_all = ["password", "password_file", "password_fd"])]
+    key_file: Option<String>,
+    #[command(flatten)]
+    aad: AadArgs,
+}
+
+/// Where the password comes from when it is not typed at the prompt.
+/// Without any of these, `MOTHRBOX_PASSWORD` is used, then an interactive prompt.
+#[derive(Args)]
+struct PasswordArgs {
+    /// Allow the deprecated password argument
+    #[arg(long)]
+    insecure_password_arg: bool,
+    /// Read the password from the first line of a file
+    #[arg(long, conflicts_with_all = ["password", "password_fd"])]
+    password_file: Option<String>,
+    /// Read the password from the first line of an open file descriptor
+    #[arg(long, conflicts_with = "password")]
+    password_fd: Option<u32>,
+}
+
+impl PasswordArgs {
+    /// Resolve the password; `confirm` asks twice when prompting for a new one
+    fn read(&self, password: Option<String>, confirm: bool) -> Result<Zeroizing<String>, Error> {
+        if let Some(password) = password {
+            let password = Zeroizing::new(password);
+            eprintln!("⚠️  Passing the password as an argument is deprecated: use a prompt, --password-file, --password-fd or MOTHRBOX_PASSWORD");
+            return non_empty(password);
+        }
+        if let Some(path) = &self.password_file {
+            return read_first_line(path);
+        }
+        if let Some(fd) = self.password_fd {
+            return read_first_line(&format!("/dev/fd/{}", fd));
+        }
+        if let Ok(password) = std::env::var(PASSWORD_ENV) {
+            return non_empty(Zeroizing::new(password));
+        }
+
+        if !std::io::stdin().is_terminal() {
+            return Err(Error::InvalidInput(format!("No password given: use --password-file, --password-fd or {}", PASSWORD_ENV)));
+        }
+        let password = Zeroizing::new(rpassword::prompt_password("Password: ").map_err(|e| Error::io("Failed to read password", e))?);
+        if confirm {
+            let again = Zeroizing::new(rpassword::prompt_password("Confirm password: ").map_err(|e| Error::io("Failed to read password", e))?);
+            if again != password {
+                return Err(Error::InvalidInput("Passwords do not match".to_string()));
+            }
+        }
+        non_empty(password)
+    }
+}
+
+const PASSWORD_ENV: &str = "MOTHRBOX_PASSWORD";
+
+fn read_first_line(path: &str) -> Result<Zeroizing<String>, Error> {
+    let contents = Zeroizing::new(std::fs::read_to_string(path).map_err(|e| Error::io("Failed to read password", e))?);
+    non_empty(Zeroizing::new(contents.lines().next().unwrap_or("").to_string()))
+}
+
+fn non_empty(password: Zeroizing<String>) -> Result<Zeroizing<String>, Error> {
+    if password.is_empty() {
+        return Err(Error::InvalidInput("Password is empty".to_string()));
+    }
+    Ok(password)
+}
+
+/// Argon2 cost for password-based encryption; recorded in the ciphertext
+#[derive(Args)]
+struct KdfArgs {
+    /// Cost preset: interactive, moderate or sensitive
+    #[arg(long)]
+    kdf_profile: Option<KdfProfile>,
+    /// Argon2 memory cost in KiB (overrides the profile)
+    #[arg(long)]
+    kdf_memory: Option<u32>,
+    /// Argon2 iterations (overrides the profile)
+    #[arg(long)]
+    kdf_iterations: Option<u32>,
+    /// Argon2 parallelism (overrides the profile)
+    #[arg(long)]
+    kdf_parallelism: Option<u32>,
+}
+
+impl KdfArgs {
+    fn params(&self) -> Result<KdfParams, Error> {
+        let mut params = self.kdf_profile.map(KdfParams::profile).unwrap_or_default();
+        if let Some(m_cost) = self.kdf_memory {
+            params.m_cost = m_cost;
+        }
+        if let Some(t_cost) = self.kdf_iterations {
+            params.t_cost = t_cost;
+        }
+        if let Some(p_cost) = self.kdf_parallelism {
+            params.p_cost = p_cost;
+        }
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+/// Context the ciphertext is bound to. It is authenticated but not stored,
+/// so the same options must be given again to decrypt.
+#[derive(Args)]
+struct AadArgs {
+    /// Associated data, e.g. an owner address, Sui object id or Walrus epoch
+    #[arg(long)]
+    aad: Option<String>,
+    /// Bind to the plaintext file name (the input when encrypting, the output when decrypting)
+    #[arg(long)]
+    bind_filename: bool,
+}
+
+impl AadArgs {
+    /// Encoded associated data, empty when nothing is bound
+    fn bytes(&self, plaintext_path: &str) -> Vec<u8> {
+        let mut aad = Vec::new();
+        if self.bind_filename {
+            let name = std::path::Path::new(plaintext_path).file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+            // NUL cannot appear in a file name, so the name and --aad never run together
+            aad.extend_from_slice(b"filename:");
+            aad.extend_from_slice(name.as_bytes());
+            aad.push(0);
+        }
+        if let Some(extra) = &self.aad {
+            aad.extend_from_slice(extra.as_bytes());
+        }
+        aad
+    }
+}
+
+/// Compress-then-encrypt. Off unless --compress or MOTHRBOX_COMPRESS asks for it.
+#[derive(Args)]
+struct CompressArgs {
+    /// Compress before encrypting: none, lz4, zstd or zstd:<1-22>
+    #[arg(long, value_name = "CODEC[:LEVEL]")]
+    compress: Option<Compression>,
+    /// Never compress, even if MOTHRBOX_COMPRESS is set. Use it for data that mixes
+    /// secrets with attacker-controlled content, whose compressed size leaks the secret (CRIME)
+    #[arg(long, conflicts_with = "compress")]
+    no_compress: bool,
+}
+
+impl CompressArgs {
+    /// Resolve the codec: the flags, then `MOTHRBOX_COMPRESS`, then none
+    fn compression(&self) -> Result<Compression, Error> {
+        if self.no_compress {
+            return Ok(Compression::NONE);
+        }
+        if let Some(compression) = self.compress {
+            return Ok(compression);
+        }
+        match std::env::var(COMPRESS_ENV) {
+            Ok(spec) => spec.parse(),
+            Err(_) => Ok(Compression::NONE),
+        }
+    }
+}
+
+const COMPRESS_ENV: &str = "MOTHRBOX_COMPRESS";
+
+/// Seal the file's name, type, size, times and hash inside the container,
+/// readable only with the key
+#[derive(Args)]
+struct ManifestArgs {
+    /// Store an encrypted manifest describing the file
+    #[arg(long)]
+    manifest: bool,
+    /// Custom manifest entry (implies --manifest); may be repeated
+    #[arg(long = "meta", value_name = "KEY=VALUE")]
+    meta: Vec<String>,
+}
+
+impl ManifestArgs {
+    /// Manifest tags, `None` when no manifest was asked for
+    fn tags(&self) -> Result<Option<BTreeMap<String, String>>, Error> {
+        if !self.manifest && self.meta.is_empty() {
+            return Ok(None);
+        }
+        let mut tags = BTreeMap::new();
+        for entry in &self.meta {
+            match entry.split_once('=') {
+                Some((key, value)) if !key.is_empty() => {
+                    tags.insert(key.to_string(), value.to_string());
+                }
+                _ => return Err(Error::InvalidInput(format!("--meta expects KEY=VALUE, got '{}'", entry))),
+            }
+        }
+        Ok(Some(tags))
+    }
+}
+
+/// Content-defined chunking, so re-uploading a changed file only sends the changed chunks
+#[derive(Args)]
+struct DedupArgs {
+    /// Upload in deduplicated chunks, skipping chunks already stored (see ~/.mothrbox/chunks.jsonl)
+    #[arg(long, conflicts_with_all = ["manifest", "meta"])]
+    dedup: bool,
+    /// Derive chunk keys from this key file, so chunks are only shared between uploads
+    /// made with it (default: convergent keys, which show whether a known file was stored)
+    #[arg(long, requires = "dedup", value_name = "KEY_FILE")]
+    vault_key: Option<String>,
+}
+
+impl DedupArgs {
+    fn keying(&self) -> Result<ChunkKeying, Error> {
+        match &self.vault_key {
+            Some(path) => Ok(ChunkKeying::Vault(KeyFile::load(path)?.key.clone())),
+            None => Ok(ChunkKeying::Convergent),
+        }
+    }
 }
 
 #[derive(Subcommand)]
 enum AesCommands {
+    /// Generate a random 256-bit AES key file
+    Keygen {
+        /// Key file path
+        #[arg(long)]
+        out: String,
+    },
     /// Encrypt a file with AES-256-GCM
     Encrypt {
         /// Input file path
         input: String,
         /// Output file path
         output: String,
-        /// Encryption password
-        password: String,
+        /// Deprecated: password as an argument, visible to `ps` and shell history
+        #[arg(requires = "insecure_password_arg")]
+        password: Option<String>,
+        #[command(flatten)]
+        password_source: PasswordArgs,
+        /// Encrypt under a raw key from 'aes keygen' instead of a password
+        #[arg(long, conflicts_with_all = ["password", "password_file", "password_fd"])]
+        key_file: Option<String>,
+        /// Encrypt in authenticated chunks without loading the file into memory (never compressed)
+        #[arg(long, conflicts_with_all = ["compress", "manifest", "meta"])]
+        stream: bool,
+        /// Plaintext bytes per chunk in streaming mode
+        #[arg(long, default_value_t = stream::DEFAULT_CHUNK_SIZE, requires = "stream")]
+        chunk_size: u32,
+        #[command(flatten)]
+        kdf: KdfArgs,
+        #[command(flatten)]
+        aad: AadArgs,
+        #[command(flatten)]
+        compress: CompressArgs,
+        #[command(flatten)]
+        manifest: ManifestArgs,
     },
     /// Decrypt a file with AES-256-GCM
     Decrypt {
@@ -56,24 +568,74 @@ enum AesCommands {
         input: String,
         /// Output file path
         output: String,
-        /// Decryption password
-        password: String,
+        /// Deprecated: password as an argument, visible to `ps` and shell history
+        #[arg(requires = "insecure_password_arg")]
+        password: Option<String>,
+        #[command(flatten)]
+        password_source: PasswordArgs,
+        /// Raw key file used to encrypt
+        #[arg(long, conflicts_with_all = ["password", "password_file", "password_fd"])]
+        key_file: Option<String>,
+        /// Read the headerless format written before MBX1 containers
+        #[arg(long, conflicts_with_all = ["aad", "bind_filename"])]
+        legacy: bool,
+        #[command(flatten)]
+        aad: AadArgs,
     },
 }
 
 #[derive(Subcommand)]
 enum ChachaCommands {
+    /// Generate a random 256-bit ChaCha20 key file
+    Keygen {
+        /// Key file path
+        #[arg(long)]
+        out: String,
+    },
     /// Encrypt a file with ChaCha20-Poly1305
     Encrypt {
         input: String,
         output: String,
-        password: String,
+        /// Deprecated: password as an argument, visible to `ps` and shell history
+        #[arg(requires = "insecure_password_arg")]
+        password: Option<String>,
+        #[command(flatten)]
+        password_source: PasswordArgs,
+        /// Encrypt under a raw key from 'chacha keygen' instead of a password
+        #[arg(long, conflicts_with_all = ["password", "password_file", "password_fd"])]
+        key_file: Option<String>,
+        /// Encrypt in authenticated chunks without loading the file into memory (never compressed)
+        #[arg(long, conflicts_with_all = ["compress", "manifest", "meta"])]
+        stream: bool,
+        /// Plaintext bytes per chunk in streaming mode
+        #[arg(long, default_value_t = stream::DEFAULT_CHUNK_SIZE, requires = "stream")]
+        chunk_size: u32,
+        #[command(flatten)]
+        kdf: KdfArgs,
+        #[command(flatten)]
+        aad: AadArgs,
+        #[command(flatten)]
+        compress: CompressArgs,
+        #[command(flatten)]
+        manifest: ManifestArgs,
     },
     /// Decrypt a file with ChaCha20-Poly1305
     Decrypt {
         input: String,
         output: String,
-        password: String,
+        /// Deprecated: password as an argument, visible to `ps` and shell history
+        #[arg(requires = "insecure_password_arg")]
+        password: Option<String>,
+        #[command(flatten)]
+        password_source: PasswordArgs,
+        /// Raw key file used to encrypt
+        #[arg(long, conflicts_with_all = ["password", "password_file", "password_fd"])]
+        key_file: Option<String>,
+        /// Read the headerless format written before MBX1 containers
+        #[arg(long, conflicts_with_all = ["aad", "bind_filename"])]
+        legacy: bool,
+        #[command(flatten)]
+        aad: AadArgs,
     },
 }
 
@@ -82,13 +644,40 @@ enum EccCommands {
     /// Generate ECC key pair
     Keygen {
         dir: String,
+        /// Curve: p256, or x25519
+        #[arg(long, default_value = "p256")]
+        curve: ecc::Curve,
+    },
+    /// Derive an X25519 key from a Sui Ed25519 key, to encrypt to a Sui account
+    FromSui {
+        dir: String,
+        /// The account's published key (base64 flag || key as 'sui keytool list' prints it, or hex); writes public.key
+        #[arg(long, value_name = "KEY", required_unless_present = "keystore", conflicts_with = "keystore")]
+        public_key: Option<String>,
+        /// sui.keystore, or a file holding one suiprivkey1... key; writes private.key and public.key
+        #[arg(long, value_name = "FILE")]
+        keystore: Option<String>,
+        /// Sui address the key must belong to; picks the key from a keystore holding several
+        #[arg(long)]
+        address: Option<String>,
     },
     /// Encrypt a file with ECC public key
     Encrypt {
         input: String,
         output: String,
         /// Public key file path
-        public_key: String,
+        #[arg(required_unless_present = "recipient")]
+        public_key: Option<String>,
+        /// Encrypt once for several recipients instead; may be repeated
+        #[arg(short = 'r', long, value_name = "PUBLIC_KEY", conflicts_with = "public_key")]
+        recipient: Vec<String>,
+        /// Cipher for the payload shared by the recipients: aes (default) or chacha
+        #[arg(long, value_name = "ALGO", requires = "recipient")]
+        algo: Option<Algorithm>,
+        #[command(flatten)]
+        compress: CompressArgs,
+        #[command(flatten)]
+        manifest: ManifestArgs,
     },
     /// Decrypt a file with ECC private key
     Decrypt {
@@ -96,6 +685,96 @@ enum EccCommands {
         output: String,
         /// Private key file path
         private_key: String,
+        /// Read the headerless format written before MBX1 containers
+        #[arg(long)]
+        legacy: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum HpkeCommands {
+    /// Generate an HPKE key pair (private.key, public.key)
+    Keygen {
+        dir: String,
+        /// Key type: x25519, or p256 (the same key files as 'ecc keygen')
+        #[arg(long, default_value = "x25519")]
+        kem: hpke::Kem,
+    },
+    /// Encrypt a file to a P-256 or X25519 public key
+    Encrypt {
+        input: String,
+        output: String,
+        /// Recipient public key file path
+        public_key: String,
+        /// Sender private key file (same key type): auth mode, so the recipient can tell who sent it
+        #[arg(long, value_name = "PRIVATE_KEY")]
+        sender: Option<String>,
+        /// AEAD: aes (AES-256-GCM) or chacha (ChaCha20-Poly1305)
+        #[arg(long, value_name = "ALGO", default_value = "aes")]
+        algo: Algorithm,
+        #[command(flatten)]
+        aad: AadArgs,
+        #[command(flatten)]
+        compress: CompressArgs,
+    },
+    /// Decrypt a file with a P-256 or X25519 private key
+    Decrypt {
+        input: String,
+        output: String,
+        /// Private key file path
+        private_key: String,
+        /// Sender public key file, required for files encrypted in auth mode
+        #[arg(long, value_name = "PUBLIC_KEY")]
+        sender: Option<String>,
+        #[command(flatten)]
+        aad: AadArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum RekeyCommands {
+    /// Wrap the data key for more recipients, rewriting only the header
+    AddRecipient {
+        /// Container file path, rewritten in place unless --output is given
+        file: String,
+        /// Public key file of a recipient to add; may be repeated
+        #[arg(short = 'r', long, required = true)]
+        recipient: Vec<String>,
+        /// Private key file of a current recipient, to unwrap the data key
+        #[arg(long)]
+        private_key: String,
+        /// Write the result here instead
+        #[arg(long)]
+        output: Option<String>,
+        /// Associated data the container is bound to (single-recipient containers are re-encrypted)
+        #[arg(long)]
+        aad: Option<String>,
+    },
+    /// Drop recipients from the header; --rotate also re-encrypts so they are locked out for good
+    RemoveRecipient {
+        /// Container file path, rewritten in place unless --output is given
+        file: String,
+        /// Public key file of a recipient to remove; may be repeated
+        #[arg(short = 'r', long, required_unless_present = "key_id")]
+        recipient: Vec<String>,
+        /// Key id of a recipient to remove, as shown by inspect; may be repeated
+        #[arg(long, value_name = "HEX")]
+        key_id: Vec<String>,
+        /// Re-encrypt under a fresh data key for the remaining recipients
+        #[arg(long, requires_all = ["private_key", "keep"])]
+        rotate: bool,
+        /// Private key file of a remaining recipient (--rotate)
+        #[arg(long, requires = "rotate")]
+        private_key: Option<String>,
+        /// Public key file of every remaining recipient (--rotate); may be repeated
+        #[arg(long, value_name = "PUBLIC_KEY", requires = "rotate")]
+        keep: Vec<String>,
+        /// Write the result here instead
+        #[arg(long)]
+        output: Option<String>,
+        /// Associated data the container is bound to (--rotate)
+        #[arg(long, requires = "rotate")]
+        aad: Option<String>,
     },
 }
 
@@ -109,36 +788,76 @@ enum WalrusCommands {
     /// Download file from Walrus (raw)
     Download {
         /// Blob ID
+        #[arg(allow_hyphen_values = true)]
         blob_id: String,
         /// Output file path
         output: String,
     },
-    /// Encrypt with AES and upload to Walrus
-    UploadAes {
+    /// Encrypt a file and upload it
+    Put {
         /// File to encrypt and upload
         file: String,
-        /// Encryption password
-        password: String,
+        /// Algorithm to encrypt with: aes, chacha or ecc
+        #[arg(long, value_name = "ALGO")]
+        algo: Algorithm,
+        /// Recipient public key file (ecc); repeat to encrypt once for several recipients
+        #[arg(short = 'r', long)]
+        recipient: Vec<String>,
+        /// Encrypt under a raw key from 'aes keygen' / 'chacha keygen' instead of a password
+        #[arg(long, conflicts_with_all = ["recipient", "password", "password_file", "password_fd"])]
+        key_file: Option<String>,
+        /// Deprecated: password as an argument, visible to `ps` and shell history
+        #[arg(long, requires = "insecure_password_arg")]
+        password: Option<String>,
+        #[command(flatten)]
+        password_source: PasswordArgs,
+        #[command(flatten)]
+        kdf: KdfArgs,
+        #[command(flatten)]
+        aad: AadArgs,
+        #[command(flatten)]
+        compress: CompressArgs,
+        #[command(flatten)]
+        manifest: ManifestArgs,
+        #[command(flatten)]
+        dedup: DedupArgs,
+        /// Name to record the upload under in the local catalog (default: the file name)
+        #[arg(long)]
+        name: Option<String>,
+        /// Tag the catalog entry; may be repeated
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Do not record the upload in the local catalog
+        #[arg(long, conflicts_with_all = ["name", "tags"])]
+        no_catalog: bool,
     },
-    /// Download from Walrus and decrypt with AES
-    DownloadAes {
+    /// Download a blob and decrypt it
+    #[command(group(clap::ArgGroup::new("detect").args(["auto", "algo"]).required(true)))]
+    Get {
         /// Blob ID
+        #[arg(allow_hyphen_values = true)]
         blob_id: String,
         /// Output file path
         output: String,
-        /// Decryption password
-        password: String,
-    },
-    /// Encrypt with ChaCha20 and upload to Walrus
-    UploadChacha {
-        file: String,
-        password: String,
-    },
-    /// Download from Walrus and decrypt with ChaCha20
-    DownloadChacha {
-        blob_id: String,
-        output: String,
-        password: String,
+        /// Accept whichever algorithm the header names
+        #[arg(long)]
+        auto: bool,
+        /// Refuse blobs not encrypted with this algorithm
+        #[arg(long, value_name = "ALGO")]
+        algo: Option<Algorithm>,
+        /// Deprecated: password as an argument, visible to `ps` and shell history
+        #[arg(long, requires = "insecure_password_arg")]
+        password: Option<String>,
+        #[command(flatten)]
+        password_source: PasswordArgs,
+        /// Private key file path (ecc blobs)
+        #[arg(long, conflicts_with_all = ["key_file", "password", "password_file", "password_fd"])]
+        private_key: Option<String>,
+        /// Raw key file from 'aes keygen' / 'chacha keygen'
+        #[arg(long, conflicts_with_all = ["password", "password_file", "password_fd"])]
+        key_file: Option<String>,
+        #[command(flatten)]
+        aad: AadArgs,
     },
 }
 
@@ -149,7 +868,22 @@ fn main() {
         Commands::Aes { action } => handle_aes(action),
         Commands::Chacha { action } => handle_chacha(action),
         Commands::Ecc { action } => handle_ecc(action),
-        Commands::Walrus { action } => handle_walrus(action),
+        Commands::Hpke { action } => handle_hpke(action),
+        Commands::Walrus { action } => handle_walrus(action, cli.backend),
+        Commands::Decrypt(args) => handle_decrypt(args),
+        Commands::Inspect { input } => handle_inspect(input),
+        Commands::Ls { tag } => handle_ls(tag),
+        Commands::Show { name } => handle_show(name),
+        Commands::Rm { name, delete_blob } => handle_rm(name, delete_blob, cli.backend),
+        Commands::Get(args) => handle_get(args, cli.backend),
+        Commands::Pack(args) => handle_pack(args, cli.backend),
+        Commands::Unpack(args) => handle_unpack(args, cli.backend),
+        Commands::Backup(args) => handle_backup(args, cli.backend),
+        Commands::Snapshots { vault } => handle_snapshots(vault),
+        Commands::Restore(args) => handle_restore(args, cli.backend),
+        Commands::Diff(args) => handle_diff(args, cli.backend),
+        Commands::Prune(args) => handle_prune(args),
+        Commands::Rekey { action } => handle_rekey(action),
     };
 
     match result {
@@ -160,103 +894,802 @@ fn main() {
         }
         Err(e) => {
             eprintln!("❌ Error: {}", e);
-            std::process::exit(1);
+            std::process::exit(exit_code(&e));
+        }
+    }
+}
+
+/// Process exit status for each kind of failure, so scripts can tell a wrong
+/// password apart from a corrupt file or a storage outage
+fn exit_code(error: &Error) -> i32 {
+    match error {
+        Error::AuthenticationFailed => 3,
+        Error::MalformedHeader(_)
+        | Error::UnsupportedVersion(_)
+        | Error::Unsupported(_)
+        | Error::AlgorithmMismatch { .. }
+        | Error::Truncated(_) => 4,
+        Error::InvalidKey(_) | Error::KeyDerivation(_) => 5,
+        Error::Io(_) => 6,
+        Error::Storage { .. } | Error::NotFound { .. } => 7,
+        Error::InvalidInput(_) | Error::Encryption(_) => 1,
+    }
+}
+
+fn handle_aes(action: AesCommands) -> Result<String, Error> {
+    match action {
+        AesCommands::Keygen { out } => {
+            let key_file = KeyFile::generate(Algorithm::Aes256Gcm)?;
+            key_file.save(&out)?;
+            Ok(format!("Generated: {} (key id {})", out, key_file.key_id()))
+        }
+        AesCommands::Encrypt { input, output, password, password_source, key_file, stream, chunk_size, kdf, aad, compress, manifest } => {
+            let aad = aad.bytes(&input);
+            let tags = manifest.tags()?;
+            match key_file {
+                Some(key_file) => {
+                    let key_file = KeyFile::load(&key_file)?;
+                    let key = key_file.expect_algorithm(Algorithm::Aes256Gcm)?;
+                    if stream {
+                        aes::encrypt_file_stream_with_key(&input, &output, key, chunk_size, &aad)?;
+                    } else if let Some(tags) = tags {
+                        mothrbox_engine::encrypt_file_with_manifest(&input, &output, &EncryptionKey::RawKey { algorithm: Algorithm::Aes256Gcm, key }, &aad, compress.compression()?, tags)?;
+                    } else {
+                        aes::encrypt_file_with_key(&input, &output, key, &aad, compress.compression()?)?;
+                    }
+                }
+                None => {
+                    let params = kdf.params()?;
+                    let password = password_source.read(password, true)?;
+                    if stream {
+                        aes::encrypt_file_stream(&input, &output, &password, params, chunk_size, &aad)?;
+                    } else if let Some(tags) = tags {
+                        mothrbox_engine::encrypt_file_with_manifest(&input, &output, &EncryptionKey::Password { algorithm: Algorithm::Aes256Gcm, password: &password, kdf: params }, &aad, compress.compression()?, tags)?;
+                    } else {
+                        aes::encrypt_file_with_params(&input, &output, &password, params, &aad, compress.compression()?)?;
+                    }
+                }
+            }
+            Ok(format!("Encrypted: {} -> {}", input, output))
+        }
+        AesCommands::Decrypt { input, output, password, password_source, key_file, legacy, aad } => {
+            let aad = aad.bytes(&output);
+            match key_file {
+                Some(key_file) => {
+                    let key_file = KeyFile::load(&key_file)?;
+                    let key = key_file.expect_algorithm(Algorithm::Aes256Gcm)?;
+                    if legacy {
+                        decrypt_file_legacy_with_key(&input, &output, key, "aes")?;
+                    } else {
+                        aes::decrypt_file_with_key(&input, &output, key, &aad)?;
+                    }
+                }
+                None => {
+                    let password = password_source.read(password, false)?;
+                    if legacy {
+                        aes::decrypt_file_legacy(&input, &output, &password)?;
+                    } else {
+                        aes::decrypt_file_with_aad(&input, &output, &password, &aad)?;
+                    }
+                }
+            }
+            Ok(format!("Decrypted: {} -> {}", input, output))
         }
     }
 }
 
-fn handle_aes(action: AesCommands) -> Result<String, String> {
+fn handle_chacha(action: ChachaCommands) -> Result<String, Error> {
     match action {
-        AesCommands::Encrypt { input, output, password } => {
-            aes::encrypt_file(&input, &output, &password)?;
+        ChachaCommands::Keygen { out } => {
+            let key_file = KeyFile::generate(Algorithm::ChaCha20Poly1305)?;
+            key_file.save(&out)?;
+            Ok(format!("Generated: {} (key id {})", out, key_file.key_id()))
+        }
+        ChachaCommands::Encrypt { input, output, password, password_source, key_file, stream, chunk_size, kdf, aad, compress, manifest } => {
+            let aad = aad.bytes(&input);
+            let tags = manifest.tags()?;
+            match key_file {
+                Some(key_file) => {
+                    let key_file = KeyFile::load(&key_file)?;
+                    let key = key_file.expect_algorithm(Algorithm::ChaCha20Poly1305)?;
+                    if stream {
+                        chacha::encrypt_file_stream_with_key(&input, &output, key, chunk_size, &aad)?;
+                    } else if let Some(tags) = tags {
+                        mothrbox_engine::encrypt_file_with_manifest(&input, &output, &EncryptionKey::RawKey { algorithm: Algorithm::ChaCha20Poly1305, key }, &aad, compress.compression()?, tags)?;
+                    } else {
+                        chacha::encrypt_file_with_key(&input, &output, key, &aad, compress.compression()?)?;
+                    }
+                }
+                None => {
+                    let params = kdf.params()?;
+                    let password = password_source.read(password, true)?;
+                    if stream {
+                        chacha::encrypt_file_stream(&input, &output, &password, params, chunk_size, &aad)?;
+                    } else if let Some(tags) = tags {
+                        mothrbox_engine::encrypt_file_with_manifest(&input, &output, &EncryptionKey::Password { algorithm: Algorithm::ChaCha20Poly1305, password: &password, kdf: params }, &aad, compress.compression()?, tags)?;
+                    } else {
+                        chacha::encrypt_file_with_params(&input, &output, &password, params, &aad, compress.compression()?)?;
+                    }
+                }
+            }
             Ok(format!("Encrypted: {} -> {}", input, output))
         }
-        AesCommands::Decrypt { input, output, password } => {
-            aes::decrypt_file(&input, &output, &password)?;
+        ChachaCommands::Decrypt { input, output, password, password_source, key_file, legacy, aad } => {
+            let aad = aad.bytes(&output);
+            match key_file {
+                Some(key_file) => {
+                    let key_file = KeyFile::load(&key_file)?;
+                    let key = key_file.expect_algorithm(Algorithm::ChaCha20Poly1305)?;
+                    if legacy {
+                        decrypt_file_legacy_with_key(&input, &output, key, "chacha")?;
+                    } else {
+                        chacha::decrypt_file_with_key(&input, &output, key, &aad)?;
+                    }
+                }
+                None => {
+                    let password = password_source.read(password, false)?;
+                    if legacy {
+                        chacha::decrypt_file_legacy(&input, &output, &password)?;
+                    } else {
+                        chacha::decrypt_file_with_aad(&input, &output, &password, &aad)?;
+                    }
+                }
+            }
             Ok(format!("Decrypted: {} -> {}", input, output))
         }
     }
 }
 
-fn handle_chacha(action: ChachaCommands) -> Result<String, String> {
+fn handle_ecc(action: EccCommands) -> Result<String, Error> {
     match action {
-        ChachaCommands::Encrypt { input, output, password } => {
-            chacha::encrypt_file(&input, &output, &password)?;
+        EccCommands::Keygen { dir, curve } => {
+            let public_key = ecc::generate_keypair(curve, &format!("{dir}/private.key"), &format!("{dir}/public.key"))?;
+            Ok(format!("Generated {} key pair: private.key, public.key (key id {})", curve.name(), hex::encode(ecc::ECCEncryption::key_id(&public_key))))
+        }
+        EccCommands::FromSui { dir, public_key, keystore, address } => {
+            let seed = keystore.map(|keystore| sui::read_private_key(&keystore, address.as_deref())).transpose()?;
+            let ed25519_public = match &seed {
+                Some(seed) => sui::ed25519_public_key(seed),
+                None => sui::parse_public_key(&public_key.expect("clap requires --public-key without --keystore"))?,
+            };
+            let owner = sui::address(&ed25519_public);
+            if let Some(address) = address.filter(|address| !sui::same_address(address, &owner)) {
+                return Err(Error::InvalidKey(format!("public key belongs to {}, not {}", owner, address)));
+            }
+            let public_key = sui::x25519_public_key(&ed25519_public)?;
+            let written = match &seed {
+                Some(seed) => {
+                    ecc::write_keypair(&sui::x25519_private_key(seed), &public_key, &format!("{dir}/private.key"), &format!("{dir}/public.key"))?;
+                    "private.key, public.key"
+                }
+                None => {
+                    std::fs::write(format!("{dir}/public.key"), public_key.to_bytes()).map_err(|e| Error::io("Failed to write public key", e))?;
+                    "public.key"
+                }
+            };
+            Ok(format!("Derived x25519 key for Sui address {}: {} (key id {})", owner, written, hex::encode(ecc::ECCEncryption::key_id(&public_key))))
+        }
+        EccCommands::Encrypt { input, output, public_key, recipient, algo, compress, manifest } => {
+            if !recipient.is_empty() {
+                let recipients = read_public_keys(&recipient)?;
+                let key = EncryptionKey::EccRecipients { algorithm: algo.unwrap_or(Algorithm::Aes256Gcm), recipients: &recipients };
+                match manifest.tags()? {
+                    Some(tags) => mothrbox_engine::encrypt_file_with_manifest(&input, &output, &key, &[], compress.compression()?, tags)?,
+                    None => ecc::encrypt_file_for_recipients(&input, &output, &recipients, key.data_algorithm(), compress.compression()?)?,
+                }
+                return Ok(format!("Encrypted for {} recipients: {} -> {}", recipients.len(), input, output));
+            }
+            let public_key = public_key.expect("clap requires a public key without --recipient");
+            match manifest.tags()? {
+                Some(tags) => {
+                    let public_key = ecc::read_public_key(&public_key)?;
+                    mothrbox_engine::encrypt_file_with_manifest(&input, &output, &EncryptionKey::EccPublicKey(&public_key), &[], compress.compression()?, tags)?;
+                }
+                None => ecc::encrypt_file_compressed(&input, &output, &public_key, compress.compression()?)?,
+            }
             Ok(format!("Encrypted: {} -> {}", input, output))
         }
-        ChachaCommands::Decrypt { input, output, password } => {
-            chacha::decrypt_file(&input, &output, &password)?;
+        EccCommands::Decrypt { input, output, private_key, legacy } => {
+            if legacy {
+                ecc::decrypt_file_legacy(&input, &output, &private_key)?;
+            } else {
+                ecc::decrypt_file(&input, &output, &private_key)?;
+            }
             Ok(format!("Decrypted: {} -> {}", input, output))
         }
     }
 }
 
-fn handle_ecc(action: EccCommands) -> Result<String, String> {
+fn handle_hpke(action: HpkeCommands) -> Result<String, Error> {
     match action {
-        EccCommands::Keygen {dir} => {
-            
-            ecc::generate_keypair(format!("{dir}/private.key").as_str(), format!("{dir}/public.key").as_str())?;
-            Ok("Generated: private.key, public.key".to_string())
+        HpkeCommands::Keygen { dir, kem } => {
+            hpke::generate_keypair(kem, &format!("{dir}/private.key"), &format!("{dir}/public.key"))?;
+            Ok(format!("Generated {} key pair: private.key, public.key", kem.name()))
         }
-        EccCommands::Encrypt { input, output, public_key } => {
-            ecc::encrypt_file(&input, &output, &public_key)?;
+        HpkeCommands::Encrypt { input, output, public_key, sender, algo, aad, compress } => {
+            let sender = sender.map(|path| ecc::read_private_key(&path)).transpose()?;
+            let aad = aad.bytes(&input);
+            hpke::encrypt_file(&input, &output, &public_key, sender.as_deref(), algo, &aad, compress.compression()?)?;
             Ok(format!("Encrypted: {} -> {}", input, output))
         }
-        EccCommands::Decrypt { input, output, private_key } => {
-            ecc::decrypt_file(&input, &output, &private_key)?;
+        HpkeCommands::Decrypt { input, output, private_key, sender, aad } => {
+            let private_key = ecc::read_private_key(&private_key)?;
+            let sender = sender.map(|path| hpke::read_public_key(&path)).transpose()?;
+            let aad = aad.bytes(&output);
+            hpke::decrypt_file(&input, &output, &private_key, sender.as_ref(), &aad)?;
             Ok(format!("Decrypted: {} -> {}", input, output))
         }
     }
 }
 
-fn handle_walrus(action: WalrusCommands) -> Result<String, String> {
-    let cli = WalrusCli::new();
-    
+fn handle_decrypt(args: DecryptArgs) -> Result<String, Error> {
+    let DecryptArgs { input, output, restore_name, password, password_source, private_key, key_file, aad } = args;
+    if restore_name {
+        let dir = output.unwrap_or_else(|| ".".to_string());
+        let aad = aad.bytes("");
+        let (path, manifest) = with_decryption_key(password, &password_source, private_key, key_file, |key| {
+            mothrbox_engine::decrypt_file_restoring_name(&input, &dir, key, &aad)
+        })?;
+        for (key, value) in &manifest.tags {
+            println!("   {} = {}", key, value);
+        }
+        return Ok(format!("Decrypted: {} -> {}", input, path.display()));
+    }
+
+    let output = output.expect("clap requires an output without --restore-name");
+    let aad = aad.bytes(&output);
+    with_decryption_key(password, &password_source, private_key, key_file, |key| {
+        mothrbox_engine::decrypt_file_with_aad(&input, &output, key, &aad)
+    })?;
+    Ok(format!("Decrypted: {} -> {}", input, output))
+}
+
+/// Everything a container reveals without the key
+fn handle_inspect(input: String) -> Result<String, Error> {
+    let header = stream::peek_file_header(&input)?.ok_or_else(|| Error::MalformedHeader(format!("{} is not an MBX1 container", input)))?;
+    let size = std::fs::metadata(&input).map_err(|e| Error::io("Failed to read file metadata", e))?.len();
+    println!("File:        {} ({} bytes)", input, size);
+    println!("Format:      MBX1 version {}", header.version);
+    match header.algorithm {
+        Algorithm::EciesP256V1 => println!("Algorithm:   ecc (ECIES v1, decrypt-only; encrypt again to upgrade)"),
+        Algorithm::EciesX25519 => println!("Algorithm:   ecc (X25519)"),
+        Algorithm::Hpke => {
+            let container = std::fs::read(&input).map_err(|e| Error::io("Failed to read input file", e))?;
+            let (_, header_len) = Header::parse(&container)?;
+            println!("Algorithm:   hpke ({})", hpke::Suite::parse(&container[header_len..])?.describe());
+        }
+        algorithm => println!("Algorithm:   {}", algorithm.name()),
+    }
+    match &header.kdf {
+        Kdf::Argon2 { params, .. } => println!("KDF:         {} (m={} KiB, t={}, p={})", params.algorithm.name(), params.m_cost, params.t_cost, params.p_cost),
+        Kdf::None => println!("KDF:         none (raw or public key)"),
+    }
+    if let Some(chunk_size) = header.chunk_size {
+        println!("Streamed:    {} byte chunks", chunk_size);
+    }
+    println!("Compression: {}", header.codec.name());
+    if header.requires_aad() {
+        println!("AAD:         required (pass the same --aad / --bind-filename)");
+    }
+    if header.has_manifest() {
+        println!("Manifest:    present (encrypted; decrypt with --restore-name to use it)");
+    }
+    if header.is_chunk_list() {
+        println!("Contents:    chunk list of a deduplicated upload (fetch with get)");
+    }
+    if header.has_recipients() {
+        println!("Recipients:  {}", header.recipients.len());
+        for recipient in &header.recipients {
+            println!("   key id {}", hex::encode(recipient.key_id));
+        }
+    }
+    Ok(String::new())
+}
+
+fn handle_rekey(action: RekeyCommands) -> Result<String, Error> {
+    match action {
+        RekeyCommands::AddRecipient { file, recipient, private_key, output, aad } => {
+            let container = std::fs::read(&file).map_err(|e| Error::io("Failed to read container", e))?;
+            let private_key = ecc::read_private_key(&private_key)?;
+            let rekeyed = rekey::add_recipients(&container, &private_key, &read_public_keys(&recipient)?, aad.unwrap_or_default().as_bytes())?;
+            write_rekeyed(&file, output, &rekeyed)
+        }
+        RekeyCommands::RemoveRecipient { file, recipient, key_id, rotate, private_key, keep, output, aad } => {
+            let container = std::fs::read(&file).map_err(|e| Error::io("Failed to read container", e))?;
+            let mut key_ids: Vec<[u8; 8]> = read_public_keys(&recipient)?.iter().map(ecc::ECCEncryption::key_id).collect();
+            for key_id in &key_id {
+                let parsed = hex::decode(key_id).ok().and_then(|bytes| bytes.try_into().ok());
+                key_ids.push(parsed.ok_or_else(|| Error::InvalidInput(format!("key id must be 16 hex digits, got '{}'", key_id)))?);
+            }
+            let removed = rekey::remove_recipients(&container, &key_ids)?;
+            if !rotate {
+                eprintln!("⚠️  Removed recipients who kept a copy or its data key can still read it: use --rotate to lock them out");
+                return write_rekeyed(&file, output, &removed);
+            }
+
+            // The fresh data key is wrapped for exactly the recipients still listed
+            let keep = read_public_keys(&keep)?;
+            let mut kept: Vec<_> = keep.iter().map(ecc::ECCEncryption::key_id).collect();
+            let mut remaining: Vec<_> = Header::parse(&removed)?.0.recipients.iter().map(|stanza| stanza.key_id).collect();
+            kept.sort();
+            kept.dedup();
+            remaining.sort();
+            if kept != remaining {
+                let ids: Vec<_> = remaining.iter().map(hex::encode).collect();
+                return Err(Error::InvalidInput(format!("--keep must list the public key of each remaining recipient (key ids {})", ids.join(", "))));
+            }
+            let private_key = ecc::read_private_key(&private_key.expect("clap requires --private-key with --rotate"))?;
+            let rotated = rekey::rotate(&removed, &private_key, &keep, aad.unwrap_or_default().as_bytes())?;
+            write_rekeyed(&file, output, &rotated)
+        }
+    }
+}
+
+fn write_rekeyed(file: &str, output: Option<String>, container: &[u8]) -> Result<String, Error> {
+    let output = output.unwrap_or_else(|| file.to_string());
+    mothrbox_engine::atomic::write_atomic(&output, container)?;
+    let recipients = Header::parse(container)?.0.recipients.len();
+    Ok(format!("Rekeyed: {} -> {} ({} recipients)", file, output, recipients))
+}
+
+/// Load the key for an auto-detecting decrypt: a private key, a raw key file or a password
+fn with_decryption_key<T>(
+    password: Option<String>,
+    password_source: &PasswordArgs,
+    private_key: Option<String>,
+    key_file: Option<String>,
+    decrypt: impl FnOnce(&DecryptionKey) -> Result<T, Error>,
+) -> Result<T, Error> {
+    match (private_key, key_file) {
+        (Some(private_key_path), None) => {
+            let private_key = ecc::read_private_key(&private_key_path)?;
+            decrypt(&DecryptionKey::EccPrivateKey(&private_key))
+        }
+        (None, Some(key_file)) => {
+            let key_file = KeyFile::load(&key_file)?;
+            decrypt(&DecryptionKey::RawKey(&key_file.key))
+        }
+        _ => {
+            let password = password_source.read(password, false)?;
+            decrypt(&DecryptionKey::Password(&password))
+        }
+    }
+}
+
+/// Load the key to encrypt with `algorithm`: a recipient public key for ecc,
+/// recipients sharing a data key (for several, or with aes / chacha), otherwise
+/// a raw key file or a (confirmed) password
+fn with_encryption_key<T>(
+    algorithm: Algorithm,
+    recipient: Vec<String>,
+    key_file: Option<String>,
+    password: Option<String>,
+    password_source: &PasswordArgs,
+    kdf: &KdfArgs,
+    encrypt: impl FnOnce(&EncryptionKey) -> Result<T, Error>,
+) -> Result<T, Error> {
+    match (algorithm, recipient.as_slice(), key_file) {
+        (Algorithm::EciesP256, [recipient], None) => {
+            let public_key = ecc::read_public_key(recipient)?;
+            encrypt(&EncryptionKey::EccPublicKey(&public_key))
+        }
+        (Algorithm::EciesP256, [], _) => Err(Error::InvalidInput("'ecc' needs --recipient <public key file>".to_string())),
+        (algorithm, [_, ..], None) => {
+            let recipients = read_public_keys(&recipient)?;
+            let algorithm = if algorithm == Algorithm::EciesP256 { Algorithm::Aes256Gcm } else { algorithm };
+            encrypt(&EncryptionKey::EccRecipients { algorithm, recipients: &recipients })
+        }
+        (algorithm, _, Some(key_file)) => {
+            let key_file = KeyFile::load(&key_file)?;
+            let key = key_file.expect_algorithm(algorithm)?;
+            encrypt(&EncryptionKey::RawKey { algorithm, key })
+        }
+        (algorithm, _, None) => {
+            let kdf = kdf.params()?;
+            let password = password_source.read(password, true)?;
+            encrypt(&EncryptionKey::Password { algorithm, password: &password, kdf })
+        }
+    }
+}
+
+fn read_public_keys(paths: &[String]) -> Result<Vec<ecc::MothrboxPublicKey>, Error> {
+    paths.iter().map(|path| ecc::read_public_key(path)).collect()
+}
+
+/// Decrypt a headerless raw-key file produced before the MBX1 container format
+fn decrypt_file_legacy_with_key(input: &str, output: &str, key: &[u8; 32], algorithm: &str) -> Result<(), Error> {
+    let encrypted = std::fs::read(input).map_err(|e| Error::io("Failed to read input file", e))?;
+    let plaintext = mothrbox_engine::decrypt_legacy(&encrypted, &DecryptionKey::RawKey(key), algorithm)?;
+    mothrbox_engine::atomic::write_atomic(output, &plaintext)
+}
+
+fn handle_walrus(action: WalrusCommands, backend_spec: Option<String>) -> Result<String, Error> {
+    let backend_spec = backend_spec.unwrap_or_else(storage::backend_spec_from_env);
+    let backend = storage::backend_by_name(&backend_spec)?;
+
     match action {
         WalrusCommands::Upload { file } => {
-            let blob_id = cli.upload(&file)?;
+            let blob_id = backend.put_file(&file)?;
             println!("📦 Blob ID: {}", blob_id);
             Ok(String::new())
         }
         WalrusCommands::Download { blob_id, output } => {
-            cli.download(&blob_id, &output)?;
+            backend.get_file(&blob_id, &output)?;
             Ok(format!("Downloaded: {}", output))
         }
-        WalrusCommands::UploadAes { file, password } => {
-            let blob_id = encrypt_and_upload_aes(&file, &password)?;
-            println!("📦 Encrypted Blob ID: {}", blob_id);
-            Ok(String::new())
+        WalrusCommands::Put { file, algo, recipient, key_file, password, password_source, kdf, aad, compress, manifest, dedup, name, tags, no_catalog } => {
+            let aad = aad.bytes(&file);
+            let compression = compress.compression()?;
+            let manifest_tags = manifest.tags()?;
+            if dedup.dedup {
+                let catalog = Catalog::open_default()?;
+                if let Some(name) = &name {
+                    catalog::check_name(name)?;
+                }
+                let index = ChunkIndex::beside(catalog.path())?;
+                let keying = dedup.keying()?;
+                let (mut entry, stats) = with_encryption_key(algo, recipient, key_file, password, &password_source, &kdf, |key| {
+                    let mut store = ChunkStore::new(backend.as_ref(), &backend_spec, index, keying).with_algorithm(key.data_algorithm()).with_compression(compression);
+                    walrus::encrypt_and_upload_chunked(&mut store, &file, key, &aad)
+                })?;
+                println!("📦 Encrypted Blob ID: {}", entry.blob_id);
+                println!("♻️  Uploaded {} of {} chunks ({} of {} bytes)", stats.new_chunks, stats.chunks, stats.uploaded_bytes, stats.bytes);
+                if no_catalog {
+                    return Ok(String::new());
+                }
+                if let Some(name) = name {
+                    entry.name = name;
+                }
+                entry.tags = tags;
+                return catalog_upload(&catalog, entry);
+            }
+            if no_catalog {
+                let blob_id = with_encryption_key(algo, recipient, key_file, password, &password_source, &kdf, |key| {
+                    walrus::encrypt_and_upload(backend.as_ref(), &file, key, &aad, compression, manifest_tags)
+                })?;
+                println!("📦 Encrypted Blob ID: {}", blob_id);
+                return Ok(String::new());
+            }
+
+            let catalog = Catalog::open_default()?;
+            if let Some(name) = &name {
+                catalog::check_name(name)?;
+            }
+            let mut entry = with_encryption_key(algo, recipient, key_file, password, &password_source, &kdf, |key| {
+                walrus::encrypt_and_catalog(backend.as_ref(), &file, key, &aad, compression, manifest_tags)
+            })?;
+            if let Some(name) = name {
+                entry.name = name;
+            }
+            entry.backend = backend_spec;
+            entry.tags = tags;
+            println!("📦 Encrypted Blob ID: {}", entry.blob_id);
+            catalog_upload(&catalog, entry)
         }
-        WalrusCommands::DownloadAes { blob_id, output, password } => {
-            download_and_decrypt_aes(&blob_id, &output, &password)?;
-            Ok(format!("Decrypted: {}", output))
-        }
-        WalrusCommands::UploadChacha { file, password } => {
-            // Encrypt with ChaCha20
-            let encrypted_path = format!("{}.enc", file);
-            chacha::encrypt_file(&file, &encrypted_path, &password)?;
-            
-            // Upload to Walrus
-            let blob_id = cli.upload(&encrypted_path)?;
-            
-            // Clean up
-            let _ = std::fs::remove_file(&encrypted_path);
-            
-            println!("📦 Encrypted Blob ID: {}", blob_id);
-            Ok(String::new())
+        WalrusCommands::Get { blob_id, output, auto: _, algo, password, password_source, private_key, key_file, aad } => {
+            let aad = aad.bytes(&output);
+            let algorithm = with_decryption_key(password, &password_source, private_key, key_file, |key| {
+                walrus::download_and_decrypt(backend.as_ref(), &blob_id, &output, key, &aad, algo)
+            })?;
+            Ok(format!("Decrypted ({}): {}", algorithm.name(), output))
         }
-        WalrusCommands::DownloadChacha { blob_id, output, password } => {
-            // Download from Walrus
-            let encrypted_path = format!("{}.enc", output);
-            cli.download(&blob_id, &encrypted_path)?;
-            
-            // Decrypt with ChaCha20
-            chacha::decrypt_file(&encrypted_path, &output, &password)?;
-            
-            // Clean up
-            let _ = std::fs::remove_file(&encrypted_path);
-            
-            Ok(format!("Decrypted: {}", output))
+    }
+}
+
+/// Record an upload, noting the blob a reused name pointed at before
+fn catalog_upload(catalog: &Catalog, entry: CatalogEntry) -> Result<String, Error> {
+    let (name, blob_id) = (entry.name.clone(), entry.blob_id.clone());
+    if let Some(replaced) = catalog.insert(entry)? {
+        if replaced.blob_id != blob_id {
+            println!("ℹ️  '{}' previously pointed at {}", name, replaced.blob_id);
         }
     }
+    Ok(format!("Cataloged as '{}'", name))
+}
+
+/// The catalog's table of uploads
+fn handle_ls(tag: Option<String>) -> Result<String, Error> {
+    let catalog = Catalog::open_default()?;
+    let entries: Vec<_> = catalog
+        .entries()?
+        .into_iter()
+        .filter(|entry| tag.as_ref().is_none_or(|tag| entry.tags.contains(tag)))
+        .collect();
+    if entries.is_empty() {
+        return Ok(format!("Nothing cataloged in {}", catalog.path().display()));
+    }
+
+    let width = entries.iter().map(|entry| entry.name.chars().count()).max().unwrap_or(0).max(4);
+    println!("{:<width$}  {:<6}  {:>12}  {:<16}  {:>9}  BLOB ID", "NAME", "ALGO", "SIZE", "UPLOADED", "END EPOCH");
+    for entry in &entries {
+        let end_epoch = entry.end_epoch.map_or("-".to_string(), |epoch| epoch.to_string());
+        println!(
+            "{:<width$}  {:<6}  {:>12}  {:<16}  {:>9}  {}",
+            entry.name, entry.algorithm, entry.size, catalog::format_time(entry.uploaded_at), end_epoch, entry.blob_id
+        );
+    }
+    Ok(String::new())
+}
+
+/// Everything recorded about one upload
+fn handle_show(name: String) -> Result<String, Error> {
+    let entry = Catalog::open_default()?.find(&name)?;
+    println!("Name:        {}", entry.name);
+    println!("Blob ID:     {}", entry.blob_id);
+    println!("Backend:     {}", entry.backend);
+    println!("File:        {}", entry.filename);
+    println!("Size:        {} bytes ({} stored)", entry.size, entry.stored_size);
+    println!("SHA-256:     {}", entry.sha256);
+    println!("Algorithm:   {}", entry.algorithm);
+    if let Some(kdf) = &entry.kdf {
+        println!("KDF:         {} (m={} KiB, t={}, p={})", kdf.algorithm, kdf.m_cost, kdf.t_cost, kdf.p_cost);
+    }
+    println!("Compression: {}", entry.compression);
+    if let Some(chunks) = entry.chunks {
+        println!("Chunks:      {} (deduplicated; the blob lists them)", chunks);
+    }
+    if entry.aad {
+        println!("AAD:         required (pass the same --aad / --bind-filename)");
+    }
+    println!("Uploaded:    {} UTC", catalog::format_time(entry.uploaded_at));
+    if let Some(end_epoch) = entry.end_epoch {
+        println!("Expires:     after Walrus epoch {}", end_epoch);
+    }
+    if !entry.tags.is_empty() {
+        println!("Tags:        {}", entry.tags.join(", "));
+    }
+    Ok(String::new())
+}
+
+fn handle_rm(name: String, delete_blob: bool, backend_spec: Option<String>) -> Result<String, Error> {
+    let catalog = Catalog::open_default()?;
+    let entry = catalog.find(&name)?;
+    if delete_blob {
+        // Delete first: if the backend refuses, the entry is kept
+        storage::backend_by_name(backend_spec.as_deref().unwrap_or(&entry.backend))?.delete(&entry.blob_id)?;
+    }
+    catalog.remove(&entry.name)?;
+    Ok(format!("Removed '{}' (blob {})", entry.name, entry.blob_id))
+}
+
+/// Fetch a cataloged upload from the backend it was stored on, pinning the
+/// recorded algorithm and checking the plaintext against the recorded hash
+fn handle_get(args: GetArgs, backend_spec: Option<String>) -> Result<String, Error> {
+    let GetArgs { name, output, password, password_source, private_key, key_file, aad } = args;
+    let entry = Catalog::open_default()?.find(&name)?;
+    let backend = storage::backend_by_name(backend_spec.as_deref().unwrap_or(&entry.backend))?;
+    let algorithm: Algorithm = entry.algorithm.parse()?;
+    let output = output.unwrap_or_else(|| entry.filename.clone());
+
+    let aad = aad.bytes(&output);
+    let (header, plaintext) = with_decryption_key(password, &password_source, private_key, key_file, |key| {
+        walrus::get_and_decrypt(backend.as_ref(), &entry.blob_id, key, &aad, Some(algorithm))
+    })?;
+    let plaintext = Zeroizing::new(plaintext);
+    if header.is_chunk_list() {
+        let list = ChunkList::parse(&plaintext)?;
+        if list.sha256 != entry.sha256 {
+            return Err(Error::storage("catalog", format!("chunk list {} does not match the SHA-256 recorded for '{}'", entry.blob_id, entry.name)));
+        }
+        dedup::reassemble_file(backend.as_ref(), &list, &walrus::container_path(&output))?;
+        return Ok(format!("Decrypted '{}' ({}, {} chunks): {}", entry.name, algorithm.name(), list.chunks.len(), output));
+    }
+    if !entry.matches(&plaintext) {
+        return Err(Error::storage("catalog", format!("blob {} does not match the SHA-256 recorded for '{}'", entry.blob_id, entry.name)));
+    }
+    mothrbox_engine::atomic::write_atomic(walrus::container_path(&output), &plaintext)?;
+    Ok(format!("Decrypted '{}' ({}): {}", entry.name, algorithm.name(), output))
+}
+
+fn handle_pack(args: PackArgs, backend_spec: Option<String>) -> Result<String, Error> {
+    let PackArgs { dir, output, algo, recipient, key_file, password, password_source, kdf, include, exclude, upload, per_file } = args;
+    let filter = archive::Filter::new(&include, &exclude)?;
+    if !upload {
+        let output = output.expect("clap requires an output without --upload");
+        let entries = with_encryption_key(algo, recipient, key_file, password, &password_source, &kdf, |key| {
+            archive::pack_file(&dir, &output, key, &filter)
+        })?;
+        return Ok(format!("Packed {}: {} -> {}", describe_archive(&entries), dir, output));
+    }
+
+    let backend = storage::backend_by_name(&backend_spec.unwrap_or_else(storage::backend_spec_from_env))?;
+    let (blob_id, entries) = with_encryption_key(algo, recipient, key_file, password, &password_source, &kdf, |key| {
+        if per_file {
+            archive::pack_to_blobs(backend.as_ref(), &dir, key, &filter)
+        } else {
+            archive::pack_to_blob(backend.as_ref(), &dir, key, &filter)
+        }
+    })?;
+    println!("📦 Archive Blob ID: {}", blob_id);
+    Ok(format!("Packed {}: {}", describe_archive(&entries), dir))
+}
+
+fn handle_unpack(args: UnpackArgs, backend_spec: Option<String>) -> Result<String, Error> {
+    let UnpackArgs { input, dest, blob, password, password_source, private_key, key_file } = args;
+    // Only needed for blobs, or a saved index of per-file blobs
+    let backend = match (blob, backend_spec) {
+        (true, spec) => Some(storage::backend_by_name(&spec.unwrap_or_else(storage::backend_spec_from_env))?),
+        (false, Some(spec)) => Some(storage::backend_by_name(&spec)?),
+        (false, None) => None,
+    };
+    let entries = with_decryption_key(password, &password_source, private_key, key_file, |key| match &backend {
+        Some(backend) if blob => archive::unpack_blob(backend.as_ref(), &input, &dest, key),
+        backend => archive::unpack_file(&input, &dest, key, backend.as_deref()),
+    })?;
+    Ok(format!("Unpacked {}: {} -> {}", describe_archive(&entries), input, dest))
+}
+
+fn handle_backup(args: BackupArgs, backend_spec: Option<String>) -> Result<String, Error> {
+    let BackupArgs { dir, vault, algo, recipient, key_file, password, password_source, kdf, compress, include, exclude, vault_key } = args;
+    catalog::check_name(&vault)?;
+    let filter = archive::Filter::new(&include, &exclude)?;
+    let compression = compress.compression()?;
+    let keying = match vault_key {
+        Some(path) => ChunkKeying::Vault(KeyFile::load(&path)?.key.clone()),
+        None => ChunkKeying::Convergent,
+    };
+    let backend_spec = backend_spec.unwrap_or_else(storage::backend_spec_from_env);
+    let backend = storage::backend_by_name(&backend_spec)?;
+    let catalog = Catalog::open_default()?;
+    let log = SnapshotLog::beside(catalog.path());
+    let index = ChunkIndex::beside(catalog.path())?;
+    let previous = log.latest(&vault, &backend_spec)?;
+
+    let (record, stats) = with_encryption_key(algo, recipient, key_file, password, &password_source, &kdf, |key| {
+        let previous = match (&previous, key.decryption_key()) {
+            (Some(record), Some(decryption_key)) => Some(Snapshot::open(backend.as_ref(), &record.blob_id, &decryption_key)?),
+            (Some(record), None) => {
+                println!("ℹ️  Snapshot {} can't be read with a public key: reading every file again", record.id);
+                None
+            }
+            (None, _) => None,
+        };
+        let mut store = ChunkStore::new(backend.as_ref(), &backend_spec, index, keying).with_algorithm(key.data_algorithm()).with_compression(compression);
+        backup::backup(&mut store, &dir, &vault, &filter, key, previous)
+    })?;
+    log.append(record.clone())?;
+    println!("📸 Snapshot {} of {} ({} files, {} bytes)", record.id, record.vault, record.files, record.size);
+    println!(
+        "♻️  {} unchanged, {} changed, {} added, {} removed; uploaded {} of {} chunks read ({} bytes)",
+        stats.unchanged, stats.changed, stats.added, stats.removed, stats.dedup.new_chunks, stats.dedup.chunks, record.uploaded
+    );
+    Ok(format!("Backed up {}", dir))
+}
+
+fn handle_snapshots(vault: Option<String>) -> Result<String, Error> {
+    let log = SnapshotLog::beside(Catalog::open_default()?.path());
+    let records: Vec<_> = log.records()?.into_iter().filter(|record| vault.as_ref().is_none_or(|vault| &record.vault == vault)).collect();
+    if records.is_empty() {
+        return Ok(format!("No snapshots in {}", log.path().display()));
+    }
+
+    let width = records.iter().map(|record| record.vault.chars().count()).max().unwrap_or(0).max(5);
+    println!("{:<8}  {:<width$}  {:<16}  {:>7}  {:>12}  {:>12}  ROOT", "ID", "VAULT", "CREATED", "FILES", "SIZE", "UPLOADED");
+    for record in &records {
+        println!(
+            "{:<8}  {:<width$}  {:<16}  {:>7}  {:>12}  {:>12}  {}",
+            record.id, record.vault, catalog::format_time(record.created), record.files, record.size, record.uploaded, record.root
+        );
+    }
+    Ok(String::new())
+}
+
+/// Fetch and decrypt the snapshot a log record names, from `backend_spec` if given
+fn open_snapshot(record: &SnapshotRecord, backend_spec: Option<&str>, key: &DecryptionKey) -> Result<Snapshot, Error> {
+    let backend = storage::backend_by_name(backend_spec.unwrap_or(&record.backend))?;
+    Snapshot::open(backend.as_ref(), &record.blob_id, key)
+}
+
+fn handle_restore(args: RestoreArgs, backend_spec: Option<String>) -> Result<String, Error> {
+    let RestoreArgs { snapshot, dest, vault, password, password_source, private_key, key_file } = args;
+    let record = SnapshotLog::beside(Catalog::open_default()?.path()).find(&snapshot, vault.as_deref())?;
+    let backend = storage::backend_by_name(backend_spec.as_deref().unwrap_or(&record.backend))?;
+    let snapshot = with_decryption_key(password, &password_source, private_key, key_file, |key| Snapshot::open(backend.as_ref(), &record.blob_id, key))?;
+    backup::restore(backend.as_ref(), &snapshot, &dest)?;
+    let entries: Vec<_> = snapshot.entries.into_iter().map(|entry| entry.entry).collect();
+    Ok(format!("Restored {} from snapshot {}: {}", describe_archive(&entries), record.id, dest))
+}
+
+fn handle_diff(args: DiffArgs, backend_spec: Option<String>) -> Result<String, Error> {
+    let DiffArgs { old, new, vault, password, password_source, private_key, key_file } = args;
+    let log = SnapshotLog::beside(Catalog::open_default()?.path());
+    let (old, new) = match new {
+        Some(new) => (log.find(&old, vault.as_deref())?, log.find(&new, vault.as_deref())?),
+        None => {
+            let new = log.find(&old, vault.as_deref())?;
+            let parent = new.parent.as_deref().ok_or_else(|| Error::InvalidInput(format!("snapshot {} is the first of its vault: name one to compare it with", new.id)))?;
+            (log.find(parent, None)?, new)
+        }
+    };
+    let (old_snapshot, new_snapshot) = with_decryption_key(password, &password_source, private_key, key_file, |key| {
+        Ok((open_snapshot(&old, backend_spec.as_deref(), key)?, open_snapshot(&new, backend_spec.as_deref(), key)?))
+    })?;
+    let changes = backup::diff(&old_snapshot, &new_snapshot);
+    for (change, path) in &changes {
+        println!("{}  {}", change.symbol(), path);
+    }
+    Ok(format!("{} changes from {} to {}", changes.len(), old.id, new.id))
+}
+
+fn handle_prune(args: PruneArgs) -> Result<String, Error> {
+    let PruneArgs { vault, keep_last, delete_blobs, password, password_source, private_key, key_file } = args;
+    let catalog = Catalog::open_default()?;
+    let log = SnapshotLog::beside(catalog.path());
+    let records = log.records()?;
+    let pruned = backup::prune_plan(&records, &vault, keep_last);
+    if pruned.is_empty() {
+        return Ok(format!("Nothing to prune: {} has at most {} snapshots", vault, keep_last));
+    }
+    let ids: Vec<_> = pruned.iter().map(|record| record.id.clone()).collect();
+    if !delete_blobs {
+        log.remove(&ids)?;
+        return Ok(format!("Forgot {} snapshots of {}; their blobs are left in place", ids.len(), vault));
+    }
+
+    // Chunks are shared by content, so every snapshot on a backend is needed
+    // to tell which ones nothing else uses
+    let mut backends: Vec<_> = pruned.iter().map(|record| record.backend.clone()).collect();
+    backends.sort();
+    backends.dedup();
+    let shared = catalog.entries()?.into_iter().find(|entry| entry.chunks.is_some() && backends.contains(&entry.backend));
+    if let Some(entry) = shared {
+        return Err(Error::InvalidInput(format!(
+            "'{}' is a deduplicated upload on {} and may share chunks: prune without --delete-blobs",
+            entry.name, entry.backend
+        )));
+    }
+    let stats = with_decryption_key(password, &password_source, private_key, key_file, |key| {
+        let mut total = backup::PruneStats::default();
+        let mut index = ChunkIndex::beside(catalog.path())?;
+        for spec in &backends {
+            let backend = storage::backend_by_name(spec)?;
+            let (mut pruned_here, mut kept) = (Vec::new(), Vec::new());
+            for record in records.iter().filter(|record| &record.backend == spec) {
+                let snapshot = Snapshot::open(backend.as_ref(), &record.blob_id, key).map_err(|e| match e {
+                    Error::AuthenticationFailed => Error::InvalidInput(format!(
+                        "snapshot {} of {} is sealed with another key, so its chunks can't be checked: prune without --delete-blobs",
+                        record.id, record.vault
+                    )),
+                    e => e,
+                })?;
+                if ids.contains(&record.id) {
+                    pruned_here.push((record.clone(), snapshot));
+                } else {
+                    kept.push(snapshot);
+                }
+            }
+            let stats = backup::delete_snapshots(backend.as_ref(), &mut index, spec, &pruned_here, &kept)?;
+            let forgotten: Vec<_> = pruned_here.into_iter().map(|(record, _)| record.id).collect();
+            log.remove(&forgotten)?;
+            total.snapshots += stats.snapshots;
+            total.chunks += stats.chunks;
+            total.bytes += stats.bytes;
+        }
+        Ok(total)
+    })?;
+    Ok(format!("Pruned {} snapshots of {}: deleted {} chunks ({} bytes)", stats.snapshots, vault, stats.chunks, stats.bytes))
+}
+
+/// "3 files (120 bytes), 1 directory, 1 symlink"
+fn describe_archive(entries: &[archive::ArchiveEntry]) -> String {
+    let (mut files, mut bytes, mut dirs, mut symlinks) = (0, 0, 0, 0);
+    for entry in entries {
+        match entry.kind {
+            archive::EntryKind::File { size, .. } => {
+                files += 1;
+                bytes += size;
+            }
+            archive::EntryKind::Dir => dirs += 1,
+            archive::EntryKind::Symlink { .. } => symlinks += 1,
+        }
+    }
+    let count = |n: usize, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+    let mut parts = vec![format!("{} ({} bytes)", count(files, "file", "files"), bytes)];
+    if dirs > 0 {
+        parts.push(count(dirs, "directory", "directories"));
+    }
+    if symlinks > 0 {
+        parts.push(count(symlinks, "symlink", "symlinks"));
+    }
+    parts.join(", ")
 }
\ No newline at end of file
@@ -0,0 +1,205 @@
+// storage.rs - Where encrypted blobs are kept
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::Error;
+
+mod local;
+mod memory;
+
+pub use local::LocalDirBackend;
+pub use memory::MemoryBackend;
+
+/// What a backend knows about a stored blob
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobMetadata {
+    pub blob_id: String,
+    /// Stored (encrypted) size in bytes
+    pub size: u64,
+}
+
+/// Receipt for a newly stored blob
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredBlob {
+    pub blob_id: String,
+    /// Last Walrus epoch the blob is paid for, on backends where blobs expire
+    pub end_epoch: Option<u32>,
+}
+
+/// A place to put ciphertext blobs and fetch them back by id.
+/// Backends only ever see encrypted data.
+pub trait StorageBackend: Send + Sync {
+    /// Short name used in errors and log lines
+    fn name(&self) -> &'static str;
+
+    /// Store `data`, returning the id to fetch it with
+    fn put(&self, data: &[u8]) -> Result<String, Error>;
+
+    /// Store `data`, also reporting how long it is kept
+    fn store(&self, data: &[u8]) -> Result<StoredBlob, Error> {
+        Ok(StoredBlob {
+            blob_id: self.put(data)?,
+            end_epoch: None,
+        })
+    }
+
+    /// Fetch the blob stored under `blob_id`
+    fn get(&self, blob_id: &str) -> Result<Vec<u8>, Error>;
+
+    /// Describe the blob stored under `blob_id`; `Error::NotFound` if there is none
+    fn metadata(&self, blob_id: &str) -> Result<BlobMetadata, Error>;
+
+    /// Remove the blob stored under `blob_id`
+    fn delete(&self, blob_id: &str) -> Result<(), Error>;
+
+    /// Whether a blob is stored under `blob_id`
+    fn exists(&self, blob_id: &str) -> Result<bool, Error> {
+        match self.metadata(blob_id) {
+            Ok(_) => Ok(true),
+            Err(Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Store the contents of a file
+    fn put_file(&self, path: &str) -> Result<String, Error> {
+        let data = fs::read(path).map_err(|e| Error::io("Failed to read blob file", e))?;
+        self.put(&data)
+    }
+
+    /// Fetch a blob into a file
+    fn get_file(&self, blob_id: &str, output_path: &str) -> Result<(), Error> {
+        crate::atomic::write_atomic(output_path, &self.get(blob_id)?)
+    }
+}
+
+/// Content address used by the local and in-memory backends: hex SHA-256
+pub fn content_id(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Reject ids that are not content addresses before they reach a path or map
+fn check_content_id(backend: &'static str, blob_id: &str) -> Result<(), Error> {
+    let valid = blob_id.len() == 64
+        && blob_id
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidInput(format!(
+            "invalid {} blob id '{}' (expected 64 hex digits)",
+            backend, blob_id
+        )))
+    }
+}
+
+/// Environment variable selecting the backend, see `backend_by_name`
+pub const BACKEND_ENV: &str = "MOTHRBOX_BACKEND";
+
+/// Environment variable overriding the `local` backend directory
+pub const LOCAL_DIR_ENV: &str = "MOTHRBOX_LOCAL_DIR";
+
+/// Backend named by `MOTHRBOX_BACKEND`, Walrus over HTTP if unset
+pub fn backend_from_env() -> Result<Box<dyn StorageBackend>, Error> {
+    backend_by_name(&backend_spec_from_env())
+}
+
+/// The spec `backend_from_env` builds its backend from
+pub fn backend_spec_from_env() -> String {
+    std::env::var(BACKEND_ENV)
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "walrus".to_string())
+}
+
+/// Build a backend from its name:
+///
+/// - `walrus`: Walrus publisher/aggregator over HTTP (`WALRUS_*` variables)
+/// - `walrus-cli`: the Deno Walrus script, needs `SUI_SECRET_KEY`
+/// - `local` or `local:<dir>`: content-addressed directory, by default
+///   `MOTHRBOX_LOCAL_DIR` or `~/.mothrbox/blobs`
+/// - `memory`: kept in this process only, for tests
+pub fn backend_by_name(spec: &str) -> Result<Box<dyn StorageBackend>, Error> {
+    let (name, arg) = match spec.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (spec, None),
+    };
+    match (name, arg) {
+        #[cfg(feature = "walrus-http")]
+        ("walrus", None) => Ok(Box::new(crate::walrus::WalrusHttpClient::from_env()?)),
+        #[cfg(not(feature = "walrus-http"))]
+        ("walrus", None) => Err(Error::Unsupported(
+            "Walrus HTTP client (built without the 'walrus-http' feature, use walrus-cli)"
+                .to_string(),
+        )),
+        ("walrus-cli", None) => Ok(Box::new(crate::walrus::WalrusCliBackend::new())),
+        ("local", Some(dir)) if !dir.is_empty() => Ok(Box::new(LocalDirBackend::new(dir)?)),
+        ("local", None) => Ok(Box::new(LocalDirBackend::new(default_local_dir()?)?)),
+        ("memory", None) => Ok(Box::new(MemoryBackend::new())),
+        _ => Err(Error::InvalidInput(format!(
+            "unknown storage backend '{}' (use walrus, walrus-cli, local[:<dir>] or memory)",
+            spec
+        ))),
+    }
+}
+
+fn default_local_dir() -> Result<PathBuf, Error> {
+    if let Some(dir) = std::env::var_os(LOCAL_DIR_ENV).filter(|dir| !dir.is_empty()) {
+        return Ok(dir.into());
+    }
+    Ok(mothrbox_dir(&format!("use local:<dir> or {}", LOCAL_DIR_ENV))?.join("blobs"))
+}
+
+/// `~/.mothrbox`, where per-user state lives; `hint` says how to do without it
+pub(crate) fn mothrbox_dir(hint: &str) -> Result<PathBuf, Error> {
+    let home = std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .ok_or_else(|| Error::InvalidInput(format!("HOME is not set: {}", hint)))?;
+    Ok(PathBuf::from(home).join(".mothrbox"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Behaviour every content-addressed backend must share
+    fn check_backend(backend: &dyn StorageBackend) {
+        let blob_id = backend.put(b"ciphertext").unwrap();
+        assert_eq!(blob_id, content_id(b"ciphertext"));
+        assert_eq!(backend.put(b"ciphertext").unwrap(), blob_id);
+        assert_eq!(backend.get(&blob_id).unwrap(), b"ciphertext");
+        assert!(backend.exists(&blob_id).unwrap());
+        assert_eq!(backend.metadata(&blob_id).unwrap().size, 10);
+
+        backend.delete(&blob_id).unwrap();
+        assert!(!backend.exists(&blob_id).unwrap());
+        assert!(matches!(backend.get(&blob_id), Err(Error::NotFound { .. })));
+        assert!(matches!(
+            backend.delete(&blob_id),
+            Err(Error::NotFound { .. })
+        ));
+        assert!(matches!(
+            backend.get("../../etc/passwd"),
+            Err(Error::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_local_and_memory_backends() {
+        let dir = tempfile::tempdir().unwrap();
+        let local = backend_by_name(&format!("local:{}", dir.path().display())).unwrap();
+        check_backend(local.as_ref());
+        check_backend(&MemoryBackend::new());
+
+        // Content addressing catches blobs modified on disk
+        let blob_id = local.put(b"ciphertext").unwrap();
+        let path = dir.path().join(&blob_id[..2]).join(&blob_id);
+        fs::write(path, b"tampered!!").unwrap();
+        assert!(matches!(local.get(&blob_id), Err(Error::Storage { .. })));
+
+        assert!(backend_by_name("s3").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+// storage/local.rs - Content-addressed blob directory
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use super::{check_content_id, content_id, BlobMetadata, StorageBackend};
+use crate::atomic::write_atomic;
+use crate::Error;
+
+/// Blobs kept as files named by their SHA-256 under `root/<2 hex>/<id>`.
+/// Storing the same ciphertext twice keeps one copy, and reads are checked
+/// against the id so a blob changed on disk is reported, not returned.
+pub struct LocalDirBackend {
+    root: PathBuf,
+}
+
+impl LocalDirBackend {
+    /// Use `root`, creating it if needed
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, Error> {
+        let root = root.into();
+        fs::create_dir_all(&root)
+            .map_err(|e| Error::io(&format!("Failed to create {}", root.display()), e))?;
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn blob_path(&self, blob_id: &str) -> Result<PathBuf, Error> {
+        check_content_id("local", blob_id)?;
+        Ok(self.root.join(&blob_id[..2]).join(blob_id))
+    }
+
+    fn not_found(blob_id: &str, e: std::io::Error) -> Error {
+        if e.kind() == ErrorKind::NotFound {
+            Error::not_found("local", blob_id)
+        } else {
+            Error::io(&format!("Failed to access blob {}", blob_id), e)
+        }
+    }
+}
+
+impl StorageBackend for LocalDirBackend {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn put(&self, data: &[u8]) -> Result<String, Error> {
+        let blob_id = content_id(data);
+        let path = self.blob_path(&blob_id)?;
+        if path.exists() {
+            return Ok(blob_id);
+        }
+
+        let dir = path.parent().expect("blob paths have a parent");
+        fs::create_dir_all(dir)
+            .map_err(|e| Error::io(&format!("Failed to create {}", dir.display()), e))?;
+
+        // Written beside the final name and renamed, so readers never see half a blob
+        write_atomic(&path, data)?;
+        Ok(blob_id)
+    }
+
+    fn get(&self, blob_id: &str) -> Result<Vec<u8>, Error> {
+        let data = fs::read(self.blob_path(blob_id)?).map_err(|e| Self::not_found(blob_id, e))?;
+        if content_id(&data) != blob_id {
+            return Err(Error::storage(
+                "local",
+                format!("blob {} is corrupted (content hash mismatch)", blob_id),
+            ));
+        }
+        Ok(data)
+    }
+
+    fn metadata(&self, blob_id: &str) -> Result<BlobMetadata, Error> {
+        let metadata =
+            fs::metadata(self.blob_path(blob_id)?).map_err(|e| Self::not_found(blob_id, e))?;
+        Ok(BlobMetadata {
+            blob_id: blob_id.to_string(),
+            size: metadata.len(),
+        })
+    }
+
+    fn delete(&self, blob_id: &str) -> Result<(), Error> {
+        fs::remove_file(self.blob_path(blob_id)?).map_err(|e| Self::not_found(blob_id, e))
+    }
+}
@@ -0,0 +1,77 @@
+// storage/memory.rs - In-process blob store
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use super::{check_content_id, content_id, BlobMetadata, StorageBackend};
+use crate::Error;
+
+/// Blobs kept in a map for the life of the process, addressed like
+/// `LocalDirBackend`. Meant for tests and dry runs.
+#[derive(Default)]
+pub struct MemoryBackend {
+    blobs: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of blobs stored
+    pub fn len(&self) -> usize {
+        self.blobs().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn blobs(&self) -> MutexGuard<'_, HashMap<String, Vec<u8>>> {
+        // A panic elsewhere cannot leave a half-written entry, so keep going
+        self.blobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn put(&self, data: &[u8]) -> Result<String, Error> {
+        let blob_id = content_id(data);
+        self.blobs()
+            .entry(blob_id.clone())
+            .or_insert_with(|| data.to_vec());
+        Ok(blob_id)
+    }
+
+    fn get(&self, blob_id: &str) -> Result<Vec<u8>, Error> {
+        check_content_id("memory", blob_id)?;
+        self.blobs()
+            .get(blob_id)
+            .cloned()
+            .ok_or_else(|| Error::not_found("memory", blob_id))
+    }
+
+    fn metadata(&self, blob_id: &str) -> Result<BlobMetadata, Error> {
+        check_content_id("memory", blob_id)?;
+        let size = self
+            .blobs()
+            .get(blob_id)
+            .map(|data| data.len() as u64)
+            .ok_or_else(|| Error::not_found("memory", blob_id))?;
+        Ok(BlobMetadata {
+            blob_id: blob_id.to_string(),
+            size,
+        })
+    }
+
+    fn delete(&self, blob_id: &str) -> Result<(), Error> {
+        check_content_id("memory", blob_id)?;
+        self.blobs()
+            .remove(blob_id)
+            .map(|_| ())
+            .ok_or_else(|| Error::not_found("memory", blob_id))
+    }
+}
@@ -1,6 +1,24 @@
 use std::process::Command;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
+use std::path::Path;
+
+use crate::atomic::write_atomic;
+use crate::catalog::{self, CatalogEntry};
+use crate::compress::Compression;
+use crate::dedup::{self, ChunkList, ChunkStore, DedupStats};
+use crate::manifest::Manifest;
+use crate::header::{Algorithm, Header, KdfParams};
+use crate::storage::{self, BlobMetadata, StorageBackend};
+use crate::{DecryptionKey, EncryptOptions, EncryptionKey, Error};
+
+#[cfg(feature = "walrus-http")]
+mod http;
+#[cfg(feature = "walrus-http")]
+pub use http::{WalrusHttpClient, WalrusHttpConfig, DEFAULT_AGGREGATOR_URL, DEFAULT_PUBLISHER_URL};
+#[cfg(feature = "walrus-mock")]
+pub mod mock;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WalrusUploadResponse {
@@ -17,12 +35,20 @@ pub struct WalrusDownloadResponse {
     pub error: Option<String>,
 }
 
-/// Call Deno Walrus CLI directly (no HTTP server needed)
+/// Call Deno Walrus CLI directly (no HTTP server needed).
+/// Needs Deno, the `mothrbox_ts` checkout and a funded `SUI_SECRET_KEY`;
+/// `WalrusHttpClient` needs none of them.
 pub struct WalrusCli {
     deno_path: String,
     cli_script: String,
 }
 
+impl Default for WalrusCli {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl WalrusCli {
     pub fn new() -> Self {
         Self {
@@ -37,14 +63,14 @@ impl WalrusCli {
     }
 
     /// Read environment variables (prefer real env, fallback to .env file)
-    fn read_env_vars() -> Result<(String, String), String> {
+    fn read_env_vars() -> Result<(String, String), Error> {
         // 1) Prefer real environment variables (good for Docker)
         let env_sui_key = std::env::var("SUI_SECRET_KEY").ok();
         let env_sui_network = std::env::var("SUI_NETWORK").ok();
 
         if let Some(sui_key) = env_sui_key {
             if sui_key.is_empty() {
-                return Err("SUI_SECRET_KEY is set but empty".to_string());
+                return Err(Error::storage("walrus", "SUI_SECRET_KEY is set but empty"));
             }
             let sui_network = env_sui_network.unwrap_or_else(|| "testnet".to_string());
             return Ok((sui_key, sui_network));
@@ -53,7 +79,7 @@ impl WalrusCli {
         // 2) Fallback to .env file (local dev)
         let env_path = "mothrbox_ts/.env";
         let env_content = fs::read_to_string(env_path)
-            .map_err(|e| format!("Failed to read .env file at {}: {}", env_path, e))?;
+            .map_err(|e| Error::storage("walrus", format!("Failed to read .env file at {}: {}", env_path, e)))?;
         
         let mut sui_key = String::new();
         let mut sui_network = String::from("testnet");
@@ -68,7 +94,7 @@ impl WalrusCli {
         }
         
         if sui_key.is_empty() {
-            return Err("SUI_SECRET_KEY not found in .env file or environment".to_string());
+            return Err(Error::storage("walrus", "SUI_SECRET_KEY not found in .env file or environment"));
         }
 
         Ok((sui_key, sui_network))
@@ -98,16 +124,26 @@ impl WalrusCli {
         }
     }
 
+    /// Path argument for the Deno script, which runs from `mothrbox_ts`.
+    /// Paths under the data volume are rewritten; other absolute paths (temp files) pass through.
+    fn deno_arg(file_path: &str) -> String {
+        let in_data_volume = file_path.starts_with("/app/") || file_path.starts_with("/data/");
+        if std::path::Path::new(file_path).is_absolute() && !in_data_volume {
+            return file_path.to_string();
+        }
+        // Example: "/app/data/secret.pdf.enc" -> "data/secret.pdf.enc"
+        let rel = Self::normalize_path_for_deno(file_path);
+        // Deno side expects "../" + rel
+        format!("../{}", rel)
+    }
+
     /// Upload file to Walrus storage
-    pub fn upload(&self, file_path: &str) -> Result<String, String> {
+    pub fn upload(&self, file_path: &str) -> Result<String, Error> {
         println!("📤 Uploading {} to Walrus...", file_path);
 
         let (sui_key, sui_network) = Self::read_env_vars()?;
 
-        // Example: "/app/data/secret.pdf.enc" -> "data/secret.pdf.enc"
-        let rel = Self::normalize_path_for_deno(file_path);
-        // Deno side expects "../" + rel
-        let deno_arg = format!("../{}", rel);
+        let deno_arg = Self::deno_arg(file_path);
 
         let output = Command::new(&self.deno_path)
             .current_dir("mothrbox_ts")
@@ -123,35 +159,34 @@ impl WalrusCli {
             .arg("upload")
             .arg(&deno_arg)
             .output()
-            .map_err(|e| format!("Failed to execute Deno CLI: {}", e))?;
+            .map_err(|e| Error::storage("walrus", format!("Failed to execute Deno CLI: {}", e)))?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("Walrus CLI error: {}", stderr));
+            return Err(Error::storage("walrus", format!("CLI error: {}", stderr)));
         }
 
         let stdout = String::from_utf8_lossy(&output.stdout);
         
         let response: WalrusUploadResponse = serde_json::from_str(&stdout)
-            .map_err(|e| format!("Failed to parse response: {}. Output: {}", e, stdout))?;
+            .map_err(|e| Error::storage("walrus", format!("Failed to parse response: {}. Output: {}", e, stdout)))?;
 
         if let Some(error) = response.error {
-            return Err(format!("Walrus upload failed: {}", error));
+            return Err(Error::storage("walrus", format!("upload failed: {}", error)));
         }
 
         response
             .blob_id
-            .ok_or_else(|| "No blob ID in response".to_string())
+            .ok_or_else(|| Error::storage("walrus", "No blob ID in response"))
     }
 
     /// Download file from Walrus storage
-    pub fn download(&self, blob_id: &str, output_path: &str) -> Result<(), String> {
+    pub fn download(&self, blob_id: &str, output_path: &str) -> Result<(), Error> {
         println!("📥 Downloading {} from Walrus...", blob_id);
 
         let (sui_key, sui_network) = Self::read_env_vars()?;
 
-        let rel = Self::normalize_path_for_deno(output_path);
-        let deno_arg = format!("../{}", rel);
+        let deno_arg = Self::deno_arg(output_path);
 
         let output = Command::new(&self.deno_path)
             .current_dir("mothrbox_ts")
@@ -168,97 +203,291 @@ impl WalrusCli {
             .arg(blob_id)
             .arg(&deno_arg)
             .output()
-            .map_err(|e| format!("Failed to execute Deno CLI: {}", e))?;
+            .map_err(|e| Error::storage("walrus", format!("Failed to execute Deno CLI: {}", e)))?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("Walrus CLI error: {}", stderr));
+            return Err(Error::storage("walrus", format!("CLI error: {}", stderr)));
         }
 
         let stdout = String::from_utf8_lossy(&output.stdout);
         
         let response: WalrusDownloadResponse = serde_json::from_str(&stdout)
-            .map_err(|e| format!("Failed to parse response: {}. Output: {}", e, stdout))?;
+            .map_err(|e| Error::storage("walrus", format!("Failed to parse response: {}. Output: {}", e, stdout)))?;
 
         if let Some(error) = response.error {
-            return Err(format!("Walrus download failed: {}", error));
+            return Err(Error::storage("walrus", format!("download failed: {}", error)));
         }
 
         if response.success.unwrap_or(false) {
             println!("✅ Downloaded {} bytes", response.size.unwrap_or(0));
             Ok(())
         } else {
-            Err("Download failed".to_string())
+            Err(Error::storage("walrus", "Download failed"))
         }
     }
 }
 
-/// Upload encrypted file to Walrus
-pub fn upload_encrypted_to_walrus(encrypted_path: &str) -> Result<String, String> {
-    let cli = WalrusCli::new();
-    cli.upload(encrypted_path)
+/// `StorageBackend` over the Deno Walrus script
+#[derive(Default)]
+pub struct WalrusCliBackend {
+    cli: WalrusCli,
 }
 
-/// Download and save from Walrus
-pub fn download_from_walrus(blob_id: &str, output_path: &str) -> Result<(), String> {
-    let cli = WalrusCli::new();
-    cli.download(blob_id, output_path)
+impl WalrusCliBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_cli(cli: WalrusCli) -> Self {
+        Self { cli }
+    }
 }
 
-/// Full workflow: Encrypt file and upload to Walrus
-pub fn encrypt_and_upload_aes(
+impl StorageBackend for WalrusCliBackend {
+    fn name(&self) -> &'static str {
+        "walrus-cli"
+    }
+
+    fn put(&self, data: &[u8]) -> Result<String, Error> {
+        let blob = TempBlob::create()?;
+        fs::write(&blob.path, data).map_err(|e| Error::io("Failed to write temporary blob file", e))?;
+        self.cli.upload(&blob.path)
+    }
+
+    fn get(&self, blob_id: &str) -> Result<Vec<u8>, Error> {
+        let blob = TempBlob::create()?;
+        self.cli.download(blob_id, &blob.path)?;
+        fs::read(&blob.path).map_err(|e| Error::io("Failed to read temporary blob file", e))
+    }
+
+    /// The script has no metadata call, so this downloads the blob
+    fn metadata(&self, blob_id: &str) -> Result<BlobMetadata, Error> {
+        Ok(BlobMetadata {
+            blob_id: blob_id.to_string(),
+            size: self.get(blob_id)?.len() as u64,
+        })
+    }
+
+    fn delete(&self, _blob_id: &str) -> Result<(), Error> {
+        Err(Error::Unsupported(
+            "deleting Walrus blobs (they expire after their storage epochs)".to_string(),
+        ))
+    }
+
+    fn put_file(&self, path: &str) -> Result<String, Error> {
+        self.cli.upload(path)
+    }
+
+    fn get_file(&self, blob_id: &str, output_path: &str) -> Result<(), Error> {
+        self.cli.download(blob_id, output_path)
+    }
+}
+
+/// Private scratch file for a ciphertext handed to or received from the Deno
+/// script; removed when dropped, whether or not the transfer succeeded
+struct TempBlob {
+    path: String,
+}
+
+impl TempBlob {
+    fn create() -> Result<Self, Error> {
+        loop {
+            let mut suffix = [0u8; 8];
+            rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut suffix);
+            let path = std::env::temp_dir()
+                .join(format!("mothrbox-{}-{}.blob", std::process::id(), hex::encode(suffix)))
+                .to_string_lossy()
+                .into_owned();
+            match crate::atomic::create_private(std::path::Path::new(&path)) {
+                Ok(_) => return Ok(Self { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(Error::io("Failed to create temporary blob file", e)),
+            }
+        }
+    }
+}
+
+impl Drop for TempBlob {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Upload encrypted file to the backend chosen by `MOTHRBOX_BACKEND` (Walrus over HTTP by default)
+pub fn upload_encrypted_to_walrus(encrypted_path: &str) -> Result<String, Error> {
+    storage::backend_from_env()?.put_file(encrypted_path)
+}
+
+/// Download and save from the backend chosen by `MOTHRBOX_BACKEND`
+pub fn download_from_walrus(blob_id: &str, output_path: &str) -> Result<(), Error> {
+    storage::backend_from_env()?.get_file(blob_id, output_path)
+}
+
+/// Full workflow: Encrypt file under `key` and upload it to `backend`.
+/// With `manifest_tags`, a manifest (file name, type, size, times, hash and
+/// the tags) is sealed with the data.
+pub fn encrypt_and_upload(
+    backend: &dyn StorageBackend,
     input_path: &str,
-    password: &str,
-) -> Result<String, String> {
-    use crate::encryption::aes::encrypt_file;
-    
-    // Encrypt file
-    let encrypted_path = format!("{}.enc", input_path);
-    encrypt_file(input_path, &encrypted_path, password)?;
-    
-    // Upload to Walrus
-    let blob_id = upload_encrypted_to_walrus(&encrypted_path)?;
-    
-    // Clean up encrypted file
-    let _ = fs::remove_file(&encrypted_path);
-    
-    Ok(blob_id)
+    key: &EncryptionKey,
+    aad: &[u8],
+    compression: Compression,
+    manifest_tags: Option<BTreeMap<String, String>>,
+) -> Result<String, Error> {
+    let (_, encrypted) = read_and_encrypt(input_path, key, aad, compression, manifest_tags)?;
+    backend.put(&encrypted)
 }
 
-/// Full workflow: Download from Walrus and decrypt
-pub fn download_and_decrypt_aes(
+/// Encrypt a file in memory, compressing first if asked (Walrus bills by stored bytes).
+/// Returns the plaintext and the container.
+fn read_and_encrypt(
+    input_path: &str,
+    key: &EncryptionKey,
+    aad: &[u8],
+    compression: Compression,
+    manifest_tags: Option<BTreeMap<String, String>>,
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let plaintext = fs::read(input_path).map_err(|e| Error::io("Failed to read input file", e))?;
+    let manifest = manifest_tags
+        .map(|tags| Manifest::for_file(input_path, &plaintext).map(|m| Manifest { tags, ..m }))
+        .transpose()?;
+    let options = EncryptOptions { aad, compression, manifest: manifest.as_ref(), ..Default::default() };
+    let encrypted = crate::encrypt_with_options(&plaintext, key, &options)?;
+    Ok((plaintext, encrypted))
+}
+
+/// `encrypt_and_upload`, describing the upload as a catalog entry named after
+/// the file and recording `backend.name()`. Callers storing it under another
+/// name or a fuller backend spec (`local:<dir>`) set those fields afterwards.
+pub fn encrypt_and_catalog(
+    backend: &dyn StorageBackend,
+    input_path: &str,
+    key: &EncryptionKey,
+    aad: &[u8],
+    compression: Compression,
+    manifest_tags: Option<BTreeMap<String, String>>,
+) -> Result<CatalogEntry, Error> {
+    let filename = Path::new(input_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    catalog::check_name(&filename)?;
+
+    let (plaintext, encrypted) =
+        read_and_encrypt(input_path, key, aad, compression, manifest_tags)?;
+    let stored = backend.store(&encrypted)?;
+    CatalogEntry::new(&filename, &filename, backend.name(), &plaintext, &encrypted, &stored)
+}
+
+/// Deduplicated `encrypt_and_catalog`: chunk the file into `store`, which
+/// only uploads chunks it does not hold yet, then store the list of chunks
+/// encrypted with `key`. That list blob is the entry's blob id.
+pub fn encrypt_and_upload_chunked(
+    store: &mut ChunkStore,
+    input_path: &str,
+    key: &EncryptionKey,
+    aad: &[u8],
+) -> Result<(CatalogEntry, DedupStats), Error> {
+    let filename = Path::new(input_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    catalog::check_name(&filename)?;
+
+    let (list, stats) = store.put_file(input_path)?;
+    let (stored, sealed) = dedup::store_chunk_list(store.backend(), &list, key, aad)?;
+    let entry = CatalogEntry::for_chunks(&filename, store.backend_spec(), &list, &sealed, &stored)?;
+    Ok((entry, stats))
+}
+
+/// Full workflow: Download from `backend` and decrypt, detecting the algorithm
+/// from the header. With `expected`, any other algorithm is refused before the
+/// key is tried. Blobs the header marks as chunk lists of deduplicated uploads
+/// are followed to their chunks. Returns the algorithm the blob was encrypted with.
+pub fn download_and_decrypt(
+    backend: &dyn StorageBackend,
     blob_id: &str,
     output_path: &str,
-    password: &str,
-) -> Result<(), String> {
-    use crate::encryption::aes::decrypt_file;
-
-    // Normalize output path for inside-container layout.
-    // The bash wrapper passes "/data/<file>".
-    // Inside the container, the volume is mounted at "/app/data",
-    // so we'll actually read/write "/app/data/<file>".
-    let mut internal_output = output_path.to_string();
-
-    if internal_output.starts_with("/data/") {
-        // Map "/data/foo.pdf" -> "/app/data/foo.pdf"
-        internal_output = format!("/app{}", internal_output);
+    key: &DecryptionKey,
+    aad: &[u8],
+    expected: Option<Algorithm>,
+) -> Result<Algorithm, Error> {
+    let internal_output = container_path(output_path);
+
+    // 1. Download and decrypt in memory
+    let (header, plaintext) = get_and_decrypt(backend, blob_id, key, aad, expected)?;
+    // 2. Only now, authenticated, does the plaintext reach the output path
+    if header.is_chunk_list() {
+        dedup::reassemble_file(backend, &ChunkList::parse(&plaintext)?, &internal_output)?;
+    } else {
+        write_atomic(&internal_output, &plaintext)?;
     }
 
-    // Encrypted file lives next to the final output
-    let encrypted_path = format!("{}.enc", &internal_output);
+    Ok(header.algorithm)
+}
 
-    // 1. Download encrypted blob to encrypted_path
-    download_from_walrus(blob_id, &encrypted_path)?;
+/// Normalize an output path for the inside-container layout.
+/// The bash wrapper passes "/data/<file>"; inside the container the volume
+/// is mounted at "/app/data", so "/data/foo.pdf" -> "/app/data/foo.pdf".
+pub fn container_path(output_path: &str) -> String {
+    if output_path.starts_with("/data/") {
+        format!("/app{}", output_path)
+    } else {
+        output_path.to_string()
+    }
+}
 
-    // 2. Decrypt into internal_output
-    decrypt_file(&encrypted_path, &internal_output, password)?;
+/// Fetch a blob and decrypt it in memory, refusing containers whose header
+/// names a different algorithm than `expected`. Returns the header with the
+/// plaintext.
+pub fn get_and_decrypt(
+    backend: &dyn StorageBackend,
+    blob_id: &str,
+    key: &DecryptionKey,
+    aad: &[u8],
+    expected: Option<Algorithm>,
+) -> Result<(Header, Vec<u8>), Error> {
+    let encrypted = backend.get(blob_id)?;
+    let (header, _) = Header::parse(&encrypted)?;
+    // By name, so 'ecc' covers both ECIES versions
+    if let Some(expected) = expected.filter(|expected| expected.name() != header.algorithm.name()) {
+        return Err(Error::AlgorithmMismatch {
+            expected: expected.name(),
+            found: header.algorithm.name(),
+        });
+    }
 
-    // 3. Clean up encrypted temp file
-    let _ = std::fs::remove_file(&encrypted_path);
+    let plaintext = crate::decrypt_with_aad(&encrypted, key, aad)?;
+    Ok((header, plaintext))
+}
 
-    Ok(())
+/// `encrypt_and_upload` with an AES password and default Argon2 cost
+pub fn encrypt_and_upload_aes(
+    backend: &dyn StorageBackend,
+    input_path: &str,
+    password: &str,
+    compression: Compression,
+) -> Result<String, Error> {
+    let key = EncryptionKey::Password {
+        algorithm: Algorithm::Aes256Gcm,
+        password,
+        kdf: KdfParams::default(),
+    };
+    encrypt_and_upload(backend, input_path, &key, &[], compression, None)
 }
 
+/// `download_and_decrypt` for an AES password
+pub fn download_and_decrypt_aes(
+    backend: &dyn StorageBackend,
+    blob_id: &str,
+    output_path: &str,
+    password: &str,
+) -> Result<(), Error> {
+    let key = DecryptionKey::Password(password);
+    download_and_decrypt(backend, blob_id, output_path, &key, &[], Some(Algorithm::Aes256Gcm))?;
+    Ok(())
+}
 
 #[cfg(test)]
 mod tests {
@@ -285,4 +514,72 @@ mod tests {
             "data/test.enc".to_string()
         );
     }
+
+    #[test]
+    fn test_deno_arg_passes_temp_paths_through() {
+        assert_eq!(WalrusCli::deno_arg("/app/data/test.enc"), "../data/test.enc");
+        assert_eq!(WalrusCli::deno_arg("/tmp/mothrbox-1.blob"), "/tmp/mothrbox-1.blob");
+    }
+
+    #[test]
+    fn test_upload_download_workflow() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("report.txt");
+        let output = dir.path().join("restored.txt");
+        fs::write(&input, b"quarterly numbers").unwrap();
+
+        let backend = storage::MemoryBackend::new();
+        let blob_id =
+            encrypt_and_upload_aes(&backend, input.to_str().unwrap(), "pw", Compression::NONE)
+                .unwrap();
+        assert_eq!(backend.len(), 1);
+        assert!(backend.get(&blob_id).unwrap().starts_with(b"MBX1"));
+
+        download_and_decrypt_aes(&backend, &blob_id, output.to_str().unwrap(), "pw").unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"quarterly numbers");
+        assert!(matches!(
+            download_and_decrypt_aes(&backend, &blob_id, output.to_str().unwrap(), "wrong"),
+            Err(Error::AuthenticationFailed)
+        ));
+
+        // ChaCha blobs are refused when AES is expected, and accepted when detected
+        let key = EncryptionKey::Password {
+            algorithm: Algorithm::ChaCha20Poly1305,
+            password: "pw",
+            kdf: KdfParams::default(),
+        };
+        let blob_id = encrypt_and_upload(&backend, input.to_str().unwrap(), &key, b"ctx", Compression::lz4(), None).unwrap();
+        assert!(matches!(
+            download_and_decrypt_aes(&backend, &blob_id, output.to_str().unwrap(), "pw"),
+            Err(Error::AlgorithmMismatch { .. })
+        ));
+        let detected = download_and_decrypt(&backend, &blob_id, output.to_str().unwrap(), &DecryptionKey::Password("pw"), b"ctx", None).unwrap();
+        assert_eq!(detected, Algorithm::ChaCha20Poly1305);
+        assert_eq!(fs::read(&output).unwrap(), b"quarterly numbers");
+    }
+
+    #[test]
+    fn test_file_starting_like_a_chunk_list_downloads_as_is() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("upload.bin");
+        let output = dir.path().join("restored.bin");
+        let backend = storage::MemoryBackend::new();
+
+        // A well-formed chunk list naming no chunks, which must not be followed
+        let list = ChunkList {
+            filename: Some("other.img".to_string()),
+            size: 0,
+            sha256: hex::encode(<sha2::Sha256 as sha2::Digest>::digest(b"")),
+            keying: "convergent".to_string(),
+            algorithm: "aes".to_string(),
+            compression: "none".to_string(),
+            chunks: Vec::new(),
+        };
+        for contents in [b"MBXC\x02 holiday photos".to_vec(), b"MBXC\x01{not json".to_vec(), list.encode().to_vec()] {
+            fs::write(&input, &contents).unwrap();
+            let blob_id = encrypt_and_upload_aes(&backend, input.to_str().unwrap(), "pw", Compression::NONE).unwrap();
+            download_and_decrypt_aes(&backend, &blob_id, output.to_str().unwrap(), "pw").unwrap();
+            assert_eq!(fs::read(&output).unwrap(), contents);
+        }
+    }
 }
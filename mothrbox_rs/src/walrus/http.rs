@@ -0,0 +1,329 @@
+// walrus/http.rs - Walrus publisher/aggregator HTTP client
+
+use std::io::Read;
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::storage::{BlobMetadata, StorageBackend, StoredBlob};
+use crate::Error;
+
+/// Public testnet publisher, used when `WALRUS_PUBLISHER_URL` is not set
+pub const DEFAULT_PUBLISHER_URL: &str = "https://publisher.walrus-testnet.walrus.space";
+
+/// Public testnet aggregator, used when `WALRUS_AGGREGATOR_URL` is not set
+pub const DEFAULT_AGGREGATOR_URL: &str = "https://aggregator.walrus-testnet.walrus.space";
+
+/// Where and how to talk to Walrus
+#[derive(Debug, Clone)]
+pub struct WalrusHttpConfig {
+    /// Publisher base URL, blobs are stored with `PUT {publisher}/v1/blobs`
+    pub publisher_url: String,
+    /// Aggregator base URL, blobs are read with `GET {aggregator}/v1/blobs/{id}`
+    pub aggregator_url: String,
+    /// Storage epochs bought for each blob
+    pub epochs: u32,
+    /// Per-request timeout, covering connect, upload and download
+    pub timeout: Duration,
+    /// Extra attempts after a connection error, 429 or 5xx response
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each further one
+    pub retry_delay: Duration,
+}
+
+impl Default for WalrusHttpConfig {
+    fn default() -> Self {
+        Self {
+            publisher_url: DEFAULT_PUBLISHER_URL.to_string(),
+            aggregator_url: DEFAULT_AGGREGATOR_URL.to_string(),
+            epochs: 3,
+            timeout: Duration::from_secs(300),
+            retries: 3,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl WalrusHttpConfig {
+    /// Defaults overridden by `WALRUS_PUBLISHER_URL`, `WALRUS_AGGREGATOR_URL`,
+    /// `WALRUS_EPOCHS`, `WALRUS_TIMEOUT_SECS` and `WALRUS_RETRIES`
+    pub fn from_env() -> Result<Self, Error> {
+        let mut config = Self::default();
+        if let Some(url) = env_var("WALRUS_PUBLISHER_URL") {
+            config.publisher_url = url;
+        }
+        if let Some(url) = env_var("WALRUS_AGGREGATOR_URL") {
+            config.aggregator_url = url;
+        }
+        if let Some(epochs) = env_number("WALRUS_EPOCHS")? {
+            config.epochs = epochs;
+        }
+        if let Some(secs) = env_number("WALRUS_TIMEOUT_SECS")? {
+            config.timeout = Duration::from_secs(secs.into());
+        }
+        if let Some(retries) = env_number("WALRUS_RETRIES")? {
+            config.retries = retries;
+        }
+        Ok(config)
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn env_number(name: &str) -> Result<Option<u32>, Error> {
+    env_var(name)
+        .map(|value| {
+            value.parse().map_err(|_| {
+                Error::InvalidInput(format!("{} must be a number, got '{}'", name, value))
+            })
+        })
+        .transpose()
+}
+
+/// Publisher response to `PUT /v1/blobs`: either a new blob object or the
+/// certification of an identical blob stored earlier
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoreResponse {
+    newly_created: Option<NewlyCreated>,
+    already_certified: Option<AlreadyCertified>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NewlyCreated {
+    blob_object: BlobObject,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlobObject {
+    blob_id: String,
+    storage: Option<StorageResource>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StorageResource {
+    end_epoch: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AlreadyCertified {
+    blob_id: String,
+    end_epoch: Option<u32>,
+}
+
+/// Talks to Walrus over HTTP directly, no Deno or Sui key needed
+pub struct WalrusHttpClient {
+    config: WalrusHttpConfig,
+    agent: ureq::Agent,
+}
+
+impl WalrusHttpClient {
+    pub fn new(config: WalrusHttpConfig) -> Self {
+        let agent = ureq::AgentBuilder::new().timeout(config.timeout).build();
+        Self { config, agent }
+    }
+
+    /// Client configured from the `WALRUS_*` environment variables
+    pub fn from_env() -> Result<Self, Error> {
+        Ok(Self::new(WalrusHttpConfig::from_env()?))
+    }
+
+    pub fn config(&self) -> &WalrusHttpConfig {
+        &self.config
+    }
+
+    /// Store `data` for the configured number of epochs
+    pub fn upload(&self, data: &[u8]) -> Result<StoredBlob, Error> {
+        let url = format!(
+            "{}/v1/blobs",
+            self.config.publisher_url.trim_end_matches('/')
+        );
+        let epochs = self.config.epochs.to_string();
+        let request = self.agent.put(&url).query("epochs", &epochs);
+        let response = self.send(None, request, Some(data))?;
+
+        let response: StoreResponse =
+            serde_json::from_reader(response.into_reader()).map_err(|e| {
+                Error::storage(
+                    "walrus",
+                    format!("Failed to parse publisher response: {}", e),
+                )
+            })?;
+        match response {
+            StoreResponse {
+                newly_created: Some(created),
+                ..
+            } => Ok(StoredBlob {
+                blob_id: created.blob_object.blob_id,
+                end_epoch: created.blob_object.storage.map(|s| s.end_epoch),
+            }),
+            StoreResponse {
+                already_certified: Some(certified),
+                ..
+            } => Ok(StoredBlob {
+                blob_id: certified.blob_id,
+                end_epoch: certified.end_epoch,
+            }),
+            _ => Err(Error::storage("walrus", "No blob ID in publisher response")),
+        }
+    }
+
+    /// Read a blob back from the aggregator
+    pub fn download(&self, blob_id: &str) -> Result<Vec<u8>, Error> {
+        let response = self.send(
+            Some(blob_id),
+            self.agent.get(&self.blob_url(blob_id)?),
+            None,
+        )?;
+
+        let mut data = Vec::new();
+        response.into_reader().read_to_end(&mut data).map_err(|e| {
+            Error::storage("walrus", format!("Failed to read blob {}: {}", blob_id, e))
+        })?;
+        Ok(data)
+    }
+
+    /// Size of a blob, from a `HEAD` request to the aggregator
+    pub fn blob_size(&self, blob_id: &str) -> Result<u64, Error> {
+        let response = self.send(
+            Some(blob_id),
+            self.agent.head(&self.blob_url(blob_id)?),
+            None,
+        )?;
+        response
+            .header("Content-Length")
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| Error::storage("walrus", "aggregator did not report the blob size"))
+    }
+
+    fn blob_url(&self, blob_id: &str) -> Result<String, Error> {
+        check_blob_id(blob_id)?;
+        Ok(format!(
+            "{}/v1/blobs/{}",
+            self.config.aggregator_url.trim_end_matches('/'),
+            blob_id
+        ))
+    }
+
+    /// Send `request` with an optional body, retrying connection errors, 429 and 5xx
+    /// with exponential backoff. A 404 for `blob_id` is `Error::NotFound`.
+    fn send(
+        &self,
+        blob_id: Option<&str>,
+        request: ureq::Request,
+        body: Option<&[u8]>,
+    ) -> Result<ureq::Response, Error> {
+        let what = match blob_id {
+            Some(blob_id) => format!("blob {}", blob_id),
+            None => "publisher".to_string(),
+        };
+        let mut delay = self.config.retry_delay;
+        let mut attempt = 0;
+        loop {
+            let result = match body {
+                Some(body) => request.clone().send_bytes(body),
+                None => request.clone().call(),
+            };
+            let error = match (result, blob_id) {
+                (Ok(response), _) => return Ok(response),
+                (Err(ureq::Error::Status(404, _)), Some(blob_id)) => {
+                    return Err(Error::not_found("walrus", blob_id))
+                }
+                (Err(ureq::Error::Status(status, response)), _) => {
+                    let body = response.into_string().unwrap_or_default();
+                    let message = format!("{}: HTTP {}: {}", what, status, body.trim());
+                    if status != 429 && status < 500 {
+                        return Err(Error::storage("walrus", message));
+                    }
+                    message
+                }
+                (Err(ureq::Error::Transport(transport)), _) => format!("{}: {}", what, transport),
+            };
+
+            if attempt >= self.config.retries {
+                return Err(Error::storage("walrus", error));
+            }
+            attempt += 1;
+            thread::sleep(delay);
+            delay = delay.saturating_mul(2);
+        }
+    }
+}
+
+/// Blob ids are URL-safe base64; anything else could rewrite the request path
+fn check_blob_id(blob_id: &str) -> Result<(), Error> {
+    let valid = !blob_id.is_empty()
+        && blob_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidInput(format!(
+            "invalid blob id '{}'",
+            blob_id
+        )))
+    }
+}
+
+impl StorageBackend for WalrusHttpClient {
+    fn name(&self) -> &'static str {
+        "walrus"
+    }
+
+    fn put(&self, data: &[u8]) -> Result<String, Error> {
+        Ok(self.upload(data)?.blob_id)
+    }
+
+    fn store(&self, data: &[u8]) -> Result<StoredBlob, Error> {
+        self.upload(data)
+    }
+
+    fn get(&self, blob_id: &str) -> Result<Vec<u8>, Error> {
+        self.download(blob_id)
+    }
+
+    fn metadata(&self, blob_id: &str) -> Result<BlobMetadata, Error> {
+        Ok(BlobMetadata {
+            blob_id: blob_id.to_string(),
+            size: self.blob_size(blob_id)?,
+        })
+    }
+
+    /// Walrus blobs cannot be deleted through the publisher; they expire
+    /// after the epochs they were stored for
+    fn delete(&self, _blob_id: &str) -> Result<(), Error> {
+        Err(Error::Unsupported(
+            "deleting Walrus blobs over HTTP (they expire after their storage epochs)".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_responses_and_blob_ids() {
+        let created: StoreResponse = serde_json::from_str(
+            r#"{"newlyCreated":{"blobObject":{"id":"0x1","blobId":"abc-_1","size":4},"cost":1}}"#,
+        )
+        .unwrap();
+        assert_eq!(created.newly_created.unwrap().blob_object.blob_id, "abc-_1");
+
+        let certified: StoreResponse =
+            serde_json::from_str(r#"{"alreadyCertified":{"blobId":"xyz","endEpoch":9}}"#).unwrap();
+        assert_eq!(certified.already_certified.unwrap().blob_id, "xyz");
+
+        assert!(check_blob_id("abc-_1").is_ok());
+        assert!(check_blob_id("../v1/api").is_err());
+        assert!(check_blob_id("").is_err());
+    }
+}
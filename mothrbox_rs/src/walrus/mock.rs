@@ -0,0 +1,276 @@
+// walrus/mock.rs - In-process stand-in for a Walrus publisher and aggregator
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::Error;
+
+/// Serves the publisher (`PUT /v1/blobs?epochs=N`) and aggregator
+/// (`GET`/`HEAD /v1/blobs/{id}`) APIs from one local port.
+///
+/// Blob ids are the URL-safe base64 SHA-256 of the contents, so the same
+/// data always gets the same id. Latency, failed responses and the epoch
+/// clock are controlled by the test; blobs past their end epoch are gone.
+/// The server stops when this is dropped.
+pub struct MockWalrus {
+    url: String,
+    state: Arc<Mutex<State>>,
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct StoredBlob {
+    data: Vec<u8>,
+    object_id: String,
+    end_epoch: u32,
+}
+
+#[derive(Default)]
+struct State {
+    blobs: HashMap<String, StoredBlob>,
+    epoch: u32,
+    latency: Duration,
+    /// Responses still to fail, and with which status
+    fail_next: (u32, u16),
+    /// Fail every Nth request (0 = never), and with which status
+    fail_every: (u32, u16),
+    requests: u64,
+    objects: u64,
+}
+
+impl MockWalrus {
+    /// Listen on a free port on 127.0.0.1
+    pub fn start() -> Result<Self, Error> {
+        Self::bind("127.0.0.1:0")
+    }
+
+    /// Listen on `addr`, e.g. `127.0.0.1:31415`
+    pub fn bind(addr: &str) -> Result<Self, Error> {
+        let server = Server::http(addr).map_err(|e| {
+            Error::io(
+                &format!("Failed to bind mock Walrus to {}", addr),
+                std::io::Error::other(e),
+            )
+        })?;
+        let addr = server
+            .server_addr()
+            .to_ip()
+            .expect("mock Walrus listens on TCP");
+        let server = Arc::new(server);
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let thread = {
+            let server = server.clone();
+            let state = state.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let state = state.clone();
+                    thread::spawn(move || handle(&state, request));
+                }
+            })
+        };
+
+        Ok(Self {
+            url: format!("http://{}", addr),
+            state,
+            server,
+            thread: Some(thread),
+        })
+    }
+
+    /// Base URL for both `WALRUS_PUBLISHER_URL` and `WALRUS_AGGREGATOR_URL`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Client settings pointing at this server, retrying quickly
+    #[cfg(feature = "walrus-http")]
+    pub fn http_config(&self) -> super::WalrusHttpConfig {
+        super::WalrusHttpConfig {
+            publisher_url: self.url.clone(),
+            aggregator_url: self.url.clone(),
+            retry_delay: Duration::from_millis(10),
+            ..Default::default()
+        }
+    }
+
+    /// The id this server gives `data`
+    pub fn blob_id(data: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(data))
+    }
+
+    /// Delay every response by `latency`
+    pub fn set_latency(&self, latency: Duration) {
+        self.state().latency = latency;
+    }
+
+    /// Answer the next `count` requests with `status` instead of serving them
+    pub fn fail_next(&self, count: u32, status: u16) {
+        self.state().fail_next = (count, status);
+    }
+
+    /// Answer every `n`th request with `status`; 0 turns this off
+    pub fn fail_every(&self, n: u32, status: u16) {
+        self.state().fail_every = (n, status);
+    }
+
+    /// Current storage epoch, starting at 0
+    pub fn epoch(&self) -> u32 {
+        self.state().epoch
+    }
+
+    /// Move the epoch clock on, expiring blobs whose storage ran out
+    pub fn advance_epochs(&self, epochs: u32) {
+        let mut state = self.state();
+        state.epoch += epochs;
+        let epoch = state.epoch;
+        state.blobs.retain(|_, blob| blob.end_epoch > epoch);
+    }
+
+    /// Blobs currently stored
+    pub fn blob_count(&self) -> usize {
+        self.state().blobs.len()
+    }
+
+    /// Requests received so far, failed ones included
+    pub fn request_count(&self) -> u64 {
+        self.state().requests
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+
+impl Drop for MockWalrus {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn handle(state: &Mutex<State>, mut request: Request) {
+    let (latency, failure) = {
+        let mut state = lock(state);
+        state.requests += 1;
+        let failure = if state.fail_next.0 > 0 {
+            state.fail_next.0 -= 1;
+            Some(state.fail_next.1)
+        } else {
+            let (every, status) = state.fail_every;
+            (every > 0 && state.requests.is_multiple_of(u64::from(every))).then_some(status)
+        };
+        (state.latency, failure)
+    };
+    thread::sleep(latency);
+
+    let response = match failure {
+        Some(status) => text(status, "injected failure"),
+        None => route(state, &mut request),
+    };
+    let _ = request.respond(response);
+}
+
+type Reply = Response<std::io::Cursor<Vec<u8>>>;
+
+fn route(state: &Mutex<State>, request: &mut Request) -> Reply {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+
+    match (request.method(), path.strip_prefix("/v1/blobs")) {
+        (Method::Put, Some("")) => {
+            let epochs = query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("epochs="))
+                .map_or(Ok(1), str::parse::<u32>);
+            let epochs = match epochs {
+                Ok(epochs) if epochs > 0 => epochs,
+                _ => return text(400, "epochs must be a positive number"),
+            };
+            let mut data = Vec::new();
+            if request.as_reader().read_to_end(&mut data).is_err() {
+                return text(400, "failed to read request body");
+            }
+            store(&mut lock(state), data, epochs)
+        }
+        (Method::Get | Method::Head, Some(rest)) if rest.starts_with('/') => {
+            let state = lock(state);
+            match state.blobs.get(&rest[1..]) {
+                Some(blob) => Response::from_data(blob.data.clone()).with_header(
+                    Header::from_bytes("Content-Type", "application/octet-stream").unwrap(),
+                ),
+                None => text(404, "blob not found"),
+            }
+        }
+        (_, Some(_)) => text(405, "method not allowed"),
+        _ => text(404, "not found"),
+    }
+}
+
+/// Store a blob, answering like a publisher: `newlyCreated` the first time,
+/// `alreadyCertified` while an identical blob is still stored
+fn store(state: &mut State, data: Vec<u8>, epochs: u32) -> Reply {
+    let blob_id = MockWalrus::blob_id(&data);
+    let size = data.len();
+    let epoch = state.epoch;
+
+    let body = match state.blobs.get(&blob_id) {
+        Some(blob) => json!({
+            "alreadyCertified": {
+                "blobId": blob_id,
+                "object": blob.object_id,
+                "endEpoch": blob.end_epoch,
+            }
+        }),
+        None => {
+            state.objects += 1;
+            let object_id = format!("0x{:064x}", state.objects);
+            let end_epoch = epoch + epochs;
+            state.blobs.insert(
+                blob_id.clone(),
+                StoredBlob {
+                    data,
+                    object_id: object_id.clone(),
+                    end_epoch,
+                },
+            );
+            json!({
+                "newlyCreated": {
+                    "blobObject": {
+                        "id": object_id,
+                        "registeredEpoch": epoch,
+                        "blobId": blob_id,
+                        "size": size,
+                        "certifiedEpoch": epoch,
+                        "storage": {
+                            "startEpoch": epoch,
+                            "endEpoch": end_epoch,
+                        },
+                        "deletable": false,
+                    },
+                    "cost": size as u64 * u64::from(epochs),
+                }
+            })
+        }
+    };
+    Response::from_data(body.to_string())
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn text(status: u16, message: &str) -> Reply {
+    Response::from_string(message).with_status_code(status)
+}
@@ -0,0 +1,1067 @@
+// End-to-end tests against the mock Walrus server: the HTTP client through
+// the library, and the mothrbox-cli binary through its environment.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::time::Duration;
+
+use mothrbox_engine::storage::StorageBackend;
+use mothrbox_engine::walrus::mock::MockWalrus;
+use mothrbox_engine::walrus::WalrusHttpClient;
+use mothrbox_engine::Error;
+
+fn client(walrus: &MockWalrus) -> WalrusHttpClient {
+    WalrusHttpClient::new(walrus.http_config())
+}
+
+/// Run mothrbox-cli with the Walrus HTTP backend pointed at `walrus`,
+/// keeping its catalog in `dir`
+fn cli(walrus: &MockWalrus, dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mothrbox-cli"))
+        .args(args)
+        .env("MOTHRBOX_BACKEND", "walrus")
+        .env("MOTHRBOX_CATALOG", dir.join("catalog.jsonl"))
+        .env("WALRUS_PUBLISHER_URL", walrus.url())
+        .env("WALRUS_AGGREGATOR_URL", walrus.url())
+        .env("WALRUS_RETRIES", "0")
+        .env_remove("MOTHRBOX_COMPRESS")
+        .output()
+        .expect("failed to run mothrbox-cli")
+}
+
+/// The blob id printed by an upload command
+fn printed_blob_id(output: &Output) -> String {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "upload failed: {}", stdout);
+    stdout
+        .lines()
+        .find_map(|line| line.split("Blob ID: ").nth(1))
+        .expect("no blob id printed")
+        .trim()
+        .to_string()
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn test_http_client_roundtrip() {
+    let walrus = MockWalrus::start().unwrap();
+    let client = client(&walrus);
+
+    let blob_id = client.put(b"ciphertext").unwrap();
+    assert_eq!(blob_id, MockWalrus::blob_id(b"ciphertext"));
+    assert_eq!(client.get(&blob_id).unwrap(), b"ciphertext");
+    assert_eq!(client.metadata(&blob_id).unwrap().size, 10);
+
+    // Storing the same bytes again is answered with `alreadyCertified`
+    assert_eq!(client.put(b"ciphertext").unwrap(), blob_id);
+    assert_eq!(walrus.blob_count(), 1);
+
+    let missing = MockWalrus::blob_id(b"never stored");
+    assert!(!client.exists(&missing).unwrap());
+    assert!(matches!(client.get(&missing), Err(Error::NotFound { .. })));
+}
+
+#[test]
+fn test_retries_and_injected_failures() {
+    let walrus = MockWalrus::start().unwrap();
+    let client = client(&walrus);
+
+    // Two 503s are retried away (three retries by default)
+    walrus.fail_next(2, 503);
+    let blob_id = client.put(b"retried").unwrap();
+    assert_eq!(walrus.request_count(), 3);
+
+    // Persistent outages surface as storage errors
+    walrus.fail_next(10, 502);
+    assert!(matches!(client.get(&blob_id), Err(Error::Storage { .. })));
+    walrus.fail_next(0, 0);
+
+    // Client errors are not retried
+    walrus.fail_next(1, 400);
+    let before = walrus.request_count();
+    assert!(matches!(
+        client.put(b"rejected"),
+        Err(Error::Storage { .. })
+    ));
+    assert_eq!(walrus.request_count(), before + 1);
+
+    walrus.fail_every(2, 503);
+    for i in 0..4u8 {
+        assert!(client.put(&[i]).is_ok());
+    }
+}
+
+#[test]
+fn test_latency_and_timeouts() {
+    let walrus = MockWalrus::start().unwrap();
+    walrus.set_latency(Duration::from_millis(300));
+
+    let mut config = walrus.http_config();
+    config.timeout = Duration::from_millis(50);
+    config.retries = 0;
+    let impatient = WalrusHttpClient::new(config);
+    assert!(matches!(impatient.put(b"slow"), Err(Error::Storage { .. })));
+
+    assert!(client(&walrus).put(b"slow").is_ok());
+}
+
+#[test]
+fn test_blobs_expire_after_their_epochs() {
+    let walrus = MockWalrus::start().unwrap();
+    let mut config = walrus.http_config();
+    config.epochs = 2;
+    let client = WalrusHttpClient::new(config);
+
+    let blob_id = client.put(b"short-lived").unwrap();
+    walrus.advance_epochs(1);
+    assert!(client.exists(&blob_id).unwrap());
+    walrus.advance_epochs(1);
+    assert_eq!(walrus.blob_count(), 0);
+    assert!(matches!(client.get(&blob_id), Err(Error::NotFound { .. })));
+}
+
+#[test]
+fn test_cli_upload_and_download() {
+    let walrus = MockWalrus::start().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("report.txt");
+    let password = dir.path().join("password");
+    fs::write(&input, "quarterly numbers\n".repeat(100)).unwrap();
+    fs::write(&password, "correct horse\n").unwrap();
+
+    for algo in ["aes", "chacha"] {
+        let output = cli(
+            &walrus,
+            dir.path(),
+            &[
+                "walrus",
+                "put",
+                path(&input),
+                "--algo",
+                algo,
+                "--password-file",
+                path(&password),
+                "--compress",
+                "lz4",
+            ],
+        );
+        let blob_id = printed_blob_id(&output);
+
+        let restored = dir.path().join(format!("{}.txt", algo));
+        let output = cli(
+            &walrus,
+            dir.path(),
+            &[
+                "walrus",
+                "get",
+                &blob_id,
+                path(&restored),
+                "--auto",
+                "--password-file",
+                path(&password),
+            ],
+        );
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(fs::read(&restored).unwrap(), fs::read(&input).unwrap());
+    }
+
+    // ECC goes through the same path with a recipient key
+    let output = cli(&walrus, dir.path(), &["ecc", "keygen", path(dir.path())]);
+    assert!(output.status.success());
+    let public_key = dir.path().join("public.key");
+    let private_key = dir.path().join("private.key");
+    let blob_id = printed_blob_id(&cli(
+        &walrus,
+        dir.path(),
+        &[
+            "walrus",
+            "put",
+            path(&input),
+            "--algo",
+            "ecc",
+            "--recipient",
+            path(&public_key),
+        ],
+    ));
+    let restored = dir.path().join("ecc.txt");
+    let get = |algo: &str| {
+        cli(
+            &walrus,
+            dir.path(),
+            &[
+                "walrus",
+                "get",
+                &blob_id,
+                path(&restored),
+                "--algo",
+                algo,
+                "--private-key",
+                path(&private_key),
+            ],
+        )
+    };
+    // Pinning the wrong algorithm is a format error
+    assert_eq!(get("aes").status.code(), Some(4));
+    assert!(get("ecc").status.success());
+    assert_eq!(fs::read(&restored).unwrap(), fs::read(&input).unwrap());
+
+    // Raw upload of an already encrypted file stores it byte for byte
+    let encrypted = dir.path().join("report.enc");
+    let output = cli(
+        &walrus,
+        dir.path(),
+        &[
+            "aes",
+            "encrypt",
+            path(&input),
+            path(&encrypted),
+            "--password-file",
+            path(&password),
+        ],
+    );
+    assert!(output.status.success());
+    let blob_id = printed_blob_id(&cli(
+        &walrus,
+        dir.path(),
+        &["walrus", "upload", path(&encrypted)],
+    ));
+    assert_eq!(blob_id, MockWalrus::blob_id(&fs::read(&encrypted).unwrap()));
+}
+
+#[test]
+fn test_cli_storage_errors() {
+    let walrus = MockWalrus::start().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let output_path = dir.path().join("out");
+
+    // Unknown blobs and outages both exit with the storage status
+    let missing = MockWalrus::blob_id(b"never stored");
+    let output = cli(
+        &walrus,
+        dir.path(),
+        &["walrus", "download", &missing, path(&output_path)],
+    );
+    assert_eq!(output.status.code(), Some(7));
+
+    walrus.fail_next(1, 503);
+    let input = dir.path().join("blob");
+    fs::write(&input, b"ciphertext").unwrap();
+    let output = cli(&walrus, dir.path(), &["walrus", "upload", path(&input)]);
+    assert_eq!(output.status.code(), Some(7));
+}
+
+#[test]
+fn test_cli_catalog() {
+    let walrus = MockWalrus::start().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("report.txt");
+    let password = dir.path().join("password");
+    fs::write(&input, "quarterly numbers\n").unwrap();
+    fs::write(&password, "correct horse\n").unwrap();
+    let run = |args: &[&str]| cli(&walrus, dir.path(), args);
+    let stdout = |output: &Output| String::from_utf8_lossy(&output.stdout).into_owned();
+
+    let blob_id = printed_blob_id(&run(&[
+        "walrus",
+        "put",
+        path(&input),
+        "--algo",
+        "aes",
+        "--password-file",
+        path(&password),
+        "--name",
+        "q3",
+        "--tag",
+        "finance",
+    ]));
+    let output = run(&["ls"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains(&blob_id));
+    assert!(!stdout(&run(&["ls", "--tag", "legal"])).contains(&blob_id));
+
+    let shown = stdout(&run(&["show", "q3"]));
+    assert!(shown.contains("report.txt"));
+    assert!(shown.contains("argon2id"));
+    assert!(shown.contains("Tags:        finance"));
+
+    // Names resolve to blob ids, and the recorded algorithm is enforced
+    let restored = dir.path().join("restored.txt");
+    let output = run(&[
+        "get",
+        "q3",
+        path(&restored),
+        "--password-file",
+        path(&password),
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read(&restored).unwrap(), fs::read(&input).unwrap());
+
+    assert!(run(&["rm", "q3"]).status.success());
+    assert_eq!(run(&["show", "q3"]).status.code(), Some(7));
+    assert_eq!(
+        run(&["get", "q3", "--password-file", path(&password)])
+            .status
+            .code(),
+        Some(7)
+    );
+}
+
+#[test]
+fn test_cli_manifest_and_inspect() {
+    let walrus = MockWalrus::start().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("notes.md");
+    let sealed = dir.path().join("sealed.mbx");
+    let key = dir.path().join("aes.key");
+    let restore = dir.path().join("restore");
+    fs::write(&input, "# launch checklist\n").unwrap();
+    fs::create_dir(&restore).unwrap();
+    let modified = std::time::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    fs::File::options()
+        .write(true)
+        .open(&input)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    let run = |args: &[&str]| cli(&walrus, dir.path(), args);
+    let stdout = |output: &Output| String::from_utf8_lossy(&output.stdout).into_owned();
+
+    assert!(run(&["aes", "keygen", "--out", path(&key)])
+        .status
+        .success());
+    let output = run(&[
+        "aes",
+        "encrypt",
+        path(&input),
+        path(&sealed),
+        "--key-file",
+        path(&key),
+        "--meta",
+        "owner=ops",
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let inspected = stdout(&run(&["inspect", path(&sealed)]));
+    assert!(inspected.contains("Algorithm:   aes"));
+    assert!(inspected.contains("Manifest:    present"));
+    assert!(!inspected.contains("notes.md"));
+    assert_eq!(run(&["inspect", path(&input)]).status.code(), Some(4));
+
+    let output = run(&[
+        "decrypt",
+        path(&sealed),
+        path(&restore),
+        "--restore-name",
+        "--key-file",
+        path(&key),
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout(&output).contains("owner = ops"));
+    let restored = restore.join("notes.md");
+    assert_eq!(fs::read(&restored).unwrap(), fs::read(&input).unwrap());
+    assert_eq!(
+        fs::metadata(&restored).unwrap().modified().unwrap(),
+        modified
+    );
+
+    // Containers without a manifest have no name to restore
+    assert!(run(&[
+        "aes",
+        "encrypt",
+        path(&input),
+        path(&sealed),
+        "--key-file",
+        path(&key),
+    ])
+    .status
+    .success());
+    assert!(!run(&[
+        "decrypt",
+        path(&sealed),
+        path(&restore),
+        "--restore-name",
+        "--key-file",
+        path(&key),
+    ])
+    .status
+    .success());
+    assert_eq!(
+        run(&[
+            "aes",
+            "encrypt",
+            path(&input),
+            path(&sealed),
+            "--key-file",
+            path(&key),
+            "--meta",
+            "novalue"
+        ])
+        .status
+        .code(),
+        Some(1)
+    );
+}
+
+#[test]
+fn test_cli_ecc_multiple_recipients() {
+    let walrus = MockWalrus::start().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("plan.txt");
+    let sealed = dir.path().join("plan.mbx");
+    let opened = dir.path().join("opened.txt");
+    fs::write(&input, "shared with alice and bob").unwrap();
+    let run = |args: &[&str]| cli(&walrus, dir.path(), args);
+    let keys = |name: &str| {
+        let keys = dir.path().join(name);
+        fs::create_dir(&keys).unwrap();
+        assert!(run(&["ecc", "keygen", path(&keys)]).status.success());
+        keys
+    };
+    let (alice, bob, carol) = (keys("alice"), keys("bob"), keys("carol"));
+
+    let output = run(&[
+        "ecc",
+        "encrypt",
+        path(&input),
+        path(&sealed),
+        "-r",
+        path(&alice.join("public.key")),
+        "-r",
+        path(&bob.join("public.key")),
+        "--algo",
+        "chacha",
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let inspected = String::from_utf8_lossy(&run(&["inspect", path(&sealed)]).stdout).into_owned();
+    assert!(inspected.contains("Algorithm:   chacha"));
+    assert!(inspected.contains("Recipients:  2"));
+
+    let private_key = |keys: &Path| keys.join("private.key");
+    assert!(run(&[
+        "ecc",
+        "decrypt",
+        path(&sealed),
+        path(&opened),
+        path(&private_key(&bob))
+    ])
+    .status
+    .success());
+    assert_eq!(fs::read(&opened).unwrap(), fs::read(&input).unwrap());
+    fs::remove_file(&opened).unwrap();
+    assert!(run(&[
+        "decrypt",
+        path(&sealed),
+        path(&opened),
+        "--private-key",
+        path(&private_key(&alice))
+    ])
+    .status
+    .success());
+    assert_eq!(fs::read(&opened).unwrap(), fs::read(&input).unwrap());
+
+    let output = run(&[
+        "ecc",
+        "decrypt",
+        path(&sealed),
+        path(&dir.path().join("carol.txt")),
+        path(&private_key(&carol)),
+    ]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(!dir.path().join("carol.txt").exists());
+}
+
+#[test]
+fn test_cli_rekey_recipients() {
+    let walrus = MockWalrus::start().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("roster.txt");
+    let sealed = dir.path().join("roster.mbx");
+    fs::write(&input, "on call this week").unwrap();
+    let run = |args: &[&str]| cli(&walrus, dir.path(), args);
+    let keys = |name: &str| {
+        let keys = dir.path().join(name);
+        fs::create_dir(&keys).unwrap();
+        assert!(run(&["ecc", "keygen", path(&keys)]).status.success());
+        (
+            keys.join("private.key").to_str().unwrap().to_string(),
+            keys.join("public.key").to_str().unwrap().to_string(),
+        )
+    };
+    let (alice, bob, carol) = (keys("alice"), keys("bob"), keys("carol"));
+    let opens = |who: &(String, String)| {
+        let opened = dir.path().join("opened.txt");
+        let _ = fs::remove_file(&opened);
+        run(&["ecc", "decrypt", path(&sealed), path(&opened), &who.0])
+            .status
+            .success()
+    };
+    let succeeds = |output: Output| {
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        )
+    };
+
+    // A single-recipient file is converted to an envelope on first grant
+    succeeds(run(&[
+        "ecc",
+        "encrypt",
+        path(&input),
+        path(&sealed),
+        &alice.1,
+    ]));
+    succeeds(run(&[
+        "rekey",
+        "add-recipient",
+        path(&sealed),
+        "-r",
+        &bob.1,
+        "--private-key",
+        &alice.0,
+    ]));
+    let payload = fs::read(&sealed).unwrap();
+    succeeds(run(&[
+        "rekey",
+        "add-recipient",
+        path(&sealed),
+        "-r",
+        &carol.1,
+        "--private-key",
+        &bob.0,
+    ]));
+    assert!(opens(&alice) && opens(&bob) && opens(&carol));
+    let grown = fs::read(&sealed).unwrap();
+    assert_eq!(grown[grown.len() - 40..], payload[payload.len() - 40..]);
+
+    let output = run(&["rekey", "remove-recipient", path(&sealed), "-r", &bob.1]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--rotate"));
+    succeeds(output);
+    assert!(opens(&alice) && !opens(&bob) && opens(&carol));
+
+    // Rotating needs every remaining recipient's public key
+    let rotate = |keep: &[&str]| {
+        let mut args = vec![
+            "rekey",
+            "remove-recipient",
+            path(&sealed),
+            "-r",
+            &carol.1,
+            "--rotate",
+            "--private-key",
+            &alice.0,
+        ];
+        for public_key in keep {
+            args.extend(["--keep", *public_key]);
+        }
+        run(&args)
+    };
+    assert_eq!(rotate(&[&alice.1, &bob.1]).status.code(), Some(1));
+    succeeds(rotate(&[&alice.1]));
+    assert!(opens(&alice) && !opens(&carol));
+    let inspected = String::from_utf8_lossy(&run(&["inspect", path(&sealed)]).stdout).into_owned();
+    assert!(inspected.contains("Recipients:  1"));
+}
+
+#[test]
+fn test_cli_hpke_auth_mode() {
+    let walrus = MockWalrus::start().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("terms.txt");
+    let sealed = dir.path().join("terms.mbx");
+    let opened = dir.path().join("opened.txt");
+    fs::write(&input, "for our partners only").unwrap();
+    let run = |args: &[&str]| cli(&walrus, dir.path(), args);
+    let keys = |name: &str, kem: &str| {
+        let keys = dir.path().join(name);
+        fs::create_dir(&keys).unwrap();
+        assert!(run(&["hpke", "keygen", path(&keys), "--kem", kem])
+            .status
+            .success());
+        keys
+    };
+    let (partner, us, p256) = (
+        keys("partner", "x25519"),
+        keys("us", "x25519"),
+        keys("p256", "p256"),
+    );
+    assert_eq!(fs::read(partner.join("public.key")).unwrap().len(), 32);
+    assert_eq!(fs::read(p256.join("public.key")).unwrap().len(), 65);
+
+    let output = run(&[
+        "hpke",
+        "encrypt",
+        path(&input),
+        path(&sealed),
+        path(&partner.join("public.key")),
+        "--sender",
+        path(&us.join("private.key")),
+        "--algo",
+        "chacha",
+        "--aad",
+        "contract-7",
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let inspected = String::from_utf8_lossy(&run(&["inspect", path(&sealed)]).stdout).into_owned();
+    assert!(inspected
+        .contains("Algorithm:   hpke (DHKEM(X25519, HKDF-SHA256), ChaCha20-Poly1305, auth mode)"));
+
+    let partner_key = partner.join("private.key");
+    let decrypt = |sender: Option<&Path>| {
+        let mut args = vec![
+            "hpke",
+            "decrypt",
+            path(&sealed),
+            path(&opened),
+            path(&partner_key),
+            "--aad",
+            "contract-7",
+        ];
+        if let Some(sender) = sender {
+            args.extend(["--sender", path(sender)]);
+        }
+        run(&args)
+    };
+    assert!(decrypt(Some(&us.join("public.key"))).status.success());
+    assert_eq!(fs::read(&opened).unwrap(), fs::read(&input).unwrap());
+    // Auth mode needs the sender, and the right one
+    assert_eq!(decrypt(None).status.code(), Some(5));
+    assert_eq!(
+        decrypt(Some(&p256.join("public.key"))).status.code(),
+        Some(5)
+    );
+    assert_eq!(
+        decrypt(Some(&partner.join("public.key"))).status.code(),
+        Some(3)
+    );
+
+    // Base mode to a P-256 key also opens with the generic decrypt
+    assert!(run(&[
+        "hpke",
+        "encrypt",
+        path(&input),
+        path(&sealed),
+        path(&p256.join("public.key"))
+    ])
+    .status
+    .success());
+    fs::remove_file(&opened).unwrap();
+    assert!(run(&[
+        "decrypt",
+        path(&sealed),
+        path(&opened),
+        "--private-key",
+        path(&p256.join("private.key"))
+    ])
+    .status
+    .success());
+    assert_eq!(fs::read(&opened).unwrap(), fs::read(&input).unwrap());
+}
+
+#[test]
+fn test_cli_ecc_x25519_and_sui_keys() {
+    use base64::Engine as _;
+    use mothrbox_engine::sui;
+
+    let walrus = MockWalrus::start().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("invoice.txt");
+    let sealed = dir.path().join("invoice.mbx");
+    let opened = dir.path().join("opened.txt");
+    fs::write(&input, "due on delivery").unwrap();
+    let run = |args: &[&str]| cli(&walrus, dir.path(), args);
+    let key_dir = |name: &str| {
+        let keys = dir.path().join(name);
+        fs::create_dir(&keys).unwrap();
+        keys
+    };
+    let (alice, carol, wallet, published) = (
+        key_dir("alice"),
+        key_dir("carol"),
+        key_dir("wallet"),
+        key_dir("published"),
+    );
+    assert!(run(&["ecc", "keygen", path(&alice), "--curve", "x25519"])
+        .status
+        .success());
+    assert!(run(&["ecc", "keygen", path(&carol)]).status.success());
+    assert_eq!(fs::read(alice.join("public.key")).unwrap().len(), 32);
+    assert_eq!(fs::read(carol.join("public.key")).unwrap().len(), 65);
+
+    // The wallet owner derives a key pair from their Sui keystore...
+    let seed = [9u8; 32];
+    let ed25519_public = sui::ed25519_public_key(&seed);
+    let owner = sui::address(&ed25519_public);
+    let flagged = |key: &[u8; 32]| {
+        let mut bytes = vec![sui::ED25519_FLAG];
+        bytes.extend_from_slice(key);
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    };
+    let keystore = dir.path().join("sui.keystore");
+    fs::write(&keystore, format!("[\"{}\"]", flagged(&seed))).unwrap();
+    let output = run(&[
+        "ecc",
+        "from-sui",
+        path(&wallet),
+        "--keystore",
+        path(&keystore),
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&owner));
+
+    // ...and anyone else gets the same public key from the published one
+    let public_key = flagged(&ed25519_public);
+    let from_published = |address: &str| {
+        run(&[
+            "ecc",
+            "from-sui",
+            path(&published),
+            "--public-key",
+            &public_key,
+            "--address",
+            address,
+        ])
+    };
+    assert_eq!(from_published("0x1234").status.code(), Some(5));
+    assert!(from_published(&owner).status.success());
+    assert_eq!(
+        fs::read(published.join("public.key")).unwrap(),
+        fs::read(wallet.join("public.key")).unwrap()
+    );
+
+    let decrypts = |private_key: &Path| {
+        run(&[
+            "ecc",
+            "decrypt",
+            path(&sealed),
+            path(&opened),
+            path(private_key),
+        ])
+        .status
+        .success()
+            && fs::read(&opened).unwrap() == fs::read(&input).unwrap()
+    };
+    assert!(run(&[
+        "ecc",
+        "encrypt",
+        path(&input),
+        path(&sealed),
+        path(&published.join("public.key")),
+    ])
+    .status
+    .success());
+    let inspected = String::from_utf8_lossy(&run(&["inspect", path(&sealed)]).stdout).into_owned();
+    assert!(inspected.contains("Algorithm:   ecc (X25519)"));
+    assert!(decrypts(&wallet.join("private.key")));
+    assert!(!decrypts(&alice.join("private.key")));
+
+    // Envelopes take recipients on either curve
+    assert!(run(&[
+        "ecc",
+        "encrypt",
+        path(&input),
+        path(&sealed),
+        "-r",
+        path(&published.join("public.key")),
+        "-r",
+        path(&alice.join("public.key")),
+        "-r",
+        path(&carol.join("public.key")),
+    ])
+    .status
+    .success());
+    for keys in [&wallet, &alice, &carol] {
+        assert!(decrypts(&keys.join("private.key")));
+    }
+}
+
+#[test]
+fn test_cli_pack_and_unpack() {
+    let walrus = MockWalrus::start().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().join("project");
+    let key = dir.path().join("chacha.key");
+    fs::create_dir_all(project.join("src")).unwrap();
+    fs::create_dir_all(project.join("target")).unwrap();
+    fs::write(project.join("README.md"), "# project\n").unwrap();
+    fs::write(project.join("src/lib.rs"), "pub fn answer() -> u8 { 42 }\n").unwrap();
+    fs::write(project.join("target/junk"), "build output").unwrap();
+    let run = |args: &[&str]| cli(&walrus, dir.path(), args);
+    assert!(run(&["chacha", "keygen", "--out", path(&key)])
+        .status
+        .success());
+
+    // To a file, then back
+    let archive = dir.path().join("project.mbx");
+    let output = run(&[
+        "pack",
+        path(&project),
+        path(&archive),
+        "--algo",
+        "chacha",
+        "--key-file",
+        path(&key),
+        "--exclude",
+        "target",
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("2 files"));
+    let restored = dir.path().join("restored");
+    let output = run(&[
+        "unpack",
+        path(&archive),
+        path(&restored),
+        "--key-file",
+        path(&key),
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read(restored.join("src/lib.rs")).unwrap(),
+        fs::read(project.join("src/lib.rs")).unwrap()
+    );
+    assert!(!restored.join("target").exists());
+
+    // One Walrus blob per file plus an index
+    let index_id = printed_blob_id(&run(&[
+        "pack",
+        path(&project),
+        "--algo",
+        "chacha",
+        "--key-file",
+        path(&key),
+        "--upload",
+        "--per-file",
+    ]));
+    let from_walrus = dir.path().join("from_walrus");
+    let output = run(&[
+        "unpack",
+        &index_id,
+        path(&from_walrus),
+        "--blob",
+        "--key-file",
+        path(&key),
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read(from_walrus.join("target/junk")).unwrap(),
+        b"build output"
+    );
+}
+
+#[test]
+fn test_cli_dedup_put_only_sends_changed_chunks() {
+    let walrus = MockWalrus::start().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("disk.img");
+    let key = dir.path().join("aes.key");
+    let run = |args: &[&str]| cli(&walrus, dir.path(), args);
+    let stdout = |output: &Output| String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(run(&["aes", "keygen", "--out", path(&key)])
+        .status
+        .success());
+
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut data: Vec<u8> = (0..6 * 1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    fs::write(&input, &data).unwrap();
+    let put = || {
+        run(&[
+            "walrus",
+            "put",
+            path(&input),
+            "--algo",
+            "aes",
+            "--key-file",
+            path(&key),
+            "--dedup",
+            "--vault-key",
+            path(&key),
+        ])
+    };
+    let first = put();
+    assert!(
+        first.status.success(),
+        "{}",
+        String::from_utf8_lossy(&first.stderr)
+    );
+    assert!(dir.path().join("chunks.jsonl").exists());
+
+    // Change a few bytes at the end: only the last chunk goes up again
+    let end = data.len() - 10;
+    data[end..].copy_from_slice(b"0123456789");
+    fs::write(&input, &data).unwrap();
+    let second = put();
+    let second_out = stdout(&second);
+    let (new, total) = second_out
+        .lines()
+        .find_map(|line| line.split("Uploaded ").nth(1))
+        .and_then(|rest| rest.split_once(" of "))
+        .map(|(new, rest)| (new.to_string(), rest.split(' ').next().unwrap().to_string()))
+        .expect("no dedup summary printed");
+    assert_eq!(new, "1", "{}", second_out);
+    assert!(total.parse::<u32>().unwrap() > 1);
+
+    assert!(stdout(&run(&["show", "disk.img"])).contains("Chunks:"));
+    let restored = dir.path().join("restored.img");
+    let output = run(&["get", "disk.img", path(&restored), "--key-file", path(&key)]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read(&restored).unwrap(), data);
+
+    // The list blob alone is enough to fetch the file too
+    let blob_id = printed_blob_id(&second);
+    let fetched = dir.path().join("fetched.img");
+    let output = run(&[
+        "walrus",
+        "get",
+        &blob_id,
+        path(&fetched),
+        "--auto",
+        "--key-file",
+        path(&key),
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read(&fetched).unwrap(), data);
+}
+
+#[test]
+fn test_cli_backup_restore_diff_and_prune() {
+    let walrus = MockWalrus::start().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let home = dir.path().join("home");
+    let key = dir.path().join("aes.key");
+    fs::create_dir_all(home.join("docs")).unwrap();
+    fs::write(home.join("docs/plan.txt"), "step one\n").unwrap();
+    fs::write(home.join("notes.txt"), "hello\n").unwrap();
+    let run = |args: &[&str]| cli(&walrus, dir.path(), args);
+    let stdout = |output: &Output| {
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert!(run(&["aes", "keygen", "--out", path(&key)])
+        .status
+        .success());
+    let backup = || {
+        stdout(&run(&[
+            "backup",
+            path(&home),
+            "--vault",
+            "home",
+            "--algo",
+            "aes",
+            "--key-file",
+            path(&key),
+        ]))
+    };
+
+    assert!(backup().contains("2 added"));
+    fs::write(home.join("notes.txt"), "hello again\n").unwrap();
+    let blobs = walrus.blob_count();
+    let second = backup();
+    assert!(second.contains("1 unchanged, 1 changed"), "{}", second);
+    // The changed file's one chunk, then the snapshot
+    assert_eq!(walrus.blob_count(), blobs + 2);
+
+    let listed = stdout(&run(&["snapshots", "--vault", "home"]));
+    assert_eq!(listed.lines().count(), 3, "{}", listed);
+    let diff = stdout(&run(&["diff", "latest", "--key-file", path(&key)]));
+    assert!(diff.contains("M  notes.txt"), "{}", diff);
+    assert!(!diff.contains("plan.txt"));
+
+    let restored = dir.path().join("restored");
+    stdout(&run(&[
+        "restore",
+        "latest",
+        path(&restored),
+        "--vault",
+        "home",
+        "--key-file",
+        path(&key),
+    ]));
+    assert_eq!(
+        fs::read(restored.join("notes.txt")).unwrap(),
+        b"hello again\n"
+    );
+    assert_eq!(
+        fs::read(restored.join("docs/plan.txt")).unwrap(),
+        b"step one\n"
+    );
+
+    // Walrus blobs expire instead of being deleted, so prune only forgets
+    assert!(!run(&[
+        "prune",
+        "--vault",
+        "home",
+        "--keep-last",
+        "1",
+        "--delete-blobs",
+        "--key-file",
+        path(&key),
+    ])
+    .status
+    .success());
+    stdout(&run(&["prune", "--vault", "home", "--keep-last", "1"]));
+    let listed = stdout(&run(&["snapshots"]));
+    assert_eq!(listed.lines().count(), 2, "{}", listed);
+}
@@ -1,5 +1,5 @@
 use aes_gcm::{
-    Aes256Gcm, Nonce,
+    Aes256Gcm,
     aead::{Aead, AeadCore, KeyInit, OsRng},
 };
 
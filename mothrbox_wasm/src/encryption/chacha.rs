@@ -1,5 +1,5 @@
 use chacha20poly1305::{
-    XChaCha20Poly1305, XNonce,
+    XChaCha20Poly1305,
     aead::{Aead, AeadCore, KeyInit, OsRng},
 };
 
@@ -0,0 +1,3 @@
+pub mod aes;
+pub mod chacha;
+pub mod ecc;
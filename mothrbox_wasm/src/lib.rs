@@ -1,3 +1,5 @@
+pub mod encryption;
+
 use getrandom::getrandom;
 use wasm_bindgen::prelude::*;
 // use mothrbox::your_encryption_logic; // Import your existing logic if compatible
@@ -37,6 +39,25 @@ pub fn encrypt_blob(file_data: &[u8], user_password: &str) -> Result<EncryptedRe
     })
 }
 
+// ECIES to a P-256 or X25519 public key (hex), readable by mothrbox-cli
+#[wasm_bindgen]
+pub fn ecc_encrypt_to_public_key(
+    file_data: &[u8],
+    recipient_pub_hex: &str,
+) -> Result<Vec<u8>, JsError> {
+    encryption::ecc::encrypt_to_public_key(file_data, recipient_pub_hex)
+        .map_err(|e| JsError::new(&e))
+}
+
+#[wasm_bindgen]
+pub fn ecc_decrypt_with_private_key(
+    encrypted_data: &[u8],
+    private_key: &[u8],
+) -> Result<Vec<u8>, JsError> {
+    encryption::ecc::decrypt_with_private_key(encrypted_data, private_key)
+        .map_err(|e| JsError::new(&e))
+}
+
 // Mock function - Replace with your actual Mothrbox encryption
 fn mock_encrypt(data: &[u8], key: &str, nonce: &[u8]) -> Vec<u8> {
     // XOR cipher just for demo purposes
@@ -1,22 +1,31 @@
 // nautilus-enclave/src/main.rs
 // MothrBox Nautilus Enclave Server - Custom for your function signatures
 
-use actix_web::{web, App, HttpResponse, HttpServer, middleware};
+use actix_web::{web, App, HttpResponse, HttpServer, middleware, http::StatusCode};
 use serde::{Deserialize, Serialize};
-use std::fs;
 use base64::{Engine as _, engine::general_purpose};
 use sha2::{Sha256, Digest};
-use std::process::Command;
-use mothrbox_crypto;
+use mothrbox_crypto::Error as CryptoError;
+use mothrbox_crypto::compress::Compression;
+use mothrbox_crypto::ecc::MothrboxPublicKey;
+use mothrbox_crypto::header::{Algorithm, KdfParams};
+use mothrbox_crypto::{walrus, DecryptionKey, EncryptOptions, EncryptionKey};
+use mothrbox_crypto::storage::{self, StorageBackend};
+use mothrbox_crypto::secret::{LockedKey, Zeroizing};
+use secrecy::{ExposeSecret, SecretString};
 
 #[derive(Deserialize)]
 struct EncryptRequest {
     file_data: String,  // Base64 encoded
-    password: String,
+    password: SecretString,
     algorithm: String,  // "aes", "chacha", or "ecc"
     filename: String,
     #[serde(default)]
-    public_key: Option<String>,  // For ECC (base64)
+    public_key: Option<String>,  // For ECC (base64 P-256 SEC1 or 32-byte X25519)
+    /// "zstd", "zstd:<level>", "lz4" or "none" (default). Leave unset for data that
+    /// mixes secrets with attacker-controlled content: compressed size leaks it (CRIME)
+    #[serde(default)]
+    compress: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -31,10 +40,10 @@ struct EncryptResponse {
 #[derive(Deserialize)]
 struct DecryptRequest {
     blob_id: String,
-    password: String,
+    password: SecretString,
     algorithm: String,
     #[serde(default)]
-    private_key: Option<String>,  // For ECC (base64)
+    private_key: Option<SecretString>,  // For ECC (base64)
 }
 
 #[derive(Serialize)]
@@ -52,39 +61,25 @@ fn generate_attestation(user_data: &str) -> String {
     general_purpose::STANDARD.encode(hash)
 }
 
-fn upload_to_walrus(file_path: &str) -> Result<String, String> {
-    let output = Command::new("deno")
-        .args(&[
-            "run", "-A", "--env-file=../mothrbox_ts/.env",
-            "../mothrbox_ts/src/walrus-cli.ts", "upload", file_path,
-        ])
-        .output()
-        .map_err(|e| format!("Walrus upload failed: {}", e))?;
-
-    if !output.status.success() {
-        return Err(format!("Walrus error: {}", String::from_utf8_lossy(&output.stderr)));
-    }
-
-    let response: serde_json::Value = serde_json::from_slice(&output.stdout)
-        .map_err(|e| format!("Parse error: {}", e))?;
-
-    response["blobId"].as_str().map(|s| s.to_string())
-        .ok_or_else(|| "No blobId".to_string())
-}
-
-fn download_from_walrus(blob_id: &str, output_path: &str) -> Result<(), String> {
-    let output = Command::new("deno")
-        .args(&[
-            "run", "-A", "--env-file=../mothrbox_ts/.env",
-            "../mothrbox_ts/src/walrus-cli.ts", "download", blob_id, output_path,
-        ])
-        .output()
-        .map_err(|e| format!("Walrus download failed: {}", e))?;
-
-    if !output.status.success() {
-        return Err(format!("Walrus error: {}", String::from_utf8_lossy(&output.stderr)));
+/// Blob storage shared by every handler, chosen at startup by `MOTHRBOX_BACKEND`
+type Storage = web::Data<Box<dyn StorageBackend>>;
+
+/// HTTP status for an engine error: client mistakes are 4xx, storage outages 502
+fn status_for(error: &CryptoError) -> StatusCode {
+    match error {
+        CryptoError::AuthenticationFailed => StatusCode::UNAUTHORIZED,
+        CryptoError::MalformedHeader(_)
+        | CryptoError::UnsupportedVersion(_)
+        | CryptoError::Unsupported(_)
+        | CryptoError::AlgorithmMismatch { .. }
+        | CryptoError::Truncated(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        CryptoError::InvalidKey(_) | CryptoError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+        CryptoError::Storage { .. } => StatusCode::BAD_GATEWAY,
+        CryptoError::NotFound { .. } => StatusCode::NOT_FOUND,
+        CryptoError::KeyDerivation(_) | CryptoError::Encryption(_) | CryptoError::Io(_) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
     }
-    Ok(())
 }
 
 fn hash_file(data: &[u8]) -> String {
@@ -93,7 +88,7 @@ fn hash_file(data: &[u8]) -> String {
     format!("{:x}", hasher.finalize())
 }
 
-async fn encrypt_handler(req: web::Json<EncryptRequest>) -> HttpResponse {
+async fn encrypt_handler(req: web::Json<EncryptRequest>, storage: Storage) -> HttpResponse {
     log::info!("🔐 Encrypting: {} with {}", req.filename, req.algorithm);
 
     let file_data = match general_purpose::STANDARD.decode(&req.file_data) {
@@ -104,92 +99,74 @@ async fn encrypt_handler(req: web::Json<EncryptRequest>) -> HttpResponse {
         }),
     };
 
-    let temp_id = uuid::Uuid::new_v4();
-    let temp_dir = format!("/tmp/encrypt_{}", temp_id);
-    fs::create_dir_all(&temp_dir).unwrap();
-
-    let input_path = format!("{}/input.bin", temp_dir);
-    let output_path = format!("{}/output.enc", temp_dir);
+    let compression: Compression = match req.compress.as_deref().map(str::parse).transpose() {
+        Ok(compression) => compression.unwrap_or(Compression::NONE),
+        Err(e) => return HttpResponse::BadRequest().json(EncryptResponse {
+            success: false, blob_id: None, file_hash: None,
+            attestation_document: None, error: Some(e.to_string()),
+        }),
+    };
 
-    if let Err(e) = fs::write(&input_path, &file_data) {
-        return HttpResponse::InternalServerError().json(EncryptResponse {
+    let algorithm: Algorithm = match req.algorithm.parse() {
+        Ok(algorithm) => algorithm,
+        Err(_) => return HttpResponse::BadRequest().json(EncryptResponse {
             success: false, blob_id: None, file_hash: None,
-            attestation_document: None, error: Some(format!("Write error: {}", e)),
-        });
-    }
+            attestation_document: None,
+            error: Some("Invalid algorithm (use 'aes', 'chacha', or 'ecc')".to_string()),
+        }),
+    };
 
-    // Encrypt based on algorithm
-    let encrypt_result = match req.algorithm.as_str() {
-        "aes" => {
-            mothrbox_crypto::aes::encrypt_file(&input_path, &output_path, &req.password)
-        },
-        "chacha" => {
-            mothrbox_crypto::chacha::encrypt_file(&input_path, &output_path, &req.password)
-        },
-        "ecc" => {
-            // For ECC, we need a public key file
-            if let Some(public_key_b64) = &req.public_key {
-                let public_key_data = match general_purpose::STANDARD.decode(public_key_b64) {
-                    Ok(data) => data,
-                    Err(e) => return HttpResponse::BadRequest().json(EncryptResponse {
-                        success: false, blob_id: None, file_hash: None,
-                        attestation_document: None, 
-                        error: Some(format!("Invalid public key base64: {}", e)),
-                    }),
-                };
-                
-                let pubkey_path = format!("{}/public.key", temp_dir);
-                if let Err(e) = fs::write(&pubkey_path, public_key_data) {
-                    return HttpResponse::InternalServerError().json(EncryptResponse {
-                        success: false, blob_id: None, file_hash: None,
-                        attestation_document: None, error: Some(format!("Key write error: {}", e)),
-                    });
-                }
-                
-                mothrbox_crypto::ecc::encrypt_file(&input_path, &output_path, &pubkey_path)
-            } else {
+    // Encrypt in memory: the plaintext never touches the enclave's disk
+    let options = EncryptOptions { compression, ..Default::default() };
+    let encrypt_result = match algorithm {
+        Algorithm::EciesP256 => {
+            let Some(public_key_b64) = &req.public_key else {
                 return HttpResponse::BadRequest().json(EncryptResponse {
                     success: false, blob_id: None, file_hash: None,
-                    attestation_document: None, 
+                    attestation_document: None,
                     error: Some("ECC requires public_key field".to_string()),
                 });
-            }
+            };
+            let public_key = match general_purpose::STANDARD.decode(public_key_b64) {
+                Ok(data) => MothrboxPublicKey::from_bytes(&data),
+                Err(e) => return HttpResponse::BadRequest().json(EncryptResponse {
+                    success: false, blob_id: None, file_hash: None,
+                    attestation_document: None,
+                    error: Some(format!("Invalid public key base64: {}", e)),
+                }),
+            };
+            public_key.and_then(|public_key| {
+                mothrbox_crypto::encrypt_with_options(&file_data, &EncryptionKey::EccPublicKey(&public_key), &options)
+            })
+        },
+        algorithm => {
+            let key = EncryptionKey::Password {
+                algorithm,
+                password: req.password.expose_secret(),
+                kdf: KdfParams::default(),
+            };
+            mothrbox_crypto::encrypt_with_options(&file_data, &key, &options)
         },
-        _ => {
-            return HttpResponse::BadRequest().json(EncryptResponse {
-                success: false, blob_id: None, file_hash: None,
-                attestation_document: None, 
-                error: Some("Invalid algorithm (use 'aes', 'chacha', or 'ecc')".to_string()),
-            });
-        }
     };
 
-    if let Err(e) = encrypt_result {
-        return HttpResponse::InternalServerError().json(EncryptResponse {
-            success: false, blob_id: None, file_hash: None,
-            attestation_document: None, error: Some(format!("Encryption error: {}", e)),
-        });
-    }
-
-    let encrypted_data = match fs::read(&output_path) {
+    let encrypted_data = match encrypt_result {
         Ok(data) => data,
-        Err(e) => return HttpResponse::InternalServerError().json(EncryptResponse {
+        Err(e) => return HttpResponse::build(status_for(&e)).json(EncryptResponse {
             success: false, blob_id: None, file_hash: None,
-            attestation_document: None, error: Some(format!("Read error: {}", e)),
+            attestation_document: None, error: Some(format!("Encryption error: {}", e)),
         }),
     };
 
     let file_hash = hash_file(&encrypted_data);
-    let blob_id = match upload_to_walrus(&output_path) {
+    let blob_id = match storage.put(&encrypted_data) {
         Ok(id) => id,
-        Err(e) => return HttpResponse::InternalServerError().json(EncryptResponse {
+        Err(e) => return HttpResponse::build(status_for(&e)).json(EncryptResponse {
             success: false, blob_id: None, file_hash: None,
-            attestation_document: None, error: Some(e),
+            attestation_document: None, error: Some(e.to_string()),
         }),
     };
 
     let attestation = generate_attestation(&format!("{}:{}", blob_id, file_hash));
-    fs::remove_dir_all(&temp_dir).ok();
 
     HttpResponse::Ok().json(EncryptResponse {
         success: true,
@@ -200,80 +177,63 @@ async fn encrypt_handler(req: web::Json<EncryptRequest>) -> HttpResponse {
     })
 }
 
-async fn decrypt_handler(req: web::Json<DecryptRequest>) -> HttpResponse {
+async fn decrypt_handler(req: web::Json<DecryptRequest>, storage: Storage) -> HttpResponse {
     log::info!("🔓 Decrypting: {} with {}", req.blob_id, req.algorithm);
 
-    let temp_id = uuid::Uuid::new_v4();
-    let temp_dir = format!("/tmp/decrypt_{}", temp_id);
-    fs::create_dir_all(&temp_dir).unwrap();
-
-    let encrypted_path = format!("{}/encrypted.bin", temp_dir);
-    let decrypted_path = format!("{}/decrypted.bin", temp_dir);
-
-    if let Err(e) = download_from_walrus(&req.blob_id, &encrypted_path) {
-        return HttpResponse::InternalServerError().json(DecryptResponse {
-            success: false, file_data: None, attestation_document: None, error: Some(e),
-        });
-    }
+    let algorithm: Algorithm = match req.algorithm.parse() {
+        Ok(algorithm) => algorithm,
+        Err(_) => return HttpResponse::BadRequest().json(DecryptResponse {
+            success: false, file_data: None, attestation_document: None,
+            error: Some("Invalid algorithm (use 'aes', 'chacha', or 'ecc')".to_string()),
+        }),
+    };
 
-    let decrypt_result = match req.algorithm.as_str() {
-        "aes" => {
-            mothrbox_crypto::aes::decrypt_file(&encrypted_path, &decrypted_path, &req.password)
-        },
-        "chacha" => {
-            mothrbox_crypto::chacha::decrypt_file(&encrypted_path, &decrypted_path, &req.password)
-        },
-        "ecc" => {
-            if let Some(private_key_b64) = &req.private_key {
-                let private_key_data = match general_purpose::STANDARD.decode(private_key_b64) {
-                    Ok(data) => data,
-                    Err(e) => return HttpResponse::BadRequest().json(DecryptResponse {
-                        success: false, file_data: None, attestation_document: None,
-                        error: Some(format!("Invalid private key base64: {}", e)),
-                    }),
-                };
-                
-                let privkey_path = format!("{}/private.key", temp_dir);
-                if let Err(e) = fs::write(&privkey_path, private_key_data) {
-                    return HttpResponse::InternalServerError().json(DecryptResponse {
-                        success: false, file_data: None, attestation_document: None,
-                        error: Some(format!("Key write error: {}", e)),
-                    });
-                }
-                
-                mothrbox_crypto::ecc::decrypt_file(&encrypted_path, &decrypted_path, &privkey_path)
-            } else {
-                return HttpResponse::BadRequest().json(DecryptResponse {
+    let private_key = match (&algorithm, &req.private_key) {
+        (Algorithm::EciesP256, Some(private_key_b64)) => {
+            let private_key_data = match general_purpose::STANDARD.decode(private_key_b64.expose_secret()) {
+                Ok(data) => Zeroizing::new(data),
+                Err(e) => return HttpResponse::BadRequest().json(DecryptResponse {
                     success: false, file_data: None, attestation_document: None,
-                    error: Some("ECC requires private_key field".to_string()),
-                });
+                    error: Some(format!("Invalid private key base64: {}", e)),
+                }),
+            };
+            match LockedKey::from_slice(&private_key_data) {
+                Ok(key) => Some(key),
+                Err(e) => return HttpResponse::BadRequest().json(DecryptResponse {
+                    success: false, file_data: None, attestation_document: None,
+                    error: Some(format!("Invalid private key: {}", e)),
+                }),
             }
         },
-        _ => {
+        (Algorithm::EciesP256, None) => {
             return HttpResponse::BadRequest().json(DecryptResponse {
                 success: false, file_data: None, attestation_document: None,
-                error: Some("Invalid algorithm (use 'aes', 'chacha', or 'ecc')".to_string()),
+                error: Some("ECC requires private_key field".to_string()),
             });
-        }
+        },
+        _ => None,
+    };
+    // The key stays in (locked) memory; it is never written to disk
+    let key = match &private_key {
+        Some(private_key) => DecryptionKey::EccPrivateKey(private_key),
+        None => DecryptionKey::Password(req.password.expose_secret()),
     };
 
-    if let Err(e) = decrypt_result {
-        return HttpResponse::InternalServerError().json(DecryptResponse {
-            success: false, file_data: None, attestation_document: None,
-            error: Some(format!("Decryption error: {}", e)),
-        });
-    }
-
-    let decrypted_data = match fs::read(&decrypted_path) {
-        Ok(data) => data,
-        Err(e) => return HttpResponse::InternalServerError().json(DecryptResponse {
-            success: false, file_data: None, attestation_document: None,
-            error: Some(format!("Read error: {}", e)),
-        }),
+    // Fetch and decrypt in memory; the plaintext only leaves in the response
+    let decrypted_data = match walrus::get_and_decrypt(storage.get_ref().as_ref(), &req.blob_id, &key, &[], Some(algorithm)) {
+        Ok((_, data)) => Zeroizing::new(data),
+        Err(e) => {
+            let error = match &e {
+                CryptoError::Storage { .. } | CryptoError::NotFound { .. } => e.to_string(),
+                _ => format!("Decryption error: {}", e),
+            };
+            return HttpResponse::build(status_for(&e)).json(DecryptResponse {
+                success: false, file_data: None, attestation_document: None, error: Some(error),
+            });
+        }
     };
 
     let attestation = generate_attestation(&format!("decrypt:{}", req.blob_id));
-    fs::remove_dir_all(&temp_dir).ok();
     let file_data_b64 = general_purpose::STANDARD.encode(&decrypted_data);
 
     HttpResponse::Ok().json(DecryptResponse {
@@ -293,23 +253,114 @@ async fn health_check() -> HttpResponse {
     }))
 }
 
+/// Endpoints served by the enclave; handlers need a `Storage` in the app data
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/health", web::get().to(health_check))
+        .route("/encrypt", web::post().to(encrypt_handler))
+        .route("/decrypt", web::post().to(decrypt_handler));
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
 
     log::info!("🚀 Starting MothrBox Nautilus Enclave Server");
     log::info!("🔐 Algorithms: AES-256-GCM, ChaCha20-Poly1305, ECC P-256");
-    log::info!("⛓️  Storage: Walrus Protocol");
+    let backend = storage::backend_from_env().map_err(std::io::Error::other)?;
+    log::info!("⛓️  Storage: {}", backend.name());
     log::info!("🌐 Listening on http://127.0.0.1:8080");
 
-    HttpServer::new(|| {
+    let storage: Storage = web::Data::new(backend);
+    HttpServer::new(move || {
         App::new()
+            .app_data(storage.clone())
             .wrap(middleware::Logger::default())
-            .route("/health", web::get().to(health_check))
-            .route("/encrypt", web::post().to(encrypt_handler))
-            .route("/decrypt", web::post().to(decrypt_handler))
+            .configure(routes)
     })
     .bind("127.0.0.1:8080")?
     .run()
     .await
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use mothrbox_crypto::walrus::mock::MockWalrus;
+    use mothrbox_crypto::walrus::WalrusHttpClient;
+    use serde_json::{json, Value};
+
+    fn mock_storage(walrus: &MockWalrus) -> Storage {
+        let backend: Box<dyn StorageBackend> = Box::new(WalrusHttpClient::new(walrus.http_config()));
+        web::Data::new(backend)
+    }
+
+    fn encrypt_request(algorithm: &str, data: &[u8]) -> test::TestRequest {
+        test::TestRequest::post().uri("/encrypt").set_json(json!({
+            "file_data": general_purpose::STANDARD.encode(data),
+            "password": "enclave password",
+            "algorithm": algorithm,
+            "filename": "record.txt",
+            "compress": "lz4",
+        }))
+    }
+
+    fn decrypt_request(algorithm: &str, blob_id: &str, password: &str) -> test::TestRequest {
+        test::TestRequest::post().uri("/decrypt").set_json(json!({
+            "blob_id": blob_id,
+            "password": password,
+            "algorithm": algorithm,
+        }))
+    }
+
+    #[actix_web::test]
+    async fn test_encrypt_and_decrypt_through_walrus() {
+        let walrus = MockWalrus::start().unwrap();
+        let app = test::init_service(App::new().app_data(mock_storage(&walrus)).configure(routes)).await;
+        let record = b"patient: 42, diagnosis: fine\n".repeat(20);
+
+        for algorithm in ["aes", "chacha"] {
+            let response: Value =
+                test::call_and_read_body_json(&app, encrypt_request(algorithm, &record).to_request()).await;
+            assert_eq!(response["success"], true, "{}", response);
+            let blob_id = response["blob_id"].as_str().unwrap();
+            assert!(walrus.blob_count() > 0);
+
+            let response: Value = test::call_and_read_body_json(
+                &app,
+                decrypt_request(algorithm, blob_id, "enclave password").to_request(),
+            )
+            .await;
+            assert_eq!(response["success"], true, "{}", response);
+            let file_data = general_purpose::STANDARD.decode(response["file_data"].as_str().unwrap()).unwrap();
+            assert_eq!(file_data, record);
+
+            let response =
+                test::call_service(&app, decrypt_request(algorithm, blob_id, "wrong").to_request()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_storage_errors_become_status_codes() {
+        let walrus = MockWalrus::start().unwrap();
+        let app = test::init_service(App::new().app_data(mock_storage(&walrus)).configure(routes)).await;
+
+        let missing = MockWalrus::blob_id(b"never stored");
+        let response = test::call_service(&app, decrypt_request("aes", &missing, "pw").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // More failures than the client retries
+        walrus.fail_next(10, 503);
+        let response = test::call_service(&app, encrypt_request("aes", b"data").to_request()).await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+        walrus.fail_next(0, 0);
+        let response: Value =
+            test::call_and_read_body_json(&app, encrypt_request("aes", b"data").to_request()).await;
+        let blob_id = response["blob_id"].as_str().unwrap().to_string();
+        walrus.advance_epochs(3);
+        let response = test::call_service(&app, decrypt_request("aes", &blob_id, "enclave password").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}